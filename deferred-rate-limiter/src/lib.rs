@@ -59,6 +59,19 @@ where
         }
     }
 
+    /// How many requests the key has made this period, according to redis. This does not count as a request.
+    /// Recent requests might still be in the local cache and not in redis yet
+    pub async fn peek(&self, key: K) -> anyhow::Result<u64> {
+        let redis_key = format!("{}:{}", self.prefix, key);
+
+        self.rrl.peek_label(&redis_key).await
+    }
+
+    /// Seconds until the current period ends and every key's count resets
+    pub fn seconds_until_reset(&self) -> f32 {
+        self.rrl.seconds_left_in_period(self.rrl.now_as_secs())
+    }
+
     /// if setting max_per_period, be sure to keep the period the same for all requests to this label
     /// TODO: max_per_period being None means two things. some places it means unlimited, but here it means to use the default. make an enum
     pub async fn throttle(
@@ -13,10 +13,11 @@ pub struct Model {
     pub rpc_key_id: u64,
     pub timestamp: DateTimeUtc,
     pub method: Method,
-    #[serde(serialize_with = "serialization::vec_as_address")]
+    #[serde(serialize_with = "serialization::vec_as_checksum_address")]
     pub to: Vec<u8>,
-    #[sea_orm(column_type = "Text", nullable)]
-    pub call_data: Option<String>,
+    #[sea_orm(column_type = "Binary(BlobSize::Long)", nullable)]
+    #[serde(serialize_with = "serialization::option_vec_as_bytes")]
+    pub call_data: Option<Vec<u8>>,
     pub chain_id: u64,
 }
 
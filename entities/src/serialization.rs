@@ -1,5 +1,6 @@
 //! sea-orm types don't always serialize how we want. this helps that, though it won't help every case.
-use ethers::prelude::Address;
+use ethers::prelude::{Address, Bytes};
+use ethers::utils::to_checksum;
 use sea_orm::prelude::Uuid;
 use serde::{Serialize, Serializer};
 use std::convert::TryInto;
@@ -19,6 +20,25 @@ where
     x.serialize(s)
 }
 
+/// checksummed so that it matches what block explorers show
+pub fn vec_as_checksum_address<S>(x: &[u8], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let x = Address::from_slice(x);
+
+    to_checksum(&x, None).serialize(s)
+}
+
+pub fn option_vec_as_bytes<S>(x: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let x = x.clone().map(Bytes::from);
+
+    x.serialize(s)
+}
+
 pub fn uuid_as_ulid<S>(x: &Uuid, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
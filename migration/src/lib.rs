@@ -32,6 +32,7 @@ mod m20230607_221917_total_deposits;
 mod m20230615_221201_handle_payment_uncles;
 mod m20230618_230611_longer_payload;
 mod m20230619_172237_default_tracking;
+mod m20230620_142133_typed_revert_log_call_data;
 
 pub struct Migrator;
 
@@ -71,6 +72,7 @@ impl MigratorTrait for Migrator {
             Box::new(m20230615_221201_handle_payment_uncles::Migration),
             Box::new(m20230618_230611_longer_payload::Migration),
             Box::new(m20230619_172237_default_tracking::Migration),
+            Box::new(m20230620_142133_typed_revert_log_call_data::Migration),
         ]
     }
 }
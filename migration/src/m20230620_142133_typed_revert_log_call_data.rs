@@ -0,0 +1,92 @@
+//! Store revert log call data as bytes instead of whatever hex string the user sent
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .add_column(
+                        ColumnDef::new(RevertLog::CallDataBytes)
+                            .blob(BlobSize::Long)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // copy the old hex strings into the new column
+        let db_conn = manager.get_connection();
+        let db_backend = manager.get_database_backend();
+
+        let copy_call_data = Query::update()
+            .table(RevertLog::Table)
+            .value(
+                RevertLog::CallDataBytes,
+                Expr::cust("UNHEX(SUBSTRING(`call_data`, 3))"),
+            )
+            .and_where(Expr::col(RevertLog::CallData).like("0x%"))
+            .to_owned();
+
+        db_conn.execute(db_backend.build(&copy_call_data)).await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .drop_column(RevertLog::CallData)
+                    .rename_column(RevertLog::CallDataBytes, RevertLog::CallData)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .add_column(ColumnDef::new(RevertLog::CallDataText).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        let db_conn = manager.get_connection();
+        let db_backend = manager.get_database_backend();
+
+        let copy_call_data = Query::update()
+            .table(RevertLog::Table)
+            .value(
+                RevertLog::CallDataText,
+                Expr::cust("CONCAT('0x', LOWER(HEX(`call_data`)))"),
+            )
+            .and_where(Expr::col(RevertLog::CallData).is_not_null())
+            .to_owned();
+
+        db_conn.execute(db_backend.build(&copy_call_data)).await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .drop_column(RevertLog::CallData)
+                    .rename_column(RevertLog::CallDataText, RevertLog::CallData)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RevertLog {
+    Table,
+    CallData,
+    CallDataBytes,
+    CallDataText,
+}
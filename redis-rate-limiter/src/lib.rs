@@ -54,12 +54,44 @@ impl RedisRateLimiter {
         (now_as_secs / self.period) % self.period
     }
 
+    /// periods line up with the clock. they do not start at a label's first request
+    pub fn seconds_left_in_period(&self, now_as_secs: f32) -> f32 {
+        self.period - (now_as_secs % self.period)
+    }
+
     pub fn next_period(&self, now_as_secs: f32) -> Instant {
-        let seconds_left_in_period = self.period - (now_as_secs % self.period);
+        let seconds_left_in_period = self.seconds_left_in_period(now_as_secs);
 
         Instant::now().add(Duration::from_secs_f32(seconds_left_in_period))
     }
 
+    fn throttle_key(&self, label: &str, now_as_secs: f32) -> String {
+        // if self.period is 60, period_id will be the minute of the current time
+        let period_id = self.period_id(now_as_secs);
+
+        // TODO: include max per period in the throttle key?
+        format!("{}:{}:{}", self.key_prefix, label, period_id)
+    }
+
+    /// The label's count for the current period. Unlike throttle_label, this does not count as a request
+    pub async fn peek_label(&self, label: &str) -> anyhow::Result<u64> {
+        let throttle_key = self.throttle_key(label, self.now_as_secs());
+
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .context("get redis connection for rate limits")?;
+
+        let count: Option<u64> = redis::cmd("GET")
+            .arg(&throttle_key)
+            .query_async(&mut *conn)
+            .await
+            .context("cannot get rate limit")?;
+
+        Ok(count.unwrap_or_default())
+    }
+
     /// label might be an ip address or a rpc_key id.
     /// if setting max_per_period, be sure to keep the period the same for all requests to this label
     pub async fn throttle_label(
@@ -76,11 +108,7 @@ impl RedisRateLimiter {
 
         let now = self.now_as_secs();
 
-        // if self.period is 60, period_id will be the minute of the current time
-        let period_id = self.period_id(now);
-
-        // TODO: include max per period in the throttle key?
-        let throttle_key = format!("{}:{}:{}", self.key_prefix, label, period_id);
+        let throttle_key = self.throttle_key(label, now);
 
         let mut conn = self
             .pool
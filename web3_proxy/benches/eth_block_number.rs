@@ -0,0 +1,84 @@
+//! Compare serializing the eth_blockNumber response on every call with serving it from the head block cache.
+//!
+//! `cargo bench --bench eth_block_number` prints the allocations per call for both.
+#![feature(test)]
+
+extern crate test;
+
+use ethers::types::U64;
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use test::{black_box, Bencher};
+use web3_proxy::response_cache::{BlockNumberResponseCache, JsonRpcResponseEnum};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CALLS: usize = 10_000;
+
+fn allocations_per_call<T>(f: impl Fn() -> T) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+
+    for _ in 0..CALLS {
+        black_box(f());
+    }
+
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+
+    (after - before) as f64 / CALLS as f64
+}
+
+/// what eth_blockNumber did before the cache
+fn serialize(block_num: &U64) -> JsonRpcResponseEnum<Arc<RawValue>> {
+    JsonRpcResponseEnum::from(json!(block_num))
+}
+
+#[bench]
+fn bench_serialize_every_call(b: &mut Bencher) {
+    let block_num = U64::from(17_000_000);
+
+    println!(
+        "serialize every call: {} allocations per call",
+        allocations_per_call(|| serialize(&block_num))
+    );
+
+    b.iter(|| serialize(black_box(&block_num)));
+}
+
+#[bench]
+fn bench_cached(b: &mut Bencher) {
+    let block_num = U64::from(17_000_000);
+
+    let cache = BlockNumberResponseCache::default();
+
+    cache.update(block_num);
+
+    // arc_swap sets up a thread local the first time it is loaded on a thread
+    black_box(cache.get(&block_num));
+
+    let allocations = allocations_per_call(|| cache.get(&block_num));
+
+    println!("cached: {} allocations per call", allocations);
+
+    assert_eq!(allocations, 0.0);
+
+    b.iter(|| cache.get(black_box(&block_num)));
+}
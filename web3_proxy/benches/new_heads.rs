@@ -0,0 +1,79 @@
+//! Compare serializing the head block for every newHeads subscriber with serializing it once per head block.
+//!
+//! `cargo bench --bench new_heads` sends one head block to 1000 simulated subscribers.
+#![feature(test)]
+
+extern crate test;
+
+use ethers::types::{Block, Bytes, TxHash, H256, U64};
+use serde_json::json;
+use std::sync::Arc;
+use test::{black_box, Bencher};
+use web3_proxy::response_cache::{new_heads_notification, NewHeadsResponseCache};
+use web3_proxy::rpcs::blockchain::Web3ProxyBlock;
+
+const SUBSCRIBERS: u64 = 1_000;
+
+/// a block with about as many transactions as a busy mainnet block
+fn head_block() -> Web3ProxyBlock {
+    let block = Block::<TxHash> {
+        hash: Some(H256::repeat_byte(1)),
+        parent_hash: H256::repeat_byte(2),
+        number: Some(17_000_000.into()),
+        extra_data: Bytes::from(vec![0; 32]),
+        transactions: (0..200).map(H256::from_low_u64_be).collect(),
+        ..Default::default()
+    };
+
+    Web3ProxyBlock::try_new(Arc::new(block)).unwrap()
+}
+
+/// what eth_subscribe did for every subscriber before the cache
+fn serialize(subscription_id: U64, head_block: &Web3ProxyBlock) -> String {
+    let response_json = json!({
+        "jsonrpc": "2.0",
+        "method": "eth_subscription",
+        "params": {
+            "subscription": subscription_id,
+            "result": head_block.block,
+        },
+    });
+
+    serde_json::to_string(&response_json).unwrap()
+}
+
+#[bench]
+fn bench_serialize_every_subscriber(b: &mut Bencher) {
+    let head_block = head_block();
+
+    b.iter(|| {
+        for subscription_id in 0..SUBSCRIBERS {
+            black_box(serialize(subscription_id.into(), &head_block));
+        }
+    });
+}
+
+#[bench]
+fn bench_serialize_once(b: &mut Bencher) {
+    let head_block = head_block();
+
+    let cache = NewHeadsResponseCache::default();
+
+    // both ways send the same message
+    cache.update(&head_block);
+    assert_eq!(
+        new_heads_notification(&7.into(), &cache.get(&head_block)),
+        serialize(7.into(), &head_block)
+    );
+
+    b.iter(|| {
+        // the app updates the cache once for every new head block
+        cache.update(&head_block);
+
+        for subscription_id in 0..SUBSCRIBERS {
+            let block = cache.get(&head_block);
+
+            black_box(new_heads_notification(&subscription_id.into(), &block));
+        }
+    });
+}
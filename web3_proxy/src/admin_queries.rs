@@ -6,7 +6,7 @@ use axum::{
     headers::{authorization::Bearer, Authorization},
     Json, TypedHeader,
 };
-use entities::{admin, login, user, user_tier};
+use entities::{admin, login, rpc_key, user, user_tier};
 use ethers::prelude::Address;
 use hashbrown::HashMap;
 use migration::sea_orm::{
@@ -91,11 +91,23 @@ pub async fn query_admin_modify_usertier<'a>(
     if user.user_tier_id == new_user_tier.id {
         info!("user already has that tier");
     } else {
-        let mut user = user.clone().into_active_model();
+        let mut active_user = user.clone().into_active_model();
 
-        user.user_tier_id = sea_orm::Set(new_user_tier.id);
+        active_user.user_tier_id = sea_orm::Set(new_user_tier.id);
 
-        user.save(db_conn).await?;
+        active_user.save(db_conn).await?;
+
+        // the keys' cached authorization checks have the old tier's limits
+        let rpc_keys = rpc_key::Entity::find()
+            .filter(rpc_key::Column::UserId.eq(user.id))
+            .all(db_conn)
+            .await?;
+
+        for rpc_key_entity in rpc_keys {
+            app.rpc_secret_key_cache
+                .invalidate(&rpc_key_entity.secret_key.into())
+                .await;
+        }
 
         info!("user's tier changed");
     }
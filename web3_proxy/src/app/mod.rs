@@ -1,29 +1,61 @@
 mod ws;
 
 use crate::block_number::CacheMode;
-use crate::config::{AppConfig, TopConfig};
-use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
+use crate::call_bundle::{BundleSimulator, CallBundleParams};
+use crate::call_gas::{is_gas_too_high, retry_params};
+use crate::compute_unit_quota::ComputeUnitQuota;
+use crate::compute_units::ComputeUnit;
+use crate::config::{AppConfig, PeerCountMode, TopConfig, Web3RpcConfig};
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult, ERROR_RESPONSES};
+#[cfg(feature = "disk_cache")]
+use crate::disk_response_cache::DiskResponseCache;
+use crate::fee_history::{
+    FeeHistory, FeeHistoryCache, FeeHistoryParams, DEFAULT_MAX_PRIORITY_FEE_PER_GAS,
+    FEE_HISTORY_MAX_BLOCKS, PRIORITY_FEE_BLOCKS, PRIORITY_FEE_PERCENTILE,
+};
+use crate::filters::{
+    filter_cache, filter_id_from_params, logs_changes_params, logs_filter_from_params,
+    new_filter_id, Filter, FilterCache, FilterKind,
+};
 use crate::frontend::authorization::{
     Authorization, AuthorizationChecks, Balance, RequestMetadata, RequestOrMethod, ResponseOrBytes,
     RpcSecretKey,
 };
-use crate::frontend::rpc_proxy_ws::ProxyMode;
+use crate::frontend::rpc_proxy_ws::{
+    ws_resume_cache, ProxyMode, ResumableSubscription, WsResumeKey,
+};
 use crate::jsonrpc::{
     JsonRpcErrorData, JsonRpcForwardedResponse, JsonRpcForwardedResponseEnum, JsonRpcId,
     JsonRpcParams, JsonRpcRequest, JsonRpcRequestEnum, JsonRpcResultData,
 };
+use crate::logs_chunks::{merge_logs_chunks, split_logs_range, MAX_CONCURRENT_CHUNKS};
+use crate::logs_pagination::LogsPage;
+use crate::method_filter::{
+    blocked_entry, check_method, check_method_for_tier, check_premium, method_available_on_chain,
+    MethodAccess, BLOCKED_METHODS,
+};
+use crate::prometheus::{self, PrivateRelayMetrics, RejectedMethodMetrics, ResponseCacheMetrics};
+use crate::raw_transaction::validate_raw_transaction;
 use crate::relational_db::{get_db, get_migrated_db, DatabaseConnection, DatabaseReplica};
 use crate::response_cache::{
-    JsonRpcQueryCacheKey, JsonRpcResponseCache, JsonRpcResponseEnum, JsonRpcResponseWeigher,
+    BlockNumberResponseCache, CacheStatus, CachedJsonRpcResponse, JsonRpcQueryCacheKey,
+    JsonRpcResponseCache, JsonRpcResponseEnum, JsonRpcResponseExpiry, JsonRpcResponseWeigher,
+    NewHeadsResponseCache, MAX_CACHED_PARAMS_BYTES,
 };
-use crate::rpcs::blockchain::Web3ProxyBlock;
+use crate::rpcs::blockchain::{ArcBlock, Web3ProxyBlock};
 use crate::rpcs::consensus::RankedRpcs;
 use crate::rpcs::many::Web3Rpcs;
 use crate::rpcs::one::Web3Rpc;
 use crate::rpcs::provider::{connect_http, EthersHttpProvider};
-use crate::rpcs::transactions::TxStatus;
+use crate::rpcs::request::UpstreamErrorKind;
+use crate::rpcs::retry::{is_idempotent, RetryPolicy};
+use crate::rpcs::transactions::{PendingTransactionCache, TxStatus};
+use crate::shared_response_cache::SharedResponseCache;
+use crate::signer::SigningService;
 use crate::stats::{AppStat, StatBuffer};
+use crate::trace_replay::{is_confirmed, TraceReplayParams};
 use crate::user_token::UserBearerToken;
+use crate::webhooks::WebhookSender;
 use anyhow::Context;
 use axum::http::StatusCode;
 use chrono::Utc;
@@ -31,32 +63,33 @@ use deferred_rate_limiter::DeferredRateLimiter;
 use derive_more::From;
 use entities::user;
 use ethers::core::utils::keccak256;
-use ethers::prelude::{Address, Bytes, Transaction, TxHash, H256, U64};
-use ethers::types::U256;
+use ethers::prelude::{Address, BlockNumber, Bytes, Transaction, H256, U64};
+use ethers::types::{TransactionReceipt, U256};
 use ethers::utils::rlp::{Decodable, Rlp};
-use futures::future::join_all;
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::stream::{self, FuturesUnordered, StreamExt};
 use hashbrown::{HashMap, HashSet};
+use influxdb2::api::write::TimestampPrecision;
+use migration::sea_orm::prelude::Decimal;
 use migration::sea_orm::{DatabaseTransaction, EntityTrait, PaginatorTrait, TransactionTrait};
 use moka::future::{Cache, CacheBuilder};
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 use redis_rate_limiter::redis::AsyncCommands;
 use redis_rate_limiter::{redis, DeadpoolRuntime, RedisConfig, RedisPool, RedisRateLimiter};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::value::RawValue;
 use std::fmt;
 use std::net::IpAddr;
 use std::num::NonZeroU64;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::{atomic, Arc};
 use std::time::Duration;
-use tokio::sync::{broadcast, watch, Semaphore};
+use tokio::sync::{broadcast, watch, Mutex, Semaphore};
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, timeout};
-use tracing::{error, info, trace, warn, Level};
+use tokio::time::{interval, sleep, timeout};
+use tracing::{debug, error, info, trace, warn, Level};
 
 // TODO: make this customizable?
 // TODO: include GIT_REF in here. i had trouble getting https://docs.rs/vergen/latest/vergen/ to work with a workspace. also .git is in .dockerignore
@@ -67,9 +100,16 @@ pub static APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_VERSION")
 );
 
+/// timeouts for the shared http client. servers can override these in their config
+pub const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
 /// aggregate across 1 week
 pub const BILLING_PERIOD_SECONDS: i64 = 60 * 60 * 24 * 7;
 
+/// how many eth_getTransactionReceipt requests are in flight at once when assembling eth_getBlockReceipts
+const BLOCK_RECEIPTS_CONCURRENCY: usize = 10;
+
 /// Convenience type
 pub type Web3ProxyJoinHandle<T> = JoinHandle<Web3ProxyResult<T>>;
 
@@ -77,6 +117,22 @@ pub type Web3ProxyJoinHandle<T> = JoinHandle<Web3ProxyResult<T>>;
 pub type RpcSecretKeyCache = Cache<RpcSecretKey, AuthorizationChecks>;
 /// Cache data from the database about user balances
 pub type UserBalanceCache = Cache<NonZeroU64, Arc<RwLock<Balance>>>;
+/// Subscriptions of recently closed websockets. Keyed by their resume token
+pub type WsResumeCache = Cache<WsResumeKey, Arc<Vec<ResumableSubscription>>>;
+
+/// A response to a request (or a batch of requests) and what it took to answer it
+#[derive(Debug)]
+pub struct ProxyResponse<R = JsonRpcForwardedResponseEnum> {
+    /// batches are always OK. the status of each request is in its response
+    pub status_code: StatusCode,
+    pub response: R,
+    /// the backend rpcs that were used. empty if the response came from a cache or was answered locally
+    pub rpcs: Vec<Arc<Web3Rpc>>,
+    /// summed across a batch
+    pub compute_units: Decimal,
+    /// merged across a batch. None if no response cache was checked
+    pub cache_status: Option<CacheStatus>,
+}
 
 /// The application
 // TODO: i'm sure this is more arcs than necessary, but spawning futures makes references hard
@@ -87,12 +143,45 @@ pub struct Web3ProxyApp {
     pub bearer_token_semaphores: Cache<UserBearerToken, Arc<Semaphore>>,
     /// Send 4337 Abstraction Bundler requests to one of these servers
     pub bundler_4337_rpcs: Option<Arc<Web3Rpcs>>,
+    /// the eth_blockNumber response for the current head block
+    pub block_number_response_cache: BlockNumberResponseCache,
+    /// the head block serialized for newHeads subscriptions
+    pub new_heads_response_cache: NewHeadsResponseCache,
     /// application config
     /// TODO: this will need a large refactor to handle reloads while running. maybe use a watch::Receiver?
     pub config: AppConfig,
+    /// daily and monthly compute unit quotas for users without a paid balance
+    pub compute_unit_quota: Option<ComputeUnitQuota>,
+    /// eth_feeHistory for recent blocks
+    pub fee_history_cache: FeeHistoryCache,
+    /// filters from eth_newFilter and eth_newBlockFilter
+    pub filters: FilterCache,
     pub http_client: Option<reqwest::Client>,
+    /// keep responses for deep blocks on disk so that they survive restarts
+    #[cfg(feature = "disk_cache")]
+    pub disk_response_cache: Option<DiskResponseCache>,
+    /// eth_sendRawTransaction submissions that are waiting on the relays. keyed by transaction hash
+    pub inflight_raw_transactions: Cache<H256, JsonRpcResponseEnum<Arc<RawValue>>>,
+    /// requests that skip the response cache and are waiting on the backends. keyed by the response cache key's hash
+    /// the rpcs that served the response are kept so that every request sharing it is billed for them
+    pub inflight_uncached_requests:
+        Cache<u64, (JsonRpcResponseEnum<Arc<RawValue>>, Vec<Arc<Web3Rpc>>)>,
     /// track JSONRPC responses
     pub jsonrpc_response_cache: JsonRpcResponseCache,
+    /// transaction hashes whose eth_getTransactionReceipt was recently null. cleared when the transaction is in a new head block
+    pub null_receipt_cache: Option<Cache<H256, ()>>,
+    /// subscriptions that websocket clients can get back when they reconnect
+    pub ws_resume_cache: Option<WsResumeCache>,
+    /// track hits and misses on jsonrpc_response_cache
+    pub response_cache_metrics: ResponseCacheMetrics,
+    /// requests for blocked or unimplemented methods. these never reach a backend rpc
+    pub rejected_method_metrics: RejectedMethodMetrics,
+    /// share responses with other proxies
+    pub shared_response_cache: Option<SharedResponseCache>,
+    /// sign eth_sendTransaction for entitled keys
+    pub signer: Option<SigningService>,
+    /// simulates eth_callBundle
+    pub bundle_simulator: Option<BundleSimulator>,
     /// rpc clients that subscribe to newHeads use this channel
     /// don't drop this or the sender will stop working
     /// TODO: broadcast channel instead?
@@ -108,6 +197,8 @@ pub struct Web3ProxyApp {
     pub frontend_port: Arc<AtomicU16>,
     /// rate limit anonymous users
     pub frontend_ip_rate_limiter: Option<DeferredRateLimiter<IpAddr>>,
+    /// rate limit requests from the origins in `origin_rate_limits`
+    pub frontend_origin_rate_limiter: Option<RedisRateLimiter>,
     /// rate limit authenticated users
     pub frontend_registered_user_rate_limiter: Option<DeferredRateLimiter<u64>>,
     /// concurrent/parallel request limits for anonymous users
@@ -116,12 +207,19 @@ pub struct Web3ProxyApp {
     /// rate limit the login endpoint
     /// we do this because each pending login is a row in the database
     pub login_rate_limiter: Option<RedisRateLimiter>,
+    /// the most peers any backend has. only used if net_peer_count is max_backend_peers
+    /// None is cached too. backends without net_peerCount would otherwise be asked on every request
+    pub peer_count_cache: Cache<(), Option<U64>>,
     /// store pending transactions that we've seen so that we don't send duplicates to subscribers
     /// TODO: think about this more. might be worth storing if we sent the transaction or not and using this for automatic retries
-    pub pending_transactions: Cache<TxHash, TxStatus>,
+    pub pending_transactions: PendingTransactionCache,
     /// Send private requests (like eth_sendRawTransaction) to all these servers
     /// TODO: include another type so that we can use private miner relays that do not use JSONRPC requests
     pub private_rpcs: Option<Arc<Web3Rpcs>>,
+    /// Standby private relays. Only used when every one of private_rpcs errors
+    pub secondary_private_rpcs: Option<Arc<Web3Rpcs>>,
+    /// which set of private relays accepted each transaction
+    pub private_relay_metrics: PrivateRelayMetrics,
     pub prometheus_port: Arc<AtomicU16>,
     /// cache authenticated users so that we don't have to query the database on the hot path
     // TODO: should the key be our RpcSecretKey class instead of Ulid?
@@ -136,6 +234,9 @@ pub struct Web3ProxyApp {
     /// channel for sending stats in a background task
     pub stat_sender: Option<flume::Sender<AppStat>>,
 
+    /// alert operators about important events
+    pub webhooks: Option<Arc<WebhookSender>>,
+
     /// Optional time series database for making pretty graphs that load quickly
     influxdb_client: Option<influxdb2::Client>,
     /// Simple way to connect ethers Contracsts to the proxy
@@ -182,6 +283,19 @@ pub struct Web3ProxyAppSpawn {
     pub ranked_rpcs: watch::Receiver<Option<Arc<RankedRpcs>>>,
 }
 
+/// The only param of eth_sendPrivateTransaction
+/// <https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_sendprivatetransaction>
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendPrivateTransactionParams {
+    /// signed transaction
+    pub tx: Bytes,
+    /// the relays will stop trying to include the transaction after this block
+    pub max_block_number: Option<U64>,
+    /// relay specific options. they are passed through untouched
+    pub preferences: Option<serde_json::Value>,
+}
+
 impl Web3ProxyApp {
     /// The main entrypoint.
     pub async fn spawn(
@@ -204,6 +318,38 @@ impl Web3ProxyApp {
             );
         }
 
+        if top_config.app.require_https_backends {
+            let insecure = top_config.insecure_backend_urls();
+
+            anyhow::ensure!(
+                insecure.is_empty(),
+                "require_https_backends is set, but these backends do not use TLS: {}",
+                insecure.join(", ")
+            );
+        }
+
+        // 0 would disconnect every websocket client right after the first ping
+        anyhow::ensure!(
+            top_config.app.ws_pong_timeout > 0,
+            "ws_pong_timeout must be at least 1 second"
+        );
+
+        // 0 would drop every filter before its first poll
+        anyhow::ensure!(
+            top_config.app.filter_ttl > 0,
+            "filter_ttl must be at least 1 second"
+        );
+
+        anyhow::ensure!(
+            top_config.app.rate_limit_period > 0,
+            "rate_limit_period must be at least 1 second"
+        );
+
+        anyhow::ensure!(
+            top_config.app.origin_rate_limit_period > 0,
+            "origin_rate_limit_period must be at least 1 second"
+        );
+
         if !top_config.extra.is_empty() {
             warn!(
                 extra=?top_config.extra.keys(),
@@ -218,6 +364,12 @@ impl Web3ProxyApp {
             );
         }
 
+        for method in top_config.app.private_methods.iter() {
+            if !ComputeUnit::is_known_method(method, top_config.app.chain_id) {
+                warn!(%method, "unknown method in private_methods. check for typos");
+            }
+        }
+
         // these futures are key parts of the app. if they stop running, the app has encountered an irrecoverable error
         // TODO: this is a small enough group, that a vec with try_join_all is probably fine
         let app_handles: FuturesUnordered<Web3ProxyJoinHandle<()>> = FuturesUnordered::new();
@@ -416,11 +568,11 @@ impl Web3ProxyApp {
 
         // make a http shared client
         // TODO: can we configure the connection pool? should we?
-        // TODO: timeouts from config. defaults are hopefully good
+        // servers with their own timeouts in their config build their own client
         let http_client = Some(
             reqwest::ClientBuilder::new()
-                .connect_timeout(Duration::from_secs(5))
-                .timeout(Duration::from_secs(5 * 60))
+                .connect_timeout(HTTP_CONNECT_TIMEOUT)
+                .timeout(HTTP_REQUEST_TIMEOUT)
                 .user_agent(APP_USER_AGENT)
                 .build()?,
         );
@@ -428,6 +580,7 @@ impl Web3ProxyApp {
         // create rate limiters
         // these are optional. they require redis
         let mut frontend_ip_rate_limiter = None;
+        let mut frontend_origin_rate_limiter = None;
         let mut frontend_registered_user_rate_limiter = None;
         let mut login_rate_limiter = None;
 
@@ -438,7 +591,7 @@ impl Web3ProxyApp {
                     &format!("web3_proxy:{}", top_config.app.chain_id),
                     "frontend",
                     public_requests_per_period,
-                    60.0,
+                    top_config.app.rate_limit_period as f32,
                     redis_pool.clone(),
                 );
 
@@ -452,6 +605,18 @@ impl Web3ProxyApp {
                     Some(DeferredRateLimiter::<u64>::new(10_000, "key", rpc_rrl, None).await);
             }
 
+            if !top_config.app.origin_rate_limits.is_empty() {
+                // not deferred. only requests from listed origins check this
+                // every origin has its own limit, so the default max is never used
+                frontend_origin_rate_limiter = Some(RedisRateLimiter::new(
+                    &format!("web3_proxy:{}", top_config.app.chain_id),
+                    "origin",
+                    0,
+                    top_config.app.origin_rate_limit_period as f32,
+                    redis_pool.clone(),
+                ));
+            }
+
             // login rate limiter
             login_rate_limiter = Some(RedisRateLimiter::new(
                 "web3_proxy",
@@ -469,19 +634,17 @@ impl Web3ProxyApp {
         // TODO: use this? it could listen for confirmed transactions and then clear pending_transactions, but the head_block_sender is doing that
         // TODO: don't drop the pending_tx_receiver. instead, read it to mark transactions as "seen". once seen, we won't re-send them?
         // TODO: once a transaction is "Confirmed" we remove it from the map. this should prevent major memory leaks.
-        // TODO: we should still have some sort of expiration or maximum size limit for the map
         drop(pending_tx_receiver);
 
-        // TODO: capacity from configs
-        // all these are the same size, so no need for a weigher
-        // TODO: this used to have a time_to_idle
         // TODO: different chains might handle this differently
-        // TODO: what should we set? 5 minutes is arbitrary. the nodes themselves hold onto transactions for much longer
-        // TODO: this used to be time_to_update, but
-        let pending_transactions = CacheBuilder::new(10_000)
-            .name("pending_transactions")
-            .time_to_live(Duration::from_secs(300))
-            .build();
+        let pending_transactions = PendingTransactionCache::new(
+            top_config.app.chain_id,
+            top_config.app.pending_transactions_max_entries,
+            Duration::from_secs(top_config.app.pending_transactions_ttl),
+            vredis_pool
+                .clone()
+                .filter(|_| top_config.app.pending_transactions_redis),
+        );
 
         // responses can be very different in sizes, so this is a cache with a max capacity and a weigher
         // TODO: we should emit stats to calculate a more accurate expected cache size
@@ -493,10 +656,94 @@ impl Web3ProxyApp {
         let jsonrpc_response_cache: JsonRpcResponseCache =
             CacheBuilder::new(top_config.app.response_cache_max_bytes)
                 .name("jsonrpc_response_cache")
-                .time_to_idle(Duration::from_secs(3600))
+                .expire_after(JsonRpcResponseExpiry {
+                    time_to_idle: Duration::from_secs(3600),
+                })
                 .weigher(move |k, v| jsonrpc_weigher.weigh(k, v))
                 .build();
 
+        let shared_response_cache = vredis_pool
+            .clone()
+            .filter(|_| top_config.app.shared_response_cache)
+            .map(|redis_pool| {
+                SharedResponseCache::new(
+                    top_config.app.chain_id,
+                    top_config.app.shared_response_cache_max_entries,
+                    top_config.app.shared_response_cache_min_depth,
+                    top_config.app.shared_response_cache_max_bytes,
+                    (top_config.app.shared_response_cache_lookup_ttl > 0).then(|| {
+                        Duration::from_secs(top_config.app.shared_response_cache_lookup_ttl)
+                    }),
+                    redis_pool,
+                )
+            });
+
+        #[cfg(feature = "disk_cache")]
+        let disk_response_cache = top_config
+            .app
+            .disk_response_cache_path
+            .as_ref()
+            .map(|path| {
+                DiskResponseCache::open(
+                    path,
+                    top_config.app.disk_response_cache_min_depth,
+                    top_config.app.disk_response_cache_max_entries,
+                )
+            })
+            .transpose()?;
+
+        #[cfg(not(feature = "disk_cache"))]
+        if top_config.app.disk_response_cache_path.is_some() {
+            warn!("disk_response_cache_path is set, but this build doesn't have the disk_cache feature");
+        }
+
+        let compute_unit_quota = vredis_pool.clone().and_then(|redis_pool| {
+            ComputeUnitQuota::new(
+                top_config.app.chain_id,
+                top_config.app.user_daily_compute_unit_quota,
+                top_config.app.user_monthly_compute_unit_quota,
+                redis_pool,
+            )
+        });
+
+        if compute_unit_quota.is_none()
+            && (top_config.app.user_daily_compute_unit_quota.is_some()
+                || top_config.app.user_monthly_compute_unit_quota.is_some())
+        {
+            warn!("compute unit quotas need volatile_redis_url. users will not be limited");
+        }
+
+        // peer counts change slowly. asking every backend on every request would be a waste
+        let peer_count_cache = CacheBuilder::new(1)
+            .name("peer_count_cache")
+            .time_to_live(Duration::from_secs(60))
+            .build();
+
+        // entries are invalidated as soon as the relays respond. the ttl is only a backstop
+        let inflight_raw_transactions = CacheBuilder::new(10_000)
+            .name("inflight_raw_transactions")
+            .time_to_live(Duration::from_secs(60))
+            .build();
+        let inflight_uncached_requests = CacheBuilder::new(10_000)
+            .name("inflight_uncached_requests")
+            .time_to_live(Duration::from_secs(300))
+            .build();
+
+        // entries are invalidated when their transaction is in a new head block. the short ttl covers head blocks that were skipped
+        let null_receipt_cache = top_config.app.null_receipt_cache_ms.map(|ms| {
+            CacheBuilder::new(10_000)
+                .name("null_receipt_cache")
+                .time_to_live(Duration::from_millis(ms))
+                .build()
+        });
+
+        let ws_resume_cache = ws_resume_cache(top_config.app.ws_resume_ttl);
+
+        let filters = filter_cache(
+            top_config.app.filters_max_entries,
+            top_config.app.filter_ttl,
+        );
+
         // TODO: how should we handle hitting this max?
         let max_users = 20_000;
 
@@ -511,16 +758,53 @@ impl Web3ProxyApp {
 
         let chain_id = top_config.app.chain_id;
 
+        let hostname = hostname::get()
+            .ok()
+            .and_then(|x| x.to_str().map(|x| x.to_string()));
+
+        let webhooks =
+            WebhookSender::new(&top_config.app, http_client.clone(), hostname.clone());
+
+        let signer = top_config.app.signer_url.clone().map(|url| {
+            SigningService::new(
+                url,
+                top_config.app.signer_rpc_key_ids.clone(),
+                Duration::from_secs(top_config.app.signer_timeout),
+                http_client.clone(),
+            )
+        });
+
+        let bundle_simulator = top_config.app.call_bundle_url.clone().map(|url| {
+            BundleSimulator::new(
+                url,
+                Duration::from_secs(top_config.app.call_bundle_timeout),
+                http_client.clone(),
+            )
+        });
+
+        // every set of rpcs retries reads the same way. methods that send transactions are never backed off or capped
+        let retry_policy = RetryPolicy::new(
+            top_config.app.request_retries,
+            top_config.app.request_retry_backoff_ms,
+        );
+
         let (balanced_rpcs, balanced_handle, consensus_connections_watcher) = Web3Rpcs::spawn(
             chain_id,
             db_conn.clone(),
+            top_config.app.head_quorum,
             top_config.app.max_head_block_lag,
             top_config.app.min_synced_rpcs,
             top_config.app.min_sum_soft_limit,
             "balanced rpcs".to_string(),
             pending_transactions.clone(),
             Some(pending_tx_sender.clone()),
+            retry_policy,
+            top_config
+                .app
+                .unconfirmed_head_tolerance_ms
+                .map(Duration::from_millis),
             Some(watch_consensus_head_sender),
+            webhooks.clone(),
         )
         .await
         .web3_context("spawning balanced rpcs")?;
@@ -539,7 +823,8 @@ impl Web3ProxyApp {
             let (private_rpcs, private_handle, _) = Web3Rpcs::spawn(
                 chain_id,
                 db_conn.clone(),
-                // private rpcs don't get subscriptions, so no need for max_head_block_lag
+                // private rpcs don't get subscriptions, so no need for head_quorum or max_head_block_lag
+                None,
                 None,
                 0,
                 0,
@@ -547,11 +832,14 @@ impl Web3ProxyApp {
                 pending_transactions.clone(),
                 // TODO: subscribe to pending transactions on the private rpcs? they seem to have low rate limits, but they should have
                 None,
+                retry_policy,
+                None,
                 // subscribing to new heads here won't work well. if they are fast, they might be ahead of balanced_rpcs
                 // they also often have low rate limits
                 // however, they are well connected to miners/validators. so maybe using them as a safety check would be good
                 // TODO: but maybe we could include privates in the "backup" tier
                 None,
+                webhooks.clone(),
             )
             .await
             .web3_context("spawning private_rpcs")?;
@@ -561,6 +849,37 @@ impl Web3ProxyApp {
             Some(private_rpcs)
         };
 
+        let secondary_private_rpcs = if top_config.secondary_private_rpcs.is_none() {
+            None
+        } else {
+            if private_rpcs.is_none() {
+                warn!("secondary_private_rpcs are only used after private_rpcs fail. configure private_rpcs too");
+            }
+
+            // same settings as private_rpcs
+            let (secondary_private_rpcs, secondary_private_handle, _) = Web3Rpcs::spawn(
+                chain_id,
+                db_conn.clone(),
+                None,
+                None,
+                0,
+                0,
+                "secondary protected rpcs".to_string(),
+                pending_transactions.clone(),
+                None,
+                retry_policy,
+                None,
+                None,
+                webhooks.clone(),
+            )
+            .await
+            .web3_context("spawning secondary_private_rpcs")?;
+
+            app_handles.push(secondary_private_handle);
+
+            Some(secondary_private_rpcs)
+        };
+
         // prepare a Web3Rpcs to hold all our 4337 Abstraction Bundler connections
         // only some chains have this, so this is optional
         let bundler_4337_rpcs = if top_config.bundler_4337_rpcs.is_none() {
@@ -571,14 +890,18 @@ impl Web3ProxyApp {
             let (bundler_4337_rpcs, bundler_4337_rpcs_handle, _) = Web3Rpcs::spawn(
                 chain_id,
                 db_conn.clone(),
-                // bundler_4337_rpcs don't get subscriptions, so no need for max_head_block_lag
+                // bundler_4337_rpcs don't get subscriptions, so no need for head_quorum or max_head_block_lag
+                None,
                 None,
                 0,
                 0,
                 "eip4337 rpcs".to_string(),
                 pending_transactions.clone(),
                 None,
+                retry_policy,
                 None,
+                None,
+                webhooks.clone(),
             )
             .await
             .web3_context("spawning bundler_4337_rpcs")?;
@@ -588,38 +911,55 @@ impl Web3ProxyApp {
             Some(bundler_4337_rpcs)
         };
 
-        let hostname = hostname::get()
-            .ok()
-            .and_then(|x| x.to_str().map(|x| x.to_string()));
-
         let app = Self {
             balanced_rpcs,
             bearer_token_semaphores,
+            block_number_response_cache: Default::default(),
+            new_heads_response_cache: Default::default(),
             bundler_4337_rpcs,
+            compute_unit_quota,
             config: top_config.app.clone(),
             db_conn,
             db_replica,
+            #[cfg(feature = "disk_cache")]
+            disk_response_cache,
+            fee_history_cache: Default::default(),
+            filters,
             frontend_port: frontend_port.clone(),
             frontend_ip_rate_limiter,
+            frontend_origin_rate_limiter,
             frontend_registered_user_rate_limiter,
             hostname,
             http_client,
+            inflight_raw_transactions,
+            inflight_uncached_requests,
             influxdb_client,
             internal_provider: Default::default(),
             ip_semaphores,
             jsonrpc_response_cache,
             kafka_producer,
             login_rate_limiter,
+            null_receipt_cache,
+            peer_count_cache,
             pending_transactions,
             pending_tx_sender,
             private_rpcs,
+            private_relay_metrics: Default::default(),
             prometheus_port: prometheus_port.clone(),
+            response_cache_metrics: Default::default(),
+            rejected_method_metrics: Default::default(),
+            secondary_private_rpcs,
+            shared_response_cache,
             rpc_secret_key_cache,
+            signer,
+            bundle_simulator,
             stat_sender,
             user_balance_cache,
             user_semaphores,
             vredis_pool,
             watch_consensus_head_receiver,
+            webhooks,
+            ws_resume_cache,
         };
 
         let app = Arc::new(app);
@@ -649,6 +989,129 @@ impl Web3ProxyApp {
             app_handles.push(config_handle);
         }
 
+        // fill the fee history cache, the eth_blockNumber response, and the newHeads block with every new head block
+        // also forget null receipts for transactions that are now in a block
+        {
+            let app = app.clone();
+            let mut head_block_receiver = app.head_block_receiver();
+
+            let fee_history_handle = tokio::spawn(async move {
+                loop {
+                    if let Some(head_block) = head_block_receiver.borrow_and_update().clone() {
+                        app.fee_history_cache.on_new_head(&head_block);
+
+                        app.block_number_response_cache.update(*head_block.number());
+
+                        app.new_heads_response_cache.update(&head_block);
+
+                        if let Some(null_receipt_cache) = app.null_receipt_cache.as_ref() {
+                            for tx_hash in head_block.block.transactions.iter() {
+                                null_receipt_cache.invalidate(tx_hash).await;
+                            }
+                        }
+                    }
+
+                    head_block_receiver
+                        .changed()
+                        .await
+                        .web3_context("failed awaiting head block change")?;
+                }
+            });
+
+            app_handles.push(fee_history_handle);
+        }
+
+        // keep "safe" and "finalized" up to date so that requests for them can be cached like any other old block
+        {
+            let app = app.clone();
+            let mut head_block_receiver = app.head_block_receiver();
+
+            let finalized_blocks_handle = tokio::spawn(async move {
+                loop {
+                    let head_block_num = head_block_receiver
+                        .borrow_and_update()
+                        .as_ref()
+                        .map(|x| *x.number());
+
+                    if let Some(head_block_num) = head_block_num {
+                        app.balanced_rpcs
+                            .update_finalized_blocks(&head_block_num)
+                            .await;
+                    }
+
+                    head_block_receiver
+                        .changed()
+                        .await
+                        .web3_context("failed awaiting head block change")?;
+                }
+            });
+
+            app_handles.push(finalized_blocks_handle);
+        }
+
+        // save each rpc's latency percentiles so that operators can graph every backend
+        if let (Some(influxdb_client), Some(influxdb_bucket)) = (
+            app.influxdb_client.clone(),
+            app.config.influxdb_bucket.clone(),
+        ) && app.config.influxdb_latency_interval > 0
+        {
+            let app = app.clone();
+
+            let latency_handle = tokio::spawn(async move {
+                let mut interval =
+                    interval(Duration::from_secs(app.config.influxdb_latency_interval));
+
+                // the first tick is immediate and there is nothing to save yet
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+
+                    let timestamp = Utc::now().timestamp();
+
+                    let points = app
+                        .balanced_rpcs
+                        .latency_points(app.config.chain_id, timestamp);
+
+                    if points.is_empty() {
+                        continue;
+                    }
+
+                    let num_points = points.len();
+
+                    if let Err(err) = influxdb_client
+                        .write_with_precision(
+                            &influxdb_bucket,
+                            stream::iter(points),
+                            TimestampPrecision::Seconds,
+                        )
+                        .await
+                    {
+                        error!(?err, "unable to save {} rpc latency points", num_points);
+                    }
+                }
+            });
+
+            app_handles.push(latency_handle);
+        }
+
+        // load responses that other proxies already fetched. this is bounded by shared_response_cache_max_entries
+        if let Some(shared_response_cache) = app.shared_response_cache.clone() {
+            let app = app.clone();
+
+            tokio::spawn(async move {
+                if let Err(err) = shared_response_cache
+                    .warm(
+                        &app.jsonrpc_response_cache,
+                        app.config.response_cache_compression,
+                    )
+                    .await
+                {
+                    warn!(?err, "unable to warm the response cache from redis");
+                }
+            });
+        }
+
         if important_background_handles.is_empty() {
             trace!("no important background handles");
 
@@ -674,39 +1137,146 @@ impl Web3ProxyApp {
     pub async fn apply_top_config(&self, new_top_config: TopConfig) -> Web3ProxyResult<()> {
         // TODO: also update self.config from new_top_config.app
 
+        if self.config.require_https_backends {
+            let insecure = new_top_config.insecure_backend_urls();
+
+            if !insecure.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "require_https_backends is set, but these backends do not use TLS: {}",
+                    insecure.join(", ")
+                )
+                .into());
+            }
+        }
+
+        // check everything before changing anything so that a bad config isn't half applied
+        self.check_rpc_configs(
+            &new_top_config.balanced_rpcs,
+            new_top_config.private_rpcs.as_ref(),
+            new_top_config.secondary_private_rpcs.as_ref(),
+            new_top_config.bundler_4337_rpcs.as_ref(),
+        )?;
+
         // connect to the backends
         self.balanced_rpcs
             .apply_server_configs(self, new_top_config.balanced_rpcs)
             .await
             .web3_context("updating balanced rpcs")?;
 
-        if let Some(private_rpc_configs) = new_top_config.private_rpcs {
-            if let Some(ref private_rpcs) = self.private_rpcs {
-                private_rpcs
-                    .apply_server_configs(self, private_rpc_configs)
-                    .await
-                    .web3_context("updating private_rpcs")?;
-            } else {
-                // TODO: maybe we should have private_rpcs just be empty instead of being None
-                todo!("handle toggling private_rpcs")
-            }
+        if let (Some(configs), Some(rpcs)) =
+            (new_top_config.private_rpcs, self.private_rpcs.as_ref())
+        {
+            rpcs.apply_server_configs(self, configs)
+                .await
+                .web3_context("updating private_rpcs")?;
         }
 
-        if let Some(bundler_4337_rpc_configs) = new_top_config.bundler_4337_rpcs {
-            if let Some(ref bundler_4337_rpcs) = self.bundler_4337_rpcs {
-                bundler_4337_rpcs
-                    .apply_server_configs(self, bundler_4337_rpc_configs)
-                    .await
-                    .web3_context("updating bundler_4337_rpcs")?;
-            } else {
-                // TODO: maybe we should have bundler_4337_rpcs just be empty instead of being None
-                todo!("handle toggling bundler_4337_rpcs")
+        if let (Some(configs), Some(rpcs)) = (
+            new_top_config.secondary_private_rpcs,
+            self.secondary_private_rpcs.as_ref(),
+        ) {
+            rpcs.apply_server_configs(self, configs)
+                .await
+                .web3_context("updating secondary_private_rpcs")?;
+        }
+
+        if let (Some(configs), Some(rpcs)) = (
+            new_top_config.bundler_4337_rpcs,
+            self.bundler_4337_rpcs.as_ref(),
+        ) {
+            rpcs.apply_server_configs(self, configs)
+                .await
+                .web3_context("updating bundler_4337_rpcs")?;
+        }
+
+        Ok(())
+    }
+
+    /// Err if any of these configs can't be applied. Nothing is changed, so a reload can check every group before updating any of them.
+    /// Groups that were not configured at startup can't be added without a restart.
+    fn check_rpc_configs(
+        &self,
+        balanced_rpcs: &HashMap<String, Web3RpcConfig>,
+        private_rpcs: Option<&HashMap<String, Web3RpcConfig>>,
+        secondary_private_rpcs: Option<&HashMap<String, Web3RpcConfig>>,
+        bundler_4337_rpcs: Option<&HashMap<String, Web3RpcConfig>>,
+    ) -> Web3ProxyResult<()> {
+        self.balanced_rpcs
+            .check_server_configs(balanced_rpcs)
+            .web3_context("checking balanced rpcs")?;
+
+        for (label, configs, rpcs) in [
+            ("private_rpcs", private_rpcs, self.private_rpcs.as_ref()),
+            (
+                "secondary_private_rpcs",
+                secondary_private_rpcs,
+                self.secondary_private_rpcs.as_ref(),
+            ),
+            (
+                "bundler_4337_rpcs",
+                bundler_4337_rpcs,
+                self.bundler_4337_rpcs.as_ref(),
+            ),
+        ] {
+            match (configs, rpcs) {
+                (None, _) => {}
+                (Some(_), None) => {
+                    return Err(Web3ProxyError::BadRequest(
+                        format!(
+                            "{} were not configured at startup. restart to add them",
+                            label
+                        )
+                        .into(),
+                    ));
+                }
+                (Some(configs), Some(rpcs)) => {
+                    rpcs.check_server_configs(configs)
+                        .web3_context(format!("checking {}", label))?;
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Reconcile the backend rpcs with new configs without restarting. new rpcs are connected and removed rpcs are drained.
+    /// Sets that were not configured at startup can not be added here. The next config file reload will overwrite these changes.
+    pub async fn reload_rpc_configs(
+        &self,
+        balanced_rpcs: HashMap<String, Web3RpcConfig>,
+        private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
+        secondary_private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
+    ) -> Web3ProxyResult<()> {
+        // check everything before changing anything
+        self.check_rpc_configs(
+            &balanced_rpcs,
+            private_rpcs.as_ref(),
+            secondary_private_rpcs.as_ref(),
+            None,
+        )?;
+
+        self.balanced_rpcs
+            .apply_server_configs(self, balanced_rpcs)
+            .await
+            .web3_context("reloading balanced rpcs")?;
+
+        if let (Some(configs), Some(rpcs)) = (private_rpcs, self.private_rpcs.as_ref()) {
+            rpcs.apply_server_configs(self, configs)
+                .await
+                .web3_context("reloading private_rpcs")?;
+        }
+
+        if let (Some(configs), Some(rpcs)) =
+            (secondary_private_rpcs, self.secondary_private_rpcs.as_ref())
+        {
+            rpcs.apply_server_configs(self, configs)
+                .await
+                .web3_context("reloading secondary_private_rpcs")?;
+        }
+
+        Ok(())
+    }
+
     pub fn head_block_receiver(&self) -> watch::Receiver<Option<Web3ProxyBlock>> {
         self.watch_consensus_head_receiver.clone()
     }
@@ -904,8 +1474,97 @@ impl Web3ProxyApp {
         };
 
         // TODO: i don't like this library. it doesn't include HELP or TYPE lines and so our prometheus server fails to parse it
-        serde_prometheus::to_string(&metrics, Some("web3_proxy"), globals)
-            .expect("prometheus metrics should always serialize")
+        let mut serialized = serde_prometheus::to_string(&metrics, Some("web3_proxy"), globals)
+            .expect("prometheus metrics should always serialize");
+
+        serialized.push_str(&self.response_cache_metrics.to_prometheus());
+        serialized.push_str(&self.rejected_method_metrics.to_prometheus());
+        serialized.push_str(&self.private_relay_metrics.to_prometheus());
+
+        ERROR_RESPONSES.write_prometheus_with_label(
+            &mut serialized,
+            "web3_proxy_error_responses_total",
+            "Error responses sent to users by error type.",
+            "variant",
+        );
+
+        prometheus::write_gauge(
+            &mut serialized,
+            "web3_proxy_pending_transactions",
+            "Pending transactions remembered so that subscribers only see them once.",
+            self.pending_transactions.entry_count(),
+        );
+
+        let circuit_breakers: Vec<_> = self
+            .balanced_rpcs
+            .by_name
+            .read()
+            .values()
+            .filter_map(|x| Some((x.name.clone(), x.circuit_breaker_state()?.as_u64())))
+            .collect();
+
+        prometheus::write_rpc_gauges(
+            &mut serialized,
+            "web3_proxy_circuit_breaker_state",
+            "Circuit breaker of each balanced rpc. 0 is closed, 1 is open, 2 is half-open.",
+            circuit_breakers,
+        );
+
+        let request_budgets: Vec<_> = self
+            .balanced_rpcs
+            .by_name
+            .read()
+            .values()
+            .filter_map(|x| Some((x.name.clone(), x.request_budget_remaining()?)))
+            .collect();
+
+        prometheus::write_rpc_gauges(
+            &mut serialized,
+            "web3_proxy_request_budget_remaining",
+            "Requests left this month for each balanced rpc with a monthly_request_limit.",
+            request_budgets,
+        );
+
+        let upstream_errors: Vec<_> = self
+            .balanced_rpcs
+            .by_name
+            .read()
+            .values()
+            .flat_map(|x| {
+                UpstreamErrorKind::ALL
+                    .into_iter()
+                    .map(|kind| (x.name.clone(), kind.as_str(), x.upstream_errors(kind)))
+            })
+            .collect();
+
+        prometheus::write_rpc_error_counters(
+            &mut serialized,
+            "web3_proxy_upstream_errors_total",
+            "Failed requests to each balanced rpc by kind: timeout, rate_limited_upstream, revert, rpc_error, or connection_error.",
+            upstream_errors,
+        );
+
+        let upstream_rate_limited: Vec<_> = self
+            .balanced_rpcs
+            .by_name
+            .read()
+            .values()
+            .map(|x| {
+                (
+                    x.name.clone(),
+                    x.upstream_errors(UpstreamErrorKind::RateLimitedUpstream),
+                )
+            })
+            .collect();
+
+        prometheus::write_rpc_counters(
+            &mut serialized,
+            "web3_proxy_upstream_rate_limited_total",
+            "Requests that each balanced rpc rate limited. The rpc is skipped until its Retry-After passes.",
+            upstream_rate_limited,
+        );
+
+        serialized
     }
 
     /// make an internal request with stats and caching
@@ -931,7 +1590,10 @@ impl Web3ProxyApp {
         // TODO: proper ids
         let request = JsonRpcRequest::new(JsonRpcId::Number(1), method.to_string(), json!(params))?;
 
-        let (_, response, _) = self.proxy_request(request, authorization, None).await;
+        let response = self
+            .proxy_request(request, authorization, None, None)
+            .await
+            .response;
 
         if let Some(result) = response.result {
             let result = serde_json::from_str(result.get())?;
@@ -946,36 +1608,40 @@ impl Web3ProxyApp {
     }
 
     /// send the request or batch of requests to the approriate RPCs
+    /// the compute units are summed across a batch
     pub async fn proxy_web3_rpc(
         self: &Arc<Self>,
         authorization: Arc<Authorization>,
         request: JsonRpcRequestEnum,
-    ) -> Web3ProxyResult<(StatusCode, JsonRpcForwardedResponseEnum, Vec<Arc<Web3Rpc>>)> {
+    ) -> Web3ProxyResult<ProxyResponse> {
         // trace!(?request, "proxy_web3_rpc");
 
         let response = match request {
             JsonRpcRequestEnum::Single(request) => {
-                let (status_code, response, rpcs) = self
-                    .proxy_request(request, authorization.clone(), None)
+                let x = self
+                    .proxy_request(request, authorization.clone(), None, None)
                     .await;
 
-                (
-                    status_code,
-                    JsonRpcForwardedResponseEnum::Single(response),
-                    rpcs,
-                )
+                ProxyResponse {
+                    status_code: x.status_code,
+                    response: JsonRpcForwardedResponseEnum::Single(x.response),
+                    rpcs: x.rpcs,
+                    compute_units: x.compute_units,
+                    cache_status: x.cache_status,
+                }
             }
             JsonRpcRequestEnum::Batch(requests) => {
-                let (responses, rpcs) = self
+                let x = self
                     .proxy_web3_rpc_requests(&authorization, requests)
                     .await?;
 
-                // TODO: real status code. if an error happens, i don't think we are following the spec here
-                (
-                    StatusCode::OK,
-                    JsonRpcForwardedResponseEnum::Batch(responses),
-                    rpcs,
-                )
+                ProxyResponse {
+                    status_code: x.status_code,
+                    response: JsonRpcForwardedResponseEnum::Batch(x.response),
+                    rpcs: x.rpcs,
+                    compute_units: x.compute_units,
+                    cache_status: x.cache_status,
+                }
             }
         };
 
@@ -988,42 +1654,88 @@ impl Web3ProxyApp {
         self: &Arc<Self>,
         authorization: &Arc<Authorization>,
         requests: Vec<JsonRpcRequest>,
-    ) -> Web3ProxyResult<(Vec<JsonRpcForwardedResponse>, Vec<Arc<Web3Rpc>>)> {
+    ) -> Web3ProxyResult<ProxyResponse<Vec<JsonRpcForwardedResponse>>> {
         // TODO: we should probably change ethers-rs to support this directly. they pushed this off to v2 though
         let num_requests = requests.len();
 
         if num_requests == 0 {
-            return Ok((vec![], vec![]));
+            return Ok(ProxyResponse {
+                status_code: StatusCode::OK,
+                response: vec![],
+                rpcs: vec![],
+                compute_units: Decimal::ZERO,
+                cache_status: None,
+            });
+        }
+
+        if let Some(max_batch_size) = self.config.max_batch_size {
+            if num_requests > max_batch_size {
+                return Err(Web3ProxyError::BadRequest(
+                    format!(
+                        "batch of {} requests is larger than the max of {}",
+                        num_requests, max_batch_size
+                    )
+                    .into(),
+                ));
+            }
         }
 
         // get the head block now so that any requests that need it all use the same block
         // TODO: this still has an edge condition if there is a reorg in the middle of the request!!!
-        let head_block: Web3ProxyBlock = self
-            .balanced_rpcs
-            .head_block()
-            .ok_or(Web3ProxyError::NoServersSynced)?
-            .clone();
-
-        // TODO: use streams and buffers so we don't overwhelm our server
-        let responses = join_all(
-            requests
-                .into_iter()
-                .map(|request| {
-                    self.proxy_request(request, authorization.clone(), Some(&head_block))
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await;
+        // if no servers are synced, that is not an error for the whole batch.
+        // requests that need a head block get their own error response. requests answered locally still work
+        let head_block: Option<Web3ProxyBlock> = self.balanced_rpcs.head_block();
+
+        // optionally send the whole batch to one rpc on that head block so that every request sees the same state
+        let pinned_rpc = if self.config.batch_pin_rpc
+            && let Some(head_block) = head_block.as_ref()
+        {
+            let pinned_rpc = self.balanced_rpcs.rpc_on_block(head_block);
+
+            if pinned_rpc.is_none() {
+                debug!(head_block=%head_block.hash(), "no rpc to pin the batch to");
+            }
+
+            pinned_rpc
+        } else {
+            None
+        };
+
+        // a bounded number of requests run at once so that a large batch doesn't overwhelm our servers
+        // `buffered` keeps the responses in the same order as the requests
+        // proxy_request turns errors into responses, so one failed request doesn't fail the whole batch
+        // notifications are still sent, but they are left out of the batch's responses
+        let responses: Vec<_> = stream::iter(requests)
+            .map(|request| {
+                let notification = request.notification;
+
+                let response = self.proxy_request(
+                    request,
+                    authorization.clone(),
+                    head_block.as_ref(),
+                    pinned_rpc.clone(),
+                );
+
+                async move { (notification, response.await) }
+            })
+            .buffered(self.config.max_batch_concurrency.max(1))
+            .collect()
+            .await;
 
         let mut collected: Vec<JsonRpcForwardedResponse> = Vec::with_capacity(num_requests);
         let mut collected_rpc_names: HashSet<String> = HashSet::new();
         let mut collected_rpcs: Vec<Arc<Web3Rpc>> = vec![];
-        for response in responses {
+        let mut collected_compute_units = Decimal::ZERO;
+        let mut collected_cache_status = None;
+        for (notification, response) in responses {
             // TODO: any way to attach the tried rpcs to the error? it is likely helpful
-            let (_status_code, response, rpcs) = response;
-
-            collected.push(response);
-            collected_rpcs.extend(rpcs.into_iter().filter(|x| {
+            if !notification {
+                collected.push(response.response);
+            }
+            collected_compute_units += response.compute_units;
+            collected_cache_status =
+                CacheStatus::merge_opt(collected_cache_status, response.cache_status);
+            collected_rpcs.extend(response.rpcs.into_iter().filter(|x| {
                 if collected_rpc_names.contains(&x.name) {
                     false
                 } else {
@@ -1035,7 +1747,30 @@ impl Web3ProxyApp {
             // TODO: what should we do with the status code? check the jsonrpc spec
         }
 
-        Ok((collected, collected_rpcs))
+        // TODO: real status code. if an error happens, i don't think we are following the spec here
+        Ok(ProxyResponse {
+            status_code: StatusCode::OK,
+            response: collected,
+            rpcs: collected_rpcs,
+            compute_units: collected_compute_units,
+            cache_status: collected_cache_status,
+        })
+    }
+
+    /// Anonymous users and users with a paid balance don't have a compute unit quota
+    pub fn compute_unit_quota_for(
+        &self,
+        authorization: &Authorization,
+    ) -> Option<&ComputeUnitQuota> {
+        let quota = self.compute_unit_quota.as_ref()?;
+
+        if authorization.checks.user_id == 0
+            || authorization.checks.latest_balance.read().remaining() > Decimal::ZERO
+        {
+            return None;
+        }
+
+        Some(quota)
     }
 
     #[inline]
@@ -1070,31 +1805,130 @@ impl Web3ProxyApp {
         }
     }
 
-    /// try to send transactions to the best available rpcs with protected/private mempools
-    /// if no protected rpcs are configured, then some public rpcs are used instead
+    /// the transaction hash to check the null receipt cache for. None if the cache is off or this isn't eth_getTransactionReceipt
+    fn null_receipt_tx_hash(&self, method: &str, params: &serde_json::Value) -> Option<H256> {
+        if method != "eth_getTransactionReceipt" {
+            return None;
+        }
+
+        self.null_receipt_cache.as_ref()?;
+
+        params.get(0)?.as_str()?.parse().ok()
+    }
+
+    /// true if the receipt was recently null and the transaction is not in the current head block
+    async fn null_receipt_is_cached(
+        &self,
+        tx_hash: &H256,
+        head_block: Option<&Web3ProxyBlock>,
+    ) -> bool {
+        let Some(null_receipt_cache) = self.null_receipt_cache.as_ref() else {
+            return false;
+        };
+
+        if !null_receipt_cache.contains_key(tx_hash) {
+            return false;
+        }
+
+        // the head block loop might not have cleared the entry yet
+        if let Some(head_block) = head_block
+            && head_block.block.transactions.contains(tx_hash)
+        {
+            null_receipt_cache.invalidate(tx_hash).await;
+            return false;
+        }
+
+        true
+    }
+
+    /// Metric label for a method. Users can send any method name, so names that aren't known or allowed share one label
+    fn method_label<'a>(&self, method: &'a str) -> &'a str {
+        if ComputeUnit::is_known_method(method, self.config.chain_id)
+            || check_method(&self.config.allowed_methods, &[], method) == MethodAccess::Allowed
+        {
+            method
+        } else {
+            "other"
+        }
+    }
+
+    /// None if the method isn't in private_methods or there are no private rpcs
+    fn private_rpcs_for_method(&self, method: &str) -> Option<&Arc<Web3Rpcs>> {
+        if !self.config.private_methods.iter().any(|x| x == method) {
+            return None;
+        }
+
+        self.private_rpcs.as_ref().filter(|x| !x.is_empty())
+    }
+
+    /// try to send transactions to the best available rpcs with protected/private mempools
+    /// if every private rpc errors, the secondary private rpcs are tried next. one at a time, in order of their names
+    /// if no protected rpcs are configured, then some public rpcs are used instead
     async fn try_send_protected<P: JsonRpcParams>(
         self: &Arc<Self>,
         method: &str,
         params: &P,
         request_metadata: &Arc<RequestMetadata>,
     ) -> Web3ProxyResult<Box<RawValue>> {
-        if let Some(protected_rpcs) = self.private_rpcs.as_ref() {
-            if !protected_rpcs.is_empty() {
-                let protected_response = protected_rpcs
-                    .try_send_all_synced_connections(
-                        method,
-                        params,
-                        Some(request_metadata),
-                        None,
-                        None,
-                        Some(Duration::from_secs(30)),
-                        Some(Level::TRACE.into()),
-                        None,
-                    )
-                    .await;
+        let protected_rpcs = self.private_rpcs.as_ref().filter(|x| !x.is_empty());
+        let secondary_rpcs = self
+            .secondary_private_rpcs
+            .as_ref()
+            .filter(|x| !x.is_empty());
+
+        if let Some(protected_rpcs) = protected_rpcs {
+            let protected_response = protected_rpcs
+                .try_send_all_synced_connections(
+                    method,
+                    params,
+                    Some(request_metadata),
+                    None,
+                    None,
+                    Some(Duration::from_secs(30)),
+                    Some(Level::TRACE.into()),
+                    None,
+                )
+                .await;
+
+            match (protected_response, secondary_rpcs) {
+                (Ok(x), _) => {
+                    self.private_relay_metrics.primary.incr(method);
+                    return Ok(x);
+                }
+                (Err(err), None) => {
+                    self.private_relay_metrics.failed.incr(method);
+                    return Err(err);
+                }
+                (Err(err), Some(_)) => {
+                    warn!(?err, %method, "private rpcs failed. trying secondary private rpcs");
+                }
+            }
+        }
 
-                return protected_response;
+        if let Some(secondary_rpcs) = secondary_rpcs {
+            let (secondary_response, failed) = secondary_rpcs
+                .try_send_in_order(
+                    method,
+                    params,
+                    Some(request_metadata),
+                    Some(Level::TRACE.into()),
+                )
+                .await;
+
+            for rpc_name in failed {
+                self.private_relay_metrics.secondary_errors.incr(&rpc_name);
             }
+
+            return match secondary_response {
+                Ok((rpc, x)) => {
+                    self.private_relay_metrics.secondary.incr(&rpc.name);
+                    Ok(x)
+                }
+                Err(err) => {
+                    self.private_relay_metrics.failed.incr(method);
+                    Err(err)
+                }
+            };
         }
 
         let num_public_rpcs = match request_metadata.proxy_mode() {
@@ -1124,13 +1958,542 @@ impl Web3ProxyApp {
             .await
     }
 
+    /// we don't keep track of which relays saw which private transaction, so every private relay is asked to cancel it.
+    /// true if any relay acknowledged the cancellation
+    async fn try_cancel_private_transaction(
+        self: &Arc<Self>,
+        private_rpcs: &Web3Rpcs,
+        method: &str,
+        params: &serde_json::Value,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<bool> {
+        let active_request_handles = private_rpcs
+            .all_connections(
+                Some(request_metadata),
+                None,
+                None,
+                None,
+                Some(Level::TRACE.into()),
+            )
+            .await
+            .map_err(|_| Web3ProxyError::NoServersSynced)?;
+
+        request_metadata
+            .backend_requests
+            .lock()
+            .extend(active_request_handles.iter().map(|x| x.clone_connection()));
+
+        let num_relays = active_request_handles.len();
+
+        let responses = active_request_handles
+            .into_iter()
+            .map(|handle| async move {
+                let rpc = handle.clone_connection();
+
+                let response: Result<bool, _> = handle.request(method, params).await;
+
+                (rpc, response)
+            })
+            .collect::<FuturesUnordered<_>>()
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut num_rejected = 0;
+        let mut num_errors = 0;
+
+        for (rpc, response) in responses {
+            match response {
+                Ok(true) => return Ok(true),
+                Ok(false) => num_rejected += 1,
+                Err(err) => {
+                    // relay errors are not passed on to the user. they might include details about our setup
+                    debug!(?err, "{} failed to cancel a private transaction", rpc);
+                    num_errors += 1;
+                }
+            }
+        }
+
+        if num_rejected > 0 {
+            return Ok(false);
+        }
+
+        Err(Web3ProxyError::BadResponse(
+            format!(
+                "none of the {} private relays cancelled the transaction ({} errors)",
+                num_relays, num_errors
+            )
+            .into(),
+        ))
+    }
+
+    /// large responses use a lot of memory and would push everything else out of the response cache
+    fn check_response_size(
+        &self,
+        response_data: JsonRpcResponseEnum<Arc<RawValue>>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        if let Some(max_bytes) = self.config.max_response_bytes {
+            let num_bytes = response_data.num_bytes() as u64;
+
+            if num_bytes > max_bytes {
+                return Err(Web3ProxyError::ResponseTooLarge {
+                    num_bytes,
+                    max_bytes,
+                });
+            }
+        }
+
+        Ok(response_data)
+    }
+
+    /// send a raw transaction to the private relays (or to the balanced rpcs if there are none)
+    async fn send_raw_transaction(
+        self: &Arc<Self>,
+        method: &str,
+        params: &serde_json::Value,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let response = timeout(
+            Duration::from_secs(30),
+            self.try_send_protected(method, params, request_metadata),
+        )
+        .await?;
+
+        let mut response = response.try_into()?;
+
+        // sometimes we get an error that the transaction is already known by our nodes,
+        // that's not really an error. Return the hash like a successful response would.
+        // TODO: move this to a helper function
+        if let JsonRpcResponseEnum::RpcError { error_data, .. } = &response {
+            if error_data.code == -32000
+                && (error_data.message == "ALREADY_EXISTS: already known"
+                    || error_data.message == "INTERNAL_ERROR: existing tx with same hash")
+            {
+                let params = params
+                    .as_array()
+                    .ok_or_else(|| {
+                        Web3ProxyError::BadRequest("Unable to get array from params".into())
+                    })?
+                    .get(0)
+                    .ok_or_else(|| {
+                        Web3ProxyError::BadRequest("Unable to get item 0 from params".into())
+                    })?
+                    .as_str()
+                    .ok_or_else(|| {
+                        Web3ProxyError::BadRequest("Unable to get string from params item 0".into())
+                    })?;
+
+                let params =
+                    Bytes::from_str(params).expect("there must be Bytes if we got this far");
+
+                let rlp = Rlp::new(params.as_ref());
+
+                if let Ok(tx) = Transaction::decode(&rlp) {
+                    // TODO: decode earlier and confirm that tx.chain_id (if set) matches self.config.chain_id
+                    let tx_hash = json!(tx.hash());
+
+                    trace!("tx_hash: {:#?}", tx_hash);
+
+                    response = JsonRpcResponseEnum::from(tx_hash);
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// serve trace_replayTransaction out of the response cache once the transaction is deep enough to never change
+    async fn trace_replay_transaction(
+        self: &Arc<Self>,
+        method: &str,
+        params: &serde_json::Value,
+        head_block: Option<&Web3ProxyBlock>,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        // without a confirmation depth, nothing is ever deep enough to cache
+        let confirmations = self
+            .config
+            .trace_replay_cache_confirmations
+            .unwrap_or(u64::MAX);
+
+        let replay_params = TraceReplayParams::try_from_params(params);
+
+        let cache_key = replay_params.as_ref().map(|x| x.cache_key());
+
+        if let Some(ref cache_key) = cache_key {
+            if let Some(cached) = self.jsonrpc_response_cache.get(&cache_key.hash()) {
+                self.response_cache_metrics
+                    .hits
+                    .incr(self.method_label(method));
+
+                request_metadata.record_cache_status(CacheStatus::Hit { age: cached.age() });
+
+                return cached.into_response();
+            }
+
+            self.response_cache_metrics
+                .misses
+                .incr(self.method_label(method));
+
+            request_metadata.record_cache_status(CacheStatus::Miss);
+        }
+
+        // only archive servers keep the state needed to replay old transactions
+        request_metadata
+            .archive_request
+            .store(true, atomic::Ordering::Release);
+
+        let backend_request_timeout = Duration::from_secs(240);
+
+        let response_data = timeout(
+            backend_request_timeout + Duration::from_millis(100),
+            self.balanced_rpcs.try_proxy_connection::<_, Arc<RawValue>>(
+                method,
+                params,
+                Some(request_metadata),
+                max_tries,
+                Some(backend_request_timeout),
+                Some(&U64::one()),
+                None,
+            ),
+        )
+        .await?;
+
+        let response_data = self.check_response_size(response_data.try_into()?)?;
+
+        let (Some(replay_params), Some(cache_key)) = (replay_params, cache_key) else {
+            return Ok(response_data);
+        };
+
+        // errors are never cached
+        if !matches!(response_data, JsonRpcResponseEnum::Result { .. }) {
+            return Ok(response_data);
+        }
+
+        let Some(head_block_num) = head_block
+            .map(|x| *x.number())
+            .or_else(|| self.balanced_rpcs.head_block_num())
+        else {
+            return Ok(response_data);
+        };
+
+        // the trace doesn't say which block the transaction is in. the receipt does
+        let receipt = self
+            .internal_request::<_, Option<TransactionReceipt>>(
+                "eth_getTransactionReceipt",
+                (replay_params.tx_hash,),
+            )
+            .await;
+
+        match receipt {
+            Ok(Some(TransactionReceipt {
+                block_number: Some(tx_block_num),
+                ..
+            })) if is_confirmed(tx_block_num, head_block_num, confirmations) => {
+                self.jsonrpc_response_cache
+                    .insert(
+                        cache_key.hash(),
+                        CachedJsonRpcResponse::new(
+                            response_data.clone(),
+                            self.config.response_cache_compression,
+                        ),
+                    )
+                    .await;
+            }
+            Ok(_) => {
+                trace!(tx_hash=?replay_params.tx_hash, "not caching trace of unconfirmed transaction");
+            }
+            Err(err) => {
+                debug!(?err, tx_hash=?replay_params.tx_hash, "failed checking transaction confirmations");
+            }
+        }
+
+        Ok(response_data)
+    }
+
+    /// Older or limited backends don't have eth_maxPriorityFeePerGas.
+    /// If none of them do, derive a fee from the rewards of recent blocks in the fee history cache
+    async fn max_priority_fee_fallback(
+        self: &Arc<Self>,
+        head_block: Option<&Web3ProxyBlock>,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let mut fee_history_params = json!([
+            U64::from(PRIORITY_FEE_BLOCKS),
+            "latest",
+            [PRIORITY_FEE_PERCENTILE],
+        ]);
+
+        let fee_history = self
+            .fee_history(
+                "eth_feeHistory",
+                &mut fee_history_params,
+                head_block,
+                max_tries,
+                request_metadata,
+            )
+            .await;
+
+        let median_reward = match fee_history {
+            Ok(JsonRpcResponseEnum::Result { value, .. }) => {
+                serde_json::from_str::<FeeHistory>(value.get())
+                    .ok()
+                    .and_then(|x| x.median_reward())
+            }
+            Ok(_) => None,
+            Err(err) => {
+                debug!(?err, "no fee history for eth_maxPriorityFeePerGas");
+                None
+            }
+        };
+
+        let (fee, source) = match median_reward {
+            Some(x) => (x, "fee_history"),
+            None => (U256::from(DEFAULT_MAX_PRIORITY_FEE_PER_GAS), "default"),
+        };
+
+        info!(%fee, %source, "synthesized eth_maxPriorityFeePerGas");
+
+        Ok(JsonRpcResponseEnum::from(json!(fee)))
+    }
+
+    /// Some backends are missing methods that can be built from other requests. The native method is always tried first.
+    /// This runs wherever a backend response is handled, so uncached requests get the fallback too
+    async fn method_fallback(
+        self: &Arc<Self>,
+        method: &str,
+        params: &serde_json::Value,
+        head_block: &Web3ProxyBlock,
+        response_data: JsonRpcResponseEnum<Arc<RawValue>>,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        match MethodFallback::new(
+            method,
+            &response_data,
+            self.config.block_receipts_fallback_max_txs,
+        ) {
+            None => Ok(response_data),
+            Some(MethodFallback::BlockReceipts) => {
+                self.block_receipts_fallback(params, max_tries, request_metadata)
+                    .await
+            }
+            Some(MethodFallback::MaxPriorityFee) => {
+                self.max_priority_fee_fallback(Some(head_block), max_tries, request_metadata)
+                    .await
+            }
+        }
+    }
+
+    /// Some backends don't have eth_getBlockReceipts. Get the block and then each of its transaction's receipts instead.
+    /// Blocks with more than block_receipts_fallback_max_txs transactions are rejected instead of fanned out.
+    async fn block_receipts_fallback(
+        self: &Arc<Self>,
+        params: &serde_json::Value,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let block_id = params
+            .get(0)
+            .ok_or_else(|| Web3ProxyError::BadRequest("expected a block number or hash".into()))?;
+
+        // the block param is a number, a tag, a hash, or an EIP-1898 object
+        let (block_method, block_id) = match block_id {
+            serde_json::Value::String(x) if x.len() == 66 => ("eth_getBlockByHash", block_id),
+            serde_json::Value::Object(x) => match (x.get("blockHash"), x.get("blockNumber")) {
+                (Some(block_hash), _) => ("eth_getBlockByHash", block_hash),
+                (None, Some(block_num)) => ("eth_getBlockByNumber", block_num),
+                (None, None) => {
+                    return Err(Web3ProxyError::BadRequest(
+                        "expected blockHash or blockNumber".into(),
+                    ))
+                }
+            },
+            _ => ("eth_getBlockByNumber", block_id),
+        };
+
+        let block: Option<ArcBlock> = self
+            .balanced_rpcs
+            .try_proxy_connection(
+                block_method,
+                &json!([block_id, false]),
+                Some(request_metadata),
+                max_tries,
+                Some(Duration::from_secs(30)),
+                None,
+                None,
+            )
+            .await?;
+
+        // unknown blocks are null, just like the real method
+        let Some(block) = block else {
+            return Ok(JsonRpcResponseEnum::from(serde_json::Value::Null));
+        };
+
+        let max_txs = self.config.block_receipts_fallback_max_txs;
+
+        if block.transactions.len() > max_txs {
+            return Err(Web3ProxyError::BadRequest(
+                format!(
+                    "eth_getBlockReceipts is not available and this block has too many transactions ({}) to get their receipts one at a time. the max is {}",
+                    block.transactions.len(),
+                    max_txs
+                )
+                .into(),
+            ));
+        }
+
+        let block_num = block.number;
+
+        // buffered keeps the receipts in the same order as the transactions
+        let receipts: Vec<Arc<RawValue>> = stream::iter(block.transactions.iter())
+            .map(|tx_hash| async move {
+                self.balanced_rpcs
+                    .try_proxy_connection::<_, Arc<RawValue>>(
+                        "eth_getTransactionReceipt",
+                        &[*tx_hash],
+                        Some(request_metadata),
+                        max_tries,
+                        Some(Duration::from_secs(30)),
+                        block_num.as_ref(),
+                        None,
+                    )
+                    .await
+            })
+            .buffered(BLOCK_RECEIPTS_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Web3ProxyResult<_>>()?;
+
+        // a server that has the block should have all of its receipts
+        if receipts.iter().any(|x| x.get() == "null") {
+            return Err(Web3ProxyError::BadResponse(
+                "missing receipts while assembling eth_getBlockReceipts".into(),
+            ));
+        }
+
+        trace!(?block_num, num_receipts = receipts.len(), "assembled eth_getBlockReceipts");
+
+        let receipts = serde_json::value::to_raw_value(&receipts)
+            .expect("receipts should always serialize");
+
+        Ok(JsonRpcResponseEnum::from(Arc::<RawValue>::from(receipts)))
+    }
+
+    /// serve eth_feeHistory out of the fee history cache. only the missing blocks are requested from the backends
+    async fn fee_history(
+        self: &Arc<Self>,
+        method: &str,
+        params: &mut serde_json::Value,
+        head_block: Option<&Web3ProxyBlock>,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        if let Some(requested) =
+            FeeHistoryParams::clamp_block_count(params, self.config.fee_history_max_blocks)
+        {
+            debug!(
+                %requested,
+                max=%self.config.fee_history_max_blocks,
+                "clamped eth_feeHistory block count",
+            );
+        }
+
+        let head_block_num = head_block
+            .map(|x| *x.number())
+            .or_else(|| self.balanced_rpcs.head_block_num())
+            .ok_or(Web3ProxyError::NoServersSynced)?
+            .as_u64();
+
+        let range = FeeHistoryParams::try_from_params(params).and_then(|x| {
+            let newest = match x.newest_block {
+                BlockNumber::Latest => head_block_num,
+                BlockNumber::Number(num) => num.as_u64(),
+                // the pending block changes constantly. the other tags are rarely used for this
+                _ => return None,
+            };
+
+            if x.block_count == 0 || newest > head_block_num {
+                return None;
+            }
+
+            let oldest = (newest + 1).saturating_sub(x.block_count);
+
+            if head_block_num - oldest >= FEE_HISTORY_MAX_BLOCKS {
+                return None;
+            }
+
+            Some((oldest, newest, x.reward_percentiles))
+        });
+
+        if let Some((oldest, newest, reward_percentiles)) = range {
+            if let Some((first_missing, last_missing)) =
+                self.fee_history_cache
+                    .missing(oldest, newest, &reward_percentiles)
+            {
+                let first_missing = U64::from(first_missing);
+                let last_missing = U64::from(last_missing);
+
+                let missing_params = json!([
+                    last_missing - first_missing + 1,
+                    last_missing,
+                    reward_percentiles,
+                ]);
+
+                let fee_history: FeeHistory = self
+                    .balanced_rpcs
+                    .try_proxy_connection(
+                        method,
+                        &missing_params,
+                        Some(request_metadata),
+                        max_tries,
+                        Some(Duration::from_secs(30)),
+                        Some(&first_missing),
+                        Some(&last_missing),
+                    )
+                    .await?;
+
+                self.fee_history_cache
+                    .insert(&fee_history, &reward_percentiles);
+            }
+
+            if let Some(fee_history) =
+                self.fee_history_cache
+                    .get(oldest, newest, &reward_percentiles)
+            {
+                return Ok(JsonRpcResponseEnum::from(json!(fee_history)));
+            }
+
+            // the backend didn't have all the blocks. let it answer the full request
+            trace!(%oldest, %newest, "fee history cache incomplete");
+        }
+
+        let response = self
+            .balanced_rpcs
+            .try_proxy_connection::<_, Arc<RawValue>>(
+                method,
+                params,
+                Some(request_metadata),
+                max_tries,
+                Some(Duration::from_secs(30)),
+                None,
+                None,
+            )
+            .await;
+
+        response.try_into()
+    }
+
     /// proxy request with up to 3 tries.
     async fn proxy_request(
         self: &Arc<Self>,
         mut request: JsonRpcRequest,
         authorization: Arc<Authorization>,
         head_block: Option<&Web3ProxyBlock>,
-    ) -> (StatusCode, JsonRpcForwardedResponse, Vec<Arc<Web3Rpc>>) {
+        pinned_rpc: Option<Arc<Web3Rpc>>,
+    ) -> ProxyResponse<JsonRpcForwardedResponse> {
         let request_metadata = RequestMetadata::new(
             self,
             authorization,
@@ -1139,16 +2502,80 @@ impl Web3ProxyApp {
         )
         .await;
 
+        if let Some(pinned_rpc) = pinned_rpc {
+            // the metadata was just created. nothing else could have set this
+            let _ = request_metadata.pinned_rpc.set(pinned_rpc);
+        }
+
         let response_id = request.id;
 
+        // users without a paid balance are cut off once their free compute units are used up
+        let checked = match request.check_params_bytes(self.config.max_params_bytes) {
+            Ok(()) => request_metadata.reserve_compute_unit_quota().await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = checked {
+            let (code, response_data) = err.as_response_parts_for_request(
+                Some(request_metadata.request_ulid),
+                self.config.expose_internal_errors,
+            );
+
+            let response = JsonRpcForwardedResponse::from_response_data(response_data, response_id);
+
+            request_metadata.add_response(ResponseOrBytes::Response(&response));
+
+            return ProxyResponse {
+                status_code: code,
+                response,
+                rpcs: vec![],
+                compute_units: request_metadata.compute_units(),
+                cache_status: None,
+            };
+        }
+
+        // eth_getLogs pagination is a proxy extension. the backends never see those params
+        let logs_page = if request.method == "eth_getLogs" {
+            match LogsPage::take_from_params(&mut request.params) {
+                Ok(x) => x,
+                Err(err) => {
+                    let (code, response_data) = err.as_response_parts_for_request(
+                        Some(request_metadata.request_ulid),
+                        self.config.expose_internal_errors,
+                    );
+
+                    let response =
+                        JsonRpcForwardedResponse::from_response_data(response_data, response_id);
+
+                    request_metadata.add_response(ResponseOrBytes::Response(&response));
+
+                    return ProxyResponse {
+                        status_code: code,
+                        response,
+                        rpcs: vec![],
+                        compute_units: request_metadata.compute_units(),
+                        cache_status: None,
+                    };
+                }
+            }
+        } else {
+            None
+        };
+
         // TODO: trace log request.params before we send them to _proxy_request_with_caching which might modify them
 
+        // reads already go back through every rpc with the retry policy. trying them again here would multiply that
         // TODO: I think we have sufficient retries elsewhere and this will just slow us down.
-        let mut tries = 3;
+        let mut tries = if is_idempotent(&request.method) { 1 } else { 3 };
         let mut last_code_and_response = None;
+        let mut last_err = None;
         while tries > 0 {
-            let (code, response_data) = match self
-                ._proxy_request_with_caching(
+            let response_data = if request.method == "eth_getLogs" {
+                self.proxy_get_logs(&mut request.params, head_block, &request_metadata)
+                    .await
+                    .map(|x| too_many_logs_error(x, &request.params))
+            } else {
+                self._proxy_request_with_caching(
                     &request.method,
                     &mut request.params,
                     head_block,
@@ -1156,149 +2583,366 @@ impl Web3ProxyApp {
                     &request_metadata,
                 )
                 .await
-            {
+            };
+
+            // some backends reject eth_call when the gas is above their cap. try again with less gas
+            let response_data = if request.method == "eth_call"
+                && is_gas_too_high(&response_data)
+                && let Some(params) = retry_params(
+                    &request.params,
+                    self.config.eth_call_gas_too_high,
+                    self.config.eth_call_gas_cap,
+                ) {
+                info!(
+                    old_params=%request.params,
+                    new_params=%params,
+                    mode=?self.config.eth_call_gas_too_high,
+                    "eth_call gas too high. retrying",
+                );
+
+                request.params = params;
+
+                self._proxy_request_with_caching(
+                    &request.method,
+                    &mut request.params,
+                    head_block,
+                    Some(2),
+                    &request_metadata,
+                )
+                .await
+            } else {
+                response_data
+            };
+
+            let response_data = match logs_page.as_ref() {
+                Some(logs_page) => response_data
+                    .and_then(|x| logs_page.paginate(x, self.config.get_logs_page_bytes)),
+                None => response_data,
+            };
+
+            // checked after paginating so that a large eth_getLogs result can still be paged through
+            let response_data = response_data.and_then(|x| self.check_response_size(x));
+
+            // errors are only reported for the final attempt. see below
+            let (code, response_data) = match response_data {
                 Ok(response_data) => (StatusCode::OK, response_data),
-                Err(err) => err.as_response_parts(),
+                Err(err) => {
+                    let parts = err.as_response_parts(self.config.expose_internal_errors);
+                    last_err = Some(err);
+                    parts
+                }
             };
 
-            last_code_and_response = Some((code, response_data));
+            last_code_and_response = Some((code, response_data));
+
+            // the response will be just as large if we try again
+            if code == StatusCode::OK || code == StatusCode::PAYLOAD_TOO_LARGE {
+                break;
+            }
+
+            tries -= 1;
+
+            // TODO: emit a stat?
+            // TODO: only log params in development
+            warn!(method=%request.method, params=%request.params, response=?last_code_and_response, "request failed ({} tries remain)", tries);
+
+            // TODO: sleep a randomized amount of time?
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let (code, response) = last_code_and_response.expect("there should always be a response");
+
+        // an earlier attempt's error is stale if the final attempt succeeded
+        if code != StatusCode::OK && let Some(err) = last_err {
+            err.report_response(code, Some(request_metadata.request_ulid));
+        }
+
+        let response = JsonRpcForwardedResponse::from_response_data(response, response_id);
+
+        request_metadata.add_response(ResponseOrBytes::Response(&response));
+
+        let rpcs = request_metadata.backend_rpcs_used();
+
+        ProxyResponse {
+            status_code: code,
+            response,
+            rpcs,
+            compute_units: request_metadata.compute_units(),
+            cache_status: request_metadata.cache_status(),
+        }
+    }
+
+    /// eth_getLogs over more than get_logs_max_block_range blocks is split into smaller queries.
+    /// The chunks are sent concurrently and their logs are concatenated in block order.
+    async fn proxy_get_logs(
+        self: &Arc<Self>,
+        params: &mut serde_json::Value,
+        head_block: Option<&Web3ProxyBlock>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        // without a head block, open-ended ranges would never be split
+        let head_block = head_block
+            .cloned()
+            .or_else(|| self.balanced_rpcs.head_block());
+        let head_block = head_block.as_ref();
+
+        let chunks = match self.config.get_logs_max_block_range {
+            Some(max_blocks) => {
+                split_logs_range(params, head_block.map(|x| *x.number()), max_blocks)?
+            }
+            None => None,
+        };
+
+        let Some(mut chunks) = chunks else {
+            return self
+                ._proxy_request_with_caching(
+                    "eth_getLogs",
+                    params,
+                    head_block,
+                    Some(2),
+                    request_metadata,
+                )
+                .await;
+        };
+
+        trace!(num_chunks = chunks.len(), "splitting eth_getLogs");
+
+        // buffered keeps the responses in the same order as the chunks
+        let responses: Vec<_> = stream::iter(chunks.iter_mut())
+            .map(|chunk| async move {
+                self._proxy_request_with_caching(
+                    "eth_getLogs",
+                    &mut chunk.params,
+                    head_block,
+                    Some(2),
+                    request_metadata,
+                )
+                .await
+            })
+            .buffered(MAX_CONCURRENT_CHUNKS)
+            .collect()
+            .await;
+
+        // the merged size is checked by proxy_request after any pagination
+        merge_logs_chunks(chunks.iter().zip(responses).collect())
+    }
+
+    /// Methods that are never proxied get a 403 that names them. They are counted so admins can see what users want.
+    /// The count's label is whatever blocked the method (a config entry or a built-in name), never the user's method string
+    fn blocked_method(&self, method: &str, label: &str) -> Web3ProxyError {
+        self.rejected_method_metrics.blocked.incr(label);
+
+        Web3ProxyError::AccessDenied(format!("the method {} is blocked on this proxy", method).into())
+    }
+
+    /// main logic for proxy_cached_request but in a dedicated function so the try operator is easy to use
+    /// TODO: how can we make this generic?
+    async fn _proxy_request_with_caching(
+        self: &Arc<Self>,
+        method: &str,
+        params: &mut serde_json::Value,
+        head_block: Option<&Web3ProxyBlock>,
+        max_tries: Option<usize>,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        // TODO: don't clone into a new string?
+        let request_method = method.to_string();
+
+        let authorization = request_metadata.authorization.clone().unwrap_or_default();
+
+        let method_access = check_method_for_tier(
+            authorization.checks.method_filter.as_ref(),
+            &self.config.allowed_methods,
+            &self.config.blocked_methods,
+            method,
+        );
+
+        if method_access == MethodAccess::Blocked {
+            let label = blocked_entry(
+                authorization.checks.method_filter.as_ref(),
+                &self.config.blocked_methods,
+                method,
+            )
+            .unwrap_or("other");
+
+            return Err(self.blocked_method(method, label));
+        }
+
+        check_premium(
+            &self.config.requires_premium,
+            authorization.checks.premium,
+            method,
+        )?;
+
+        {
+            let trace_limits = authorization
+                .checks
+                .trace_limits
+                .as_ref()
+                .unwrap_or(&self.config.trace_limits);
+
+            let head_block_num = head_block
+                .map(|x| *x.number())
+                .or_else(|| self.balanced_rpcs.head_block_num())
+                .unwrap_or_default()
+                .as_u64();
+
+            trace_limits.check(method, params, head_block_num)?;
+        }
+
+        let private_rpcs_for_method = self.private_rpcs_for_method(&request_method);
+
+        // TODO: don't force RawValue
+        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = match request_method.as_ref() {
+            // lots of commands are blocked unless allowed_methods allows them
+            method
+                if method_access != MethodAccess::Allowed
+                    && BLOCKED_METHODS.contains(&method) =>
+            {
+                // i don't think we will ever support these methods. the built-in list is small, so the name is a fine label
+                return Err(self.blocked_method(method, method));
+            }
+            // some namespaces only exist on some chains. the backends would give a confusing error
+            method
+                if method_access != MethodAccess::Allowed
+                    && !method_available_on_chain(method, self.config.chain_id) =>
+            {
+                JsonRpcErrorData {
+                    message: format!(
+                        "the method {} is not available on chain {}",
+                        method, self.config.chain_id
+                    )
+                    .into(),
+                    code: -32601,
+                    data: None,
+                }
+                .into()
+            }
+            // operators can send some methods to the private rpcs instead of the balanced rpcs
+            method if let Some(private_rpcs) = private_rpcs_for_method => {
+                let response = timeout(
+                    Duration::from_secs(30),
+                    private_rpcs.try_send_all_synced_connections(
+                        method,
+                        params,
+                        Some(request_metadata),
+                        None,
+                        None,
+                        Some(Duration::from_secs(30)),
+                        None,
+                        Some(1),
+                    ),
+                )
+                .await?;
+
+                response.try_into()?
+            }
+            // filters are stored here instead of on a backend rpc so that polls can go to any server
+            "eth_newBlockFilter" | "eth_newFilter" => {
+                let kind = if method == "eth_newFilter" {
+                    FilterKind::Logs(logs_filter_from_params(params)?)
+                } else {
+                    FilterKind::Blocks
+                };
+
+                let head_block = head_block
+                    .cloned()
+                    .or_else(|| self.balanced_rpcs.head_block())
+                    .ok_or(Web3ProxyError::NoServersSynced)?;
+
+                let filter = Filter {
+                    kind,
+                    last_block: *head_block.number(),
+                };
+
+                let filter_id = new_filter_id();
+
+                self.filters
+                    .insert(filter_id.clone(), Arc::new(Mutex::new(filter)))
+                    .await;
 
-            if code == StatusCode::OK {
-                break;
+                JsonRpcResponseEnum::from(json!(filter_id))
             }
+            // unknown and expired ids get the standard error. clients recreate their filter when they see it
+            "eth_getFilterChanges" | "eth_getFilterLogs" => {
+                let filter_id = filter_id_from_params(method, params)?;
 
-            tries -= 1;
+                let Some(filter) = self.filters.get(&filter_id) else {
+                    return Ok(JsonRpcErrorData::filter_not_found().into());
+                };
 
-            // TODO: emit a stat?
-            // TODO: only log params in development
-            warn!(method=%request.method, params=%request.params, response=?last_code_and_response, "request failed ({} tries remain)", tries);
+                let head_block = head_block
+                    .cloned()
+                    .or_else(|| self.balanced_rpcs.head_block())
+                    .ok_or(Web3ProxyError::NoServersSynced)?;
 
-            // TODO: sleep a randomized amount of time?
-            sleep(Duration::from_millis(10)).await;
-        }
+                // hold the lock across the query so that concurrent polls don't return the same changes
+                let mut filter = filter.lock().await;
+                let filter = &mut *filter;
 
-        let (code, response) = last_code_and_response.expect("there should always be a response");
+                match (method, &filter.kind) {
+                    ("eth_getFilterChanges", FilterKind::Blocks) => {
+                        let mut hashes = vec![];
 
-        let response = JsonRpcForwardedResponse::from_response_data(response, response_id);
+                        let mut num = filter.last_block + 1;
+                        while num <= *head_block.number() {
+                            let (hash, _) =
+                                self.balanced_rpcs.block_hash(&authorization, &num).await?;
 
-        // TODO: this serializes twice :/
-        request_metadata.add_response(ResponseOrBytes::Response(&response));
+                            hashes.push(hash);
 
-        let rpcs = request_metadata.backend_rpcs_used();
+                            num += 1.into();
+                        }
 
-        (code, response, rpcs)
-    }
+                        filter.last_block = *head_block.number();
 
-    /// main logic for proxy_cached_request but in a dedicated function so the try operator is easy to use
-    /// TODO: how can we make this generic?
-    async fn _proxy_request_with_caching(
-        self: &Arc<Self>,
-        method: &str,
-        params: &mut serde_json::Value,
-        head_block: Option<&Web3ProxyBlock>,
-        max_tries: Option<usize>,
-        request_metadata: &Arc<RequestMetadata>,
-    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
-        // TODO: don't clone into a new string?
-        let request_method = method.to_string();
+                        JsonRpcResponseEnum::from(json!(hashes))
+                    }
+                    ("eth_getFilterChanges", FilterKind::Logs(logs_filter)) => {
+                        let response = match logs_changes_params(
+                            logs_filter,
+                            filter.last_block,
+                            *head_block.number(),
+                        ) {
+                            Some(mut params) => {
+                                self.proxy_get_logs(&mut params, Some(&head_block), request_metadata)
+                                    .await?
+                            }
+                            None => JsonRpcResponseEnum::from(json!([])),
+                        };
 
-        let authorization = request_metadata.authorization.clone().unwrap_or_default();
+                        filter.last_block = *head_block.number();
 
-        // TODO: serve net_version without querying the backend
-        // TODO: don't force RawValue
-        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = match request_method.as_ref() {
-            // lots of commands are blocked
-            method @ ("db_getHex"
-            | "db_getString"
-            | "db_putHex"
-            | "db_putString"
-            | "debug_accountRange"
-            | "debug_backtraceAt"
-            | "debug_blockProfile"
-            | "debug_bundler_clearState"
-            | "debug_bundler_dumpMempool"
-            | "debug_bundler_sendBundleNow"
-            | "debug_chaindbCompact"
-            | "debug_chaindbProperty"
-            | "debug_cpuProfile"
-            | "debug_freeOSMemory"
-            | "debug_freezeClient"
-            | "debug_gcStats"
-            | "debug_goTrace"
-            | "debug_memStats"
-            | "debug_mutexProfile"
-            | "debug_setBlockProfileRate"
-            | "debug_setGCPercent"
-            | "debug_setHead"
-            | "debug_setMutexProfileFraction"
-            | "debug_standardTraceBadBlockToFile"
-            | "debug_standardTraceBlockToFile"
-            | "debug_startCPUProfile"
-            | "debug_startGoTrace"
-            | "debug_stopCPUProfile"
-            | "debug_stopGoTrace"
-            | "debug_writeBlockProfile"
-            | "debug_writeMemProfile"
-            | "debug_writeMutexProfile"
-            | "erigon_cacheCheck"
-            | "eth_compileLLL"
-            | "eth_compileSerpent"
-            | "eth_compileSolidity"
-            | "eth_getCompilers"
-            | "eth_sendTransaction"
-            | "eth_sign"
-            | "eth_signTransaction"
-            | "eth_submitHashrate"
-            | "eth_submitWork"
-            | "les_addBalance"
-            | "les_setClientParams"
-            | "les_setDefaultParams"
-            | "miner_setEtherbase"
-            | "miner_setExtra"
-            | "miner_setGasLimit"
-            | "miner_setGasPrice"
-            | "miner_start"
-            | "miner_stop"
-            | "personal_ecRecover"
-            | "personal_importRawKey"
-            | "personal_listAccounts"
-            | "personal_lockAccount"
-            | "personal_newAccount"
-            | "personal_sendTransaction"
-            | "personal_sign"
-            | "personal_unlockAccount"
-            | "shh_addToGroup"
-            | "shh_getFilterChanges"
-            | "shh_getMessages"
-            | "shh_hasIdentity"
-            | "shh_newFilter"
-            | "shh_newGroup"
-            | "shh_newIdentity"
-            | "shh_post"
-            | "shh_uninstallFilter"
-            | "shh_version") => {
-                // i don't think we will ever support these methods. maybe do Forbidden?
-                // TODO: what error code?
-                JsonRpcErrorData::from(format!(
-                    "the method {} does not exist/is not available",
-                    method
-                )).into()
+                        response
+                    }
+                    ("eth_getFilterLogs", FilterKind::Logs(logs_filter)) => {
+                        let mut params = json!([logs_filter]);
+
+                        self.proxy_get_logs(&mut params, Some(&head_block), request_metadata)
+                            .await?
+                    }
+                    // geth only has logs for log filters
+                    _ => JsonRpcErrorData::filter_not_found().into(),
+                }
+            }
+            "eth_uninstallFilter" => {
+                let filter_id = filter_id_from_params(method, params)?;
+
+                let existed = self.filters.get(&filter_id).is_some();
+
+                self.filters.invalidate(&filter_id).await;
+
+                JsonRpcResponseEnum::from(json!(existed))
             }
             // TODO: implement these commands
-            method @ ("eth_getFilterChanges"
-            | "eth_getFilterLogs"
-            | "eth_newBlockFilter"
-            | "eth_newFilter"
-            | "eth_newPendingTransactionFilter"
-            | "eth_pollSubscriptions"
-            | "eth_uninstallFilter") => {
-                // TODO: unsupported command stat. use the count to prioritize new features
-                // TODO: what error code?
-                JsonRpcErrorData::from(format!(
-                    "the method {} is not yet implemented. contact us if you need this",
-                    method
-                ))
-                .into()
+            method @ ("eth_newPendingTransactionFilter"
+            | "eth_pollSubscriptions") => {
+                // the count is used to prioritize new features
+                self.rejected_method_metrics.not_implemented.incr(method);
+
+                return Err(Web3ProxyError::NotImplemented(
+                    format!("the method {}", method).into(),
+                ));
             }
             method @ ("eth_sendUserOperation"
             | "eth_estimateUserOperationGas"
@@ -1330,7 +2974,10 @@ impl Web3ProxyApp {
             "eth_accounts" => JsonRpcResponseEnum::from(serde_json::Value::Array(vec![])),
             "eth_blockNumber" => {
                 match head_block.cloned().or(self.balanced_rpcs.head_block()) {
-                    Some(head_block) => JsonRpcResponseEnum::from(json!(head_block.number())),
+                    Some(head_block) => self
+                        .block_number_response_cache
+                        .get(head_block.number())
+                        .unwrap_or_else(|| JsonRpcResponseEnum::from(json!(head_block.number()))),
                     None => {
                         // TODO: what does geth do if this happens?
                         // TODO: standard not synced error
@@ -1338,14 +2985,25 @@ impl Web3ProxyApp {
                     }
                 }
             }
-            "eth_chainId" => JsonRpcResponseEnum::from(json!(U64::from(self.config.chain_id))),
-            // TODO: eth_callBundle (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_callbundle)
-            // TODO: eth_cancelPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_cancelprivatetransaction, but maybe just reject)
-            // TODO: eth_sendPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_sendprivatetransaction)
-            "eth_coinbase" => {
-                // no need for serving coinbase
-                JsonRpcResponseEnum::from(json!(Address::zero()))
-            }
+            // the chain id is in our config. no need to ask the backends
+            "eth_chainId" => JsonRpcResponseEnum::from(eth_chain_id(self.config.chain_id)),
+            // https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_callbundle
+            "eth_callBundle" => match self.bundle_simulator {
+                None => {
+                    return Err(Web3ProxyError::NotImplemented(
+                        "eth_callBundle without a call_bundle_url in the config".into(),
+                    ));
+                }
+                Some(ref bundle_simulator) => {
+                    CallBundleParams::try_from_params(params)?;
+
+                    let result = bundle_simulator.call_bundle(params).await?;
+
+                    JsonRpcResponseEnum::from(result)
+                }
+            },
+            // the same for every user. this never returns an address tied to a user
+            "eth_coinbase" => JsonRpcResponseEnum::from(eth_coinbase(self.config.coinbase)),
             "eth_estimateGas" => {
                 // TODO: timeout
                 let mut gas_estimate = self
@@ -1379,6 +3037,18 @@ impl Web3ProxyApp {
                 JsonRpcResponseEnum::from(json!(gas_estimate))
             }
             "eth_getTransactionReceipt" | "eth_getTransactionByHash" => {
+                let null_receipt_tx_hash = self.null_receipt_tx_hash(method, params);
+
+                // clients poll for the receipt of a transaction they just sent. skip the backends while it is still pending
+                if let Some(tx_hash) = null_receipt_tx_hash
+                    && self.null_receipt_is_cached(&tx_hash, head_block).await
+                {
+                    return Ok(JsonRpcResponseEnum::from(serde_json::Value::Null));
+                }
+
+                // a head block that arrives during the request might have the transaction
+                let head_block_hash_before = self.balanced_rpcs.head_block_hash();
+
                 // try to get the transaction without specifying a min_block_height
                 // TODO: timeout
 
@@ -1422,74 +3092,79 @@ impl Web3ProxyApp {
                         .await;
                 }
 
+                if let (Some(tx_hash), Ok(value)) = (null_receipt_tx_hash, &response_data)
+                    && keep_null_receipt(
+                        value,
+                        head_block_hash_before,
+                        self.balanced_rpcs.head_block_hash(),
+                    )
+                    && let Some(null_receipt_cache) = self.null_receipt_cache.as_ref()
+                {
+                    null_receipt_cache.insert(tx_hash, ()).await;
+                }
+
                 response_data.try_into()?
             }
+            "eth_feeHistory" => {
+                self.fee_history(method, params, head_block, max_tries, request_metadata)
+                    .await?
+            }
+            "trace_replayTransaction" if self.config.trace_replay_cache_confirmations.is_some() => {
+                self.trace_replay_transaction(method, params, head_block, max_tries, request_metadata)
+                    .await?
+            }
             // TODO: eth_gasPrice that does awesome magic to predict the future
             "eth_hashrate" => JsonRpcResponseEnum::from(json!(U64::zero())),
             "eth_mining" => JsonRpcResponseEnum::from(serde_json::Value::Bool(false)),
+            // only available for entitled keys when a signing service is configured
+            "eth_sendTransaction" => match self.signer {
+                // we don't hold any keys. this is the same as the other blocked methods
+                None => return Err(self.blocked_method(method, method)),
+                Some(ref signer) => {
+                    let raw_tx = signer
+                        .sign_transaction(authorization.checks.rpc_secret_key_id, params)
+                        .await?;
+
+                    let params = json!([raw_tx]);
+
+                    self.send_raw_transaction("eth_sendRawTransaction", &params, request_metadata)
+                        .await?
+                }
+            },
             // TODO: eth_sendBundle (flashbots/eden command)
             // broadcast transactions to all private rpcs at once
             "eth_sendRawTransaction" => {
-                // TODO: decode the transaction
-
-                // TODO: error if the chain_id is incorrect
-
-                let response = timeout(
-                    Duration::from_secs(30),
-                    self
-                        .try_send_protected(
-                            method,
-                            params,
-                            request_metadata,
-                        )
-                )
-                .await?;
-
-                let mut response = response.try_into()?;
-
-                // sometimes we get an error that the transaction is already known by our nodes,
-                // that's not really an error. Return the hash like a successful response would.
-                // TODO: move this to a helper function
-                if let JsonRpcResponseEnum::RpcError{ error_data, ..} = &response {
-                    if error_data.code == -32000
-                        && (error_data.message == "ALREADY_EXISTS: already known"
-                            || error_data.message == "INTERNAL_ERROR: existing tx with same hash")
-                    {
-                        let params = params
-                            .as_array()
-                            .ok_or_else(|| {
-                                Web3ProxyError::BadRequest(
-                                    "Unable to get array from params".into(),
-                                )
-                            })?
-                            .get(0)
-                            .ok_or_else(|| {
-                                Web3ProxyError::BadRequest(
-                                    "Unable to get item 0 from params".into(),
-                                )
-                            })?
-                            .as_str()
-                            .ok_or_else(|| {
-                                Web3ProxyError::BadRequest(
-                                    "Unable to get string from params item 0".into(),
-                                )
-                            })?;
-
-                        let params = Bytes::from_str(params)
-                            .expect("there must be Bytes if we got this far");
-
-                        let rlp = Rlp::new(params.as_ref());
-
-                        if let Ok(tx) = Transaction::decode(&rlp) {
-                            // TODO: decode earlier and confirm that tx.chain_id (if set) matches self.config.chain_id
-                            let tx_hash = json!(tx.hash());
+                // malformed transactions would be rejected by every relay. reject them here instead
+                validate_raw_transaction(params, self.config.chain_id, self.config.raw_tx_validation)?;
+
+                // the hash of the raw transaction is the transaction hash
+                let tx_hash = params
+                    .get(0)
+                    .and_then(|x| x.as_str())
+                    .and_then(|x| Bytes::from_str(x).ok())
+                    .map(|x| H256::from(keccak256(x)));
+
+                let response = match tx_hash {
+                    Some(tx_hash) => {
+                        // clients that retry wait for the submission that is already in flight instead of hitting every relay again
+                        let response = self
+                            .inflight_raw_transactions
+                            .try_get_with(
+                                tx_hash,
+                                self.send_raw_transaction(method, params, request_metadata),
+                            )
+                            .await;
 
-                            trace!("tx_hash: {:#?}", tx_hash);
+                        // only dedupe while in flight. sending the same transaction again later is allowed
+                        self.inflight_raw_transactions.invalidate(&tx_hash).await;
 
-                            response = JsonRpcResponseEnum::from(tx_hash);
-                        }
+                        response?
                     }
-                }
+                    None => {
+                        self.send_raw_transaction(method, params, request_metadata)
+                            .await?
+                    }
+                };
 
                 // emit transaction count stats
                 // TODO: use this cache to avoid sending duplicate transactions?
@@ -1531,11 +3206,114 @@ impl Web3ProxyApp {
 
                 response
             }
+            "eth_cancelPrivateTransaction" => match self.private_rpcs.as_ref() {
+                Some(private_rpcs) if !private_rpcs.is_empty() => {
+                    let cancelled = timeout(
+                        Duration::from_secs(30),
+                        self.try_cancel_private_transaction(
+                            private_rpcs,
+                            method,
+                            params,
+                            request_metadata,
+                        ),
+                    )
+                    .await??;
+
+                    JsonRpcResponseEnum::from(serde_json::Value::Bool(cancelled))
+                }
+                _ => JsonRpcErrorData {
+                    message: "eth_cancelPrivateTransaction is not available. no private relays are configured".into(),
+                    code: -32601,
+                    data: None,
+                }
+                .into(),
+            },
+            // private transactions must never be broadcast to the public mempool
+            "eth_sendPrivateTransaction" => {
+                let (private_params,): (SendPrivateTransactionParams,) =
+                    serde_json::from_value(params.clone()).map_err(|err| {
+                        trace!(?err, "bad eth_sendPrivateTransaction params");
+                        Web3ProxyError::BadRequest(
+                            "eth_sendPrivateTransaction expects a single object with a tx".into(),
+                        )
+                    })?;
+
+                if private_params.tx.is_empty() {
+                    return Err(Web3ProxyError::BadRequest("tx must not be empty".into()));
+                }
+
+                if let Some(max_block_number) = private_params.max_block_number {
+                    let head_block_num = head_block
+                        .map(|x| *x.number())
+                        .or_else(|| self.balanced_rpcs.head_block_num());
+
+                    if let Some(head_block_num) = head_block_num {
+                        if max_block_number <= head_block_num {
+                            return Err(Web3ProxyError::BadRequest(
+                                format!(
+                                    "maxBlockNumber {} is not after the head block {}",
+                                    max_block_number, head_block_num
+                                )
+                                .into(),
+                            ));
+                        }
+                    }
+                }
+
+                trace!(
+                    max_block_number=?private_params.max_block_number,
+                    preferences=?private_params.preferences,
+                    "sending private transaction",
+                );
+
+                match self.private_rpcs.as_ref() {
+                    Some(private_rpcs) if !private_rpcs.is_empty() => {
+                        let response = timeout(
+                            Duration::from_secs(30),
+                            private_rpcs.try_send_all_synced_connections(
+                                method,
+                                params,
+                                Some(request_metadata),
+                                None,
+                                None,
+                                Some(Duration::from_secs(30)),
+                                Some(Level::TRACE.into()),
+                                None,
+                            ),
+                        )
+                        .await?;
+
+                        response.try_into()?
+                    }
+                    _ => JsonRpcErrorData {
+                        message: "eth_sendPrivateTransaction is not available. no private relays are configured".into(),
+                        code: -32601,
+                        data: None,
+                    }
+                    .into(),
+                }
+            }
             "eth_syncing" => {
                 // no stats on this. its cheap
-                // TODO: return a real response if all backends are syncing or if no servers in sync
-                // TODO: const
-                JsonRpcResponseEnum::from(serde_json::Value::Bool(false))
+                if self.balanced_rpcs.synced() {
+                    JsonRpcResponseEnum::from(serde_json::Value::Bool(false))
+                } else {
+                    // no servers are synced. the consensus head is where we are. the highest rpc is where we are going
+                    // TODO: track the block we were on when we started syncing
+                    let current_block = self.balanced_rpcs.head_block_num().unwrap_or_default();
+
+                    let highest_block = self
+                        .balanced_rpcs
+                        .highest_block_num()
+                        .unwrap_or_default()
+                        .max(current_block);
+
+                    JsonRpcResponseEnum::from(json!({
+                        "startingBlock": current_block,
+                        "currentBlock": current_block,
+                        "highestBlock": highest_block,
+                    }))
+                }
             }
             "eth_subscribe" => JsonRpcErrorData {
                 message: "notifications not supported. eth_subscribe is only available over a websocket".into(),
@@ -1553,9 +3331,25 @@ impl Web3ProxyApp {
                 // TODO: const
                 JsonRpcResponseEnum::from(serde_json::Value::Bool(true))
             }
-            "net_peerCount" => 
-                JsonRpcResponseEnum::from(json!(U64::from(self.balanced_rpcs.num_synced_rpcs())))
-            ,
+            "net_peerCount" => {
+                let synced_rpcs = U64::from(self.balanced_rpcs.num_synced_rpcs());
+
+                let peer_count = match self.config.net_peer_count {
+                    PeerCountMode::SyncedRpcs => synced_rpcs,
+                    PeerCountMode::MaxBackendPeers => self
+                        .peer_count_cache
+                        .get_with((), self.balanced_rpcs.max_peer_count())
+                        .await
+                        .unwrap_or(synced_rpcs),
+                };
+
+                JsonRpcResponseEnum::from(json!(peer_count))
+            }
+            // the chain id is in our config. no need to ask the backends
+            "net_version" => JsonRpcResponseEnum::from(net_version(self.config.chain_id)),
+            "proxy_chainConfig" => self.config.chain_config_response(
+                authorization.checks.rpc_secret_key_id.is_some(),
+            )?,
             "web3_clientVersion" => 
                 JsonRpcResponseEnum::from(serde_json::Value::String(APP_USER_AGENT.to_string()))
             ,
@@ -1614,8 +3408,20 @@ impl Web3ProxyApp {
             // anything else gets sent to backend rpcs and cached
             method => {
                 if method.starts_with("admin_") {
-                    // TODO: emit a stat? will probably just be noise
-                    return Err(Web3ProxyError::AccessDenied("admin methods are not allowed".into()));
+                    return Err(self.blocked_method(method, "admin_*"));
+                }
+
+                // stricter deployments only proxy methods that have a cost or that they allowed by name
+                if self.config.reject_unknown_methods
+                    && method_access != MethodAccess::Allowed
+                    && !ComputeUnit::is_known_method(method, self.config.chain_id)
+                {
+                    // clients can send any method name. one label keeps the metric small. the names are in the logs
+                    self.rejected_method_metrics.not_implemented.incr("unknown");
+
+                    return Err(Web3ProxyError::NotImplemented(
+                        format!("the method {}", method).into(),
+                    ));
                 }
 
                 // TODO: if no servers synced, wait for them to be synced? probably better to error and let haproxy retry another server
@@ -1626,16 +3432,23 @@ impl Web3ProxyApp {
 
                 // we do this check before checking caches because it might modify the request params
                 // TODO: add a stat for archive vs full since they should probably cost different
-                // TODO: this cache key can be rather large. is that okay?
-                let cache_key: Option<JsonRpcQueryCacheKey> = match CacheMode::new(
+                let cache_mode = CacheMode::new(
                     &authorization,
                     method,
                     params,
                     &head_block,
                     &self.balanced_rpcs,
                 )
-                .await
-                {
+                .await;
+
+                // hashing huge params for a key costs more than the cache would save. requests this large are never cached
+                let cacheable = request_metadata.params_bytes <= MAX_CACHED_PARAMS_BYTES;
+
+                // equivalent requests share a key. the params sent to the backends are unchanged
+                let key_params = cache_mode.cache_key_params(method, params);
+
+                let cache_key: Option<JsonRpcQueryCacheKey> = match cache_mode {
+                    _ if !cacheable => None,
                     CacheMode::CacheSuccessForever => Some(JsonRpcQueryCacheKey::new(
                         None,
                         None,
@@ -1643,6 +3456,9 @@ impl Web3ProxyApp {
                         params,
                         false,
                     )),
+                    CacheMode::CacheNever if self.config.method_cache_ttl.contains_key(method) => {
+                        Some(JsonRpcQueryCacheKey::new(None, None, method, params, false))
+                    }
                     CacheMode::CacheNever => None,
                     CacheMode::Cache {
                         block,
@@ -1660,7 +3476,7 @@ impl Web3ProxyApp {
                             Some(block),
                             None,
                             method,
-                            params,
+                            &key_params,
                             cache_errors,
                         ))
                     }
@@ -1690,16 +3506,91 @@ impl Web3ProxyApp {
                 // TODO: different timeouts for different user tiers. get the duration out of the request_metadata
                 let backend_request_timetout = Duration::from_secs(240);
 
-                if let Some(cache_key) = cache_key {
+                if let Some(cache_key) = cache_key.as_ref().filter(|_| authorization.no_cache) {
+                    // skip the cache, but identical requests that are already in flight still share one backend request
+                    let from_block_num = cache_key.from_block_num().copied();
+                    let to_block_num = cache_key.to_block_num().copied();
+
+                    let leader = AtomicBool::new(false);
+
+                    let response_data = self
+                        .inflight_uncached_requests
+                        .try_get_with::<_, Web3ProxyError>(cache_key.hash(), async {
+                            leader.store(true, atomic::Ordering::Relaxed);
+
+                            let num_backend_requests = request_metadata.backend_requests.lock().len();
+
+                            let response_data = timeout(
+                                backend_request_timetout + Duration::from_millis(100),
+                                self.balanced_rpcs.try_proxy_connection::<_, Arc<RawValue>>(
+                                    method,
+                                    params,
+                                    Some(request_metadata),
+                                    max_tries,
+                                    Some(backend_request_timetout),
+                                    from_block_num.as_ref(),
+                                    to_block_num.as_ref(),
+                                ),
+                            )
+                            .await?;
+
+                            let response_data = self
+                                .method_fallback(method, params, &head_block, response_data.try_into()?, max_tries, request_metadata)
+                                .await?;
+
+                            let rpcs = request_metadata.backend_requests.lock()[num_backend_requests..].to_vec();
+
+                            Ok((response_data, rpcs))
+                        })
+                        .await;
+
+                    // only dedupe while in flight. the next request gets a fresh response too
+                    self.inflight_uncached_requests.invalidate(&cache_key.hash()).await;
+
+                    let (response_data, rpcs) = response_data?;
+
+                    if !leader.load(atomic::Ordering::Relaxed) {
+                        // this request shared another request's backend requests. it is billed like it made them, not like a cache hit
+                        request_metadata.backend_requests.lock().extend(rpcs);
+                    }
+
+                    request_metadata.record_cache_status(CacheStatus::Miss);
+
+                    response_data
+                } else if let Some(cache_key) = cache_key {
                     let from_block_num = cache_key.from_block_num().copied();
                     let to_block_num = cache_key.to_block_num().copied();
                     let cache_jsonrpc_errors = cache_key.cache_errors();
 
                     // TODO: try to fetch out of s3
 
-                    self
+                    // these are only used for metrics. a race here will only make the hit/dedup counts slightly off
+                    let already_cached = self.jsonrpc_response_cache.contains_key(&cache_key.hash());
+                    let cache_miss = AtomicBool::new(false);
+
+                    let compress = self.config.response_cache_compression;
+                    let ttl = self.config.method_cache_ttl(method);
+
+                    let response_data = self
                         .jsonrpc_response_cache
                         .try_get_with::<_, Web3ProxyError>(cache_key.hash(), async {
+                            cache_miss.store(true, atomic::Ordering::Relaxed);
+
+                            #[cfg(feature = "disk_cache")]
+                            if let Some(ref disk_response_cache) = self.disk_response_cache
+                                && disk_response_cache.should_persist(&cache_key, head_block.number().as_u64())
+                                && let Some(response_data) = disk_response_cache.get(&cache_key, method, params).await
+                            {
+                                return Ok(CachedJsonRpcResponse::new(response_data, compress).with_ttl(ttl));
+                            }
+
+                            // another proxy might have already fetched this
+                            if let Some(ref shared_response_cache) = self.shared_response_cache
+                                && let Some(response_data) = shared_response_cache.get(&cache_key, method, params).await
+                            {
+                                return Ok(CachedJsonRpcResponse::new(response_data, compress).with_ttl(ttl));
+                            }
+
                             let response_data = timeout(
                                 backend_request_timetout + Duration::from_millis(100),
                                 self.balanced_rpcs
@@ -1720,12 +3611,64 @@ impl Web3ProxyApp {
                                 // return all the errors now. moka will not cache Err results
                                 Err(err)
                             } else {
-                                let response_data: JsonRpcResponseEnum<Arc<RawValue>> = response_data.try_into()?;
+                                // cached like the native response, so a fallback runs at most once per key
+                                let response_data = self
+                                    .method_fallback(method, params, &head_block, response_data.try_into()?, max_tries, request_metadata)
+                                    .await?;
+
+                                // huge responses would push lots of smaller responses out of the caches. they are still returned
+                                // responses over max_response_bytes are rejected by proxy_request. eth_getLogs might be paginated first
+                                let num_bytes = response_data.num_bytes() as u64;
+                                let too_large_to_cache = (method == "eth_getProof"
+                                    && num_bytes > self.config.get_proof_max_cached_bytes as u64)
+                                    || self.config.max_response_bytes.is_some_and(|max_bytes| num_bytes > max_bytes);
+
+                                if !too_large_to_cache && let Some(ref shared_response_cache) = self.shared_response_cache {
+                                    shared_response_cache.publish(
+                                        &cache_key,
+                                        method,
+                                        params,
+                                        &response_data,
+                                        head_block.number().as_u64(),
+                                        ttl,
+                                    );
+                                }
+
+                                #[cfg(feature = "disk_cache")]
+                                if !too_large_to_cache
+                                    && let Some(ref disk_response_cache) = self.disk_response_cache
+                                    && disk_response_cache.should_persist(&cache_key, head_block.number().as_u64())
+                                {
+                                    disk_response_cache.insert(&cache_key, method, params, &response_data);
+                                }
+
+                                // a ttl of zero expires the response as soon as the requests waiting on it have it
+                                let ttl = if too_large_to_cache { Some(Duration::ZERO) } else { ttl };
 
                                 // TODO: response data should maybe be Arc<JsonRpcResponseEnum<Box<RawValue>>>, but that's more work
-                                Ok(response_data)
+                                Ok(CachedJsonRpcResponse::new(response_data, compress).with_ttl(ttl))
                             }
-                        }).await?
+                        }).await;
+
+                    let method_label = self.method_label(method);
+
+                    if cache_miss.load(atomic::Ordering::Relaxed) {
+                        self.response_cache_metrics.misses.incr(method_label);
+                    } else if already_cached {
+                        self.response_cache_metrics.hits.incr(method_label);
+                    } else {
+                        self.response_cache_metrics.inflight_dedup_hits.incr(method_label);
+                    }
+
+                    let response_data = response_data?;
+
+                    if cache_miss.load(atomic::Ordering::Relaxed) {
+                        request_metadata.record_cache_status(CacheStatus::Miss);
+                    } else {
+                        request_metadata.record_cache_status(CacheStatus::Hit { age: response_data.age() });
+                    }
+
+                    response_data.into_response()?
                 } else {
                     let x = timeout(
                         backend_request_timetout + Duration::from_millis(100),
@@ -1740,9 +3683,11 @@ impl Web3ProxyApp {
                             None,
                         )
                     )
-                    .await??;
+                    .await?;
 
-                    x.into()
+                    self
+                        .method_fallback(method, params, &head_block, x.try_into()?, max_tries, request_metadata)
+                        .await?
                 }
             }
         };
@@ -1751,9 +3696,200 @@ impl Web3ProxyApp {
     }
 }
 
+/// Methods that are built from other requests when the backends don't have them
+#[derive(Debug, PartialEq)]
+enum MethodFallback {
+    BlockReceipts,
+    MaxPriorityFee,
+}
+
+impl MethodFallback {
+    fn new(
+        method: &str,
+        response_data: &JsonRpcResponseEnum<Arc<RawValue>>,
+        block_receipts_fallback_max_txs: usize,
+    ) -> Option<Self> {
+        let JsonRpcResponseEnum::RpcError { error_data, .. } = response_data else {
+            return None;
+        };
+
+        if !error_data.is_method_not_found() {
+            return None;
+        }
+
+        match method {
+            "eth_getBlockReceipts" if block_receipts_fallback_max_txs > 0 => {
+                Some(Self::BlockReceipts)
+            }
+            "eth_maxPriorityFeePerGas" => Some(Self::MaxPriorityFee),
+            _ => None,
+        }
+    }
+}
+
+/// eth_chainId is a hex quantity
+fn eth_chain_id(chain_id: u64) -> serde_json::Value {
+    json!(U64::from(chain_id))
+}
+
+/// net_version is a decimal string
+fn net_version(chain_id: u64) -> serde_json::Value {
+    serde_json::Value::String(chain_id.to_string())
+}
+
+/// we don't mine, so there is no coinbase unless the config has one
+fn eth_coinbase(coinbase: Option<Address>) -> serde_json::Value {
+    json!(coinbase.unwrap_or_else(Address::zero))
+}
+
+/// Only remember a null receipt if the head block didn't change during the request.
+/// The head block loop already forgot the new block's transactions, so a late insert would stick around until it expires.
+fn keep_null_receipt(
+    value: &RawValue,
+    head_block_hash_before: Option<H256>,
+    head_block_hash_after: Option<H256>,
+) -> bool {
+    value.get() == "null" && head_block_hash_before == head_block_hash_after
+}
+
+/// Backends refuse eth_getLogs queries that match too many logs, but they all say so differently.
+/// Give users one error that they can handle.
+fn too_many_logs_error(
+    response_data: JsonRpcResponseEnum<Arc<RawValue>>,
+    params: &serde_json::Value,
+) -> JsonRpcResponseEnum<Arc<RawValue>> {
+    let JsonRpcResponseEnum::RpcError { error_data, .. } = &response_data else {
+        return response_data;
+    };
+
+    let upstream_message = error_data.message.to_lowercase();
+
+    // geth/erigon/infura: "query returned more than 10000 results"
+    // alchemy: "Log response size exceeded. ..."
+    if !upstream_message.contains("query returned more than")
+        && !upstream_message.contains("response size exceeded")
+    {
+        return response_data;
+    }
+
+    let filter = params.get(0);
+
+    JsonRpcErrorData {
+        message: "eth_getLogs matched too many logs. try a smaller block range".into(),
+        // EIP-1474 "limit exceeded"
+        code: -32005,
+        data: Some(json!({
+            "fromBlock": filter.and_then(|x| x.get("fromBlock")),
+            "toBlock": filter.and_then(|x| x.get("toBlock")),
+            "upstreamMessage": error_data.message,
+        })),
+    }
+    .into()
+}
+
 impl fmt::Debug for Web3ProxyApp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // TODO: the default formatter takes forever to write. this is too quiet though
         f.debug_struct("Web3ProxyApp").finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_id_formats() {
+        assert_eq!(eth_chain_id(1), json!("0x1"));
+        assert_eq!(net_version(1), json!("1"));
+
+        assert_eq!(eth_chain_id(137), json!("0x89"));
+        assert_eq!(net_version(137), json!("137"));
+
+        assert_eq!(eth_chain_id(42161), json!("0xa4b1"));
+        assert_eq!(net_version(42161), json!("42161"));
+    }
+
+    #[test]
+    fn test_coinbase() {
+        assert_eq!(
+            eth_coinbase(None),
+            json!("0x0000000000000000000000000000000000000000")
+        );
+
+        let coinbase: Address = "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            eth_coinbase(Some(coinbase)),
+            json!("0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5")
+        );
+
+        // the default config doesn't have one
+        assert_eq!(AppConfig::default().coinbase, None);
+    }
+
+    #[test]
+    fn test_keep_null_receipt() {
+        let null = RawValue::from_string("null".to_string()).unwrap();
+        let receipt = RawValue::from_string("{\"status\":\"0x1\"}".to_string()).unwrap();
+
+        let head_1 = Some(H256::repeat_byte(1));
+        let head_2 = Some(H256::repeat_byte(2));
+
+        assert!(keep_null_receipt(&null, head_1, head_1));
+        assert!(keep_null_receipt(&null, None, None));
+
+        // a new head block arrived during the request. it might have the transaction
+        assert!(!keep_null_receipt(&null, head_1, head_2));
+        assert!(!keep_null_receipt(&null, None, head_1));
+
+        // only null receipts are kept
+        assert!(!keep_null_receipt(&receipt, head_1, head_1));
+    }
+
+    #[test]
+    fn test_method_fallback() {
+        let method_not_found = JsonRpcResponseEnum::from(JsonRpcErrorData {
+            code: -32601,
+            message: "the method eth_getBlockReceipts does not exist/is not available".into(),
+            data: None,
+        });
+
+        assert_eq!(
+            MethodFallback::new("eth_getBlockReceipts", &method_not_found, 100),
+            Some(MethodFallback::BlockReceipts)
+        );
+        assert_eq!(
+            MethodFallback::new("eth_maxPriorityFeePerGas", &method_not_found, 100),
+            Some(MethodFallback::MaxPriorityFee)
+        );
+
+        // the block receipts fallback can be turned off
+        assert_eq!(
+            MethodFallback::new("eth_getBlockReceipts", &method_not_found, 0),
+            None
+        );
+
+        // other methods and other errors are returned as they are
+        assert_eq!(
+            MethodFallback::new("eth_call", &method_not_found, 100),
+            None
+        );
+
+        let other_error = JsonRpcResponseEnum::from(JsonRpcErrorData::from("header not found"));
+
+        assert_eq!(
+            MethodFallback::new("eth_getBlockReceipts", &other_error, 100),
+            None
+        );
+
+        let result = JsonRpcResponseEnum::from(json!([]));
+
+        assert_eq!(
+            MethodFallback::new("eth_getBlockReceipts", &result, 100),
+            None
+        );
+    }
+}
@@ -1,7 +1,13 @@
 use argh::FromArgs;
+use futures::future::join_all;
+use redis_rate_limiter::{DeadpoolRuntime, RedisConfig, RedisPool};
 use std::fs;
+use std::time::Duration;
 use tracing::{error, info, warn};
-use web3_proxy::config::TopConfig;
+use web3_proxy::app::APP_USER_AGENT;
+use web3_proxy::config::{average_block_interval, TopConfig};
+use web3_proxy::relational_db::get_db;
+use web3_proxy::rpcs::one::Web3Rpc;
 
 #[derive(FromArgs, PartialEq, Eq, Debug)]
 /// Check the config for any problems.
@@ -10,6 +16,10 @@ pub struct CheckConfigSubCommand {
     #[argh(positional)]
     /// path to the configuration toml.
     path: String,
+
+    #[argh(switch)]
+    /// also connect to the database, redis, and every balanced and private rpc. nothing is served
+    dry_run: bool,
 }
 
 impl CheckConfigSubCommand {
@@ -80,6 +90,17 @@ impl CheckConfigSubCommand {
             }
         }
 
+        if top_config.app.require_https_backends {
+            for url in top_config.insecure_backend_urls() {
+                num_errors += 1;
+                error!(%url, "require_https_backends is set, but this backend does not use TLS");
+            }
+        }
+
+        if self.dry_run {
+            num_errors += dry_run(&top_config).await;
+        }
+
         // TODO: print num warnings and have a flag to fail even on warnings
 
         if num_errors == 0 {
@@ -90,6 +111,111 @@ impl CheckConfigSubCommand {
     }
 }
 
+/// Connect to everything in the config the same way the proxy would, then stop.
+/// Returns the number of errors. Every problem is logged instead of stopping at the first one.
+async fn dry_run(top_config: &TopConfig) -> usize {
+    let mut num_errors = 0;
+
+    if let Some(db_url) = top_config.app.db_url.clone() {
+        // migrations are not run. a dry run shouldn't change anything
+        match get_db(db_url, 1, 1).await {
+            Ok(_) => info!("connected to the database"),
+            Err(err) => {
+                num_errors += 1;
+                error!(?err, "unable to connect to the database");
+            }
+        }
+    }
+
+    let redis_pool = match top_config.app.volatile_redis_url.as_ref() {
+        None => None,
+        Some(redis_url) => match connect_redis(redis_url).await {
+            Ok(x) => {
+                info!("connected to vredis");
+                Some(x)
+            }
+            Err(err) => {
+                num_errors += 1;
+                error!(?err, "unable to connect to vredis");
+                None
+            }
+        },
+    };
+
+    let http_client = match reqwest::ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(60))
+        .user_agent(APP_USER_AGENT)
+        .build()
+    {
+        Ok(x) => x,
+        Err(err) => {
+            error!(?err, "unable to build an http client");
+            return num_errors + 1;
+        }
+    };
+
+    let chain_id = top_config.app.chain_id;
+    let block_interval = average_block_interval(chain_id);
+
+    let rpcs = top_config
+        .balanced_rpcs
+        .iter()
+        .map(|x| ("balanced_rpcs", x))
+        .chain(
+            top_config
+                .private_rpcs
+                .iter()
+                .flatten()
+                .map(|x| ("private_rpcs", x)),
+        );
+
+    let checks = rpcs.map(|(group, (name, config))| {
+        let http_client = Some(http_client.clone());
+        let redis_pool = redis_pool.clone();
+
+        async move {
+            if !config.extra.is_empty() {
+                warn!(%group, %name, extra=?config.extra.keys(), "unknown Web3RpcConfig fields!");
+            }
+
+            let result = Web3Rpc::dry_run(
+                config.clone(),
+                name.clone(),
+                chain_id,
+                http_client,
+                redis_pool,
+                block_interval,
+            )
+            .await;
+
+            (group, name, result)
+        }
+    });
+
+    for (group, name, result) in join_all(checks).await {
+        if let Err(err) = result {
+            num_errors += 1;
+            error!(%group, %name, ?err, "rpc failed its checks");
+        }
+    }
+
+    num_errors
+}
+
+async fn connect_redis(redis_url: &str) -> anyhow::Result<RedisPool> {
+    let redis_pool = RedisConfig::from_url(redis_url)
+        .builder()?
+        .max_size(1)
+        .runtime(DeadpoolRuntime::Tokio1)
+        .build()?;
+
+    // make sure we can actually connect. building the pool doesn't
+    redis_pool.get().await?;
+
+    Ok(redis_pool)
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -16,6 +16,7 @@ use tokio::time::Instant;
 use tracing::{error, info};
 use ulid::Ulid;
 use web3_proxy::app::BILLING_PERIOD_SECONDS;
+use web3_proxy::compute_units::ComputeUnit;
 use web3_proxy::config::TopConfig;
 use web3_proxy::frontend::authorization::{Authorization, RequestMetadata, RpcSecretKey};
 use web3_proxy::rpcs::one::Web3Rpc;
@@ -178,10 +179,16 @@ impl MigrateStatsToV2 {
 
                     // Create RequestMetadata
                     let request_metadata = RequestMetadata {
+                        // these are old requests. they were already logged
+                        access_log: false,
                         archive_request: x.archive_request.into(),
+                        archive_multiplier: ComputeUnit::default_archive_multiplier(),
                         authorization: Some(authorization.clone()),
                         backend_requests: Mutex::new(backend_rpcs),
+                        cache_status: Default::default(),
                         chain_id: x.chain_id,
+                        // quotas are for new requests
+                        compute_unit_quota: None,
                         error_response: x.error_response.into(),
                         // debug data is in kafka, not mysql or influx
                         kafka_debug_logger: None,
@@ -192,6 +199,7 @@ impl MigrateStatsToV2 {
                             .into(),
                         // This is not relevant in the new version
                         no_servers: 0.into(),
+                        pinned_rpc: Default::default(),
                         // Get the mean of all the request bytes
                         request_bytes: int_request_bytes as usize,
                         response_bytes: int_response_bytes.into(),
@@ -199,6 +207,7 @@ impl MigrateStatsToV2 {
                         response_from_backup_rpc: false.into(),
                         response_timestamp: x.period_datetime.timestamp().into(),
                         response_millis: int_response_millis.into(),
+                        retries: 0.into(),
                         // This is overwritten later on
                         start_instant: Instant::now(),
                         stat_sender: Some(stat_sender.clone()),
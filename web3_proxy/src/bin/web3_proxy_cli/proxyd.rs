@@ -269,17 +269,20 @@ async fn run(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::{response::IntoResponse, routing::post, Json, Router};
     use ethers::{
         prelude::{Http, Provider, U256},
-        types::Address,
+        types::{Address, H256},
         utils::{Anvil, AnvilInstance},
     };
     use hashbrown::HashMap;
     use parking_lot::Mutex;
+    use reqwest::StatusCode;
+    use serde_json::json;
     use std::{
         env,
         str::FromStr,
-        sync::atomic::{AtomicU16, Ordering},
+        sync::atomic::{AtomicU16, AtomicUsize, Ordering},
     };
     use tokio::{
         sync::broadcast::error::SendError,
@@ -288,6 +291,8 @@ mod tests {
     };
     use web3_proxy::{
         config::{AppConfig, Web3RpcConfig},
+        errors::ERROR_RESPONSES,
+        raw_transaction::RawTxValidation,
         rpcs::blockchain::ArcBlock,
     };
 
@@ -296,12 +301,18 @@ mod tests {
         _anvil: AnvilInstance,
         handle: Mutex<Option<JoinHandle<anyhow::Result<()>>>>,
         anvil_provider: Provider<Http>,
+        proxy_endpoint: String,
         proxy_provider: Provider<Http>,
         shutdown_sender: broadcast::Sender<()>,
     }
 
     impl TestApp {
         async fn spawn() -> Self {
+            Self::spawn_with(|_| {}).await
+        }
+
+        /// change the default test config before the app starts
+        async fn spawn_with(configure: impl FnOnce(&mut TopConfig)) -> Self {
             // TODO: move basic setup into a test fixture
             let path = env::var("PATH").unwrap();
 
@@ -318,7 +329,7 @@ mod tests {
 
             // make a test TopConfig
             // TODO: load TopConfig from a file? CliConfig could have `cli_config.load_top_config`. would need to inject our endpoint ports
-            let top_config = TopConfig {
+            let mut top_config = TopConfig {
                 app: AppConfig {
                     chain_id: 31337,
                     default_user_max_requests_per_period: Some(6_000_000),
@@ -341,10 +352,13 @@ mod tests {
                     },
                 )]),
                 private_rpcs: None,
+                secondary_private_rpcs: None,
                 bundler_4337_rpcs: None,
                 extra: Default::default(),
             };
 
+            configure(&mut top_config);
+
             let (shutdown_sender, _shutdown_receiver) = broadcast::channel(1);
 
             let frontend_port_arc = Arc::new(AtomicU16::new(0));
@@ -376,11 +390,12 @@ mod tests {
 
             let proxy_endpoint = format!("http://127.0.0.1:{}", frontend_port);
 
-            let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
+            let proxy_provider = Provider::<Http>::try_from(proxy_endpoint.as_str()).unwrap();
 
             Self {
                 handle: Mutex::new(Some(handle)),
                 anvil_provider,
+                proxy_endpoint,
                 proxy_provider,
                 shutdown_sender,
                 _anvil: anvil,
@@ -471,4 +486,642 @@ mod tests {
 
         x.wait().await;
     }
+
+    #[test_log::test(tokio::test)]
+    async fn it_handles_notifications() {
+        let x = TestApp::spawn().await;
+
+        let client = reqwest::Client::new();
+
+        // a notification is processed, but nothing is sent back
+        let response = client
+            .post(&x.proxy_endpoint)
+            .json(&json!({"jsonrpc": "2.0", "method": "eth_blockNumber", "params": []}))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        // notifications are left out of a batch's responses
+        let response: Vec<serde_json::Value> = client
+            .post(&x.proxy_endpoint)
+            .json(&json!([
+                {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1},
+                {"jsonrpc": "2.0", "method": "eth_blockNumber", "params": []},
+                {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 2},
+            ]))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = response.iter().map(|x| x["id"].clone()).collect();
+
+        assert_eq!(ids, vec![json!(1), json!(2)]);
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_answers_every_request_in_a_batch() {
+        let x = TestApp::spawn().await;
+
+        // 9 requests that work and 1 that reverts
+        let mut batch: Vec<_> = (1..=9)
+            .map(
+                |id| json!({"jsonrpc": "2.0", "method": "eth_blockNumber", "params": [], "id": id}),
+            )
+            .collect();
+
+        // init code that reverts: PUSH1 0 PUSH1 0 REVERT
+        batch.insert(
+            4,
+            json!({
+                "jsonrpc": "2.0",
+                "method": "eth_call",
+                "params": [{"data": "0x60006000fd"}, "latest"],
+                "id": 10,
+            }),
+        );
+
+        let response = reqwest::Client::new()
+            .post(&x.proxy_endpoint)
+            .json(&batch)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: Vec<serde_json::Value> = response.json().await.unwrap();
+
+        assert_eq!(response.len(), 10);
+
+        // the responses are in the same order as the requests
+        let ids: Vec<_> = response.iter().map(|x| x["id"].clone()).collect();
+        let expected_ids: Vec<_> = batch.iter().map(|x| x["id"].clone()).collect();
+
+        assert_eq!(ids, expected_ids);
+
+        let (errors, successes): (Vec<_>, Vec<_>) =
+            response.iter().partition(|x| x.get("error").is_some());
+
+        assert_eq!(successes.len(), 9);
+        assert!(successes.iter().all(|x| x.get("result").is_some()));
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["id"], json!(10));
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_skips_the_cache_with_no_cache() {
+        let x = TestApp::spawn().await;
+
+        let client = reqwest::Client::new();
+
+        let get_balance = |address: &'static str, no_cache: bool| {
+            let mut request = client.post(&x.proxy_endpoint).json(&json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getBalance",
+                "params": [address, "latest"],
+                "id": 1,
+            }));
+
+            if no_cache {
+                request = request.header("X-No-Cache", "true");
+            }
+
+            async move {
+                let response = request.send().await.unwrap();
+
+                let cache = response.headers()["X-Cache"].to_str().unwrap().to_string();
+
+                let body: serde_json::Value = response.json().await.unwrap();
+
+                (body["result"].clone(), cache)
+            }
+        };
+
+        let set_balance = |address: &'static str, balance: &'static str| async move {
+            // this doesn't mine a block, so the proxy's cache key doesn't change
+            let _: () = x
+                .anvil_provider
+                .request("anvil_setBalance", (address, balance))
+                .await
+                .unwrap();
+        };
+
+        let cached = "0x0000000000000000000000000000000000000c0c";
+
+        set_balance(cached, "0x1").await;
+
+        assert_eq!(
+            get_balance(cached, false).await,
+            (json!("0x1"), "MISS".to_string())
+        );
+        assert_eq!(
+            get_balance(cached, false).await,
+            (json!("0x1"), "HIT".to_string())
+        );
+
+        set_balance(cached, "0x2").await;
+
+        // the cache is bypassed
+        assert_eq!(
+            get_balance(cached, true).await,
+            (json!("0x2"), "MISS".to_string())
+        );
+
+        // and it is left alone
+        assert_eq!(
+            get_balance(cached, false).await,
+            (json!("0x1"), "HIT".to_string())
+        );
+
+        let uncached = "0x000000000000000000000000000000000000ca5e";
+
+        set_balance(uncached, "0x3").await;
+
+        assert_eq!(
+            get_balance(uncached, true).await,
+            (json!("0x3"), "MISS".to_string())
+        );
+
+        set_balance(uncached, "0x4").await;
+
+        // the response to the no-cache request was never written to the cache
+        assert_eq!(
+            get_balance(uncached, false).await,
+            (json!("0x4"), "MISS".to_string())
+        );
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_sends_private_methods_to_the_private_rpcs() {
+        let private_anvil = Anvil::new().spawn();
+
+        let private_anvil_provider = Provider::<Http>::try_from(private_anvil.endpoint()).unwrap();
+
+        let x = TestApp::spawn_with(|top_config| {
+            top_config.app.private_methods = vec!["eth_getTransactionByHash".to_string()];
+            top_config.private_rpcs = Some(HashMap::from([(
+                "anvil_private".to_string(),
+                Web3RpcConfig {
+                    http_url: Some(private_anvil.endpoint()),
+                    ..Default::default()
+                },
+            )]));
+        })
+        .await;
+
+        // only the private rpc has this transaction
+        let tx_hash: H256 = private_anvil_provider
+            .request(
+                "eth_sendTransaction",
+                [json!({
+                    "from": private_anvil.addresses()[0],
+                    "to": "0x000000000000000000000000000000000000beef",
+                    "value": "0x1",
+                })],
+            )
+            .await
+            .unwrap();
+
+        let balanced_tx: Option<serde_json::Value> = x
+            .anvil_provider
+            .request("eth_getTransactionByHash", [tx_hash])
+            .await
+            .unwrap();
+
+        assert!(balanced_tx.is_none());
+
+        let proxy_tx: Option<serde_json::Value> = x
+            .proxy_provider
+            .request("eth_getTransactionByHash", [tx_hash])
+            .await
+            .unwrap();
+
+        assert_eq!(proxy_tx.unwrap()["hash"], json!(tx_hash));
+
+        // other methods still go to the balanced rpcs
+        let balanced_block_num: U256 = x
+            .anvil_provider
+            .request("eth_blockNumber", ())
+            .await
+            .unwrap();
+
+        let proxy_block_num: U256 = x
+            .proxy_provider
+            .request("eth_blockNumber", ())
+            .await
+            .unwrap();
+
+        assert_eq!(balanced_block_num, proxy_block_num);
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_caches_confirmed_trace_replays() {
+        let replays = Arc::new(AtomicUsize::new(0));
+
+        let x = TestApp::spawn_with(|top_config| {
+            top_config.app.trace_replay_cache_confirmations = Some(2);
+
+            // anvil can't replay transactions. this answers for it and passes everything else through
+            let anvil_rpc = top_config.balanced_rpcs.get_mut("anvil_both").unwrap();
+
+            let anvil_url = anvil_rpc.http_url.take().unwrap();
+            let client = reqwest::Client::new();
+            let replays = replays.clone();
+
+            let router = Router::new().route(
+                "/",
+                post(move |Json(request): Json<serde_json::Value>| {
+                    let anvil_url = anvil_url.clone();
+                    let client = client.clone();
+                    let replays = replays.clone();
+
+                    async move {
+                        if request["method"] == "trace_replayTransaction" {
+                            replays.fetch_add(1, Ordering::SeqCst);
+
+                            return Json(json!({
+                                "jsonrpc": "2.0",
+                                "id": request["id"],
+                                "result": {"output": "0x", "trace": [], "vmTrace": null, "stateDiff": null},
+                            }));
+                        }
+
+                        let response: serde_json::Value = client
+                            .post(&anvil_url)
+                            .json(&request)
+                            .send()
+                            .await
+                            .unwrap()
+                            .json()
+                            .await
+                            .unwrap();
+
+                        Json(response)
+                    }
+                }),
+            );
+
+            let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+                .serve(router.into_make_service());
+
+            anvil_rpc.http_url = Some(format!("http://{}", server.local_addr()));
+
+            tokio::spawn(server);
+        })
+        .await;
+
+        let accounts: Vec<Address> = x.anvil_provider.request("eth_accounts", ()).await.unwrap();
+
+        let tx_hash: H256 = x
+            .anvil_provider
+            .request(
+                "eth_sendTransaction",
+                [json!({
+                    "from": accounts[0],
+                    "to": "0x000000000000000000000000000000000000beef",
+                    "value": "0x1",
+                })],
+            )
+            .await
+            .unwrap();
+
+        let trace_replay = || {
+            x.proxy_provider
+                .request::<_, serde_json::Value>("trace_replayTransaction", (tx_hash, ["trace"]))
+        };
+
+        // the transaction is in the head block. it could still be reorged, so every replay goes to the backend
+        trace_replay().await.unwrap();
+        trace_replay().await.unwrap();
+
+        assert_eq!(replays.load(Ordering::SeqCst), 2);
+
+        for _ in 0..2 {
+            let _: U256 = x.anvil_provider.request("evm_mine", ()).await.unwrap();
+        }
+
+        let anvil_block_num: U256 = x
+            .anvil_provider
+            .request("eth_blockNumber", ())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        loop {
+            if start.elapsed() > Duration::from_secs(5) {
+                panic!("took too long to sync!");
+            }
+
+            let proxy_block_num: U256 = x
+                .proxy_provider
+                .request("eth_blockNumber", ())
+                .await
+                .unwrap();
+
+            if proxy_block_num == anvil_block_num {
+                break;
+            }
+
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // now that it is confirmed, only the first replay goes to the backend
+        let first = trace_replay().await.unwrap();
+        let second = trace_replay().await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(replays.load(Ordering::SeqCst), 3);
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_counts_a_retried_error_once() {
+        let x = TestApp::spawn_with(|top_config| {
+            top_config.app.raw_tx_validation = RawTxValidation::Basic;
+        })
+        .await;
+
+        // no other test sends bad requests, so nothing else changes this count while we wait
+        let before = ERROR_RESPONSES.get("BadRequest");
+
+        // proxy_request tries this 3 times before giving up
+        let response = reqwest::Client::new()
+            .post(&x.proxy_endpoint)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "method": "eth_sendRawTransaction",
+                "params": ["0xnothex"],
+                "id": 1,
+            }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(ERROR_RESPONSES.get("BadRequest"), before + 1);
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_uses_its_own_strict_jsonrpc() {
+        let strict = TestApp::spawn_with(|top_config| {
+            top_config.app.strict_jsonrpc = true;
+        })
+        .await;
+        let lenient = TestApp::spawn().await;
+
+        // some clients leave out the version
+        let request = json!({"method": "eth_chainId", "id": 1});
+
+        let post = |proxy_endpoint: &str| {
+            reqwest::Client::new()
+                .post(proxy_endpoint)
+                .json(&request)
+                .send()
+        };
+
+        // both apps are in this process. each one uses its own config
+        assert_eq!(
+            post(&strict.proxy_endpoint).await.unwrap().status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post(&lenient.proxy_endpoint).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        strict.wait().await;
+        lenient.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_fails_over_reads_without_retries() {
+        let flaky_balances = Arc::new(AtomicUsize::new(0));
+
+        let x = TestApp::spawn_with(|top_config| {
+            // retries are only the extra rounds after every rpc failed. failing over doesn't need them
+            top_config.app.request_retries = 0;
+
+            // this rpc follows the chain but can't answer eth_getBalance
+            let anvil_url = top_config.balanced_rpcs["anvil_both"]
+                .http_url
+                .clone()
+                .unwrap();
+            let client = reqwest::Client::new();
+            let flaky_balances = flaky_balances.clone();
+
+            let router = Router::new().route(
+                "/",
+                post(move |Json(request): Json<serde_json::Value>| {
+                    let anvil_url = anvil_url.clone();
+                    let client = client.clone();
+                    let flaky_balances = flaky_balances.clone();
+
+                    async move {
+                        if request["method"] == "eth_getBalance" {
+                            flaky_balances.fetch_add(1, Ordering::SeqCst);
+
+                            return StatusCode::BAD_GATEWAY.into_response();
+                        }
+
+                        let response: serde_json::Value = client
+                            .post(&anvil_url)
+                            .json(&request)
+                            .send()
+                            .await
+                            .unwrap()
+                            .json()
+                            .await
+                            .unwrap();
+
+                        Json(response).into_response()
+                    }
+                }),
+            );
+
+            let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+                .serve(router.into_make_service());
+
+            top_config.balanced_rpcs.insert(
+                "flaky".to_string(),
+                Web3RpcConfig {
+                    http_url: Some(format!("http://{}", server.local_addr())),
+                    ..Default::default()
+                },
+            );
+
+            tokio::spawn(server);
+        })
+        .await;
+
+        // the rpcs are load balanced. keep asking until the flaky one gets a request
+        let start = Instant::now();
+        let mut i = 0u64;
+        while flaky_balances.load(Ordering::SeqCst) == 0 {
+            if start.elapsed() > Duration::from_secs(10) {
+                panic!("the flaky rpc never got a request!");
+            }
+
+            // a different address every time so that the response cache doesn't answer
+            i += 1;
+            let address = Address::from_low_u64_be(i);
+
+            let balance: U256 = x
+                .proxy_provider
+                .request("eth_getBalance", (address, "latest"))
+                .await
+                .unwrap();
+
+            assert_eq!(balance, U256::zero());
+        }
+
+        x.wait().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn it_polls_filters() {
+        let x = TestApp::spawn_with(|top_config| {
+            top_config.app.filter_ttl = 2;
+        })
+        .await;
+
+        // the whole response so that errors can be checked too
+        async fn request(
+            proxy_endpoint: &str,
+            method: &str,
+            params: serde_json::Value,
+        ) -> serde_json::Value {
+            reqwest::Client::new()
+                .post(proxy_endpoint)
+                .json(&json!({
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params,
+                    "id": 1,
+                }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap()
+        }
+
+        let filter_not_found = json!({"code": -32000, "message": "filter not found"});
+
+        // a filter that never existed
+        let response = request(&x.proxy_endpoint, "eth_getFilterChanges", json!(["0x1234"])).await;
+        assert_eq!(response["error"], filter_not_found);
+
+        let response = request(&x.proxy_endpoint, "eth_newBlockFilter", json!([])).await;
+        let filter_id = response["result"].as_str().unwrap().to_string();
+
+        // no new blocks yet
+        let response = request(
+            &x.proxy_endpoint,
+            "eth_getFilterChanges",
+            json!([filter_id]),
+        )
+        .await;
+        assert_eq!(response["result"], json!([]));
+
+        let first_block_num: U256 = x
+            .proxy_provider
+            .request("eth_blockNumber", ())
+            .await
+            .unwrap();
+
+        let _: U256 = x.anvil_provider.request("evm_mine", ()).await.unwrap();
+
+        let start = Instant::now();
+        loop {
+            if start.elapsed() > Duration::from_secs(1) {
+                panic!("took too long to sync!");
+            }
+
+            let proxy_block_num: U256 = x
+                .proxy_provider
+                .request("eth_blockNumber", ())
+                .await
+                .unwrap();
+
+            if proxy_block_num > first_block_num {
+                break;
+            }
+
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let new_block = x
+            .anvil_provider
+            .request::<_, Option<ArcBlock>>("eth_getBlockByNumber", ("latest", false))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let response = request(
+            &x.proxy_endpoint,
+            "eth_getFilterChanges",
+            json!([filter_id]),
+        )
+        .await;
+        assert_eq!(response["result"], json!([new_block.hash.unwrap()]));
+
+        // the block was already returned
+        let response = request(
+            &x.proxy_endpoint,
+            "eth_getFilterChanges",
+            json!([filter_id]),
+        )
+        .await;
+        assert_eq!(response["result"], json!([]));
+
+        let response = request(&x.proxy_endpoint, "eth_uninstallFilter", json!([filter_id])).await;
+        assert_eq!(response["result"], json!(true));
+
+        let response = request(&x.proxy_endpoint, "eth_uninstallFilter", json!([filter_id])).await;
+        assert_eq!(response["result"], json!(false));
+
+        let response = request(
+            &x.proxy_endpoint,
+            "eth_getFilterChanges",
+            json!([filter_id]),
+        )
+        .await;
+        assert_eq!(response["error"], filter_not_found);
+
+        // filters that aren't polled expire
+        let response = request(&x.proxy_endpoint, "eth_newBlockFilter", json!([])).await;
+        let filter_id = response["result"].as_str().unwrap().to_string();
+
+        sleep(Duration::from_secs(3)).await;
+
+        let response = request(
+            &x.proxy_endpoint,
+            "eth_getFilterChanges",
+            json!([filter_id]),
+        )
+        .await;
+        assert_eq!(response["error"], filter_not_found);
+
+        x.wait().await;
+    }
 }
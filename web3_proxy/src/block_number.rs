@@ -9,20 +9,97 @@ use ethers::{
     prelude::{BlockNumber, U64},
     types::H256,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tracing::{trace, warn, error};
+use tracing::{error, trace, warn};
 
 use crate::{frontend::authorization::Authorization, rpcs::many::Web3Rpcs};
 
+/// The latest "safe" and "finalized" blocks reported by the backends.
+/// These are unknown on chains without finality and until the backends have been asked.
+#[derive(Debug, Default)]
+pub struct FinalizedBlocks {
+    /// 0 if unknown
+    safe: AtomicU64,
+    /// 0 if unknown
+    finalized: AtomicU64,
+    /// don't ask the backends again until this head block
+    next_refresh: AtomicU64,
+    /// refreshes in a row that didn't find either block
+    failures: AtomicU32,
+}
+
+impl FinalizedBlocks {
+    pub fn safe(&self) -> Option<U64> {
+        match self.safe.load(Ordering::Acquire) {
+            0 => None,
+            x => Some(x.into()),
+        }
+    }
+
+    pub fn finalized(&self) -> Option<U64> {
+        match self.finalized.load(Ordering::Acquire) {
+            0 => None,
+            x => Some(x.into()),
+        }
+    }
+
+    /// these only move forward. a lagging backend won't move them back
+    pub fn update(&self, safe: Option<U64>, finalized: Option<U64>) {
+        if let Some(safe) = safe {
+            self.safe.fetch_max(safe.as_u64(), Ordering::AcqRel);
+        }
+
+        if let Some(finalized) = finalized {
+            self.finalized
+                .fetch_max(finalized.as_u64(), Ordering::AcqRel);
+        }
+    }
+
+    /// false while backing off. chains without finality fail every time, so don't ask on every head block
+    pub fn should_refresh(&self, head_block_num: &U64) -> bool {
+        head_block_num.as_u64() >= self.next_refresh.load(Ordering::Acquire)
+    }
+
+    /// After repeated failures, wait twice as many blocks before asking again. Up to 256 blocks.
+    pub fn refreshed(&self, head_block_num: &U64, found: bool) {
+        let wait = if found {
+            self.failures.store(0, Ordering::Release);
+
+            1
+        } else {
+            let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+
+            1 << failures.min(8)
+        };
+
+        self.next_refresh
+            .store(head_block_num.as_u64() + wait, Ordering::Release);
+    }
+}
+
+/// "safe" and "finalized" become a concrete number once the backends have reported them.
+/// Blocks that old won't change, so the response can be cached like any other old block.
+/// If they aren't known, the backend sees the tag and the response is cached with the head block.
+fn tagged_block(tagged: Option<U64>, latest_block: &U64) -> (U64, bool) {
+    match tagged {
+        Some(x) if x <= *latest_block => (x, true),
+        _ => (*latest_block, false),
+    }
+}
+
 #[allow(non_snake_case)]
-pub fn BlockNumber_to_U64(block_num: BlockNumber, latest_block: &U64) -> (U64, bool) {
+pub fn BlockNumber_to_U64(
+    block_num: BlockNumber,
+    latest_block: &U64,
+    finalized_blocks: &FinalizedBlocks,
+) -> (U64, bool) {
     match block_num {
         BlockNumber::Earliest => (U64::zero(), false),
-        BlockNumber::Finalized => {
-            warn!("finalized block requested! not yet implemented!");
-            (*latest_block - 10, false)
-        }
+        BlockNumber::Finalized => tagged_block(finalized_blocks.finalized(), latest_block),
         BlockNumber::Latest => {
             // change "latest" to a number
             (*latest_block, true)
@@ -36,14 +113,11 @@ pub fn BlockNumber_to_U64(block_num: BlockNumber, latest_block: &U64) -> (U64, b
             // TODO: think more about how to handle Pending
             (*latest_block, false)
         }
-        BlockNumber::Safe => {
-            warn!("safe block requested! not yet implemented!");
-            (*latest_block - 3, false)
-        }
+        BlockNumber::Safe => tagged_block(finalized_blocks.safe(), latest_block),
     }
 }
 
-#[derive(Clone, Debug, Eq, From, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, From, PartialEq, Serialize)]
 pub struct BlockNumAndHash(U64, H256);
 
 impl BlockNumAndHash {
@@ -113,8 +187,11 @@ pub async fn clean_block_number(
                     // TODO: "BlockNumber" needs a better name
                     // TODO: move this to a helper function?
                     if let Ok(block_number) = serde_json::from_value::<BlockNumber>(x.clone()) {
-                        let (block_num, change) =
-                            BlockNumber_to_U64(block_number, latest_block.number());
+                        let (block_num, change) = BlockNumber_to_U64(
+                            block_number,
+                            latest_block.number(),
+                            &rpcs.finalized_blocks,
+                        );
 
                         let (block_hash, _) = rpcs
                             .block_hash(authorization, &block_num)
@@ -141,7 +218,7 @@ pub async fn clean_block_number(
                     }
                 };
 
-                // if we changed "latest" to a number, update the params to match
+                // if we changed "latest" (or "safe" or "finalized") to a number, update the params to match
                 if change {
                     trace!(old=%x, new=%block.hash(), "changing block number");
                     *x = json!(block.hash());
@@ -153,6 +230,33 @@ pub async fn clean_block_number(
     }
 }
 
+/// Addresses are case-insensitive. Lowercase them so that checksummed and lowercase requests share a cache entry
+fn lowercase_address_param(params: &mut serde_json::Value, address_param_id: usize) {
+    if let Some(serde_json::Value::String(address)) = params.get_mut(address_param_id) {
+        address.make_ascii_lowercase();
+    }
+}
+
+/// Replace a block number param with the hash of that block.
+/// The state at a block hash never changes, so every request for the same block shares a cache entry.
+/// Tags like "pending" are left alone. "latest" was already replaced by clean_block_number.
+/// Numbers for any other block are left alone too. That happens when the block couldn't be looked up
+fn pin_block_param_to_hash(
+    params: &mut serde_json::Value,
+    block_param_id: usize,
+    block: &BlockNumAndHash,
+) {
+    let Some(x) = params.get_mut(block_param_id) else {
+        return;
+    };
+
+    if let Ok(BlockNumber::Number(num)) = serde_json::from_value::<BlockNumber>(x.clone()) {
+        if num == *block.num() {
+            *x = json!(block.hash());
+        }
+    }
+}
+
 /// TODO: change this to also return the hash needed?
 pub enum CacheMode {
     CacheSuccessForever,
@@ -214,7 +318,7 @@ impl CacheMode {
         let block_param_id = match method {
             "eth_call" => 1,
             "eth_estimateGas" => 1,
-            "eth_getBalance" => 1,
+            "eth_getBalance" | "eth_getCode" => 1,
             "eth_getBlockByHash" => {
                 // TODO: double check that any node can serve this
                 // TODO: can a block change? like what if it gets orphaned?
@@ -234,7 +338,6 @@ impl CacheMode {
                 return Ok(CacheMode::CacheSuccessForever);
             }
             "eth_getBlockTransactionCountByNumber" => 0,
-            "eth_getCode" => 1,
             "eth_getLogs" => {
                 // TODO: think about this more
                 // TODO: jsonrpc has a specific code for this
@@ -254,8 +357,11 @@ impl CacheMode {
                         // what if its a hash?
                         let block_num: BlockNumber = serde_json::from_value(x.clone())?;
 
-                        let (block_num, change) =
-                            BlockNumber_to_U64(block_num, head_block.number());
+                        let (block_num, change) = BlockNumber_to_U64(
+                            block_num,
+                            head_block.number(),
+                            &rpcs.finalized_blocks,
+                        );
 
                         if change {
                             // TODO: include the hash instead of the number?
@@ -276,8 +382,11 @@ impl CacheMode {
                         // what if its a hash?
                         let block_num: BlockNumber = serde_json::from_value(x.clone())?;
 
-                        let (block_num, change) =
-                            BlockNumber_to_U64(block_num, head_block.number());
+                        let (block_num, change) = BlockNumber_to_U64(
+                            block_num,
+                            head_block.number(),
+                            &rpcs.finalized_blocks,
+                        );
 
                         if change {
                             trace!("changing toBlock in eth_getLogs. {} -> {}", x, block_num);
@@ -298,6 +407,21 @@ impl CacheMode {
                     });
                 }
             }
+            "eth_getProof" => {
+                // proofs are large. only cache them at blocks that won't change
+                match params
+                    .get(2)
+                    .map(|x| serde_json::from_value::<BlockNumber>(x.clone()))
+                {
+                    None
+                    | Some(Ok(BlockNumber::Latest | BlockNumber::Pending | BlockNumber::Safe)) => {
+                        return Ok(CacheMode::CacheNever);
+                    }
+                    _ => {}
+                }
+
+                2
+            }
             "eth_getStorageAt" => 2,
             "eth_getTransactionByHash" => {
                 // TODO: not sure how best to look these up
@@ -360,4 +484,295 @@ impl CacheMode {
             }
         }
     }
+
+    /// The params to build the cache key with. The params sent to the backends are not changed.
+    /// eth_getBalance, eth_getCode, and eth_getProof only depend on the address and the block.
+    /// Lowercasing the address and using the block's hash lets every equivalent request share a cache entry.
+    /// A concrete block caches until it is evicted. "latest" was pinned to the head block, so it stops matching at the next head
+    pub fn cache_key_params<'a>(
+        &self,
+        method: &str,
+        params: &'a serde_json::Value,
+    ) -> Cow<'a, serde_json::Value> {
+        let block_param_id = match method {
+            "eth_getBalance" | "eth_getCode" => 1,
+            "eth_getProof" => 2,
+            _ => return Cow::Borrowed(params),
+        };
+
+        let mut params = params.clone();
+
+        lowercase_address_param(&mut params, 0);
+
+        if let Self::Cache { block, .. } = self {
+            pin_block_param_to_hash(&mut params, block_param_id, block);
+        }
+
+        Cow::Owned(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response_cache::JsonRpcQueryCacheKey;
+    use ethers::types::Block;
+    use hashbrown::HashSet;
+
+    #[test]
+    fn test_account_state_params_share_cache_keys() {
+        let block = BlockNumAndHash(U64::from(17_000_000), H256::repeat_byte(1));
+
+        // a wallet polling a balance a few different ways. "latest" has already been replaced with the head hash
+        let requests = [
+            json!(["0xAb5801a7D398351b8bE11C439e05C5B3259aeC9B", "0x1036640"]),
+            json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "0x1036640"]),
+            json!(["0xAb5801a7D398351b8bE11C439e05C5B3259aeC9B", block.hash()]),
+            json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", block.hash()]),
+        ];
+
+        let keys_before: HashSet<u64> = requests
+            .iter()
+            .map(|params| {
+                JsonRpcQueryCacheKey::new(Some(block.clone()), None, "eth_getBalance", params, true)
+                    .hash()
+            })
+            .collect();
+
+        let keys_after: HashSet<u64> = requests
+            .iter()
+            .map(|params| {
+                let mut params = params.clone();
+
+                lowercase_address_param(&mut params, 0);
+                pin_block_param_to_hash(&mut params, 1, &block);
+
+                JsonRpcQueryCacheKey::new(
+                    Some(block.clone()),
+                    None,
+                    "eth_getBalance",
+                    &params,
+                    true,
+                )
+                .hash()
+            })
+            .collect();
+
+        // 1 miss and 3 hits instead of 4 misses
+        assert_eq!(keys_before.len(), 4);
+        assert_eq!(keys_after.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_params_leave_forwarded_params_alone() {
+        let block_0 = Block {
+            number: Some(0.into()),
+            hash: Some(H256::repeat_byte(1)),
+            ..Default::default()
+        };
+        let block_1 = Block {
+            number: Some(1.into()),
+            hash: Some(H256::repeat_byte(2)),
+            parent_hash: H256::repeat_byte(1),
+            ..Default::default()
+        };
+
+        let blocks: Vec<_> = [block_0, block_1]
+            .into_iter()
+            .map(|x| Web3ProxyBlock::try_new(Arc::new(x)).unwrap())
+            .collect();
+
+        let head_block = blocks[1].clone();
+
+        let rpcs = Web3Rpcs::with_cached_blocks(blocks).await;
+
+        let authorization = Arc::new(Authorization::internal(None).unwrap());
+
+        let requests = [
+            json!(["0xAb5801a7D398351b8bE11C439e05C5B3259aeC9B", "0x1"]),
+            json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "0x1"]),
+            json!([
+                "0xAb5801a7D398351b8bE11C439e05C5B3259aeC9B",
+                head_block.hash()
+            ]),
+            json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "latest"]),
+        ];
+
+        let mut keys = HashSet::new();
+
+        for original in requests {
+            let mut params = original.clone();
+
+            let cache_mode = CacheMode::try_new(
+                &authorization,
+                "eth_getBalance",
+                &mut params,
+                &head_block,
+                &rpcs,
+            )
+            .await
+            .unwrap();
+
+            // the backends get the address and block number exactly as the user sent them
+            // "latest" is still replaced with the head block's hash
+            if original[1] == "latest" {
+                assert_eq!(params[0], original[0]);
+                assert_eq!(params[1], json!(head_block.hash()));
+            } else {
+                assert_eq!(params, original);
+            }
+
+            let CacheMode::Cache { block, .. } = &cache_mode else {
+                panic!("eth_getBalance at a known block should be cached");
+            };
+
+            assert_eq!(block.num(), head_block.number());
+
+            let key_params = cache_mode.cache_key_params("eth_getBalance", &params);
+
+            keys.insert(
+                JsonRpcQueryCacheKey::new(
+                    Some(block.clone()),
+                    None,
+                    "eth_getBalance",
+                    &key_params,
+                    true,
+                )
+                .hash(),
+            );
+        }
+
+        // 1 miss and 3 hits instead of 4 misses
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn test_other_block_numbers_are_not_pinned() {
+        let block = BlockNumAndHash(U64::from(17_000_000), H256::repeat_byte(1));
+
+        let mut params = json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "0x1"]);
+
+        pin_block_param_to_hash(&mut params, 1, &block);
+
+        assert_eq!(params[1], "0x1");
+    }
+
+    #[test]
+    fn test_pending_is_not_pinned() {
+        let block = BlockNumAndHash(U64::from(17_000_000), H256::repeat_byte(1));
+
+        let mut params = json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "pending"]);
+
+        pin_block_param_to_hash(&mut params, 1, &block);
+
+        assert_eq!(params[1], "pending");
+    }
+
+    #[tokio::test]
+    async fn test_finalized_is_cacheable() {
+        let finalized_blocks = FinalizedBlocks::default();
+
+        // unknown until the backends report it. the backends see the tag and the head block is used for the cache key
+        assert_eq!(
+            BlockNumber_to_U64(BlockNumber::Finalized, &U64::from(110), &finalized_blocks),
+            (U64::from(110), false)
+        );
+
+        let blocks: Vec<_> = (100..=111u64)
+            .map(|num| Block {
+                number: Some(num.into()),
+                hash: Some(H256::repeat_byte(num as u8)),
+                parent_hash: H256::repeat_byte(num as u8 - 1),
+                ..Default::default()
+            })
+            .map(|x| Web3ProxyBlock::try_new(Arc::new(x)).unwrap())
+            .collect();
+
+        let rpcs = Web3Rpcs::with_cached_blocks(blocks.clone()).await;
+
+        rpcs.finalized_blocks
+            .update(Some(U64::from(105)), Some(U64::from(100)));
+
+        // a lagging backend doesn't move it back
+        rpcs.finalized_blocks.update(None, Some(U64::from(90)));
+
+        let authorization = Arc::new(Authorization::internal(None).unwrap());
+
+        // the same request at different head blocks
+        let mut keys = HashSet::new();
+
+        for head_block in &blocks[10..] {
+            let mut params = json!(["0xab5801a7d398351b8be11c439e05c5b3259aec9b", "finalized"]);
+
+            let cache_mode = CacheMode::try_new(
+                &authorization,
+                "eth_getBalance",
+                &mut params,
+                head_block,
+                &rpcs,
+            )
+            .await
+            .unwrap();
+
+            let CacheMode::Cache { block, .. } = &cache_mode else {
+                panic!("eth_getBalance at the finalized block should be cached");
+            };
+
+            assert_eq!(block.num(), &U64::from(100));
+
+            // the tag is replaced with the finalized block's hash
+            assert_eq!(params[1], json!(blocks[0].hash()));
+
+            let key_params = cache_mode.cache_key_params("eth_getBalance", &params);
+
+            keys.insert(
+                JsonRpcQueryCacheKey::new(
+                    Some(block.clone()),
+                    None,
+                    "eth_getBalance",
+                    &key_params,
+                    true,
+                )
+                .hash(),
+            );
+        }
+
+        assert_eq!(keys.len(), 1);
+
+        assert_eq!(
+            BlockNumber_to_U64(BlockNumber::Safe, &U64::from(110), &rpcs.finalized_blocks),
+            (U64::from(105), true)
+        );
+    }
+
+    #[test]
+    fn test_finalized_refresh_backs_off() {
+        let finalized_blocks = FinalizedBlocks::default();
+
+        assert!(finalized_blocks.should_refresh(&U64::from(100)));
+
+        // a chain without finality. wait 2, then 4, then 8 blocks
+        finalized_blocks.refreshed(&U64::from(100), false);
+        assert!(!finalized_blocks.should_refresh(&U64::from(101)));
+        assert!(finalized_blocks.should_refresh(&U64::from(102)));
+
+        finalized_blocks.refreshed(&U64::from(102), false);
+        assert!(!finalized_blocks.should_refresh(&U64::from(105)));
+        assert!(finalized_blocks.should_refresh(&U64::from(106)));
+
+        finalized_blocks.refreshed(&U64::from(106), false);
+        assert!(!finalized_blocks.should_refresh(&U64::from(113)));
+        assert!(finalized_blocks.should_refresh(&U64::from(114)));
+
+        // the wait is capped
+        for _ in 0..20 {
+            finalized_blocks.refreshed(&U64::from(1000), false);
+        }
+        assert!(!finalized_blocks.should_refresh(&U64::from(1255)));
+        assert!(finalized_blocks.should_refresh(&U64::from(1256)));
+
+        // once the blocks are found, every head block refreshes them again
+        finalized_blocks.refreshed(&U64::from(1256), true);
+        assert!(finalized_blocks.should_refresh(&U64::from(1257)));
+    }
 }
@@ -0,0 +1,233 @@
+//! Optional eth_callBundle support through an external simulation service.
+//!
+//! Normal nodes can't simulate bundles. mev-geth style builders and relays can.
+//! The bundle is checked here and then the original params are forwarded to the simulator, including any fields that aren't checked here.
+//! Its results are returned to the user.
+//! Relays that require a signed X-Flashbots-Signature header are not supported. Use a builder or simulator that doesn't.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::jsonrpc::JsonRpcErrorData;
+use ethers::types::{BlockNumber, Bytes, U64};
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The params for `eth_callBundle` that are checked before simulating.
+/// Simulators take more fields than these (coinbase, gasLimit, baseFee, ...). Those are passed through unchecked
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallBundleParams {
+    /// signed transactions to run in order
+    pub txs: Vec<Bytes>,
+    /// the block the bundle is simulated as being in
+    pub block_number: U64,
+    /// the state to simulate on top of. usually "latest"
+    pub state_block_number: BlockNumber,
+    /// override the timestamp of the simulated block
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+impl CallBundleParams {
+    pub fn try_from_params(params: &serde_json::Value) -> Web3ProxyResult<Self> {
+        let bundle = params
+            .get(0)
+            .ok_or_else(|| Web3ProxyError::BadRequest("expected a bundle object".into()))?;
+
+        let bundle: Self = serde_json::from_value(bundle.clone()).map_err(|err| {
+            Web3ProxyError::BadRequest(format!("invalid eth_callBundle params: {}", err).into())
+        })?;
+
+        if bundle.txs.is_empty() {
+            return Err(Web3ProxyError::BadRequest(
+                "eth_callBundle needs at least one transaction".into(),
+            ));
+        }
+
+        Ok(bundle)
+    }
+}
+
+#[derive(Deserialize)]
+struct SimulatorResponse {
+    result: Option<Arc<RawValue>>,
+    error: Option<JsonRpcErrorData>,
+}
+
+pub struct BundleSimulator {
+    client: reqwest::Client,
+    timeout: Duration,
+    url: String,
+}
+
+impl BundleSimulator {
+    pub fn new(url: String, timeout: Duration, http_client: Option<reqwest::Client>) -> Self {
+        Self {
+            client: http_client.unwrap_or_default(),
+            timeout,
+            url,
+        }
+    }
+
+    /// Simulate the bundle. The params should already be checked with `CallBundleParams::try_from_params`.
+    /// Rejections from the simulator are returned to the user as is.
+    pub async fn call_bundle(&self, params: &serde_json::Value) -> Web3ProxyResult<Arc<RawValue>> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_callBundle",
+            "params": params,
+        });
+
+        let response: SimulatorResponse = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|err| {
+                // the url might have an api key in it
+                Web3ProxyError::BadResponse(
+                    format!("bundle simulator failed: {}", err.without_url()).into(),
+                )
+            })?
+            .json()
+            .await
+            .map_err(|err| {
+                Web3ProxyError::BadResponse(
+                    format!("invalid bundle simulator response: {}", err.without_url()).into(),
+                )
+            })?;
+
+        match (response.result, response.error) {
+            (_, Some(error_data)) => Err(Web3ProxyError::JsonRpcErrorData(error_data)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(Web3ProxyError::BadResponse(
+                "bundle simulator returned no result".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mock_server;
+    use axum::routing::post;
+    use axum::{Json, Router};
+
+    fn mock_simulator() -> String {
+        let router = Router::new().route(
+            "/",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                assert_eq!(request["method"], "eth_callBundle");
+
+                let bundle = &request["params"][0];
+
+                // fields that the proxy doesn't check are still forwarded
+                assert_eq!(
+                    bundle["coinbase"],
+                    "0x0000000000000000000000000000000000000001"
+                );
+
+                if bundle["blockNumber"] == "0x10" {
+                    Json(json!({
+                        "jsonrpc": "2.0",
+                        "id": 1,
+                        "result": {"bundleHash": "0x01", "results": [], "stateBlockNumber": 15},
+                    }))
+                } else {
+                    Json(json!({
+                        "jsonrpc": "2.0",
+                        "id": 1,
+                        "error": {"code": -32000, "message": "block in the past"},
+                    }))
+                }
+            }),
+        );
+
+        mock_server(router)
+    }
+
+    #[test]
+    fn test_call_bundle_params() {
+        let params = json!([{
+            "txs": ["0x02f8", "0x02f9"],
+            "blockNumber": "0x10",
+            "stateBlockNumber": "latest",
+        }]);
+
+        let bundle = CallBundleParams::try_from_params(&params).unwrap();
+
+        assert_eq!(bundle.txs.len(), 2);
+        assert_eq!(bundle.block_number, U64::from(16));
+        assert_eq!(bundle.state_block_number, BlockNumber::Latest);
+        assert_eq!(bundle.timestamp, None);
+
+        assert!(matches!(
+            CallBundleParams::try_from_params(&json!([])),
+            Err(Web3ProxyError::BadRequest(_))
+        ));
+        assert!(matches!(
+            CallBundleParams::try_from_params(&json!([{"txs": ["0x02f8"]}])),
+            Err(Web3ProxyError::BadRequest(_))
+        ));
+        assert!(matches!(
+            CallBundleParams::try_from_params(&json!([{
+                "txs": [],
+                "blockNumber": "0x10",
+                "stateBlockNumber": "latest",
+            }])),
+            Err(Web3ProxyError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bundle_simulator() {
+        let simulator = BundleSimulator::new(mock_simulator(), Duration::from_secs(5), None);
+
+        let mut params = json!([{
+            "txs": ["0x02f8"],
+            "blockNumber": "0x10",
+            "stateBlockNumber": "latest",
+            "coinbase": "0x0000000000000000000000000000000000000001",
+        }]);
+
+        CallBundleParams::try_from_params(&params).unwrap();
+
+        let result = simulator.call_bundle(&params).await.unwrap();
+
+        let result: serde_json::Value = serde_json::from_str(result.get()).unwrap();
+
+        assert_eq!(result["bundleHash"], "0x01");
+
+        // the simulator's rejection is passed through
+        params[0]["blockNumber"] = json!("0x1");
+
+        match simulator.call_bundle(&params).await.unwrap_err() {
+            Web3ProxyError::JsonRpcErrorData(x) => assert_eq!(x.message, "block in the past"),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bundle_simulator_error_hides_url() {
+        // nothing is listening here
+        let simulator = BundleSimulator::new(
+            "http://127.0.0.1:1/secret-api-key".to_string(),
+            Duration::from_secs(5),
+            None,
+        );
+
+        let params =
+            json!([{"txs": ["0x02f8"], "blockNumber": "0x10", "stateBlockNumber": "latest"}]);
+
+        match simulator.call_bundle(&params).await.unwrap_err() {
+            Web3ProxyError::BadResponse(x) => assert!(!x.contains("secret-api-key")),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Retry eth_call with less gas when a backend rejects the gas param.
+//!
+//! geth quietly caps the gas of an eth_call, but some backends reject calls above their per-call cap instead.
+//! Clients often send far more gas than they need, so those calls can usually succeed with less.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::jsonrpc::JsonRpcErrorData;
+use crate::response_cache::JsonRpcResponseEnum;
+use ethers::types::{U256, U64};
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::sync::Arc;
+
+/// Only these rejections are retried. Any other error about gas is a real error for the user.
+const GAS_TOO_HIGH_MESSAGES: &[&str] = &[
+    "gas limit is too high",
+    "gas limit too high",
+    "gas too high",
+];
+
+/// What to do when a backend rejects eth_call because the gas param is above its cap.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GasTooHighMode {
+    /// return the backend's error to the user
+    #[default]
+    Error,
+    /// retry with the gas lowered to eth_call_gas_cap
+    Clamp,
+    /// retry without any gas so the backend uses its default
+    Omit,
+}
+
+/// true if the backend rejected the call because of its gas param.
+/// cached responses have the error as data. uncached responses have it as an Err
+pub fn is_gas_too_high(
+    response_data: &Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>>,
+) -> bool {
+    let error_data = match response_data {
+        Ok(JsonRpcResponseEnum::RpcError { error_data, .. }) => Some(error_data.clone()),
+        Ok(JsonRpcResponseEnum::Result { .. }) => None,
+        Err(Web3ProxyError::EthersProvider(err)) => JsonRpcErrorData::try_from(err).ok(),
+        Err(Web3ProxyError::EthersHttpClient(err)) => JsonRpcErrorData::try_from(err).ok(),
+        Err(_) => None,
+    };
+
+    let Some(error_data) = error_data else {
+        return false;
+    };
+
+    let message = error_data.message.to_lowercase();
+
+    GAS_TOO_HIGH_MESSAGES.iter().any(|x| message.contains(x))
+}
+
+/// The params to retry eth_call with. None if the call should not be retried.
+pub fn retry_params(
+    params: &serde_json::Value,
+    mode: GasTooHighMode,
+    gas_cap: u64,
+) -> Option<serde_json::Value> {
+    let gas = params.get(0)?.get("gas")?;
+
+    let mut params = params.clone();
+
+    let call = params.get_mut(0)?.as_object_mut()?;
+
+    match mode {
+        GasTooHighMode::Error => return None,
+        GasTooHighMode::Clamp => {
+            let gas: U256 = serde_json::from_value(gas.clone()).ok()?;
+
+            if gas <= U256::from(gas_cap) {
+                // the backend's cap must be lower than ours. the same request would fail again
+                return None;
+            }
+
+            call.insert("gas".to_string(), json!(U64::from(gas_cap)));
+        }
+        GasTooHighMode::Omit => {
+            call.remove("gas");
+        }
+    }
+
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a backend that rejects calls with more than 1,000,000 gas
+    fn backend(params: &serde_json::Value) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let gas: Option<U256> = params[0]
+            .get("gas")
+            .map(|x| serde_json::from_value(x.clone()).unwrap());
+
+        if gas.map(|x| x > 1_000_000.into()).unwrap_or(false) {
+            let error_data = JsonRpcErrorData {
+                code: -32000,
+                message: "gas limit too high (max 1000000)".into(),
+                data: None,
+            };
+
+            Ok(error_data.into())
+        } else {
+            Ok(json!("0x").into())
+        }
+    }
+
+    #[test]
+    fn test_clamp_gas_too_high() {
+        let params = json!([{"to": "0x0000000000000000000000000000000000000000", "gas": "0x3b9aca00"}, "latest"]);
+
+        let response_data = backend(&params);
+
+        assert!(is_gas_too_high(&response_data));
+
+        // clamp
+        let clamped = retry_params(&params, GasTooHighMode::Clamp, 1_000_000).unwrap();
+
+        assert_eq!(clamped[0]["gas"], json!("0xf4240"));
+        assert_eq!(clamped[1], json!("latest"));
+
+        let response_data = backend(&clamped);
+
+        assert!(!is_gas_too_high(&response_data));
+        assert!(matches!(
+            response_data,
+            Ok(JsonRpcResponseEnum::Result { .. })
+        ));
+
+        // a cap that is still too high for the backend is not retried again
+        assert!(retry_params(&clamped, GasTooHighMode::Clamp, 1_000_000).is_none());
+
+        // omit
+        let omitted = retry_params(&params, GasTooHighMode::Omit, 1_000_000).unwrap();
+
+        assert!(omitted[0].get("gas").is_none());
+        assert!(matches!(
+            backend(&omitted),
+            Ok(JsonRpcResponseEnum::Result { .. })
+        ));
+
+        // error
+        assert!(retry_params(&params, GasTooHighMode::Error, 1_000_000).is_none());
+    }
+
+    #[test]
+    fn test_other_gas_errors_are_not_retried() {
+        let error_data = JsonRpcErrorData {
+            code: -32000,
+            message: "gas required exceeds allowance (30000000)".into(),
+            data: None,
+        };
+
+        assert!(!is_gas_too_high(&Ok(error_data.into())));
+    }
+}
@@ -0,0 +1,281 @@
+//! Daily and monthly compute unit quotas for users without a paid balance.
+//!
+//! Spent compute units are counted in redis so that every proxy shares the same totals.
+//! The quotas are rolling windows of the last day and the last 30 days. They do not reset at midnight or at the start of a month.
+//! Each window is counted in a key for the current period and a key for the previous period.
+//! The previous period's count is weighted by how much of it is still inside the rolling window.
+//!
+//! A request reserves its compute units before it is sent. INCRBY is atomic, so concurrent requests can't all fit under the same remaining quota.
+//! If the reservation goes over the quota, it is refunded and the request is rejected.
+//! The cost of a request isn't exact until it has a response, so the difference is added once the request is done.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use derivative::Derivative;
+use redis_rate_limiter::redis;
+use redis_rate_limiter::RedisPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::trace;
+
+const DAY_SECONDS: u64 = 86_400;
+const MONTH_SECONDS: u64 = 30 * DAY_SECONDS;
+
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct ComputeUnitQuota {
+    chain_id: u64,
+    daily: Option<u64>,
+    monthly: Option<u64>,
+    #[derivative(Debug = "ignore")]
+    redis_pool: RedisPool,
+}
+
+/// One rolling window at a point in time
+#[derive(Debug, PartialEq)]
+struct Window {
+    current_key: String,
+    previous_key: String,
+    quota: u64,
+    /// how much of the previous period is still inside the rolling window
+    previous_weight: f64,
+    /// the current key is still needed while it is the previous key
+    ttl: usize,
+}
+
+impl ComputeUnitQuota {
+    /// None if neither quota is set
+    pub fn new(
+        chain_id: u64,
+        daily: Option<u64>,
+        monthly: Option<u64>,
+        redis_pool: RedisPool,
+    ) -> Option<Self> {
+        if daily.is_none() && monthly.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            chain_id,
+            daily,
+            monthly,
+            redis_pool,
+        })
+    }
+
+    fn windows(&self, user_id: u64, now: u64) -> Vec<Window> {
+        [
+            ("day", DAY_SECONDS, self.daily),
+            ("month", MONTH_SECONDS, self.monthly),
+        ]
+        .into_iter()
+        .filter_map(|(name, period_seconds, quota)| {
+            let quota = quota?;
+
+            let period = now / period_seconds;
+
+            let key = |period: u64| {
+                format!(
+                    "compute_unit_quota:{}:{}:{}:{}",
+                    self.chain_id, user_id, name, period
+                )
+            };
+
+            Some(Window {
+                current_key: key(period),
+                previous_key: key(period.saturating_sub(1)),
+                quota,
+                previous_weight: 1.0 - (now % period_seconds) as f64 / period_seconds as f64,
+                ttl: 2 * period_seconds as usize,
+            })
+        })
+        .collect()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// How many compute units the user has left in the window that is closest to its quota.
+    /// Returns PaymentRequired if any quota is used up.
+    pub async fn check(&self, user_id: u64) -> Web3ProxyResult<u64> {
+        let windows = self.windows(user_id, Self::now());
+
+        let keys: Vec<_> = windows
+            .iter()
+            .flat_map(|x| [x.current_key.as_str(), x.previous_key.as_str()])
+            .collect();
+
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        let counts: Vec<Option<i64>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut *redis_conn)
+            .await?;
+
+        let remaining = remaining(&windows, &counts);
+
+        if remaining == 0 {
+            trace!(%user_id, "compute unit quota used up");
+            return Err(Web3ProxyError::PaymentRequired);
+        }
+
+        Ok(remaining)
+    }
+
+    /// Take compute units from every window before a request is sent.
+    /// If that goes over any quota, they are given back and PaymentRequired is returned.
+    pub async fn reserve(&self, user_id: u64, compute_units: u64) -> Web3ProxyResult<u64> {
+        let windows = self.windows(user_id, Self::now());
+
+        let mut pipe = redis::pipe();
+
+        for window in windows.iter() {
+            pipe.incr(&window.current_key, compute_units)
+                .expire(&window.current_key, window.ttl)
+                .ignore()
+                .get(&window.previous_key);
+        }
+
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        let counts: Vec<Option<i64>> = pipe.query_async(&mut *redis_conn).await?;
+
+        drop(redis_conn);
+
+        if over_quota(&windows, &counts) {
+            trace!(%user_id, compute_units, "compute unit quota used up");
+
+            self.add(user_id, -(compute_units as i64)).await?;
+
+            return Err(Web3ProxyError::PaymentRequired);
+        }
+
+        Ok(remaining(&windows, &counts))
+    }
+
+    /// Add compute units to every window. Negative to give back a reservation that was too large
+    pub async fn add(&self, user_id: u64, compute_units: i64) -> Web3ProxyResult<()> {
+        if compute_units == 0 {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+
+        for window in self.windows(user_id, Self::now()) {
+            pipe.incr(&window.current_key, compute_units)
+                .ignore()
+                .expire(&window.current_key, window.ttl)
+                .ignore();
+        }
+
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        pipe.query_async::<_, ()>(&mut *redis_conn).await?;
+
+        Ok(())
+    }
+}
+
+/// The compute units used in a rolling window. Counts are given back after they are added, so they might briefly be negative
+fn used(window: &Window, current: Option<i64>, previous: Option<i64>) -> u64 {
+    let current = current.unwrap_or_default().max(0) as u64;
+    let previous = previous.unwrap_or_default().max(0) as f64;
+
+    current + (previous * window.previous_weight).ceil() as u64
+}
+
+/// counts has the current and then the previous period for each window
+fn over_quota(windows: &[Window], counts: &[Option<i64>]) -> bool {
+    windows
+        .iter()
+        .zip(counts.chunks(2))
+        .any(|(window, counts)| used(window, counts[0], counts[1]) > window.quota)
+}
+
+/// the smallest amount left across all the windows. counts has the current and then the previous period for each window
+fn remaining(windows: &[Window], counts: &[Option<i64>]) -> u64 {
+    windows
+        .iter()
+        .zip(counts.chunks(2))
+        .map(|(window, counts)| {
+            window
+                .quota
+                .saturating_sub(used(window, counts[0], counts[1]))
+        })
+        .min()
+        .unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis_rate_limiter::{DeadpoolRuntime, RedisConfig};
+
+    fn window(quota: u64, previous_weight: f64) -> Window {
+        Window {
+            current_key: "current".to_string(),
+            previous_key: "previous".to_string(),
+            quota,
+            previous_weight,
+            ttl: 2 * DAY_SECONDS as usize,
+        }
+    }
+
+    #[test]
+    fn test_remaining() {
+        let windows = vec![window(100, 0.0), window(1_000, 0.0)];
+
+        // nothing spent yet
+        assert_eq!(remaining(&windows, &[None, None, None, None]), 100);
+
+        // the window closest to its quota wins
+        assert_eq!(remaining(&windows, &[Some(10), None, Some(950), None]), 50);
+
+        // going over doesn't underflow
+        assert_eq!(remaining(&windows, &[Some(150), None, Some(150), None]), 0);
+
+        // no windows means no quota
+        assert_eq!(remaining(&[], &[]), u64::MAX);
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        // a quarter of the way into the current day. three quarters of yesterday are still in the window
+        let windows = vec![window(100, 0.75)];
+
+        // spending the whole quota just before midnight doesn't give a whole new quota just after it
+        assert_eq!(remaining(&windows, &[None, Some(100)]), 25);
+        assert!(!over_quota(&windows, &[Some(25), Some(100)]));
+        assert!(over_quota(&windows, &[Some(26), Some(100)]));
+
+        // refunds might briefly leave a negative count
+        assert_eq!(remaining(&windows, &[Some(-5), None]), 100);
+    }
+
+    #[test]
+    fn test_windows() {
+        // the pool doesn't connect until it is used
+        let redis_pool = RedisConfig::from_url("redis://127.0.0.1")
+            .builder()
+            .unwrap()
+            .runtime(DeadpoolRuntime::Tokio1)
+            .build()
+            .unwrap();
+
+        let quota = ComputeUnitQuota::new(1, Some(100), None, redis_pool).unwrap();
+
+        let now = 10 * DAY_SECONDS + DAY_SECONDS / 4;
+
+        assert_eq!(
+            quota.windows(7, now),
+            vec![Window {
+                current_key: "compute_unit_quota:1:7:day:10".to_string(),
+                previous_key: "compute_unit_quota:1:7:day:9".to_string(),
+                quota: 100,
+                previous_weight: 0.75,
+                ttl: 2 * DAY_SECONDS as usize,
+            }]
+        );
+    }
+}
@@ -6,10 +6,32 @@
 //! TODO: pricing on compute units
 //! TODO: script that queries influx and calculates observed relative costs
 
+use hashbrown::{HashMap, HashSet};
 use migration::sea_orm::prelude::Decimal;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use std::str::FromStr;
 use tracing::warn;
 
+/// Stop remembering unknown methods after this many so that clients sending random method names can't use up memory
+const MAX_WARNED_UNKNOWN_METHODS: usize = 1_000;
+
+/// unknown methods that have already been logged
+static WARNED_UNKNOWN_METHODS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+/// Log the first time that an unknown method is seen instead of on every request
+fn warn_unknown_method(method: &str) {
+    let mut warned = WARNED_UNKNOWN_METHODS.lock();
+
+    if warned.len() >= MAX_WARNED_UNKNOWN_METHODS || warned.contains(method) {
+        return;
+    }
+
+    warned.insert(method.to_string());
+
+    warn!(%method, "unknown method. add it to compute_units if it should be supported");
+}
+
 pub struct ComputeUnit(Decimal);
 
 impl ComputeUnit {
@@ -20,6 +42,25 @@ impl ComputeUnit {
             return Self::subscription_response(response_bytes);
         }
 
+        let cu = match Self::known_method_units(chain_id, method) {
+            Some(x) => x,
+            None => {
+                warn_unknown_method(method);
+                return Self::unimplemented();
+            }
+        };
+
+        let cu = Decimal::from(cu);
+
+        Self(cu)
+    }
+
+    /// Methods without a cost are probably typos. Config checks use this to warn about them
+    pub fn is_known_method(method: &str, chain_id: u64) -> bool {
+        Self::known_method_units(chain_id, method).is_some()
+    }
+
+    fn known_method_units(chain_id: u64, method: &str) -> Option<u64> {
         let cu = match (chain_id, method) {
             (1101, "zkevm_batchNumber") => 0,
             (1101, "zkevm_batchNumberByBlockNumber") => 0,
@@ -47,6 +88,7 @@ impl ComputeUnit {
             (_, "eth_accounts") => 10,
             (_, "eth_blockNumber") => 10,
             (_, "eth_call") => 26,
+            (_, "eth_callBundle") => 75,
             (_, "eth_chainId") => 0,
             (_, "eth_createAccessList") => 10,
             (_, "eth_estimateGas") => 87,
@@ -80,9 +122,8 @@ impl ComputeUnit {
             (_, "eth_newBlockFilter") => 20,
             (_, "eth_newFilter") => 20,
             (_, "eth_newPendingTransactionFilter") => 20,
-            (_, "eth_pollSubscriptions") => {
-                return Self::unimplemented();
-            }
+            // unimplemented
+            (_, "eth_pollSubscriptions") => 2,
             (_, "eth_protocolVersion") => 0,
             (_, "eth_sendRawTransaction") => 250,
             (_, "eth_sendUserOperation") => 1000,
@@ -104,15 +145,15 @@ impl ComputeUnit {
             (_, "trace_transaction") => 26,
             (_, "web3_clientVersion") => 15,
             (_, "web3_sha3") => 15,
-            (_, method) => {
-                warn!("unknown method {}", method);
-                return Self::unimplemented();
-            }
+            _ => return None,
         };
 
-        let cu = Decimal::from(cu);
+        Some(cu)
+    }
 
-        Self(cu)
+    /// the number of compute units. does not include any discounts or multipliers
+    pub fn units(&self) -> Decimal {
+        self.0
     }
 
     /// notifications and subscription responses cost per-byte
@@ -127,16 +168,49 @@ impl ComputeUnit {
         Self(2.into())
     }
 
+    /// what one compute unit is billed at on this chain
+    pub fn usd_per_cu(chain_id: u64) -> Decimal {
+        // TODO: get from config?
+        let x = match chain_id {
+            137 => "0.000000533333333333333",
+            _ => "0.000000400000000000000",
+        };
+
+        Decimal::from_str(x).expect("usd_per_cu should always parse")
+    }
+
+    /// archive requests cost 2.5x unless the method has its own multiplier
+    pub fn default_archive_multiplier() -> Decimal {
+        Decimal::new(25, 1)
+    }
+
+    /// look up the configured archive multiplier for a method
+    pub fn archive_multiplier(
+        method: &str,
+        archive_multipliers: &HashMap<String, Decimal>,
+    ) -> Decimal {
+        archive_multipliers
+            .get(method)
+            .copied()
+            .unwrap_or_else(Self::default_archive_multiplier)
+    }
+
     /// Compute cost per request
     /// All methods cost the same
     /// The number of bytes are based on input, and output bytes
-    pub fn cost(&self, archive_request: bool, cache_hit: bool, usd_per_cu: Decimal) -> Decimal {
+    pub fn cost(
+        &self,
+        archive_request: bool,
+        archive_multiplier: Decimal,
+        cache_hit: bool,
+        usd_per_cu: Decimal,
+    ) -> Decimal {
         // TODO: server errors are free. need to split server and user errors
 
         let mut cost = self.0 * usd_per_cu;
 
         if archive_request {
-            cost *= Decimal::from_str("2.5").unwrap();
+            cost *= archive_multiplier;
         }
 
         // cache hits get a 25% discount
@@ -147,3 +221,36 @@ impl ComputeUnit {
         cost
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_multiplier_per_method() {
+        let mut archive_multipliers = HashMap::new();
+        archive_multipliers.insert("eth_getBalance".to_string(), Decimal::new(2, 0));
+        archive_multipliers.insert("eth_getStorageAt".to_string(), Decimal::new(4, 0));
+
+        let usd_per_cu = Decimal::new(1, 0);
+
+        for (method, expected) in [("eth_getBalance", 2), ("eth_getStorageAt", 4)] {
+            let multiplier = ComputeUnit::archive_multiplier(method, &archive_multipliers);
+            assert_eq!(multiplier, Decimal::new(expected, 0));
+
+            let cu = ComputeUnit::new(method, 1, 0);
+
+            let archive_cost = cu.cost(true, multiplier, false, usd_per_cu);
+            let normal_cost = cu.cost(false, multiplier, false, usd_per_cu);
+
+            assert_eq!(normal_cost, cu.units());
+            assert_eq!(archive_cost, cu.units() * Decimal::new(expected, 0));
+        }
+
+        // unlisted methods use the default
+        assert_eq!(
+            ComputeUnit::archive_multiplier("eth_call", &archive_multipliers),
+            ComputeUnit::default_archive_multiplier()
+        );
+    }
+}
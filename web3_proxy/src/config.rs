@@ -1,17 +1,32 @@
 use crate::app::Web3ProxyJoinHandle;
+use crate::call_gas::GasTooHighMode;
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::fee_history::FEE_HISTORY_MAX_BLOCKS;
+use crate::jsonrpc::JsonRpcErrorData;
+use crate::method_filter::MethodFilter;
+use crate::raw_transaction::RawTxValidation;
+use crate::response_cache::JsonRpcResponseEnum;
 use crate::rpcs::blockchain::{BlocksByHashCache, Web3ProxyBlock};
 use crate::rpcs::one::Web3Rpc;
+use crate::trace_limits::TraceLimits;
+use crate::webhooks::{WebhookEventKind, WebhookSender};
 use argh::FromArgs;
 use derivative::Derivative;
 use ethers::prelude::{Address, TxHash};
 use ethers::types::{U256, U64};
 use hashbrown::HashMap;
+use ipnet::IpNet;
+use migration::sea_orm::prelude::Decimal;
 use migration::sea_orm::DatabaseConnection;
 use sentry::types::Dsn;
 use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::warn;
+use url::{Host, Url};
 
 pub type BlockAndRpc = (Option<Web3ProxyBlock>, Arc<Web3Rpc>);
 pub type TxHashAndRpc = (TxHash, Arc<Web3Rpc>);
@@ -45,29 +60,195 @@ pub struct TopConfig {
     pub app: AppConfig,
     pub balanced_rpcs: HashMap<String, Web3RpcConfig>,
     pub private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
+    /// only sent transactions when every one of private_rpcs errors.
+    /// these are tried one at a time in order of their names until one accepts the transaction
+    pub secondary_private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
     pub bundler_4337_rpcs: Option<HashMap<String, Web3RpcConfig>>,
     /// unknown config options get put here
     #[serde(flatten, default = "HashMap::default")]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+impl TopConfig {
+    /// Every backend url that would be sent without TLS. Localhost urls are allowed.
+    /// Only the names and origins are returned so that api keys in the urls don't end up in logs.
+    pub fn insecure_backend_urls(&self) -> Vec<String> {
+        let rpc_configs = self
+            .balanced_rpcs
+            .iter()
+            .chain(self.private_rpcs.iter().flatten())
+            .chain(self.secondary_private_rpcs.iter().flatten())
+            .chain(self.bundler_4337_rpcs.iter().flatten());
+
+        let mut insecure = vec![];
+
+        for (name, rpc_config) in rpc_configs {
+            let urls = [
+                (rpc_config.http_url.as_ref(), "https"),
+                (rpc_config.ws_url.as_ref(), "wss"),
+            ];
+
+            for (url, secure_scheme) in urls {
+                let Some(url) = url else { continue };
+
+                match Url::parse(url) {
+                    Ok(url) => {
+                        if url.scheme() != secure_scheme && !is_localhost(&url) {
+                            insecure.push(format!(
+                                "{}: {}",
+                                name,
+                                url.origin().ascii_serialization()
+                            ));
+                        }
+                    }
+                    Err(_) => insecure.push(format!("{}: unparsable url", name)),
+                }
+            }
+        }
+
+        insecure.sort();
+
+        insecure
+    }
+}
+
+fn is_localhost(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
 /// shared configuration between Web3Rpcs
 // TODO: no String, only &str
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Derivative, Deserialize, PartialEq, Eq)]
+#[derivative(Debug, Default)]
 pub struct AppConfig {
     /// Request limit for allowed origins for anonymous users.
     /// These requests get rate limited by IP.
     #[serde(default = "default_allowed_origin_requests_per_period")]
+    #[derivative(Default(value = "default_allowed_origin_requests_per_period()"))]
     pub allowed_origin_requests_per_period: HashMap<String, u64>,
 
+    /// Requests per period for partner origins. Needs volatile_redis_url.
+    /// Unlike allowed_origin_requests_per_period, this replaces the ip or key limit instead of changing it.
+    /// Requests are counted per origin and ip (or user for rpc keys). Unlimited keys stay unlimited.
+    /// Origins that aren't listed use the normal limits.
+    /// The Origin header is spoofable. Any client that isn't a browser can send it, so keep these limits modest or set origin_rate_limits_require_rpc_key.
+    #[serde(default)]
+    pub origin_rate_limits: HashMap<String, u32>,
+
+    /// The number of seconds that origin_rate_limits are counted over
+    #[serde(default = "default_origin_rate_limit_period")]
+    #[derivative(Default(value = "default_origin_rate_limit_period()"))]
+    pub origin_rate_limit_period: u64,
+
+    /// Only give origin_rate_limits to requests that also have an rpc key. Requests without one use the normal ip limits.
+    #[serde(default)]
+    pub origin_rate_limits_require_rpc_key: bool,
+
+    /// If set, only browsers on these origins may call the proxy directly. "*" allows any origin.
+    /// Keys with their own allowed origins still reject every other origin.
+    /// If empty, every origin gets CORS headers.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// Requests without an rpc key are only accepted from these networks. Entries are CIDR ranges like "10.0.0.0/8".
+    /// If empty, every ip that isn't in blocked_ips is accepted. Localhost is always accepted.
+    #[serde(default)]
+    pub allowed_ips: Vec<IpNet>,
+
+    /// Requests without an rpc key are rejected from these networks. This is checked before allowed_ips.
+    #[serde(default)]
+    pub blocked_ips: Vec<IpNet>,
+
     /// erigon defaults to pruning beyond 90,000 blocks
     #[serde(default = "default_archive_depth")]
+    #[derivative(Default(value = "default_archive_depth()"))]
     pub archive_depth: u64,
 
+    /// How much more archive requests cost for specific methods.
+    /// Methods that are not listed use the default multiplier of 2.5.
+    #[serde(default)]
+    pub archive_multipliers: HashMap<String, Decimal>,
+
+    /// How often reverts are saved to the database for specific methods. 0.0 to 1.0.
+    /// This overrides the rpc key's log_revert_chance. Methods that are not listed use the key's chance.
+    /// Only eth_call and eth_estimateGas reverts are saved.
+    #[serde(default)]
+    pub log_revert_chance_by_method: HashMap<String, Decimal>,
+
+    /// What to do when a backend rejects eth_call because the gas is above its per-call cap.
+    /// "error" returns the rejection to the user. "clamp" retries with eth_call_gas_cap. "omit" retries without any gas.
+    #[serde(default)]
+    pub eth_call_gas_too_high: GasTooHighMode,
+
+    /// eth_coinbase returns this address, usually the fee recipient of this chain's builder or validator.
+    /// It is the same for every user. If None, eth_coinbase returns the zero address. eth_accounts is always empty.
+    pub coinbase: Option<Address>,
+
+    /// What net_peerCount returns. "synced_rpcs" (the default) is the number of synced backends and doesn't query anything.
+    /// "max_backend_peers" is the most peers any backend reports. It is cached for a minute.
+    #[serde(default)]
+    pub net_peer_count: PeerCountMode,
+
+    /// The gas to retry eth_call with when eth_call_gas_too_high is "clamp"
+    #[serde(default = "default_eth_call_gas_cap")]
+    #[derivative(Default(value = "default_eth_call_gas_cap()"))]
+    pub eth_call_gas_cap: u64,
+
+    /// Cache trace_replayTransaction once the transaction's block has this many confirmations.
+    /// If None, trace_replayTransaction is never cached.
+    #[serde(default)]
+    pub trace_replay_cache_confirmations: Option<u64>,
+
+    /// If a server doesn't have eth_getBlockReceipts, get the receipts one transaction at a time for blocks with up to this many transactions.
+    /// 0 disables the fallback.
+    #[serde(default = "default_block_receipts_fallback_max_txs")]
+    #[derivative(Default(value = "default_block_receipts_fallback_max_txs()"))]
+    pub block_receipts_fallback_max_txs: usize,
+
+    /// Log one line of json for every proxied request.
+    /// These logs use the "web3_proxy::access_log" target.
+    #[serde(default)]
+    pub access_log: bool,
+
+    /// Log a warning for any proxied request that takes longer than this many milliseconds.
+    /// Params are truncated in the log. None disables the slow request log.
+    #[serde(default)]
+    pub slow_request_ms: Option<u64>,
+
+    /// Remember null eth_getTransactionReceipt responses for this many milliseconds so that clients polling a pending transaction don't hit the backends every time.
+    /// Entries are cleared as soon as the transaction is in a head block. Keep this short. None disables the cache.
+    #[serde(default)]
+    pub null_receipt_cache_ms: Option<u64>,
+
+    /// Requests always go to the next rpc when an rpc can't be reached or times out.
+    /// Once every rpc has failed, try all of them again up to this many more times for reads.
+    /// Reverts and other errors from the node are not retried. Neither are methods that send transactions.
+    #[serde(default = "default_request_retries")]
+    #[derivative(Default(value = "default_request_retries()"))]
+    pub request_retries: usize,
+
+    /// Milliseconds to wait before the first retry. This doubles for each retry after that and has some random jitter added.
+    #[serde(default = "default_request_retry_backoff_ms")]
+    #[derivative(Default(value = "default_request_retry_backoff_ms()"))]
+    pub request_retry_backoff_ms: u64,
+
     /// EVM chain id. 1 for ETH
     /// TODO: better type for chain_id? max of `u64::MAX / 2 - 36` <https://github.com/ethereum/EIPs/issues/2294>
     pub chain_id: u64,
 
+    /// Static chain parameters (EIP activation blocks, etc.) that are served by the `proxy_chainConfig` method.
+    /// None = proxy_chainConfig is not available
+    pub chain_config: Option<serde_json::Value>,
+
+    /// Serve proxy_chainConfig to anonymous users. If false, an rpc key is required.
+    #[serde(default)]
+    pub chain_config_public: bool,
+
     /// Database is used for user data.
     /// Currently supports mysql or compatible backend.
     pub db_url: Option<String>,
@@ -105,6 +286,16 @@ pub struct AppConfig {
     /// percentage to increase eth_estimateGas results. 100 == 100%
     pub gas_increase_percent: Option<U256>,
 
+    /// How many synced rpcs must agree on a block before the consensus head advances to it.
+    /// This protects against a single rpc that is racing ahead or reporting a bad block.
+    /// None = min_synced_rpcs. min_synced_rpcs is still required if this is lower
+    pub head_quorum: Option<usize>,
+
+    /// Milliseconds that an rpc may report a head block that no other rpc has seen, even though they have reached the same height.
+    /// After this, the rpc is left out of consensus until another rpc sees its head.
+    /// None = never leave rpcs out for this
+    pub unconfirmed_head_tolerance_ms: Option<u64>,
+
     /// Restrict user registration.
     /// None = no code needed
     pub invite_code: Option<String>,
@@ -114,30 +305,56 @@ pub struct AppConfig {
     pub kafka_urls: Option<String>,
 
     #[serde(default = "default_kafka_protocol")]
+    #[derivative(Default(value = "default_kafka_protocol()"))]
     pub kafka_protocol: String,
 
     /// domain in sign-in-with-ethereum messages
     pub login_domain: Option<String>,
 
-    /// do not serve any requests if the best known block is behind the best known block by more than this many blocks.
+    /// Reject batch requests with more than this many requests in them.
+    /// None = allow any size
+    pub max_batch_size: Option<usize>,
+
+    /// Reject requests with params larger than this many bytes.
+    /// None = allow any size
+    pub max_params_bytes: Option<usize>,
+
+    /// Return an error instead of any backend response larger than this. Large responses are never cached.
+    /// Paginated eth_getLogs responses are checked one page at a time.
+    /// None = allow any size
+    pub max_response_bytes: Option<u64>,
+
+    /// How many requests from a single batch are proxied at the same time
+    #[serde(default = "default_max_batch_concurrency")]
+    #[derivative(Default(value = "default_max_batch_concurrency()"))]
+    pub max_batch_concurrency: usize,
+
+    /// Rpcs within this many blocks of the consensus head stay eligible for requests that don't need the head block.
+    /// Requests for "latest" still go to rpcs on the consensus head. Rpcs further behind are left out until they catch up.
+    /// Defaults to 5.
+    #[serde(alias = "max_head_lag_blocks")]
     pub max_head_block_lag: Option<U64>,
 
     /// Rate limit for bearer token authenticated entrypoints.
     /// This is separate from the rpc limits.
     #[serde(default = "default_bearer_token_max_concurrent_requests")]
+    #[derivative(Default(value = "default_bearer_token_max_concurrent_requests()"))]
     pub bearer_token_max_concurrent_requests: u64,
 
     /// Rate limit for the login entrypoint.
     /// This is separate from the rpc limits.
     #[serde(default = "default_login_rate_limit_per_period")]
+    #[derivative(Default(value = "default_login_rate_limit_per_period()"))]
     pub login_rate_limit_per_period: u64,
 
     /// The soft limit prevents thundering herds as new blocks are seen.
     #[serde(default = "default_min_sum_soft_limit")]
+    #[derivative(Default(value = "default_min_sum_soft_limit()"))]
     pub min_sum_soft_limit: u32,
 
     /// Another knob for preventing thundering herds as new blocks are seen.
     #[serde(default = "default_min_synced_rpcs")]
+    #[derivative(Default(value = "default_min_synced_rpcs()"))]
     pub min_synced_rpcs: usize,
 
     /// Concurrent request limit for anonymous users.
@@ -150,13 +367,228 @@ pub struct AppConfig {
     /// None = allow all requests
     pub public_requests_per_period: Option<u64>,
 
+    /// The number of seconds that public_requests_per_period and the user tier limits are counted over
+    #[serde(default = "default_rate_limit_period")]
+    #[derivative(Default(value = "default_rate_limit_period()"))]
+    pub rate_limit_period: u64,
+
     /// Salt for hashing recent ips. Not a perfect way to introduce privacy, but better than nothing
     pub public_recent_ips_salt: Option<String>,
 
+    /// When a websocket client sends a transaction with eth_sendRawTransaction, notify them when it is included in a block.
+    /// The notification is an "eth_subscription" message with the transaction hash as the subscription id.
+    #[serde(default)]
+    pub sent_tx_notifications: bool,
+
+    /// How strictly to check eth_sendRawTransaction before sending it to the relays.
+    /// "off" sends every transaction. "basic" rejects transactions that don't decode or are for another chain.
+    /// "strict" also requires a chain id and at least 21,000 gas.
+    /// Newer transaction types, like blob transactions, can't be decoded yet. They are always sent.
+    #[serde(default)]
+    pub raw_tx_validation: RawTxValidation,
+
+    /// How many seconds to watch for a sent transaction to be included in a block before giving up
+    #[serde(default = "default_sent_tx_notifications_timeout")]
+    #[derivative(Default(value = "default_sent_tx_notifications_timeout()"))]
+    pub sent_tx_notifications_timeout: u64,
+
+    /// How many sent transactions a single websocket can watch at once. More transactions are still sent, but get no notification
+    #[serde(default = "default_sent_tx_notifications_max_per_connection")]
+    #[derivative(Default(value = "default_sent_tx_notifications_max_per_connection()"))]
+    pub sent_tx_notifications_max_per_connection: usize,
+
+    /// When an rpc is removed or replaced, how many seconds to wait for its in-flight requests before disconnecting
+    #[serde(default = "default_rpc_drain_timeout")]
+    #[derivative(Default(value = "default_rpc_drain_timeout()"))]
+    pub rpc_drain_timeout: u64,
+
+    /// Send a ping to websocket clients every this many seconds. 0 disables pings
+    #[serde(default = "default_ws_ping_interval")]
+    #[derivative(Default(value = "default_ws_ping_interval()"))]
+    pub ws_ping_interval: u64,
+
+    /// Close websocket connections that don't answer a ping within this many seconds. Must be at least 1
+    #[serde(default = "default_ws_pong_timeout")]
+    #[derivative(Default(value = "default_ws_pong_timeout()"))]
+    pub ws_pong_timeout: u64,
+
+    /// Close websocket connections without any subscriptions after this many seconds without a message. 0 disables
+    #[serde(default = "default_ws_idle_timeout")]
+    #[derivative(Default(value = "default_ws_idle_timeout()"))]
+    pub ws_idle_timeout: u64,
+
+    /// Remember a websocket's newHeads subscriptions for this many seconds after it disconnects.
+    /// Clients that reconnect with the same `resume_token` query param get them back with the same subscription ids. 0 disables
+    #[serde(default)]
+    pub ws_resume_ttl: u64,
+
+    /// eth_feeHistory requests for more blocks than this are clamped instead of being sent to backends that would reject them
+    #[serde(default = "default_fee_history_max_blocks")]
+    #[derivative(Default(value = "default_fee_history_max_blocks()"))]
+    pub fee_history_max_blocks: u64,
+
+    /// Users without a paid balance can spend this many compute units in any 24 hours. Needs volatile_redis_url.
+    /// If None, there is no daily quota.
+    pub user_daily_compute_unit_quota: Option<u64>,
+
+    /// Users without a paid balance can spend this many compute units in any 30 days. Needs volatile_redis_url.
+    /// If None, there is no monthly quota.
+    pub user_monthly_compute_unit_quota: Option<u64>,
+
+    /// Send what each http request cost in X-Compute-Units and X-Compute-Units-USD headers.
+    /// Batches get the sum of their requests.
+    #[serde(default)]
+    pub compute_units_header: bool,
+
+    /// Send which servers answered each http request in an X-Served-By header.
+    /// Servers are named by their display_name or their name. Never by their url.
+    /// Responses from the cache are served by "cache". Rejected and locally answered requests don't get the header.
+    #[serde(default)]
+    pub served_by_header: bool,
+
+    /// Methods to proxy even though they are in the built-in list of blocked methods.
+    /// Exact names or prefixes ending in `*`. personal_* and miner_* methods have to be named to be allowed.
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+
+    /// Reject methods that are not in compute_units or allowed_methods instead of sending them to the backend rpcs.
+    /// Off by default so that new methods work without a release.
+    #[serde(default)]
+    pub reject_unknown_methods: bool,
+
+    /// Methods to reject with 403 Forbidden. Checked before allowed_methods.
+    #[serde(default)]
+    pub blocked_methods: Vec<String>,
+
+    /// allowed_methods and blocked_methods for keys of specific user tiers, by tier title.
+    /// These are checked before the app-wide lists.
+    #[serde(default)]
+    pub method_filters_by_tier: HashMap<String, MethodFilter>,
+
+    /// Methods that only premium users can call. Everyone else gets 402 Payment Required.
+    /// Exact names or prefixes ending in `*`.
+    #[serde(default)]
+    pub requires_premium: Vec<String>,
+
+    /// Per method limits on block ranges, like trace_filter's, and batch sizes, like trace_callMany's
+    #[serde(default)]
+    pub trace_limits: TraceLimits,
+
+    /// trace_limits for keys of specific user tiers, by tier title. These replace the app-wide limits
+    #[serde(default)]
+    pub trace_limits_by_tier: HashMap<String, TraceLimits>,
+
+    /// Methods to send to private_rpcs instead of balanced_rpcs. Exact names only.
+    /// If there are no private rpcs, these go to balanced_rpcs like any other method.
+    #[serde(default)]
+    pub private_methods: Vec<String>,
+
+    /// Send every request in a batch to one rpc that is on the batch's head block.
+    /// All the requests then see the same state, but a batch can't be spread across rpcs.
+    #[serde(default)]
+    pub batch_pin_rpc: bool,
+
+    /// eth_getLogs queries over more blocks than this are split into smaller queries and their logs are concatenated.
+    /// If None, queries are sent as is.
+    pub get_logs_max_block_range: Option<u64>,
+
+    /// Maximum size of one page of eth_getLogs results when a request opts in to pagination
+    #[serde(default = "default_get_logs_page_bytes")]
+    #[derivative(Default(value = "default_get_logs_page_bytes()"))]
+    pub get_logs_page_bytes: usize,
+
+    /// eth_getProof responses larger than this are not cached. Proofs are only cached at blocks that won't change
+    #[serde(default = "default_get_proof_max_cached_bytes")]
+    #[derivative(Default(value = "default_get_proof_max_cached_bytes()"))]
+    pub get_proof_max_cached_bytes: u32,
+
+    /// How many pending transactions to remember so that each one is only sent to subscribers once
+    #[serde(default = "default_pending_transactions_max_entries")]
+    #[derivative(Default(value = "default_pending_transactions_max_entries()"))]
+    pub pending_transactions_max_entries: u64,
+
+    /// How many seconds to remember a pending transaction for
+    #[serde(default = "default_pending_transactions_ttl")]
+    #[derivative(Default(value = "default_pending_transactions_ttl()"))]
+    pub pending_transactions_ttl: u64,
+
+    /// How many seconds a filter from eth_newFilter or eth_newBlockFilter lives without being polled.
+    /// Filters are kept in each proxy's memory, so clients behind a load balancer need to stick to one proxy
+    #[serde(default = "default_filter_ttl")]
+    #[derivative(Default(value = "default_filter_ttl()"))]
+    pub filter_ttl: u64,
+
+    /// How many filters to keep. The least recently used filters are dropped first
+    #[serde(default = "default_filters_max_entries")]
+    #[derivative(Default(value = "default_filters_max_entries()"))]
+    pub filters_max_entries: u64,
+
+    /// Share pending transactions with other proxies through volatile_redis_url so that only one of them has to query each transaction
+    #[serde(default)]
+    pub pending_transactions_redis: bool,
+
+    /// gzip or brotli compress http responses of at least this many bytes for clients that accept it.
+    /// Tiny responses aren't worth the cpu time.
+    #[serde(default = "default_http_compression_min_bytes")]
+    #[derivative(Default(value = "default_http_compression_min_bytes()"))]
+    pub http_compression_min_bytes: u16,
+
     /// RPC responses are cached locally
     #[serde(default = "default_response_cache_max_bytes")]
+    #[derivative(Default(value = "default_response_cache_max_bytes()"))]
     pub response_cache_max_bytes: u64,
 
+    /// Compress large responses in the local cache.
+    /// Only the compressed size counts against response_cache_max_bytes, so more responses fit.
+    #[serde(default)]
+    pub response_cache_compression: bool,
+
+    /// Seconds to keep responses to these methods in the local cache.
+    /// Methods that are usually keyed on a block hash still are, but expire after this even if they are busy.
+    /// Methods that are usually never cached are cached for this long.
+    /// Methods not listed here stay cached until they go unused for an hour. Most of their keys include a block hash, so a new head block means a new entry.
+    #[serde(default)]
+    pub method_cache_ttl: HashMap<String, u64>,
+
+    /// Share responses for old blocks with other proxies through volatile_redis_url.
+    /// On startup, the most recently shared responses are loaded into the local cache instead of being fetched from the backends again.
+    #[serde(default)]
+    pub shared_response_cache: bool,
+
+    /// How many shared responses are kept in redis. This also bounds how many are loaded on startup.
+    #[serde(default = "default_shared_response_cache_max_entries")]
+    #[derivative(Default(value = "default_shared_response_cache_max_entries()"))]
+    pub shared_response_cache_max_entries: usize,
+
+    /// Only share responses for blocks at least this far behind the head block. Shallower blocks might still be reorged.
+    #[serde(default = "default_shared_response_cache_min_depth")]
+    #[derivative(Default(value = "default_shared_response_cache_min_depth()"))]
+    pub shared_response_cache_min_depth: u64,
+
+    /// Responses larger than this are not shared.
+    #[serde(default = "default_shared_response_cache_max_bytes")]
+    #[derivative(Default(value = "default_shared_response_cache_max_bytes()"))]
+    pub shared_response_cache_max_bytes: usize,
+
+    /// Also share every response, not just old ones, for this many seconds. Methods with a shorter method_cache_ttl use that instead.
+    /// Other proxies check redis for these when their local cache misses. 0 disables
+    #[serde(default)]
+    pub shared_response_cache_lookup_ttl: u64,
+
+    /// Keep responses for deep blocks in a database at this path so that they survive restarts.
+    /// It is checked when a response isn't in the local cache. Requires the disk_cache feature.
+    pub disk_response_cache_path: Option<PathBuf>,
+
+    /// Only keep responses for blocks at least this far behind the head block on disk. Entries are never expired, so these must not be reorged.
+    #[serde(default = "default_disk_response_cache_min_depth")]
+    #[derivative(Default(value = "default_disk_response_cache_min_depth()"))]
+    pub disk_response_cache_min_depth: u64,
+
+    /// The most responses to keep on disk. Past this, the oldest responses are removed.
+    #[serde(default = "default_disk_response_cache_max_entries")]
+    #[derivative(Default(value = "default_disk_response_cache_max_entries()"))]
+    pub disk_response_cache_max_entries: usize,
+
     /// the stats page url for an anonymous user.
     pub redirect_public_url: Option<String>,
 
@@ -166,6 +598,21 @@ pub struct AppConfig {
     /// Optionally send errors to <https://sentry.io>
     pub sentry_url: Option<Dsn>,
 
+    /// Include the real error string in responses for internal errors instead of only a generic message.
+    /// Useful in development and staging. Leave this off in production so that internal details don't leak to users.
+    #[serde(default)]
+    pub expose_internal_errors: bool,
+
+    /// Reject requests that do not have `"jsonrpc": "2.0"`.
+    /// Off by default because some clients leave out the version.
+    #[serde(default)]
+    pub strict_jsonrpc: bool,
+
+    /// Refuse to start if any backend url is `http://` or `ws://`. Backend urls often contain api keys.
+    /// Localhost is allowed because local nodes are common in development.
+    #[serde(default)]
+    pub require_https_backends: bool,
+
     /// Track rate limits in a redis (or compatible backend)
     /// It is okay if this data is lost.
     pub volatile_redis_url: Option<String>,
@@ -186,11 +633,63 @@ pub struct AppConfig {
     /// influxdb bucket to use for stats
     pub influxdb_bucket: Option<String>,
 
+    /// How often to save each rpc's request latency percentiles to influxdb. 0 to disable
+    #[serde(default = "default_influxdb_latency_interval")]
+    #[derivative(Default(value = "default_influxdb_latency_interval()"))]
+    pub influxdb_latency_interval: u64,
+
+    /// POST json to this url when important events happen
+    pub webhook_url: Option<String>,
+
+    /// Which events are sent to webhook_url. If empty, all of them are sent.
+    #[serde(default)]
+    pub webhook_events: Vec<WebhookEventKind>,
+
+    /// How many webhooks can be in flight at once. Any more are dropped.
+    #[serde(default = "default_webhook_max_concurrency")]
+    #[derivative(Default(value = "default_webhook_max_concurrency()"))]
+    pub webhook_max_concurrency: usize,
+
+    /// Forward eth_sendTransaction to this signing service as eth_signTransaction and broadcast the signed transaction.
+    /// If None, eth_sendTransaction is rejected like any other method that needs keys.
+    /// IMPORTANT! Anyone with an entitled rpc key can spend from every account that the signer will sign for.
+    pub signer_url: Option<String>,
+
+    /// Database ids of the rpc keys that may use signer_url. No other keys can use eth_sendTransaction.
+    #[serde(default)]
+    pub signer_rpc_key_ids: Vec<u64>,
+
+    /// How long to wait for the signing service
+    #[serde(default = "default_signer_timeout")]
+    #[derivative(Default(value = "default_signer_timeout()"))]
+    pub signer_timeout: u64,
+
+    /// Simulate eth_callBundle requests with this builder or relay.
+    /// If None, eth_callBundle gets a 501 Not Implemented.
+    pub call_bundle_url: Option<String>,
+
+    /// How long to wait for the bundle simulator
+    #[serde(default = "default_call_bundle_timeout")]
+    #[derivative(Default(value = "default_call_bundle_timeout()"))]
+    pub call_bundle_timeout: u64,
+
     /// unknown config options get put here
     #[serde(flatten, default = "HashMap::default")]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+fn default_signer_timeout() -> u64 {
+    10
+}
+
+fn default_call_bundle_timeout() -> u64 {
+    10
+}
+
+fn default_webhook_max_concurrency() -> usize {
+    10
+}
+
 fn default_archive_depth() -> u64 {
     90_000
 }
@@ -199,6 +698,14 @@ fn default_allowed_origin_requests_per_period() -> HashMap<String, u64> {
     HashMap::new()
 }
 
+fn default_origin_rate_limit_period() -> u64 {
+    60
+}
+
+fn default_rate_limit_period() -> u64 {
+    60
+}
+
 /// This might cause a thundering herd!
 fn default_min_sum_soft_limit() -> u32 {
     1
@@ -219,16 +726,170 @@ fn default_login_rate_limit_per_period() -> u64 {
     10
 }
 
+/// Large batches are processed a few requests at a time so that one http request can't flood the backend rpcs
+fn default_max_batch_concurrency() -> usize {
+    10
+}
+
 fn default_kafka_protocol() -> String {
     "ssl".to_string()
 }
 
+/// Transactions with low fees can take a long time to confirm. Don't watch for them forever.
+fn default_sent_tx_notifications_timeout() -> u64 {
+    300
+}
+
+/// Each watcher is a task. Don't let one socket start too many of them
+fn default_sent_tx_notifications_max_per_connection() -> usize {
+    100
+}
+
+/// Most requests finish quickly. Subscriptions and slow requests shouldn't hold up a config reload forever.
+fn default_rpc_drain_timeout() -> u64 {
+    30
+}
+
+fn default_ws_ping_interval() -> u64 {
+    30
+}
+
+fn default_ws_pong_timeout() -> u64 {
+    10
+}
+
+fn default_ws_idle_timeout() -> u64 {
+    300
+}
+
+/// geth and erigon both refuse to serve more than 1024 blocks of fee history
+fn default_fee_history_max_blocks() -> u64 {
+    FEE_HISTORY_MAX_BLOCKS
+}
+
+fn default_block_receipts_fallback_max_txs() -> usize {
+    500
+}
+
+fn default_circuit_breaker_window() -> u64 {
+    60
+}
+
+fn default_circuit_breaker_cooldown() -> u64 {
+    30
+}
+
+fn default_get_logs_page_bytes() -> usize {
+    1_000_000
+}
+
+/// Most proofs of an account and a few storage slots are much smaller than this
+fn default_get_proof_max_cached_bytes() -> u32 {
+    100_000
+}
+
+fn default_pending_transactions_max_entries() -> u64 {
+    10_000
+}
+
+/// The nodes themselves hold onto transactions for much longer, but we only need to dedupe them for subscribers
+fn default_pending_transactions_ttl() -> u64 {
+    300
+}
+
+/// geth forgets filters that haven't been polled for 5 minutes
+fn default_filter_ttl() -> u64 {
+    300
+}
+
+fn default_filters_max_entries() -> u64 {
+    10_000
+}
+
+/// geth's default RPCGasCap
+fn default_eth_call_gas_cap() -> u64 {
+    50_000_000
+}
+
+fn default_shared_response_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_shared_response_cache_min_depth() -> u64 {
+    64
+}
+
+/// large responses are cheaper to fetch from the backends again than to push through redis
+fn default_shared_response_cache_max_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_disk_response_cache_min_depth() -> u64 {
+    64
+}
+
+fn default_disk_response_cache_max_entries() -> usize {
+    1_000_000
+}
+
+fn default_request_retries() -> usize {
+    2
+}
+
+fn default_request_retry_backoff_ms() -> u64 {
+    50
+}
+
+fn default_http_compression_min_bytes() -> u16 {
+    1024
+}
+
+fn default_influxdb_latency_interval() -> u64 {
+    60
+}
+
 fn default_response_cache_max_bytes() -> u64 {
     // TODO: default to some percentage of the system?
     // 100 megabytes
     10u64.pow(8)
 }
 
+impl AppConfig {
+    /// None if responses for this method should expire with the cache's default policy
+    pub fn method_cache_ttl(&self, method: &str) -> Option<Duration> {
+        self.method_cache_ttl
+            .get(method)
+            .copied()
+            .map(Duration::from_secs)
+    }
+
+    /// The response for `proxy_chainConfig`. This is served without querying any backends.
+    pub fn chain_config_response(
+        &self,
+        has_rpc_key: bool,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let chain_config = match self.chain_config.as_ref() {
+            Some(x) => x,
+            None => {
+                return Ok(JsonRpcErrorData {
+                    message: "the method proxy_chainConfig does not exist/is not available".into(),
+                    code: -32601,
+                    data: None,
+                }
+                .into())
+            }
+        };
+
+        if !has_rpc_key && !self.chain_config_public {
+            return Err(Web3ProxyError::AccessDenied(
+                "proxy_chainConfig requires an rpc key".into(),
+            ));
+        }
+
+        Ok(chain_config.clone().into())
+    }
+}
+
 /// TODO: we can't query a provider because we need this to create a provider
 pub fn average_block_interval(chain_id: u64) -> Duration {
     match chain_id {
@@ -256,9 +917,20 @@ pub fn average_block_interval(chain_id: u64) -> Duration {
     }
 }
 
+/// What to return for net_peerCount
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerCountMode {
+    /// the number of synced backends. clients that want at least a few peers are happy when we have a few backends
+    #[default]
+    SyncedRpcs,
+    /// the most peers that any backend has. if no backend answers, the number of synced backends is returned instead
+    MaxBackendPeers,
+}
+
 /// Configuration for a backend web3 RPC server
-#[derive(Clone, Debug, Derivative, Deserialize, PartialEq, Eq)]
-#[derivative(Default)]
+#[derive(Clone, Derivative, Deserialize, PartialEq, Eq)]
+#[derivative(Debug, Default)]
 pub struct Web3RpcConfig {
     /// simple way to disable a connection without deleting the row
     #[serde(default)]
@@ -271,12 +943,26 @@ pub struct Web3RpcConfig {
     pub http_url: Option<String>,
     /// block data limit. If None, will be queried
     pub block_data_limit: Option<u64>,
+    /// this server keeps all historical state. requests for old blocks are only sent to archive servers
+    #[serde(default)]
+    pub archive: bool,
+    /// send the proxy's request id to this server in this header (like "X-Request-Id").
+    /// only works with http_url. not all providers allow custom headers
+    pub request_id_header: Option<String>,
     /// the requests per second at which the server starts slowing down
     #[serde(default)]
     #[derivative(Default(value = "1"))]
     pub soft_limit: u32,
     /// the requests per second at which the server throws errors (rate limit or otherwise)
     pub hard_limit: Option<u64>,
+    /// stop sending requests to this server once it has served this many in a calendar month (UTC). Requires redis.
+    /// useful for paid providers that bill per request. the proxy's own requests (like polling for new heads) count too. If None, there is no limit.
+    /// if redis can't be reached, requests are still sent until the limit is known to be used up.
+    pub monthly_request_limit: Option<u64>,
+    /// never have more than this many requests in flight to this server. extra requests wait or go to another server.
+    /// unlike soft_limit, this is not used to choose between servers. If None, there is no limit.
+    /// when the server is full, requests from premium user tiers get the next free slot before requests from free tiers.
+    pub max_concurrent_requests: Option<u32>,
     /// only use this rpc if everything else is lagging too far. this allows us to ignore fast but very low limit rpcs
     #[serde(default)]
     pub backup: bool,
@@ -284,11 +970,38 @@ pub struct Web3RpcConfig {
     /// Don't do this with free rpcs
     #[serde(default)]
     pub subscribe_txs: bool,
+    /// stop sending requests to this server after this many errors within circuit_breaker_window seconds.
+    /// If None, the circuit breaker is disabled.
+    pub circuit_breaker_errors: Option<u32>,
+    /// errors further apart than this many seconds don't add up towards opening the circuit breaker
+    #[serde(default = "default_circuit_breaker_window")]
+    #[derivative(Default(value = "60"))]
+    pub circuit_breaker_window: u64,
+    /// seconds to wait after opening the circuit breaker before probing the server with a request
+    #[serde(default = "default_circuit_breaker_cooldown")]
+    #[derivative(Default(value = "30"))]
+    pub circuit_breaker_cooldown: u64,
+    /// seconds to wait for a http connection to this server. If None, the shared client's 5 second timeout is used.
+    /// fast local nodes can fail over sooner with a lower timeout. only works with http_url
+    pub connect_timeout: Option<u64>,
+    /// seconds to wait for a http response from this server. If None, the shared client's 5 minute timeout is used.
+    /// only works with http_url. ignored if the http_url has a username and password
+    pub request_timeout: Option<u64>,
+    /// send these headers with every request to this server. some providers take api keys this way instead of in the url.
+    /// only works with http_url. ignored if the http_url has a username and password. the values are never logged
+    #[serde(default)]
+    #[derivative(Debug(format_with = "fmt_header_names"))]
+    pub extra_headers: HashMap<String, String>,
     /// unknown config options get put here
     #[serde(flatten, default = "HashMap::default")]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+/// Only the names of extra_headers are shown. Their values are often api keys
+fn fmt_header_names(headers: &HashMap<String, String>, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_list().entries(headers.keys()).finish()
+}
+
 impl Web3RpcConfig {
     /// Create a Web3Rpc from config
     /// TODO: move this into Web3Rpc? (just need to make things pub(crate))
@@ -304,6 +1017,7 @@ impl Web3RpcConfig {
         blocks_by_hash_cache: BlocksByHashCache,
         block_sender: Option<flume::Sender<BlockAndRpc>>,
         tx_id_sender: Option<flume::Sender<TxHashAndRpc>>,
+        webhooks: Option<Arc<WebhookSender>>,
     ) -> anyhow::Result<(Arc<Web3Rpc>, Web3ProxyJoinHandle<()>)> {
         if !self.extra.is_empty() {
             warn!(extra=?self.extra.keys(), "unknown Web3RpcConfig fields!");
@@ -320,7 +1034,110 @@ impl Web3RpcConfig {
             blocks_by_hash_cache,
             block_sender,
             tx_id_sender,
+            webhooks,
         )
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_default_matches_serde() {
+        let parsed: AppConfig = toml::from_str("chain_id = 1").unwrap();
+
+        let default = AppConfig {
+            chain_id: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(parsed, default);
+    }
+
+    #[test]
+    fn test_chain_config_response() {
+        let chain_config = json!({
+            "chainId": 1,
+            "londonBlock": 12965000,
+            "shanghaiTime": 1681338455,
+        });
+
+        let config = AppConfig {
+            chain_id: 1,
+            chain_config: Some(chain_config.clone()),
+            ..Default::default()
+        };
+
+        match config.chain_config_response(true).unwrap() {
+            JsonRpcResponseEnum::Result { value, .. } => {
+                let value: serde_json::Value = serde_json::from_str(value.get()).unwrap();
+                assert_eq!(value, chain_config);
+            }
+            x => panic!("expected a result, got {:?}", x),
+        }
+
+        // anonymous users need chain_config_public
+        assert!(config.chain_config_response(false).is_err());
+
+        let config = AppConfig {
+            chain_config_public: true,
+            ..config
+        };
+
+        assert!(config.chain_config_response(false).is_ok());
+
+        // not configured
+        let config = AppConfig::default();
+
+        assert!(matches!(
+            config.chain_config_response(true).unwrap(),
+            JsonRpcResponseEnum::RpcError { .. }
+        ));
+    }
+
+    #[test]
+    fn test_insecure_backend_urls() {
+        let rpc_config = |http_url: &str, ws_url: &str| Web3RpcConfig {
+            http_url: Some(http_url.to_string()),
+            ws_url: Some(ws_url.to_string()),
+            ..Default::default()
+        };
+
+        let top_config = TopConfig {
+            app: AppConfig::default(),
+            balanced_rpcs: HashMap::from_iter([
+                (
+                    "secure".to_string(),
+                    rpc_config("https://example.com/key", "wss://example.com/key"),
+                ),
+                (
+                    "local".to_string(),
+                    rpc_config("http://localhost:8545", "ws://127.0.0.1:8546"),
+                ),
+                (
+                    "insecure".to_string(),
+                    rpc_config("http://example.com/key", "https://example.com/key"),
+                ),
+            ]),
+            private_rpcs: Some(HashMap::from_iter([(
+                "private".to_string(),
+                rpc_config("https://example.org", "ws://example.org:8546"),
+            )])),
+            secondary_private_rpcs: None,
+            bundler_4337_rpcs: None,
+            extra: Default::default(),
+        };
+
+        assert_eq!(
+            top_config.insecure_backend_urls(),
+            vec![
+                "insecure: http://example.com",
+                "insecure: https://example.com",
+                "private: ws://example.org:8546",
+            ]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Keep responses for deep blocks on disk so that they survive restarts.
+//!
+//! This is checked after the in-memory response cache misses and before the backends are asked.
+//! Blocks deeper than min_depth won't be reorged, so their responses never change and never need to be expired.
+//! The disk is still finite. Once there are more than max_entries, the oldest entries are removed.
+//! Local cache key hashes are not guaranteed to match across builds, so entries are keyed by the request inputs instead.
+use crate::block_number::BlockNumAndHash;
+use crate::response_cache::{JsonRpcQueryCacheKey, JsonRpcResponseEnum};
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Serialize)]
+struct DiskCacheKey<'a> {
+    method: &'a str,
+    params: &'a serde_json::Value,
+    from_block: Option<&'a BlockNumAndHash>,
+    to_block: Option<&'a BlockNumAndHash>,
+    cache_errors: bool,
+}
+
+#[derive(Clone)]
+pub struct DiskResponseCache {
+    db: sled::Db,
+    /// the responses' keys, keyed by when they were inserted. used to find the oldest entries
+    insert_order: sled::Tree,
+    /// sled's len walks the whole tree, so the number of entries is tracked here
+    num_entries: Arc<AtomicUsize>,
+    max_entries: usize,
+    min_depth: u64,
+}
+
+impl DiskResponseCache {
+    pub fn open(path: &Path, min_depth: u64, max_entries: usize) -> anyhow::Result<Self> {
+        let db = sled::open(path)
+            .with_context(|| format!("opening the disk response cache at {}", path.display()))?;
+
+        let x = Self::from_db(db, min_depth, max_entries)?;
+
+        info!(path=%path.display(), entries=%x.num_entries.load(Ordering::Relaxed), "opened the disk response cache");
+
+        Ok(x)
+    }
+
+    fn from_db(db: sled::Db, min_depth: u64, max_entries: usize) -> anyhow::Result<Self> {
+        let insert_order = db.open_tree("insert_order")?;
+
+        if insert_order.is_empty() {
+            // databases from before there was a limit have no order. treat their entries as the oldest
+            for key in db.iter().keys() {
+                let key = key?;
+
+                insert_order.insert(db.generate_id()?.to_be_bytes(), key)?;
+            }
+        }
+
+        let num_entries = Arc::new(AtomicUsize::new(db.len()));
+
+        Ok(Self {
+            db,
+            insert_order,
+            num_entries,
+            // 0 would remove every entry as soon as it was saved
+            max_entries: max_entries.max(1),
+            min_depth,
+        })
+    }
+
+    /// Only responses for blocks that can't be reorged are kept.
+    /// Unlike the shared cache, keys without any blocks are skipped. Those are usually cheap and aren't pinned to anything.
+    pub fn should_persist(&self, cache_key: &JsonRpcQueryCacheKey, head_block_num: u64) -> bool {
+        let newest_block = cache_key
+            .to_block_num()
+            .or_else(|| cache_key.from_block_num());
+
+        match newest_block {
+            None => false,
+            Some(x) => head_block_num.saturating_sub(x.as_u64()) >= self.min_depth,
+        }
+    }
+
+    fn disk_key(
+        cache_key: &JsonRpcQueryCacheKey,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&DiskCacheKey {
+            method,
+            params,
+            from_block: cache_key.from_block(),
+            to_block: cache_key.to_block(),
+            cache_errors: cache_key.cache_errors(),
+        })
+    }
+
+    /// Any error reading the disk is treated as a miss.
+    pub async fn get(
+        &self,
+        cache_key: &JsonRpcQueryCacheKey,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Option<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let key = Self::disk_key(cache_key, method, params).ok()?;
+
+        let db = self.db.clone();
+
+        let value = match tokio::task::spawn_blocking(move || db.get(key)).await {
+            Ok(Ok(Some(x))) => x,
+            Ok(Ok(None)) => return None,
+            Ok(Err(err)) => {
+                warn!(?err, "failed reading the disk response cache");
+                return None;
+            }
+            Err(err) => {
+                warn!(?err, "failed reading the disk response cache");
+                return None;
+            }
+        };
+
+        let value = String::from_utf8(value.to_vec()).ok()?;
+
+        let value = RawValue::from_string(value).ok()?;
+
+        Some(value.into())
+    }
+
+    /// Save a response to disk. This never slows down or fails the request.
+    /// Errors are never saved.
+    pub fn insert(
+        &self,
+        cache_key: &JsonRpcQueryCacheKey,
+        method: &str,
+        params: &serde_json::Value,
+        response_data: &JsonRpcResponseEnum<Arc<RawValue>>,
+    ) {
+        let JsonRpcResponseEnum::Result { value, .. } = response_data else {
+            return;
+        };
+
+        let key = match Self::disk_key(cache_key, method, params) {
+            Ok(x) => x,
+            Err(err) => {
+                trace!(?err, "failed building the disk response cache key");
+                return;
+            }
+        };
+
+        let value = value.get().as_bytes().to_vec();
+
+        let x = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = x.insert_blocking(key, value) {
+                warn!(?err, "failed writing the disk response cache");
+            }
+        });
+    }
+
+    fn insert_blocking(&self, key: Vec<u8>, value: Vec<u8>) -> sled::Result<()> {
+        if self.db.insert(&key, value)?.is_some() {
+            // already saved. it keeps its place in line
+            return Ok(());
+        }
+
+        self.insert_order
+            .insert(self.db.generate_id()?.to_be_bytes(), key)?;
+
+        let mut num_entries = self.num_entries.fetch_add(1, Ordering::AcqRel) + 1;
+
+        // remove the oldest entries until we are back under the limit
+        while num_entries > self.max_entries {
+            let Some((_, oldest)) = self.insert_order.pop_min()? else {
+                break;
+            };
+
+            if self.db.remove(oldest)?.is_some() {
+                num_entries = self.num_entries.fetch_sub(1, Ordering::AcqRel) - 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{H256, U64};
+    use serde_json::json;
+
+    fn disk_response_cache(max_entries: usize) -> DiskResponseCache {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        DiskResponseCache::from_db(db, 64, max_entries).unwrap()
+    }
+
+    fn cache_key(num: u64, params: &serde_json::Value) -> JsonRpcQueryCacheKey {
+        let block: BlockNumAndHash = (U64::from(num), H256::repeat_byte(num as u8)).into();
+
+        JsonRpcQueryCacheKey::new(Some(block), None, "eth_getBlockByNumber", params, false)
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let x = disk_response_cache(10);
+
+        let params = json!(["0x10", false]);
+        let key = cache_key(16, &params);
+
+        assert!(x.get(&key, "eth_getBlockByNumber", &params).await.is_none());
+
+        let disk_key = DiskResponseCache::disk_key(&key, "eth_getBlockByNumber", &params).unwrap();
+
+        x.insert_blocking(disk_key, b"{\"number\":\"0x10\"}".to_vec())
+            .unwrap();
+
+        let Some(JsonRpcResponseEnum::Result { value, .. }) =
+            x.get(&key, "eth_getBlockByNumber", &params).await
+        else {
+            panic!("the response should be on disk");
+        };
+
+        assert_eq!(value.get(), "{\"number\":\"0x10\"}");
+
+        // a different block is a different response
+        assert!(x
+            .get(&cache_key(17, &params), "eth_getBlockByNumber", &params)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_eviction() {
+        let x = disk_response_cache(2);
+
+        let params: Vec<_> = (1..=3)
+            .map(|i| json!([format!("{:#x}", i), false]))
+            .collect();
+        let keys: Vec<_> = params
+            .iter()
+            .enumerate()
+            .map(|(i, params)| cache_key(i as u64 + 1, params))
+            .collect();
+
+        for (key, params) in keys.iter().zip(params.iter()) {
+            let disk_key =
+                DiskResponseCache::disk_key(key, "eth_getBlockByNumber", params).unwrap();
+
+            x.insert_blocking(disk_key, b"null".to_vec()).unwrap();
+        }
+
+        assert_eq!(x.num_entries.load(Ordering::Relaxed), 2);
+        assert_eq!(x.db.len(), 2);
+
+        // the oldest entry was removed
+        assert!(x
+            .get(&keys[0], "eth_getBlockByNumber", &params[0])
+            .await
+            .is_none());
+        assert!(x
+            .get(&keys[1], "eth_getBlockByNumber", &params[1])
+            .await
+            .is_some());
+        assert!(x
+            .get(&keys[2], "eth_getBlockByNumber", &params[2])
+            .await
+            .is_some());
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::frontend::authorization::Authorization;
 use crate::jsonrpc::{JsonRpcErrorData, JsonRpcForwardedResponse};
+use crate::prometheus::MethodCounter;
 use crate::response_cache::JsonRpcResponseEnum;
 use crate::rpcs::provider::EthersHttpProvider;
 use axum::extract::ws::Message;
@@ -18,6 +19,7 @@ use http::header::InvalidHeaderValue;
 use http::uri::InvalidUri;
 use ipnet::AddrParseError;
 use migration::sea_orm::DbErr;
+use once_cell::sync::Lazy;
 use redis_rate_limiter::redis::RedisError;
 use redis_rate_limiter::RedisPoolError;
 use reqwest::header::ToStrError;
@@ -25,10 +27,29 @@ use rust_decimal::Error as DecimalError;
 use serde::Serialize;
 use serde_json::value::RawValue;
 use siwe::VerificationError;
+use std::fmt;
 use std::sync::Arc;
 use std::{borrow::Cow, net::IpAddr};
 use tokio::{sync::AcquireError, task::JoinError, time::Instant};
 use tracing::{debug, error, trace, warn};
+use ulid::Ulid;
+
+/// Error responses sent to users, by Web3ProxyError variant
+pub static ERROR_RESPONSES: Lazy<MethodCounter> = Lazy::new(Default::default);
+
+/// Internal error strings can include hostnames, queries, and other details that users shouldn't see.
+/// They are only added to the generic message if the app's expose_internal_errors is set.
+fn internal_error_message(
+    expose_internal_errors: bool,
+    generic: &'static str,
+    err: &dyn fmt::Display,
+) -> Cow<'static, str> {
+    if expose_internal_errors {
+        format!("{}: {}", generic, err).into()
+    } else {
+        generic.into()
+    }
+}
 
 pub type Web3ProxyResult<T> = Result<T, Web3ProxyError>;
 // TODO: take "IntoResponse" instead of Response?
@@ -110,6 +131,12 @@ pub enum Web3ProxyError {
         num_known: usize,
         min_head_rpcs: usize,
     },
+    #[display(fmt = "{} < {}", needed, head_block_num)]
+    #[from(ignore)]
+    NotEnoughArchiveRpcs {
+        needed: U64,
+        head_block_num: U64,
+    },
     #[display(fmt = "{}/{}", available, needed)]
     #[from(ignore)]
     NotEnoughSoftLimit {
@@ -139,6 +166,12 @@ pub enum Web3ProxyError {
     #[error(ignore)]
     #[from(ignore)]
     RefererNotAllowed(headers::Referer),
+    #[display(fmt = "{} > {}", num_bytes, max_bytes)]
+    #[from(ignore)]
+    ResponseTooLarge {
+        num_bytes: u64,
+        max_bytes: u64,
+    },
     SemaphoreAcquireError(AcquireError),
     SendAppStatError(flume::SendError<crate::stats::AppStat>),
     SerdeJson(serde_json::Error),
@@ -174,15 +207,118 @@ pub enum Web3ProxyError {
 }
 
 impl Web3ProxyError {
-    pub fn as_response_parts<R: Serialize>(&self) -> (StatusCode, JsonRpcResponseEnum<R>) {
-        // TODO: include a unique request id in the data
+    /// The status code and body for this error. This has no side effects, so it is safe to use for attempts that are retried.
+    /// Use `as_response_parts_for_request` for the response that is actually sent to the user.
+    /// `expose_internal_errors` should be the app config's.
+    #[inline]
+    pub fn as_response_parts<R: Serialize>(
+        &self,
+        expose_internal_errors: bool,
+    ) -> (StatusCode, JsonRpcResponseEnum<R>) {
+        let (code, err) = self.response_parts(expose_internal_errors);
+
+        (code, JsonRpcResponseEnum::from(err))
+    }
+
+    /// The response that is sent to the user for this error. It is reported with `report_response`
+    pub fn as_response_parts_for_request<R: Serialize>(
+        &self,
+        request_ulid: Option<Ulid>,
+        expose_internal_errors: bool,
+    ) -> (StatusCode, JsonRpcResponseEnum<R>) {
+        let (code, response_data) = self.as_response_parts(expose_internal_errors);
+
+        self.report_response(code, request_ulid);
+
+        (code, response_data)
+    }
+
+    /// Call this once for each error response that is sent to a user. It is counted in ERROR_RESPONSES.
+    /// Server errors are sent to sentry with the request's ulid as a tag. User errors are not.
+    /// Sending does nothing if sentry_url is not set.
+    pub fn report_response(&self, code: StatusCode, request_ulid: Option<Ulid>) {
+        ERROR_RESPONSES.incr(self.variant_name());
+
+        if code.is_server_error() {
+            self.capture_sentry_event(request_ulid);
+        }
+    }
+
+    /// Send the full error chain to sentry. `WithContext` errors are unwrapped so their inner errors are included
+    fn capture_sentry_event(&self, request_ulid: Option<Ulid>) {
+        if sentry::Hub::current().client().is_none() {
+            return;
+        }
+
+        let mut exceptions = self.sentry_exceptions();
+
+        // sentry wants the root cause first
+        exceptions.reverse();
+
+        let event = sentry::protocol::Event {
+            exception: exceptions.into(),
+            level: sentry::Level::Error,
+            ..Default::default()
+        };
+
+        sentry::with_scope(
+            |scope| {
+                if let Some(request_ulid) = request_ulid {
+                    scope.set_tag("request_ulid", request_ulid);
+                }
+            },
+            || sentry::capture_event(event),
+        );
+    }
+
+    /// The error and everything that caused it. Outermost first
+    fn sentry_exceptions(&self) -> Vec<sentry::protocol::Exception> {
+        let exception = |ty: &str, value: String| sentry::protocol::Exception {
+            ty: ty.to_string(),
+            value: Some(value),
+            ..Default::default()
+        };
+
+        let anyhow_chain = |err: &anyhow::Error| {
+            err.chain()
+                .map(|x| exception("anyhow", x.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        match self {
+            Self::Anyhow(err) => anyhow_chain(err),
+            Self::Arc(err) => err.sentry_exceptions(),
+            Self::StatusCode(status_code, msg, err) => {
+                let mut exceptions =
+                    vec![exception("StatusCode", format!("{}: {}", status_code, msg))];
+
+                if let Some(err) = err {
+                    exceptions.extend(anyhow_chain(err));
+                }
+
+                exceptions
+            }
+            Self::WithContext(err, msg) => {
+                let mut exceptions = vec![exception("WithContext", msg.to_string())];
+
+                if let Some(err) = err {
+                    exceptions.extend(err.sentry_exceptions());
+                }
+
+                exceptions
+            }
+            _ => vec![exception(self.variant_name(), self.to_string())],
+        }
+    }
+
+    fn response_parts(&self, expose_internal_errors: bool) -> (StatusCode, JsonRpcErrorData) {
         let (code, err): (StatusCode, JsonRpcErrorData) = match self {
             Self::Abi(err) => {
                 warn!(?err, "abi error");
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: err.to_string().into(),
+                        message: internal_error_message(expose_internal_errors, "abi error", err),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -205,8 +341,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        // TODO: is it safe to expose all of our anyhow strings?
-                        message: "INTERNAL SERVER ERROR".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "INTERNAL SERVER ERROR",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -214,7 +353,7 @@ impl Web3ProxyError {
             }
             Self::Arc(err) => {
                 // recurse
-                return err.as_response_parts();
+                return err.response_parts(expose_internal_errors);
             }
             Self::BadRequest(err) => {
                 trace!(?err, "BAD_REQUEST");
@@ -233,7 +372,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: format!("bad response: {}", err).into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "bad response",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -255,18 +398,37 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: format!("contract error: {}", err).into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "contract error",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
                 )
             }
+            Self::Database(err) if self.is_database_unavailable() => {
+                warn!("database unavailable err={:?}", err);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    JsonRpcErrorData {
+                        message: "database unavailable. try again later".into(),
+                        code: StatusCode::SERVICE_UNAVAILABLE.as_u16().into(),
+                        data: None,
+                    },
+                )
+            }
             Self::Database(err) => {
                 error!("database err={:#?}", err);
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "database error!".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "database error!",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -292,7 +454,11 @@ impl Web3ProxyError {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         JsonRpcErrorData {
-                            message: "ethers http client error".into(),
+                            message: internal_error_message(
+                                expose_internal_errors,
+                                "ethers http client error",
+                                err,
+                            ),
                             code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                             data: None,
                         },
@@ -308,7 +474,11 @@ impl Web3ProxyError {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         JsonRpcErrorData {
-                            message: "ethers provider error".into(),
+                            message: internal_error_message(
+                                expose_internal_errors,
+                                "ethers provider error",
+                                err,
+                            ),
                             code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                             data: None,
                         },
@@ -324,7 +494,11 @@ impl Web3ProxyError {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         JsonRpcErrorData {
-                            message: "ethers ws client error".into(),
+                            message: internal_error_message(
+                                expose_internal_errors,
+                                "ethers ws client error",
+                                err,
+                            ),
                             code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                             data: None,
                         },
@@ -336,7 +510,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "flume recv error!".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "flume recv error!",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -359,7 +537,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: err.to_string().into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "hdr record error",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -403,8 +585,7 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        // TODO: is it safe to expose these error strings?
-                        message: err.to_string().into(),
+                        message: internal_error_message(expose_internal_errors, "hyper error", err),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -416,7 +597,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "influxdb2 error!".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "influxdb2 error!",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -497,8 +682,7 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        // TODO: is it safe to expose our io error strings?
-                        message: err.to_string().into(),
+                        message: internal_error_message(expose_internal_errors, "io error", err),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -572,7 +756,11 @@ impl Web3ProxyError {
                     code,
                     JsonRpcErrorData {
                         // TODO: different messages of cancelled or not?
-                        message: "Unable to complete request".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "Unable to complete request",
+                            err,
+                        ),
                         code: code.as_u16().into(),
                         data: None,
                     },
@@ -587,7 +775,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: format!("msgpack encode error: {}", err).into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "msgpack encode error",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -688,6 +880,24 @@ impl Web3ProxyError {
                     },
                 )
             }
+            Self::NotEnoughArchiveRpcs {
+                needed,
+                head_block_num,
+            } => {
+                debug!(%needed, %head_block_num, "NotEnoughArchiveRpcs");
+                (
+                    StatusCode::BAD_GATEWAY,
+                    JsonRpcErrorData {
+                        message: format!(
+                            "block {} requires an archive server, but none are available (head is {})",
+                            needed, head_block_num
+                        )
+                        .into(),
+                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
+                        data: None,
+                    },
+                )
+            }
             Self::NotEnoughSoftLimit { available, needed } => {
                 error!("NotEnoughSoftLimit {}/{}", available, needed);
                 (
@@ -705,7 +915,7 @@ impl Web3ProxyError {
             }
             Self::NotFound => {
                 // TODO: emit a stat?
-                // TODO: instead of an error, show a normal html page for 404?
+                // browsers get an html page from frontend::errors::handler_404 instead
                 (
                     StatusCode::NOT_FOUND,
                     JsonRpcErrorData {
@@ -833,7 +1043,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "redis error!".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "redis error!",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -844,8 +1058,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        // TODO: is it safe to expose our io error strings?
-                        message: err.to_string().into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "redis pool error",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -873,13 +1090,34 @@ impl Web3ProxyError {
                     },
                 )
             }
+            Self::ResponseTooLarge {
+                num_bytes,
+                max_bytes,
+            } => {
+                debug!(%num_bytes, %max_bytes, "ResponseTooLarge");
+                (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    JsonRpcErrorData {
+                        message: format!(
+                            "response too large. {} bytes > {} bytes. try a smaller request",
+                            num_bytes, max_bytes
+                        )
+                        .into(),
+                        code: StatusCode::PAYLOAD_TOO_LARGE.as_u16().into(),
+                        data: None,
+                    },
+                )
+            }
             Self::SemaphoreAcquireError(err) => {
                 error!(?err, "semaphore acquire");
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        // TODO: is it safe to expose all of our anyhow strings?
-                        message: "semaphore acquire error".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "semaphore acquire error",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -890,7 +1128,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "error stat_sender sending response_stat".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "error stat_sender sending response_stat",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -1037,7 +1279,11 @@ impl Web3ProxyError {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     JsonRpcErrorData {
-                        message: "watch recv error!".into(),
+                        message: internal_error_message(
+                            expose_internal_errors,
+                            "watch recv error!",
+                            err,
+                        ),
                         code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                         data: None,
                     },
@@ -1068,14 +1314,18 @@ impl Web3ProxyError {
             Self::WithContext(err, msg) => match err {
                 Some(err) => {
                     warn!(?err, %msg, "error w/ context");
-                    return err.as_response_parts();
+                    return err.response_parts(expose_internal_errors);
                 }
                 None => {
                     warn!(%msg, "error w/ context");
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         JsonRpcErrorData {
-                            message: msg.clone(),
+                            message: internal_error_message(
+                                expose_internal_errors,
+                                "internal error",
+                                msg,
+                            ),
                             code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
                             data: None,
                         },
@@ -1084,12 +1334,113 @@ impl Web3ProxyError {
             },
         };
 
-        (code, JsonRpcResponseEnum::from(err))
+        (code, err)
+    }
+
+    /// true if the database is missing or could not be reached. Features that only need the database on the side should skip their work instead of failing the request
+    pub fn is_database_unavailable(&self) -> bool {
+        match self {
+            Self::NoDatabase => true,
+            Self::Database(DbErr::ConnectionAcquire | DbErr::Conn(_)) => true,
+            Self::WithContext(Some(err), _) => err.is_database_unavailable(),
+            _ => false,
+        }
+    }
+
+    /// The name of the error's variant. Used to label the error response metrics.
+    /// Wrapped errors use the name of the error inside them.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::Arc(err) => err.variant_name(),
+            Self::WithContext(Some(err), _) => err.variant_name(),
+            Self::WithContext(None, _) => "WithContext",
+            Self::Abi(..) => "Abi",
+            Self::AccessDenied(..) => "AccessDenied",
+            Self::Anyhow(..) => "Anyhow",
+            Self::BadRequest(..) => "BadRequest",
+            Self::BadResponse(..) => "BadResponse",
+            Self::BadRouting => "BadRouting",
+            Self::Contract(..) => "Contract",
+            Self::Database(..) => "Database",
+            Self::Decimal(..) => "Decimal",
+            Self::EthersHttpClient(..) => "EthersHttpClient",
+            Self::EthersProvider(..) => "EthersProvider",
+            Self::EthersWsClient(..) => "EthersWsClient",
+            Self::FlumeRecv(..) => "FlumeRecv",
+            Self::GasEstimateNotU256 => "GasEstimateNotU256",
+            Self::HdrRecord(..) => "HdrRecord",
+            Self::Headers(..) => "Headers",
+            Self::HeaderToString(..) => "HeaderToString",
+            Self::HttpUri(..) => "HttpUri",
+            Self::Hyper(..) => "Hyper",
+            Self::InfluxDb2Request(..) => "InfluxDb2Request",
+            Self::InvalidBlockBounds { .. } => "InvalidBlockBounds",
+            Self::InvalidHeaderValue(..) => "InvalidHeaderValue",
+            Self::InvalidEip => "InvalidEip",
+            Self::InvalidInviteCode => "InvalidInviteCode",
+            Self::Io(..) => "Io",
+            Self::UnknownReferralCode => "UnknownReferralCode",
+            Self::InvalidReferer => "InvalidReferer",
+            Self::InvalidSignatureLength => "InvalidSignatureLength",
+            Self::InvalidUserTier => "InvalidUserTier",
+            Self::InvalidUserAgent => "InvalidUserAgent",
+            Self::InvalidUserKey => "InvalidUserKey",
+            Self::IpAddrParse(..) => "IpAddrParse",
+            Self::IpNotAllowed(..) => "IpNotAllowed",
+            Self::JoinError(..) => "JoinError",
+            Self::JsonRpcErrorData(..) => "JsonRpcErrorData",
+            Self::MsgPackEncode(..) => "MsgPackEncode",
+            Self::NoBlockNumberOrHash => "NoBlockNumberOrHash",
+            Self::NoBlocksKnown => "NoBlocksKnown",
+            Self::NoConsensusHeadBlock => "NoConsensusHeadBlock",
+            Self::NoDatabase => "NoDatabase",
+            Self::NoHandleReady => "NoHandleReady",
+            Self::NoServersSynced => "NoServersSynced",
+            Self::NotEnoughRpcs { .. } => "NotEnoughRpcs",
+            Self::NotEnoughArchiveRpcs { .. } => "NotEnoughArchiveRpcs",
+            Self::NotEnoughSoftLimit { .. } => "NotEnoughSoftLimit",
+            Self::NotFound => "NotFound",
+            Self::NotImplemented(..) => "NotImplemented",
+            Self::NoVolatileRedisDatabase => "NoVolatileRedisDatabase",
+            Self::OriginRequired => "OriginRequired",
+            Self::OriginNotAllowed(..) => "OriginNotAllowed",
+            Self::ParseBytesError(..) => "ParseBytesError",
+            Self::ParseMsgError(..) => "ParseMsgError",
+            Self::ParseAddressError => "ParseAddressError",
+            Self::RateLimited(..) => "RateLimited",
+            Self::Redis(..) => "Redis",
+            Self::RedisDeadpool(..) => "RedisDeadpool",
+            Self::RefererRequired => "RefererRequired",
+            Self::RefererNotAllowed(..) => "RefererNotAllowed",
+            Self::ResponseTooLarge { .. } => "ResponseTooLarge",
+            Self::SemaphoreAcquireError(..) => "SemaphoreAcquireError",
+            Self::SendAppStatError(..) => "SendAppStatError",
+            Self::SerdeJson(..) => "SerdeJson",
+            Self::SiweVerification(..) => "SiweVerification",
+            Self::StatusCode(..) => "StatusCode",
+            Self::Timeout(..) => "Timeout",
+            Self::UlidDecode(..) => "UlidDecode",
+            Self::UnknownBlockHash(..) => "UnknownBlockHash",
+            Self::UnknownBlockNumber { .. } => "UnknownBlockNumber",
+            Self::UnknownKey => "UnknownKey",
+            Self::UserAgentRequired => "UserAgentRequired",
+            Self::UserAgentNotAllowed(..) => "UserAgentNotAllowed",
+            Self::UserIdZero => "UserIdZero",
+            Self::PaymentRequired => "PaymentRequired",
+            Self::WatchRecvError(..) => "WatchRecvError",
+            Self::WatchSendError => "WatchSendError",
+            Self::WebsocketOnly => "WebsocketOnly",
+        }
     }
 
     #[inline]
-    pub fn into_response_with_id(self, id: Option<Box<RawValue>>) -> Response {
-        let (status_code, response_data) = self.as_response_parts();
+    pub fn into_response_with_id(
+        self,
+        id: Option<Box<RawValue>>,
+        expose_internal_errors: bool,
+    ) -> Response {
+        let (status_code, response_data) =
+            self.as_response_parts_for_request(None, expose_internal_errors);
 
         let id = id.unwrap_or_default();
 
@@ -1112,9 +1463,10 @@ impl From<tokio::time::error::Elapsed> for Web3ProxyError {
 }
 
 impl IntoResponse for Web3ProxyError {
+    /// There is no app config here, so internal errors are never exposed
     #[inline]
     fn into_response(self) -> Response {
-        self.into_response_with_id(Default::default())
+        self.into_response_with_id(Default::default(), false)
     }
 }
 
@@ -1138,8 +1490,8 @@ where
 }
 
 impl Web3ProxyError {
-    pub fn into_message(self, id: Option<Box<RawValue>>) -> Message {
-        let (_, err) = self.as_response_parts();
+    pub fn into_message(self, id: Option<Box<RawValue>>, expose_internal_errors: bool) -> Message {
+        let (_, err) = self.as_response_parts_for_request(None, expose_internal_errors);
 
         let id = id.unwrap_or_default();
 
@@ -1151,3 +1503,24 @@ impl Web3ProxyError {
         Message::Text(msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expose_internal_errors() {
+        let message = |expose_internal_errors| {
+            let err = Web3ProxyError::WithContext(None, "secret hostname".into());
+
+            match err.as_response_parts::<()>(expose_internal_errors).1 {
+                JsonRpcResponseEnum::RpcError { error_data, .. } => error_data.message,
+                x => panic!("unexpected response: {:?}", x),
+            }
+        };
+
+        // each app passes its own setting, so one app exposing errors doesn't change another's responses
+        assert_eq!(message(true), "internal error: secret hostname");
+        assert_eq!(message(false), "internal error");
+    }
+}
@@ -0,0 +1,422 @@
+//! Cache for `eth_feeHistory`.
+//! Consecutive callers usually ask for overlapping block ranges, so the history is stored per block and sliced.
+use crate::rpcs::blockchain::Web3ProxyBlock;
+use ethers::prelude::{BlockNumber, H256, U256, U64};
+use hashbrown::HashMap;
+use itertools::Itertools;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::{debug, trace};
+
+/// geth doesn't serve more than 1024 blocks of fee history. we don't store more than that either
+pub const FEE_HISTORY_MAX_BLOCKS: u64 = 1024;
+
+/// users can ask for any percentiles. only keep a few different sets of them for each block
+const MAX_REWARD_PERCENTILES_PER_BLOCK: usize = 8;
+
+/// how many blocks of rewards to look at when a backend doesn't have eth_maxPriorityFeePerGas
+pub const PRIORITY_FEE_BLOCKS: u64 = 20;
+
+/// which reward percentile to look at when a backend doesn't have eth_maxPriorityFeePerGas
+pub const PRIORITY_FEE_PERCENTILE: f64 = 50.0;
+
+/// 1.5 gwei. used when a backend doesn't have eth_maxPriorityFeePerGas and there is no fee history either
+pub const DEFAULT_MAX_PRIORITY_FEE_PER_GAS: u64 = 1_500_000_000;
+
+/// The response to `eth_feeHistory`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistory {
+    pub oldest_block: U64,
+    /// this has one more item than gas_used_ratio. the last one is the base fee of the block after the newest block
+    pub base_fee_per_gas: Vec<U256>,
+    pub gas_used_ratio: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reward: Option<Vec<Vec<U256>>>,
+}
+
+impl FeeHistory {
+    /// The median of the first reward percentile across every block.
+    /// Empty blocks have a reward of 0 and are skipped. None if there are no rewards.
+    pub fn median_reward(&self) -> Option<U256> {
+        let mut rewards: Vec<U256> = self
+            .reward
+            .as_ref()?
+            .iter()
+            .filter_map(|x| x.first())
+            .filter(|x| !x.is_zero())
+            .copied()
+            .collect();
+
+        if rewards.is_empty() {
+            return None;
+        }
+
+        rewards.sort();
+
+        Some(rewards[rewards.len() / 2])
+    }
+}
+
+/// The params for `eth_feeHistory`
+#[derive(Debug)]
+pub struct FeeHistoryParams {
+    pub block_count: u64,
+    pub newest_block: BlockNumber,
+    pub reward_percentiles: Vec<f64>,
+}
+
+impl FeeHistoryParams {
+    /// returns None if the params are not valid. the backends will give the user a proper error
+    pub fn try_from_params(params: &serde_json::Value) -> Option<Self> {
+        let params = params.as_array()?;
+
+        // geth accepts the block count as a decimal number or as a hex string
+        let block_count = match params.get(0)? {
+            serde_json::Value::Number(x) => x.as_u64()?,
+            x @ serde_json::Value::String(_) => {
+                serde_json::from_value::<U64>(x.clone()).ok()?.as_u64()
+            }
+            _ => return None,
+        };
+
+        let newest_block = serde_json::from_value(params.get(1)?.clone()).ok()?;
+
+        let reward_percentiles = match params.get(2) {
+            None | Some(serde_json::Value::Null) => vec![],
+            Some(x) => serde_json::from_value(x.clone()).ok()?,
+        };
+
+        Some(Self {
+            block_count,
+            newest_block,
+            reward_percentiles,
+        })
+    }
+
+    /// Backends error if eth_feeHistory asks for more blocks than they allow. Ask for fewer blocks instead.
+    /// Returns the original block count if it was clamped.
+    pub fn clamp_block_count(params: &mut serde_json::Value, max_block_count: u64) -> Option<u64> {
+        let x = Self::try_from_params(params)?;
+
+        if x.block_count <= max_block_count {
+            return None;
+        }
+
+        params[0] = serde_json::json!(U64::from(max_block_count));
+
+        Some(x.block_count)
+    }
+}
+
+#[derive(Debug, Default)]
+struct FeeHistoryBlock {
+    /// only known for blocks that we saw as a consensus head. used to detect reorgs
+    hash: Option<H256>,
+    base_fee_per_gas: U256,
+    /// the base fee of the following block
+    next_base_fee_per_gas: Option<U256>,
+    gas_used_ratio: f64,
+    /// rewards keyed by the percentiles that were requested
+    rewards: HashMap<String, Vec<U256>>,
+}
+
+impl FeeHistoryBlock {
+    fn is_complete(&self, percentiles_key: Option<&str>) -> bool {
+        self.next_base_fee_per_gas.is_some()
+            && percentiles_key
+                .map(|x| self.rewards.contains_key(x))
+                .unwrap_or(true)
+    }
+}
+
+/// Fee history for the most recent blocks, indexed by block number.
+#[derive(Debug, Default)]
+pub struct FeeHistoryCache {
+    blocks: RwLock<BTreeMap<u64, FeeHistoryBlock>>,
+}
+
+fn percentiles_key(percentiles: &[f64]) -> Option<String> {
+    if percentiles.is_empty() {
+        None
+    } else {
+        Some(percentiles.iter().join(","))
+    }
+}
+
+impl FeeHistoryCache {
+    /// save what we can from the header of a new consensus head.
+    /// rewards need every receipt in the block, so those are only filled in when a user asks for them
+    pub fn on_new_head(&self, head_block: &Web3ProxyBlock) {
+        let num = head_block.number().as_u64();
+        let block = &head_block.block;
+
+        let base_fee_per_gas = block.base_fee_per_gas.unwrap_or_default();
+
+        let gas_used_ratio = if block.gas_limit.is_zero() {
+            0.0
+        } else {
+            block.gas_used.low_u128() as f64 / block.gas_limit.low_u128() as f64
+        };
+
+        let mut blocks = self.blocks.write();
+
+        if blocks.get(&num).and_then(|x| x.hash) == Some(*head_block.hash()) {
+            trace!(%num, "fee history already has this head");
+            return;
+        }
+
+        // anything at or above the new head is from an old fork
+        blocks.retain(|x, _| *x < num);
+
+        let parent_matches = num
+            .checked_sub(1)
+            .and_then(|x| blocks.get(&x))
+            .and_then(|x| x.hash)
+            .map(|x| x == *head_block.parent_hash())
+            .unwrap_or(true);
+
+        if parent_matches {
+            // the base fee of this block is the "next" base fee of its parent
+            if let Some(parent) = num.checked_sub(1).and_then(|x| blocks.get_mut(&x)) {
+                parent.next_base_fee_per_gas = Some(base_fee_per_gas);
+            }
+        } else {
+            // reorgs are rare. don't bother figuring out how far back this one went
+            debug!(%num, "reorg detected. clearing fee history");
+            blocks.clear();
+        }
+
+        blocks.insert(
+            num,
+            FeeHistoryBlock {
+                hash: Some(*head_block.hash()),
+                base_fee_per_gas,
+                next_base_fee_per_gas: None,
+                gas_used_ratio,
+                rewards: Default::default(),
+            },
+        );
+
+        // forget blocks that are too old to be served from here
+        if let Some(oldest) = num.checked_sub(FEE_HISTORY_MAX_BLOCKS) {
+            blocks.retain(|x, _| *x >= oldest);
+        }
+    }
+
+    /// save a response from a backend
+    pub fn insert(&self, fee_history: &FeeHistory, percentiles: &[f64]) {
+        let percentiles_key = percentiles_key(percentiles);
+
+        let oldest = fee_history.oldest_block.as_u64();
+
+        let mut blocks = self.blocks.write();
+
+        for (i, gas_used_ratio) in fee_history.gas_used_ratio.iter().enumerate() {
+            let (Some(base_fee_per_gas), Some(next_base_fee_per_gas)) = (
+                fee_history.base_fee_per_gas.get(i),
+                fee_history.base_fee_per_gas.get(i + 1),
+            ) else {
+                break;
+            };
+
+            let entry = blocks.entry(oldest + i as u64).or_default();
+
+            entry.base_fee_per_gas = *base_fee_per_gas;
+            entry.next_base_fee_per_gas = Some(*next_base_fee_per_gas);
+            entry.gas_used_ratio = *gas_used_ratio;
+
+            if let Some(ref percentiles_key) = percentiles_key {
+                if let Some(reward) = fee_history.reward.as_ref().and_then(|x| x.get(i)) {
+                    if entry.rewards.len() < MAX_REWARD_PERCENTILES_PER_BLOCK
+                        || entry.rewards.contains_key(percentiles_key)
+                    {
+                        entry
+                            .rewards
+                            .insert(percentiles_key.clone(), reward.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// the smallest range of blocks that covers everything this cache can't answer
+    pub fn missing(&self, oldest: u64, newest: u64, percentiles: &[f64]) -> Option<(u64, u64)> {
+        let percentiles_key = percentiles_key(percentiles);
+
+        let blocks = self.blocks.read();
+
+        let mut missing = None;
+
+        for num in oldest..=newest {
+            let complete = blocks
+                .get(&num)
+                .map(|x| x.is_complete(percentiles_key.as_deref()))
+                .unwrap_or(false);
+
+            if !complete {
+                missing = match missing {
+                    None => Some((num, num)),
+                    Some((first, _)) => Some((first, num)),
+                };
+            }
+        }
+
+        missing
+    }
+
+    /// slice the cached history. returns None if any block in the range is missing
+    pub fn get(&self, oldest: u64, newest: u64, percentiles: &[f64]) -> Option<FeeHistory> {
+        let percentiles_key = percentiles_key(percentiles);
+
+        let blocks = self.blocks.read();
+
+        let num_blocks = (newest + 1).checked_sub(oldest)? as usize;
+
+        let mut base_fee_per_gas = Vec::with_capacity(num_blocks + 1);
+        let mut gas_used_ratio = Vec::with_capacity(num_blocks);
+        let mut reward = percentiles_key
+            .as_ref()
+            .map(|_| Vec::with_capacity(num_blocks));
+
+        for num in oldest..=newest {
+            let block = blocks.get(&num)?;
+
+            base_fee_per_gas.push(block.base_fee_per_gas);
+            gas_used_ratio.push(block.gas_used_ratio);
+
+            if let (Some(reward), Some(percentiles_key)) =
+                (reward.as_mut(), percentiles_key.as_ref())
+            {
+                reward.push(block.rewards.get(percentiles_key)?.clone());
+            }
+
+            if num == newest {
+                base_fee_per_gas.push(block.next_base_fee_per_gas?);
+            }
+        }
+
+        Some(FeeHistory {
+            oldest_block: oldest.into(),
+            base_fee_per_gas,
+            gas_used_ratio,
+            reward,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_fee_history_slicing() {
+        let cache = FeeHistoryCache::default();
+
+        let percentiles = [25.0, 75.0];
+
+        let fee_history = FeeHistory {
+            oldest_block: 100.into(),
+            base_fee_per_gas: vec![10.into(), 11.into(), 12.into(), 13.into()],
+            gas_used_ratio: vec![0.5, 0.6, 0.7],
+            reward: Some(vec![
+                vec![1.into(), 2.into()],
+                vec![3.into(), 4.into()],
+                vec![5.into(), 6.into()],
+            ]),
+        };
+
+        assert_eq!(cache.missing(100, 102, &percentiles), Some((100, 102)));
+
+        cache.insert(&fee_history, &percentiles);
+
+        assert_eq!(cache.missing(100, 102, &percentiles), None);
+        assert_eq!(cache.missing(99, 103, &percentiles), Some((99, 103)));
+        assert_eq!(cache.missing(101, 104, &percentiles), Some((103, 104)));
+
+        // different percentiles need another trip to the backends
+        assert_eq!(cache.missing(100, 102, &[50.0]), Some((100, 102)));
+
+        // no percentiles can be served from anything
+        assert_eq!(cache.missing(100, 102, &[]), None);
+
+        assert_eq!(cache.get(100, 102, &percentiles), Some(fee_history));
+
+        let sliced = cache.get(101, 101, &[]).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(sliced).unwrap(),
+            json!({
+                "oldestBlock": "0x65",
+                "baseFeePerGas": ["0xb", "0xc"],
+                "gasUsedRatio": [0.6],
+            })
+        );
+    }
+
+    #[test]
+    fn test_fee_history_params() {
+        let params =
+            FeeHistoryParams::try_from_params(&json!(["0x4", "latest", [25, 75]])).unwrap();
+
+        assert_eq!(params.block_count, 4);
+        assert_eq!(params.newest_block, BlockNumber::Latest);
+        assert_eq!(params.reward_percentiles, vec![25.0, 75.0]);
+
+        let params = FeeHistoryParams::try_from_params(&json!([4, "0x10"])).unwrap();
+
+        assert_eq!(params.block_count, 4);
+        assert_eq!(params.newest_block, BlockNumber::Number(16.into()));
+        assert!(params.reward_percentiles.is_empty());
+
+        assert!(FeeHistoryParams::try_from_params(&json!([])).is_none());
+    }
+
+    #[test]
+    fn test_fee_history_block_count_clamp() {
+        let mut params = json!([2048, "latest", [50]]);
+
+        assert_eq!(
+            FeeHistoryParams::clamp_block_count(&mut params, FEE_HISTORY_MAX_BLOCKS),
+            Some(2048)
+        );
+        assert_eq!(params, json!(["0x400", "latest", [50]]));
+
+        let params = FeeHistoryParams::try_from_params(&params).unwrap();
+        assert_eq!(params.block_count, FEE_HISTORY_MAX_BLOCKS);
+
+        // requests under the cap are left alone
+        let mut params = json!(["0x10", "latest"]);
+
+        assert_eq!(FeeHistoryParams::clamp_block_count(&mut params, 1024), None);
+        assert_eq!(params, json!(["0x10", "latest"]));
+    }
+
+    #[test]
+    fn test_fee_history_median_reward() {
+        let mut fee_history = FeeHistory {
+            oldest_block: 100.into(),
+            base_fee_per_gas: vec![10.into(); 5],
+            gas_used_ratio: vec![0.5; 4],
+            reward: Some(vec![
+                vec![3.into()],
+                // empty blocks don't count
+                vec![0.into()],
+                vec![1.into()],
+                vec![2.into()],
+            ]),
+        };
+
+        assert_eq!(fee_history.median_reward(), Some(2.into()));
+
+        fee_history.reward = Some(vec![vec![0.into()]; 4]);
+
+        assert_eq!(fee_history.median_reward(), None);
+
+        fee_history.reward = None;
+
+        assert_eq!(fee_history.median_reward(), None);
+    }
+}
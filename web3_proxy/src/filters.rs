@@ -0,0 +1,167 @@
+//! Filters for `eth_newFilter` and `eth_newBlockFilter`.
+//! Filters are kept in this proxy's memory and are forgotten after `filter_ttl` seconds without a poll, like geth's.
+//! Polls of unknown or forgotten ids get geth's "filter not found" error so that clients know to create a new filter.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use ethers::types::U64;
+use moka::future::{Cache, CacheBuilder};
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use ulid::Ulid;
+
+#[derive(Debug)]
+pub enum FilterKind {
+    /// from eth_newBlockFilter
+    Blocks,
+    /// from eth_newFilter. the filter object from the request
+    Logs(serde_json::Map<String, serde_json::Value>),
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// eth_getFilterChanges returns what happened after this block
+    pub last_block: U64,
+}
+
+/// Filters by id. Each filter has its own lock so that concurrent polls don't return the same changes twice
+pub type FilterCache = Cache<String, Arc<Mutex<Filter>>>;
+
+pub fn filter_cache(max_capacity: u64, ttl_seconds: u64) -> FilterCache {
+    CacheBuilder::new(max_capacity)
+        .name("filters")
+        .time_to_idle(Duration::from_secs(ttl_seconds))
+        .build()
+}
+
+/// A new, unguessable filter id. Hex like geth's
+pub fn new_filter_id() -> String {
+    format!("{:#x}", Ulid::new().0)
+}
+
+/// The filter id from the params of eth_getFilterChanges, eth_getFilterLogs, or eth_uninstallFilter
+pub fn filter_id_from_params(method: &str, params: &serde_json::Value) -> Web3ProxyResult<String> {
+    params
+        .get(0)
+        .and_then(|x| x.as_str())
+        .map(|x| x.to_lowercase())
+        .ok_or_else(|| Web3ProxyError::BadRequest(format!("{} expects a filter id", method).into()))
+}
+
+/// The filter object from the params of eth_newFilter
+pub fn logs_filter_from_params(
+    params: &serde_json::Value,
+) -> Web3ProxyResult<serde_json::Map<String, serde_json::Value>> {
+    let filter = params.get(0).and_then(|x| x.as_object()).ok_or_else(|| {
+        Web3ProxyError::BadRequest("eth_newFilter expects a filter object".into())
+    })?;
+
+    // a block hash is one block. there would never be any changes to poll for
+    if filter.contains_key("blockHash") {
+        return Err(Web3ProxyError::BadRequest(
+            "eth_newFilter does not take a blockHash".into(),
+        ));
+    }
+
+    Ok(filter.clone())
+}
+
+/// The params for an eth_getLogs query of the filter's logs after last_block.
+/// None if there can't be any new logs, either because there are no new blocks or because the filter's toBlock has passed.
+pub fn logs_changes_params(
+    filter: &serde_json::Map<String, serde_json::Value>,
+    last_block: U64,
+    head_block: U64,
+) -> Option<serde_json::Value> {
+    let mut from_block = last_block + 1;
+    let mut to_block = head_block;
+
+    // tags like "latest" follow the head
+    if let Some(x) = filter.get("fromBlock").and_then(block_num) {
+        from_block = from_block.max(x);
+    }
+
+    if let Some(x) = filter.get("toBlock").and_then(block_num) {
+        to_block = to_block.min(x);
+    }
+
+    if from_block > to_block {
+        return None;
+    }
+
+    let mut filter = filter.clone();
+
+    filter.insert("fromBlock".to_string(), json!(from_block));
+    filter.insert("toBlock".to_string(), json!(to_block));
+
+    Some(json!([filter]))
+}
+
+/// None for block tags
+fn block_num(x: &serde_json::Value) -> Option<U64> {
+    x.as_str().and_then(|x| U64::from_str(x).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(x: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        logs_filter_from_params(&json!([x])).unwrap()
+    }
+
+    #[test]
+    fn test_logs_changes_params() {
+        let address = "0x000000000000000000000000000000000000beef";
+
+        // only the blocks after the last poll
+        assert_eq!(
+            logs_changes_params(&filter(json!({"address": address})), 10.into(), 12.into()),
+            Some(json!([{"address": address, "fromBlock": "0xb", "toBlock": "0xc"}]))
+        );
+
+        // no new blocks
+        assert_eq!(
+            logs_changes_params(&filter(json!({})), 12.into(), 12.into()),
+            None
+        );
+
+        // tags follow the head
+        assert_eq!(
+            logs_changes_params(
+                &filter(json!({"fromBlock": "earliest", "toBlock": "latest"})),
+                10.into(),
+                12.into()
+            ),
+            Some(json!([{"fromBlock": "0xb", "toBlock": "0xc"}]))
+        );
+
+        // the filter's own range still applies
+        assert_eq!(
+            logs_changes_params(
+                &filter(json!({"fromBlock": "0x14", "toBlock": "0x1e"})),
+                10.into(),
+                40.into()
+            ),
+            Some(json!([{"fromBlock": "0x14", "toBlock": "0x1e"}]))
+        );
+        assert_eq!(
+            logs_changes_params(&filter(json!({"toBlock": "0x5"})), 10.into(), 12.into()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filter_params() {
+        assert!(logs_filter_from_params(&json!([{"blockHash": "0x1234"}])).is_err());
+        assert!(logs_filter_from_params(&json!([])).is_err());
+
+        assert_eq!(
+            filter_id_from_params("eth_getFilterChanges", &json!(["0xABC"])).unwrap(),
+            "0xabc"
+        );
+        assert!(filter_id_from_params("eth_getFilterChanges", &json!([1])).is_err());
+    }
+}
@@ -3,8 +3,10 @@
 use super::authorization::login_is_authorized;
 use crate::admin_queries::query_admin_modify_usertier;
 use crate::app::Web3ProxyApp;
+use crate::config::Web3RpcConfig;
 use crate::errors::Web3ProxyResponse;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext};
+use crate::rpcs::many::Web3Rpcs;
 use crate::user_token::UserBearerToken;
 use crate::PostLogin;
 use axum::{
@@ -115,6 +117,60 @@ pub async fn admin_increase_balance(
     Ok(Json(out).into_response())
 }
 
+#[derive(Deserialize)]
+pub struct AdminReloadRpcsPost {
+    balanced_rpcs: HashMap<String, Web3RpcConfig>,
+    private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
+    secondary_private_rpcs: Option<HashMap<String, Web3RpcConfig>>,
+}
+
+/// `POST /admin/reload_rpcs` -- As an admin, replace the backend rpcs without restarting
+///
+/// - balanced_rpcs is required. private_rpcs and secondary_private_rpcs are left alone if they are not given
+/// - rpcs with changed configs are reconnected. removed rpcs finish their in-flight requests and then disconnect
+#[debug_handler]
+pub async fn admin_reload_rpcs(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AdminReloadRpcsPost>,
+) -> Web3ProxyResponse {
+    let (caller, _semaphore) = app.bearer_is_authorized(bearer).await?;
+
+    let db_replica = app.db_replica()?;
+
+    // Check if the caller is an admin (if not, return early)
+    admin::Entity::find()
+        .filter(admin::Column::UserId.eq(caller.id))
+        .one(db_replica.as_ref())
+        .await?
+        .ok_or_else(|| Web3ProxyError::AccessDenied("not an admin".into()))?;
+
+    info!(admin_id = caller.id, "reloading rpc configs");
+
+    app.reload_rpc_configs(
+        payload.balanced_rpcs,
+        payload.private_rpcs,
+        payload.secondary_private_rpcs,
+    )
+    .await?;
+
+    let names = |x: Option<&Arc<Web3Rpcs>>| {
+        x.map(|x| {
+            let mut names: Vec<_> = x.by_name.read().keys().cloned().collect();
+            names.sort();
+            names
+        })
+    };
+
+    let out = json!({
+        "balanced_rpcs": names(Some(&app.balanced_rpcs)),
+        "private_rpcs": names(app.private_rpcs.as_ref()),
+        "secondary_private_rpcs": names(app.secondary_private_rpcs.as_ref()),
+    });
+
+    Ok(Json(out).into_response())
+}
+
 /// `POST /admin/modify_role` -- As an admin, modify a user's user-tier
 ///
 /// - user_address that is to be modified
@@ -2,12 +2,20 @@
 
 use super::rpc_proxy_ws::ProxyMode;
 use crate::app::{Web3ProxyApp, APP_USER_AGENT};
+use crate::compute_unit_quota::ComputeUnitQuota;
+use crate::compute_units::ComputeUnit;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
-use crate::jsonrpc::{JsonRpcForwardedResponse, JsonRpcRequest};
+use crate::ip_filter::{ip_in_networks, ip_is_allowed};
+use crate::jsonrpc::{serialized_len, JsonRpcForwardedResponse, JsonRpcRequest};
+use crate::method_filter::MethodFilter;
+use crate::response_cache::CacheStatus;
 use crate::rpcs::blockchain::Web3ProxyBlock;
 use crate::rpcs::one::Web3Rpc;
+use crate::rpcs::priority_semaphore::RequestPriority;
 use crate::stats::{AppStat, BackendRequests, RpcQueryStats};
+use crate::trace_limits::TraceLimits;
 use crate::user_token::UserBearerToken;
+use crate::webhooks::WebhookEvent;
 use anyhow::Context;
 use axum::headers::authorization::Bearer;
 use axum::headers::{Header, Origin, Referer, UserAgent};
@@ -25,7 +33,9 @@ use ipnet::IpNet;
 use migration::sea_orm::prelude::Decimal;
 use migration::sea_orm::{self, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use migration::{Expr, OnConflict};
-use parking_lot::RwLock;
+use num_traits::ToPrimitive;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
 use rdkafka::message::{Header as KafkaHeader, OwnedHeaders as KafkaOwnedHeaders, OwnedMessage};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::Timeout as KafkaTimeout;
@@ -43,7 +53,7 @@ use std::{net::IpAddr, str::FromStr, sync::Arc};
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use tracing::{error, trace, warn};
+use tracing::{error, info, trace, warn};
 use ulid::Ulid;
 use uuid::Uuid;
 
@@ -134,10 +144,19 @@ pub struct AuthorizationChecks {
     /// depending on the caller, errors might be expected. this keeps us from bloating our database
     /// u16::MAX == 100%
     pub log_revert_chance: u16,
+    /// the app's log_revert_chance_by_method. these override log_revert_chance. Methods not in here use log_revert_chance
+    pub log_revert_chances: HashMap<String, u16>,
     /// if true, transactions are broadcast only to private mempools.
     /// IMPORTANT! Once confirmed by a miner, they will be public on the blockchain!
     pub private_txs: bool,
     pub proxy_mode: ProxyMode,
+    /// the user tier's allowed and blocked methods from the app config. None if the tier has none
+    pub method_filter: Option<MethodFilter>,
+    /// the user tier's trace limits from the app config. None uses the app's limits
+    pub trace_limits: Option<TraceLimits>,
+    /// if true, the user can call methods in the app's requires_premium.
+    /// paid tiers are the ones with a downgrade tier. users that run out of funds are already downgraded
+    pub premium: bool,
 }
 
 /// TODO: include the authorization checks in this?
@@ -150,6 +169,8 @@ pub struct Authorization {
     pub referer: Option<Referer>,
     pub user_agent: Option<UserAgent>,
     pub authorization_type: AuthorizationType,
+    /// skip the response cache. set by the X-No-Cache header
+    pub no_cache: bool,
 }
 
 pub struct KafkaDebugLogger {
@@ -310,10 +331,25 @@ impl KafkaDebugLogger {
 
 #[derive(Debug)]
 pub struct RequestMetadata {
+    /// If true, a structured log line is emitted when the request completes
+    pub access_log: bool,
+
+    /// If the request takes longer than this, a warning is logged when it completes
+    pub slow_request_threshold: Option<Duration>,
+
+    /// Size of the JSON request's params
+    pub params_bytes: usize,
+
+    /// The start of the JSON request's params. Only set if slow_request_threshold is set
+    pub params_preview: String,
+
     /// TODO: set archive_request during the new instead of after
     /// TODO: this is more complex than "requires a block older than X height". different types of data can be pruned differently
     pub archive_request: AtomicBool,
 
+    /// How much more this method costs if it turns out to be an archive request
+    pub archive_multiplier: Decimal,
+
     pub authorization: Option<Arc<Authorization>>,
 
     pub chain_id: u64,
@@ -332,8 +368,12 @@ pub struct RequestMetadata {
     /// if this is empty, there was a cache_hit
     /// otherwise, it is populated with any rpc servers that were used by this request
     pub backend_requests: BackendRequests,
+    /// If the response came out of the jsonrpc_response_cache. None if the cache was never checked
+    pub cache_status: Mutex<Option<CacheStatus>>,
     /// The number of times the request got stuck waiting because no servers were synced
     pub no_servers: AtomicU64,
+    /// How many times the request was sent to another rpc after a transport error
+    pub retries: AtomicU64,
     /// If handling the request hit an application error
     /// This does not count things like a transcation reverting or a malformed request
     pub error_response: AtomicBool,
@@ -354,6 +394,15 @@ pub struct RequestMetadata {
 
     /// Cancel-safe channel for sending stats to the buffer
     pub stat_sender: Option<flume::Sender<AppStat>>,
+
+    /// Requests in a pinned batch all go to this rpc if it can serve them
+    pub pinned_rpc: OnceCell<Arc<Web3Rpc>>,
+
+    /// Count this request's compute units against the user's quota. Taken once they are counted, or if the request was over the quota
+    pub compute_unit_quota: Mutex<Option<ComputeUnitQuota>>,
+
+    /// Compute units taken from the user's quota before the request was sent
+    pub compute_units_reserved: AtomicU64,
 }
 
 impl Default for Authorization {
@@ -366,20 +415,30 @@ impl Default for Authorization {
 impl Default for RequestMetadata {
     fn default() -> Self {
         Self {
+            access_log: Default::default(),
+            slow_request_threshold: Default::default(),
+            params_bytes: Default::default(),
+            params_preview: Default::default(),
             archive_request: Default::default(),
+            archive_multiplier: ComputeUnit::default_archive_multiplier(),
             authorization: Default::default(),
             backend_requests: Default::default(),
+            cache_status: Default::default(),
             chain_id: Default::default(),
+            compute_unit_quota: Default::default(),
+            compute_units_reserved: Default::default(),
             error_response: Default::default(),
             kafka_debug_logger: Default::default(),
             method: Default::default(),
             no_servers: Default::default(),
+            pinned_rpc: Default::default(),
             request_bytes: Default::default(),
             request_ulid: Default::default(),
             response_bytes: Default::default(),
             response_from_backup_rpc: Default::default(),
             response_millis: Default::default(),
             response_timestamp: Default::default(),
+            retries: Default::default(),
             start_instant: Instant::now(),
             stat_sender: Default::default(),
         }
@@ -406,6 +465,19 @@ impl RequestMetadata {
     }
 }
 
+/// The most characters of params included in the slow request log
+const SLOW_REQUEST_PARAMS_PREVIEW: usize = 200;
+
+/// keep the slow request log from leaking large payloads
+fn truncate_params(mut params: String) -> String {
+    if let Some((i, _)) = params.char_indices().nth(SLOW_REQUEST_PARAMS_PREVIEW) {
+        params.truncate(i);
+        params.push_str("...");
+    }
+
+    params
+}
+
 #[derive(From)]
 pub enum RequestOrMethod<'a> {
     /// jsonrpc method (or similar label) and the size that the request should count as (sometimes 0)
@@ -461,12 +533,8 @@ impl<'a> From<u64> for ResponseOrBytes<'a> {
 impl ResponseOrBytes<'_> {
     pub fn num_bytes(&self) -> usize {
         match self {
-            Self::Json(x) => serde_json::to_string(x)
-                .expect("this should always serialize")
-                .len(),
-            Self::Response(x) => serde_json::to_string(x)
-                .expect("this should always serialize")
-                .len(),
+            Self::Json(x) => serialized_len(x),
+            Self::Response(x) => x.num_bytes(),
             Self::Bytes(num_bytes) => *num_bytes,
         }
     }
@@ -513,21 +581,49 @@ impl RequestMetadata {
             }
         }
 
+        let archive_multiplier =
+            ComputeUnit::archive_multiplier(&method, &app.config.archive_multipliers);
+
+        let compute_unit_quota = app.compute_unit_quota_for(&authorization).cloned().into();
+
+        let slow_request_threshold = app.config.slow_request_ms.map(Duration::from_millis);
+
+        let params_bytes = request
+            .jsonrpc_request()
+            .map(|x| x.params_bytes())
+            .unwrap_or_default();
+
+        // params can be huge. only copy them if the slow request log might need them
+        let params_preview = match request.jsonrpc_request() {
+            Some(x) if slow_request_threshold.is_some() => truncate_params(x.params.to_string()),
+            _ => String::new(),
+        };
+
         let x = Self {
+            access_log: app.config.access_log,
+            slow_request_threshold,
+            params_bytes,
+            params_preview,
             archive_request: false.into(),
+            archive_multiplier,
             authorization: Some(authorization),
             backend_requests: Default::default(),
+            cache_status: Default::default(),
             chain_id: app.config.chain_id,
+            compute_unit_quota,
+            compute_units_reserved: 0.into(),
             error_response: false.into(),
             kafka_debug_logger,
             method,
             no_servers: 0.into(),
+            pinned_rpc: OnceCell::new(),
             request_bytes,
             request_ulid,
             response_bytes: 0.into(),
             response_from_backup_rpc: false.into(),
             response_millis: 0.into(),
             response_timestamp: 0.into(),
+            retries: 0.into(),
             start_instant: Instant::now(),
             stat_sender: app.stat_sender.clone(),
         };
@@ -539,7 +635,197 @@ impl RequestMetadata {
         self.backend_requests.lock().clone()
     }
 
+    /// Some requests check the cache multiple times. They are only a hit if every check hit
+    pub fn record_cache_status(&self, cache_status: CacheStatus) {
+        let mut x = self.cache_status.lock();
+
+        *x = CacheStatus::merge_opt(*x, Some(cache_status));
+    }
+
+    pub fn cache_status(&self) -> Option<CacheStatus> {
+        *self.cache_status.lock()
+    }
+
+    /// Emit a single json log line describing this request. Only logs once.
+    /// This is separate from the stats so that operators can get it without a database.
+    pub fn log_access(&mut self) {
+        if !mem::take(&mut self.access_log) {
+            return;
+        }
+
+        #[derive(Serialize)]
+        struct AccessLog<'a> {
+            request_id: String,
+            chain_id: u64,
+            method: &'a str,
+            backend_rpcs: Vec<String>,
+            cache_hit: bool,
+            compute_units: Decimal,
+            error_response: bool,
+            archive_request: bool,
+            request_bytes: usize,
+            response_bytes: u64,
+            response_millis: u64,
+            retries: u64,
+        }
+
+        let backend_rpcs: Vec<_> = self
+            .backend_requests
+            .lock()
+            .iter()
+            .map(|x| x.name.clone())
+            .collect();
+
+        let response_bytes = self.response_bytes.load(atomic::Ordering::Acquire);
+
+        let response_millis = match self.response_millis.load(atomic::Ordering::Acquire) {
+            // no response was recorded. use the time that the request has taken so far
+            0 => self.start_instant.elapsed().as_millis() as u64,
+            x => x,
+        };
+
+        let compute_units = ComputeUnit::new(&self.method, self.chain_id, response_bytes).units();
+
+        let x = AccessLog {
+            request_id: self.request_ulid.to_string(),
+            chain_id: self.chain_id,
+            method: &self.method,
+            // rejected, locally answered and failed requests have no backend rpcs either. only count real hits
+            cache_hit: matches!(self.cache_status(), Some(CacheStatus::Hit { .. })),
+            backend_rpcs,
+            compute_units,
+            error_response: self.error_response.load(atomic::Ordering::Acquire),
+            archive_request: self.archive_request.load(atomic::Ordering::Acquire),
+            request_bytes: self.request_bytes,
+            response_bytes,
+            response_millis,
+            retries: self.retries.load(atomic::Ordering::Acquire),
+        };
+
+        match serde_json::to_string(&x) {
+            Ok(x) => info!(target: "web3_proxy::access_log", "{}", x),
+            Err(err) => warn!(?err, "unable to serialize access log"),
+        }
+    }
+
+    /// Log a warning if this request took longer than slow_request_threshold. Only logs once.
+    pub fn log_slow_request(&mut self) {
+        let Some(threshold) = self.slow_request_threshold.take() else {
+            return;
+        };
+
+        let response_millis = match self.response_millis.load(atomic::Ordering::Acquire) {
+            // no response was recorded. use the time that the request has taken so far
+            0 => self.start_instant.elapsed().as_millis() as u64,
+            x => x,
+        };
+
+        if response_millis <= threshold.as_millis() as u64 {
+            return;
+        }
+
+        let backend_rpcs: Vec<_> = self
+            .backend_requests
+            .lock()
+            .iter()
+            .map(|x| x.name.clone())
+            .collect();
+
+        warn!(
+            request_id = %self.request_ulid,
+            method = %self.method,
+            ?backend_rpcs,
+            params_bytes = self.params_bytes,
+            params = %self.params_preview,
+            response_millis,
+            "slow request",
+        );
+    }
+
+    /// The compute units that this request is billed for. Only accurate once the response has been added
+    pub fn compute_units(&self) -> Decimal {
+        let response_bytes = self.response_bytes.load(atomic::Ordering::Acquire);
+
+        let cache_hit = self.backend_requests.lock().is_empty();
+
+        // the same multipliers and discounts as billing. a usd_per_cu of 1 keeps it in compute units
+        ComputeUnit::new(&self.method, self.chain_id, response_bytes).cost(
+            self.archive_request.load(atomic::Ordering::Acquire),
+            self.archive_multiplier,
+            cache_hit,
+            Decimal::ONE,
+        )
+    }
+
+    /// Take this request's compute units from the user's quota before it is sent. Returns PaymentRequired if the quota is used up.
+    /// The response size isn't known yet, so this is the method's base cost. The difference is added when the request is done
+    pub async fn reserve_compute_unit_quota(&self) -> Web3ProxyResult<()> {
+        let Some(compute_unit_quota) = self.compute_unit_quota.lock().clone() else {
+            return Ok(());
+        };
+
+        let Some(user_id) = self.authorization.as_ref().map(|x| x.checks.user_id) else {
+            return Ok(());
+        };
+
+        let compute_units = ComputeUnit::new(&self.method, self.chain_id, 0)
+            .cost(false, self.archive_multiplier, false, Decimal::ONE)
+            .ceil()
+            .to_u64()
+            .unwrap_or_default();
+
+        match compute_unit_quota.reserve(user_id, compute_units).await {
+            Ok(_) => {
+                self.compute_units_reserved
+                    .store(compute_units, atomic::Ordering::Release);
+            }
+            Err(Web3ProxyError::PaymentRequired) => {
+                // rejected requests don't count against the quota
+                self.compute_unit_quota.lock().take();
+
+                return Err(Web3ProxyError::PaymentRequired);
+            }
+            Err(err) => {
+                // don't reject users because redis is having trouble. the request is still counted when it is done
+                warn!(?err, %user_id, "failed reserving compute units");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the rest of this request's compute units to the user's quota. Only counts once
+    pub fn spend_compute_unit_quota(&mut self) {
+        let Some(compute_unit_quota) = self.compute_unit_quota.get_mut().take() else {
+            return;
+        };
+
+        let Some(user_id) = self.authorization.as_ref().map(|x| x.checks.user_id) else {
+            return;
+        };
+
+        let compute_units = self.compute_units().ceil().to_i64().unwrap_or_default();
+
+        let reserved = *self.compute_units_reserved.get_mut() as i64;
+
+        let compute_units = compute_units - reserved;
+
+        if compute_units == 0 {
+            return;
+        }
+
+        tokio::spawn(async move {
+            if let Err(err) = compute_unit_quota.add(user_id, compute_units).await {
+                warn!(?err, %user_id, "failed counting compute units");
+            }
+        });
+    }
+
     pub fn try_send_stat(mut self) -> Web3ProxyResult<Option<Self>> {
+        self.log_access();
+        self.log_slow_request();
+        self.spend_compute_unit_quota();
+
         if let Some(stat_sender) = self.stat_sender.take() {
             trace!("sending stat! {:?}", self);
 
@@ -606,6 +892,11 @@ impl RequestMetadata {
 // TODO: is this where the panic comes from?
 impl Drop for RequestMetadata {
     fn drop(&mut self) {
+        // requests without a stat_sender never get to try_send_stat
+        self.log_access();
+        self.log_slow_request();
+        self.spend_compute_unit_quota();
+
         if self.stat_sender.is_some() {
             // turn `&mut self` into `self`
             let x = mem::take(self);
@@ -687,6 +978,8 @@ impl Authorization {
         let authorization_checks = AuthorizationChecks {
             // any error logs on a local (internal) query are likely problems. log them all
             log_revert_chance: 100,
+            // internal queries are never blocked by requires_premium
+            premium: true,
             // default for everything else should be fine. we don't have a user_id or ip to give
             ..Default::default()
         };
@@ -755,7 +1048,7 @@ impl Authorization {
         match &authorization_checks.allowed_ips {
             None => {}
             Some(allowed_ips) => {
-                if !allowed_ips.iter().any(|x| x.contains(ip)) {
+                if !ip_in_networks(allowed_ips, ip) {
                     return Err(Web3ProxyError::IpNotAllowed(ip.to_owned()));
                 }
             }
@@ -805,8 +1098,21 @@ impl Authorization {
             referer: referer.cloned(),
             user_agent: user_agent.cloned(),
             authorization_type,
+            no_cache: false,
         })
     }
+
+    /// Premium requests get permits on a full rpc before free requests.
+    /// The proxy's own requests (head block polling, health checks) are never put behind users
+    pub fn priority(&self) -> RequestPriority {
+        if matches!(self.authorization_type, AuthorizationType::Internal) {
+            RequestPriority::Internal
+        } else if self.checks.premium {
+            RequestPriority::Premium
+        } else {
+            RequestPriority::Free
+        }
+    }
 }
 
 /// rate limit logins only by ip.
@@ -1114,6 +1420,10 @@ impl Web3ProxyApp {
             return Ok(RateLimitResult::Allowed(authorization, None));
         }
 
+        if !ip_is_allowed(&self.config.allowed_ips, &self.config.blocked_ips, ip) {
+            return Err(Web3ProxyError::IpNotAllowed(*ip));
+        }
+
         // ip rate limits don't check referer or user agent
         // they do check origin because we can override rate limits for some origins
         let authorization = Authorization::external(
@@ -1126,6 +1436,25 @@ impl Web3ProxyApp {
             None,
         )?;
 
+        if let Some(x) = self
+            .rate_limit_by_origin(origin, &ip.to_string(), false)
+            .await
+        {
+            return match x {
+                DeferredRateLimitResult::Allowed => {
+                    let semaphore = self.ip_semaphore(ip).await?;
+
+                    Ok(RateLimitResult::Allowed(authorization, semaphore))
+                }
+                DeferredRateLimitResult::RetryAt(retry_at) => {
+                    Ok(RateLimitResult::RateLimited(authorization, Some(retry_at)))
+                }
+                DeferredRateLimitResult::RetryNever => {
+                    Ok(RateLimitResult::RateLimited(authorization, None))
+                }
+            };
+        }
+
         if let Some(rate_limiter) = &self.frontend_ip_rate_limiter {
             match rate_limiter
                 .throttle(*ip, authorization.checks.max_requests_per_period, 1)
@@ -1167,6 +1496,46 @@ impl Web3ProxyApp {
         }
     }
 
+    /// Requests from origins in `origin_rate_limits` are counted per origin and identity instead of by the normal limits.
+    /// None if the origin isn't listed, there is no redis, or the config requires an rpc key and there isn't one.
+    async fn rate_limit_by_origin(
+        &self,
+        origin: Option<&Origin>,
+        identity: &str,
+        has_rpc_key: bool,
+    ) -> Option<DeferredRateLimitResult> {
+        // the origin header is easy to spoof. an rpc key is harder to come by
+        if self.config.origin_rate_limits_require_rpc_key && !has_rpc_key {
+            return None;
+        }
+
+        // TODO: i don't like the `to_string` here
+        let origin = origin?.to_string();
+
+        let max_requests_per_period = *self.config.origin_rate_limits.get(&origin)?;
+
+        let rate_limiter = self.frontend_origin_rate_limiter.as_ref()?;
+
+        let label = format!("{}:{}", origin, identity);
+
+        match rate_limiter
+            .throttle_label(&label, Some(max_requests_per_period.into()), 1)
+            .await
+        {
+            Ok(RedisRateLimitResult::Allowed(_)) => Some(DeferredRateLimitResult::Allowed),
+            Ok(RedisRateLimitResult::RetryAt(retry_at, _)) => {
+                Some(DeferredRateLimitResult::RetryAt(retry_at))
+            }
+            Ok(RedisRateLimitResult::RetryNever) => Some(DeferredRateLimitResult::RetryNever),
+            Err(err) => {
+                // internal error, not rate limit being hit
+                error!(?err, %origin, "origin rate limiter is unhappy. allowing request");
+
+                Some(DeferredRateLimitResult::Allowed)
+            }
+        }
+    }
+
     /// Get the balance for the user.
     ///
     /// If a subuser calls this function, the subuser needs to have first attained the user_id that the rpc key belongs to.
@@ -1357,6 +1726,31 @@ impl Web3ProxyApp {
                         let rpc_key_id =
                             Some(rpc_key_model.id.try_into().context("db ids are never 0")?);
 
+                        let method_filter = self
+                            .config
+                            .method_filters_by_tier
+                            .get(&user_tier_model.title)
+                            .cloned();
+
+                        let trace_limits = self
+                            .config
+                            .trace_limits_by_tier
+                            .get(&user_tier_model.title)
+                            .cloned();
+
+                        let log_revert_chances = self
+                            .config
+                            .log_revert_chance_by_method
+                            .iter()
+                            .map(|(method, x)| {
+                                let x = x.to_f64().unwrap_or(1.0).clamp(0.0, 1.0);
+
+                                let chance = x * u16::MAX as f64;
+
+                                (method.clone(), chance as u16)
+                            })
+                            .collect();
+
                         Ok(AuthorizationChecks {
                             allowed_ips,
                             allowed_origins,
@@ -1366,12 +1760,16 @@ impl Web3ProxyApp {
                             // TODO: is floating point math going to scale this correctly?
                             log_revert_chance: (rpc_key_model.log_revert_chance * u16::MAX as f64)
                                 as u16,
+                            log_revert_chances,
                             max_concurrent_requests: user_tier_model.max_concurrent_requests,
                             max_requests_per_period: user_tier_model.max_requests_per_period,
+                            method_filter,
+                            premium: user_tier_model.downgrade_tier_id.is_some(),
                             private_txs: rpc_key_model.private_txs,
                             proxy_mode,
                             rpc_secret_key: Some(*rpc_secret_key),
                             rpc_secret_key_id: rpc_key_id,
+                            trace_limits,
                             user_id: rpc_key_model.user_id,
                         })
                     }
@@ -1382,6 +1780,15 @@ impl Web3ProxyApp {
             .map_err(Into::into)
     }
 
+    fn send_quota_exceeded_webhook(&self, authorization: &Authorization) {
+        if let Some(ref webhooks) = self.webhooks {
+            webhooks.send(WebhookEvent::QuotaExceeded {
+                user_id: authorization.checks.user_id,
+                rpc_key_id: authorization.checks.rpc_secret_key_id.map(|x| x.get()),
+            });
+        }
+    }
+
     /// Authorized the ip/origin/referer/useragent and rate limit and concurrency
     pub async fn rate_limit_by_rpc_key(
         &self,
@@ -1422,6 +1829,22 @@ impl Web3ProxyApp {
             Some(x) => x,
         };
 
+        let identity = format!("user:{}", authorization.checks.user_id);
+
+        if let Some(x) = self.rate_limit_by_origin(origin, &identity, true).await {
+            return match x {
+                DeferredRateLimitResult::Allowed => {
+                    Ok(RateLimitResult::Allowed(authorization, semaphore))
+                }
+                DeferredRateLimitResult::RetryAt(retry_at) => {
+                    Ok(RateLimitResult::RateLimited(authorization, Some(retry_at)))
+                }
+                DeferredRateLimitResult::RetryNever => {
+                    Ok(RateLimitResult::RateLimited(authorization, None))
+                }
+            };
+        }
+
         // user key is valid. now check rate limits
         if let Some(rate_limiter) = &self.frontend_registered_user_rate_limiter {
             match rate_limiter
@@ -1442,12 +1865,16 @@ impl Web3ProxyApp {
                     // TODO: keys are secrets! use the id instead
                     // TODO: emit a stat
                     // // trace!(?rpc_key, "rate limit exceeded until {:?}", retry_at);
+                    self.send_quota_exceeded_webhook(&authorization);
+
                     Ok(RateLimitResult::RateLimited(authorization, Some(retry_at)))
                 }
                 Ok(DeferredRateLimitResult::RetryNever) => {
                     // TODO: keys are secret. don't log them!
                     // // trace!(?rpc_key, "rate limit is 0");
                     // TODO: emit a stat
+                    self.send_quota_exceeded_webhook(&authorization);
+
                     Ok(RateLimitResult::RateLimited(authorization, None))
                 }
                 Err(err) => {
@@ -1,7 +1,54 @@
 use crate::errors::Web3ProxyError;
-use axum::response::{IntoResponse, Response};
+use axum::response::{Html, IntoResponse, Response};
+use http::{header, HeaderMap, StatusCode};
+
+/// humans that open the base url in a browser get this instead of a jsonrpc error
+const NOT_FOUND_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>404 Not Found</title></head>
+<body>
+<h1>404 Not Found</h1>
+<p>This is a JSON-RPC endpoint. Point your wallet or web3 library at it instead of a browser.</p>
+</body>
+</html>
+"#;
 
 #[inline]
-pub async fn handler_404() -> Response {
-    Web3ProxyError::NotFound.into_response()
+pub async fn handler_404(headers: HeaderMap) -> Response {
+    let prefers_html = headers
+        .get(header::ACCEPT)
+        .and_then(|x| x.to_str().ok())
+        .map(prefers_html)
+        .unwrap_or(false);
+
+    if prefers_html {
+        (StatusCode::NOT_FOUND, Html(NOT_FOUND_HTML)).into_response()
+    } else {
+        Web3ProxyError::NotFound.into_response()
+    }
+}
+
+/// browsers ask for text/html first. api clients ask for json or send "*/*"
+fn prefers_html(accept: &str) -> bool {
+    let mut html_q = 0.0;
+    let mut json_q = 0.0;
+
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';').map(str::trim);
+
+        let media_type = parts.next().unwrap_or_default();
+
+        let q = parts
+            .find_map(|x| x.strip_prefix("q="))
+            .and_then(|x| x.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match media_type {
+            "text/html" => html_q = q,
+            "application/json" => json_q = q,
+            _ => {}
+        }
+    }
+
+    html_q > json_q
 }
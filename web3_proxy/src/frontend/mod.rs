@@ -11,11 +11,13 @@ pub mod status;
 pub mod users;
 
 use crate::app::Web3ProxyApp;
+use anyhow::Context;
 use axum::{
     routing::{get, post, put},
     Extension, Router,
 };
-use http::{header::AUTHORIZATION, StatusCode};
+use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use http::{Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
 use listenfd::ListenFd;
 use moka::future::{Cache, CacheBuilder};
 use std::sync::Arc;
@@ -23,9 +25,11 @@ use std::{iter::once, time::Duration};
 use std::{net::SocketAddr, sync::atomic::Ordering};
 use strum::{EnumCount, EnumIter};
 use tokio::sync::broadcast;
-use tower_http::cors::CorsLayer;
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::sensitive_headers::SetSensitiveRequestHeadersLayer;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::errors::Web3ProxyResult;
 
@@ -39,6 +43,67 @@ pub enum ResponseCacheKey {
 
 pub type ResponseCache = Cache<ResponseCacheKey, (StatusCode, &'static str, axum::body::Bytes)>;
 
+/// Compress responses of at least min_bytes. Websocket upgrades are never touched.
+fn compression_layer(min_bytes: u16) -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(min_bytes).and(
+        |status: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| {
+            status != StatusCode::SWITCHING_PROTOCOLS
+        },
+    );
+
+    CompressionLayer::new()
+        .gzip(true)
+        .br(true)
+        .compress_when(predicate)
+}
+
+/// Every origin gets CORS headers unless the config lists some origins. Then only those origins get them.
+/// This is checked before the rpc key's own allowed origins, which still return OriginNotAllowed.
+fn cors_layer(allowed_origins: &[String]) -> Web3ProxyResult<CorsLayer> {
+    if allowed_origins.is_empty() {
+        return Ok(CorsLayer::very_permissive());
+    }
+
+    let allow_origin = if allowed_origins.iter().any(|x| x == "*") {
+        warn!("cors allows every origin");
+
+        AllowOrigin::from(Any)
+    } else {
+        let allowed_origins = allowed_origins
+            .iter()
+            .map(|x| {
+                HeaderValue::from_str(x)
+                    .with_context(|| format!("invalid cors_allowed_origins entry: {}", x))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        AllowOrigin::list(allowed_origins)
+    };
+
+    let layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT])
+        .allow_headers([
+            ACCEPT,
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            HeaderName::from_static("x-balance-id"),
+            HeaderName::from_static("x-no-cache"),
+        ])
+        // browsers hide response headers from scripts unless they are listed here
+        .expose_headers([
+            HeaderName::from_static("x-balance-id"),
+            HeaderName::from_static("x-cache"),
+            HeaderName::from_static("x-compute-units"),
+            HeaderName::from_static("x-compute-units-usd"),
+            HeaderName::from_static("x-served-by"),
+            HeaderName::from_static("x-w3p-compute-units-remaining"),
+        ])
+        .max_age(Duration::from_secs(3600));
+
+    Ok(layer)
+}
+
 /// Start the frontend server.
 pub async fn serve(
     app: Arc<Web3ProxyApp>,
@@ -55,6 +120,8 @@ pub async fn serve(
         .time_to_live(Duration::from_secs(1))
         .build();
 
+    let cors_layer = cors_layer(&app.config.cors_allowed_origins)?;
+
     // TODO: read config for if fastest/versus should be available publicly. default off
 
     // build our axum Router
@@ -177,6 +244,10 @@ pub async fn serve(
             post(users::payment::user_balance_uncle_post),
         )
         .route("/user/keys", get(users::rpc_keys::rpc_keys_get))
+        .route(
+            "/user/rate_limit",
+            get(users::rate_limit::user_rate_limit_get),
+        )
         .route("/user/keys", post(users::rpc_keys::rpc_keys_management))
         .route("/user/keys", put(users::rpc_keys::rpc_keys_management))
         // .route("/user/referral/:referral_link", get(users::user_referral_link_get))
@@ -214,6 +285,7 @@ pub async fn serve(
             post(admin::admin_increase_balance),
         )
         .route("/admin/modify_role", post(admin::admin_change_user_roles))
+        .route("/admin/reload_rpcs", post(admin::admin_reload_rpcs))
         .route(
             "/admin/imitate_login/:admin_address/:user_address",
             get(admin::admin_imitate_login_get),
@@ -233,8 +305,10 @@ pub async fn serve(
         //
         // Mark the `Authorization` request header as sensitive so it doesn't show in logs
         .layer(SetSensitiveRequestHeadersLayer::new(once(AUTHORIZATION)))
+        // gzip or brotli for clients that send Accept-Encoding
+        .layer(compression_layer(app.config.http_compression_min_bytes))
         // handle cors
-        .layer(CorsLayer::very_permissive())
+        .layer(cors_layer)
         // application state
         .layer(Extension(app.clone()))
         // frontend caches
@@ -295,3 +369,94 @@ pub async fn serve(
 
     server
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use flate2::read::GzDecoder;
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use serde_json::json;
+    use std::io::Read;
+
+    async fn serve_json(value: serde_json::Value) -> String {
+        let router = Router::new()
+            .route(
+                "/",
+                get(move || {
+                    let value = value.clone();
+                    async move { Json(value) }
+                }),
+            )
+            .layer(compression_layer(1024));
+
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_gzip_round_trip() {
+        let logs: Vec<_> = (0..100)
+            .map(|i| json!({"logIndex": i, "data": "0x0000000000000000000000000000000000000000"}))
+            .collect();
+        let logs = json!(logs);
+
+        let url = serve_json(logs.clone()).await;
+
+        // decompress by hand so that we can see the headers
+        let client = reqwest::Client::builder().no_gzip().build().unwrap();
+
+        let response = client
+            .get(&url)
+            .header(ACCEPT_ENCODING, "gzip")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+        let compressed = response.bytes().await.unwrap();
+
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_ref())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decompressed).unwrap(),
+            logs
+        );
+
+        // clients that don't ask for compression get plain json
+        let response = client.get(&url).send().await.unwrap();
+
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(response.json::<serde_json::Value>().await.unwrap(), logs);
+    }
+
+    #[tokio::test]
+    async fn test_small_responses_are_not_compressed() {
+        let url = serve_json(json!("0x1")).await;
+
+        let client = reqwest::Client::builder().no_gzip().build().unwrap();
+
+        let response = client
+            .get(&url)
+            .header(ACCEPT_ENCODING, "gzip, br")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!("0x1")
+        );
+    }
+}
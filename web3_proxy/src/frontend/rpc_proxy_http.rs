@@ -2,8 +2,16 @@
 
 use super::authorization::{ip_is_authorized, key_is_authorized};
 use super::rpc_proxy_ws::ProxyMode;
+use crate::compute_units::ComputeUnit;
 use crate::errors::Web3ProxyError;
-use crate::{app::Web3ProxyApp, jsonrpc::JsonRpcRequestEnum};
+use crate::jsonrpc::JsonRpcForwardedResponseEnum;
+use crate::response_cache::CacheStatus;
+use crate::rpcs::one::Web3Rpc;
+use crate::{
+    app::{ProxyResponse, Web3ProxyApp},
+    jsonrpc::JsonRpcRequestEnum,
+};
+use axum::extract::rejection::JsonRejection;
 use axum::extract::Path;
 use axum::headers::{Origin, Referer, UserAgent};
 use axum::response::Response;
@@ -11,11 +19,38 @@ use axum::TypedHeader;
 use axum::{response::IntoResponse, Extension, Json};
 use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
-use http::HeaderMap;
+use http::header::AGE;
+use http::{HeaderMap, HeaderValue, StatusCode};
 use itertools::Itertools;
+use migration::sea_orm::prelude::Decimal;
+use serde_json::value::RawValue;
 use std::net::IpAddr;
 use std::sync::Arc;
 
+/// Malformed requests are a bad request that says what is wrong instead of axum's plain text rejection.
+/// The request could not be parsed, so the response has no id.
+/// The body is only checked for valid json by the extractor so that the app's strict_jsonrpc can be used here
+fn parse_payload(
+    app: &Web3ProxyApp,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
+) -> Result<JsonRpcRequestEnum, Response> {
+    let Json(payload) = payload.map_err(|err| {
+        Web3ProxyError::BadRequest(err.body_text().into())
+            .into_response_with_id(None, app.config.expose_internal_errors)
+    })?;
+
+    JsonRpcRequestEnum::parse(payload.get(), app.config.strict_jsonrpc).map_err(|err| {
+        Web3ProxyError::BadRequest(
+            format!(
+                "Failed to deserialize the JSON body into the target type: {}",
+                err
+            )
+            .into(),
+        )
+        .into_response_with_id(None, app.config.expose_internal_errors)
+    })
+}
+
 /// POST /rpc -- Public entrypoint for HTTP JSON-RPC requests. Web3 wallets use this.
 /// Defaults to rate limiting by IP address, but can also read the Authorization header for a bearer token.
 /// If possible, please use a WebSocket instead.
@@ -24,9 +59,18 @@ pub async fn proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    request_headers: HeaderMap,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Best).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        origin.as_deref(),
+        no_cache_requested(&request_headers),
+        payload,
+        ProxyMode::Best,
+    )
+    .await
 }
 
 #[debug_handler]
@@ -34,11 +78,20 @@ pub async fn fastest_proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    request_headers: HeaderMap,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
     // TODO: read the fastest number from params
     // TODO: check that the app allows this without authentication
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Fastest(0)).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        origin.as_deref(),
+        no_cache_requested(&request_headers),
+        payload,
+        ProxyMode::Fastest(0),
+    )
+    .await
 }
 
 #[debug_handler]
@@ -46,39 +99,69 @@ pub async fn versus_proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    request_headers: HeaderMap,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Versus).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        origin.as_deref(),
+        no_cache_requested(&request_headers),
+        payload,
+        ProxyMode::Versus,
+    )
+    .await
 }
 
 async fn _proxy_web3_rpc(
     app: Arc<Web3ProxyApp>,
     ip: &IpAddr,
     origin: Option<&Origin>,
-    payload: JsonRpcRequestEnum,
+    no_cache: bool,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
     proxy_mode: ProxyMode,
 ) -> Result<Response, Response> {
+    let payload = parse_payload(&app, payload)?;
+
     let first_id = payload.first_id();
 
-    let (authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode)
+    let (mut authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode)
         .await
-        .map_err(|e| e.into_response_with_id(first_id.clone()))?;
+        .map_err(|e| {
+            e.into_response_with_id(first_id.clone(), app.config.expose_internal_errors)
+        })?;
+
+    authorization.no_cache = no_cache;
 
     let authorization = Arc::new(authorization);
 
     // TODO: calculate payload bytes here (before turning into serde_json::Value). that will save serializing later
 
     // TODO: is first_id the right thing to attach to this error?
-    let (status_code, response, rpcs) = app
+    let notification = payload.is_notification();
+
+    let ProxyResponse {
+        status_code,
+        response,
+        rpcs,
+        compute_units,
+        cache_status,
+    } = app
         .proxy_web3_rpc(authorization, payload)
         .await
-        .map_err(|e| e.into_response_with_id(first_id))?;
+        .map_err(|e| e.into_response_with_id(first_id, app.config.expose_internal_errors))?;
 
-    let mut response = (status_code, Json(response)).into_response();
+    let mut response = json_rpc_response(status_code, response, notification);
 
     // TODO: DRY this up. same for public and private queries
     let response_headers = response.headers_mut();
 
+    insert_compute_unit_headers(&app, response_headers, compute_units);
+
+    insert_cache_headers(response_headers, cache_status);
+
+    insert_served_by_header(&app, response_headers, &rpcs, cache_status);
+
     // TODO: this might be slow. think about this more
     // TODO: special string if no rpcs were used (cache hit)?
     let mut backup_used = false;
@@ -109,6 +192,113 @@ async fn _proxy_web3_rpc(
     Ok(response)
 }
 
+/// Notifications are still proxied, but the user gets an empty body instead of a response object
+fn json_rpc_response(
+    status_code: StatusCode,
+    response: JsonRpcForwardedResponseEnum,
+    notification: bool,
+) -> Response {
+    if notification {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (status_code, Json(response)).into_response()
+    }
+}
+
+/// `X-No-Cache: true` asks for a fresh response from the backends. The response is not cached either.
+fn no_cache_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-no-cache")
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.trim().eq_ignore_ascii_case("true") || x.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Tell users what the request cost. Batches get the sum of their requests.
+/// This is opt-in with compute_units_header.
+fn insert_compute_unit_headers(
+    app: &Web3ProxyApp,
+    headers: &mut HeaderMap,
+    compute_units: Decimal,
+) {
+    if !app.config.compute_units_header {
+        return;
+    }
+
+    let usd = compute_units * ComputeUnit::usd_per_cu(app.config.chain_id);
+
+    headers.insert(
+        "X-Compute-Units",
+        compute_units
+            .normalize()
+            .to_string()
+            .parse()
+            .expect("X-Compute-Units should always parse"),
+    );
+
+    headers.insert(
+        "X-Compute-Units-USD",
+        usd.normalize()
+            .to_string()
+            .parse()
+            .expect("X-Compute-Units-USD should always parse"),
+    );
+}
+
+/// Tell users if the response came out of the cache and, if it did, how many seconds it was cached for.
+/// Clients that need fresher data can use this to decide to request again with `X-No-Cache`.
+fn insert_cache_headers(headers: &mut HeaderMap, cache_status: Option<CacheStatus>) {
+    match cache_status {
+        Some(CacheStatus::Hit { age }) => {
+            headers.insert("X-Cache", HeaderValue::from_static("HIT"));
+            headers.insert(AGE, age.as_secs().into());
+        }
+        // responses that never touched the cache are fresh, too
+        Some(CacheStatus::Miss) | None => {
+            headers.insert("X-Cache", HeaderValue::from_static("MISS"));
+        }
+    }
+}
+
+/// Tell users which servers answered the request. Cached responses are served by "cache".
+/// This is opt-in with served_by_header.
+fn insert_served_by_header(
+    app: &Web3ProxyApp,
+    headers: &mut HeaderMap,
+    rpcs: &[Arc<Web3Rpc>],
+    cache_status: Option<CacheStatus>,
+) {
+    if !app.config.served_by_header {
+        return;
+    }
+
+    let Some(served_by) = served_by(rpcs, cache_status) else {
+        return;
+    };
+
+    // display names are free text and might not be valid in a header
+    if let Ok(served_by) = served_by.parse() {
+        headers.insert("X-Served-By", served_by);
+    }
+}
+
+/// The display name (or name) of every server that answered. None if the request was rejected or answered by the proxy itself.
+/// The names are already sent in X-W3P-BACKEND-RPCS, so they aren't hidden here.
+fn served_by(rpcs: &[Arc<Web3Rpc>], cache_status: Option<CacheStatus>) -> Option<String> {
+    if !rpcs.is_empty() {
+        let served_by = rpcs
+            .iter()
+            .map(|x| x.display_name.as_ref().unwrap_or(&x.name))
+            .join(",");
+
+        Some(served_by)
+    } else if let Some(CacheStatus::Hit { .. }) = cache_status {
+        Some("cache".to_string())
+    } else {
+        None
+    }
+}
+
 /// Authenticated entrypoint for HTTP JSON-RPC requests. Web3 wallets use this.
 /// Rate limit and billing based on the api key in the url.
 /// Can optionally authorized based on origin, referer, or user agent.
@@ -120,8 +310,9 @@ pub async fn proxy_web3_rpc_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    request_headers: HeaderMap,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -129,6 +320,7 @@ pub async fn proxy_web3_rpc_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        no_cache_requested(&request_headers),
         rpc_key,
         payload,
         ProxyMode::Best,
@@ -147,7 +339,7 @@ pub async fn debug_proxy_web3_rpc_with_key(
     user_agent: Option<TypedHeader<UserAgent>>,
     request_headers: HeaderMap,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
     let mut response = match _proxy_web3_rpc_with_key(
         app,
@@ -155,6 +347,7 @@ pub async fn debug_proxy_web3_rpc_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        no_cache_requested(&request_headers),
         rpc_key,
         payload,
         ProxyMode::Debug,
@@ -188,8 +381,9 @@ pub async fn fastest_proxy_web3_rpc_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    request_headers: HeaderMap,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -197,6 +391,7 @@ pub async fn fastest_proxy_web3_rpc_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        no_cache_requested(&request_headers),
         rpc_key,
         payload,
         ProxyMode::Fastest(0),
@@ -211,8 +406,9 @@ pub async fn versus_proxy_web3_rpc_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    request_headers: HeaderMap,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -220,6 +416,7 @@ pub async fn versus_proxy_web3_rpc_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        no_cache_requested(&request_headers),
         rpc_key,
         payload,
         ProxyMode::Versus,
@@ -234,36 +431,71 @@ async fn _proxy_web3_rpc_with_key(
     origin: Option<&Origin>,
     referer: Option<&Referer>,
     user_agent: Option<&UserAgent>,
+    no_cache: bool,
     rpc_key: String,
-    payload: JsonRpcRequestEnum,
+    payload: Result<Json<Box<RawValue>>, JsonRejection>,
     proxy_mode: ProxyMode,
 ) -> Result<Response, Response> {
     // TODO: DRY w/ proxy_web3_rpc
 
+    let payload = parse_payload(&app, payload)?;
+
     let first_id = payload.first_id();
 
-    let rpc_key = rpc_key
-        .parse()
-        .map_err(|e: Web3ProxyError| e.into_response_with_id(first_id.clone()))?;
+    let rpc_key = rpc_key.parse().map_err(|e: Web3ProxyError| {
+        e.into_response_with_id(first_id.clone(), app.config.expose_internal_errors)
+    })?;
 
-    let (authorization, _semaphore) =
+    let (mut authorization, _semaphore) =
         key_is_authorized(&app, &rpc_key, ip, origin, proxy_mode, referer, user_agent)
             .await
-            .map_err(|e| e.into_response_with_id(first_id.clone()))?;
+            .map_err(|e| {
+                e.into_response_with_id(first_id.clone(), app.config.expose_internal_errors)
+            })?;
+
+    authorization.no_cache = no_cache;
+
+    // users without a paid balance are cut off once their free compute units are used up
+    let compute_units_remaining = if let Some(quota) = app.compute_unit_quota_for(&authorization) {
+        let remaining = quota
+            .check(authorization.checks.user_id)
+            .await
+            .map_err(|e| {
+                e.into_response_with_id(first_id.clone(), app.config.expose_internal_errors)
+            })?;
+
+        Some(remaining)
+    } else {
+        None
+    };
 
     let authorization = Arc::new(authorization);
 
     let rpc_secret_key_id = authorization.checks.rpc_secret_key_id;
 
-    let (status_code, response, rpcs) = app
+    let notification = payload.is_notification();
+
+    let ProxyResponse {
+        status_code,
+        response,
+        rpcs,
+        compute_units,
+        cache_status,
+    } = app
         .proxy_web3_rpc(authorization, payload)
         .await
-        .map_err(|e| e.into_response_with_id(first_id))?;
+        .map_err(|e| e.into_response_with_id(first_id, app.config.expose_internal_errors))?;
 
-    let mut response = (status_code, Json(response)).into_response();
+    let mut response = json_rpc_response(status_code, response, notification);
 
     let headers = response.headers_mut();
 
+    insert_compute_unit_headers(&app, headers, compute_units);
+
+    insert_cache_headers(headers, cache_status);
+
+    insert_served_by_header(&app, headers, &rpcs, cache_status);
+
     let mut backup_used = false;
 
     // TODO: special string if no rpcs were used (cache hit)? or is an empty string fine? maybe the rpc name + "cached"
@@ -300,5 +532,106 @@ async fn _proxy_web3_rpc_with_key(
         );
     }
 
+    // this is from before the request. it doesn't include this request's compute units
+    if let Some(compute_units_remaining) = compute_units_remaining {
+        headers.insert(
+            "X-W3P-COMPUTE-UNITS-REMAINING",
+            compute_units_remaining
+                .to_string()
+                .parse()
+                .expect("X-W3P-COMPUTE-UNITS-REMAINING should always parse"),
+        );
+    }
+
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_no_cache_header() {
+        let mut headers = HeaderMap::new();
+
+        assert!(!no_cache_requested(&headers));
+
+        headers.insert("X-No-Cache", HeaderValue::from_static("true"));
+        assert!(no_cache_requested(&headers));
+
+        headers.insert("X-No-Cache", HeaderValue::from_static("1"));
+        assert!(no_cache_requested(&headers));
+
+        headers.insert("X-No-Cache", HeaderValue::from_static("false"));
+        assert!(!no_cache_requested(&headers));
+
+        headers.insert("X-No-Cache", HeaderValue::from_static("0"));
+        assert!(!no_cache_requested(&headers));
+    }
+
+    #[test]
+    fn test_cache_headers() {
+        let mut headers = HeaderMap::new();
+
+        insert_cache_headers(
+            &mut headers,
+            Some(CacheStatus::Hit {
+                age: Duration::from_millis(12_500),
+            }),
+        );
+
+        assert_eq!(headers.get("X-Cache").unwrap(), "HIT");
+        assert_eq!(headers.get(AGE).unwrap(), "12");
+
+        let mut headers = HeaderMap::new();
+
+        insert_cache_headers(&mut headers, Some(CacheStatus::Miss));
+
+        assert_eq!(headers.get("X-Cache").unwrap(), "MISS");
+        assert!(headers.get(AGE).is_none());
+
+        // a batch is only a hit if every request in it was
+        let batch = CacheStatus::merge_opt(
+            Some(CacheStatus::Hit {
+                age: Duration::from_secs(3),
+            }),
+            Some(CacheStatus::Miss),
+        );
+
+        let mut headers = HeaderMap::new();
+
+        insert_cache_headers(&mut headers, batch);
+
+        assert_eq!(headers.get("X-Cache").unwrap(), "MISS");
+        assert!(headers.get(AGE).is_none());
+    }
+
+    #[test]
+    fn test_served_by() {
+        let named = Arc::new(Web3Rpc {
+            name: "llama".to_string(),
+            display_name: Some("Llama Nodes".to_string()),
+            ..Default::default()
+        });
+        let unnamed = Arc::new(Web3Rpc {
+            name: "local".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            served_by(&[named, unnamed], Some(CacheStatus::Miss)).as_deref(),
+            Some("Llama Nodes,local")
+        );
+
+        let hit = Some(CacheStatus::Hit {
+            age: Duration::from_secs(1),
+        });
+
+        assert_eq!(served_by(&[], hit).as_deref(), Some("cache"));
+
+        // rejected and locally answered requests didn't come from the cache
+        assert_eq!(served_by(&[], Some(CacheStatus::Miss)), None);
+        assert_eq!(served_by(&[], None), None);
+    }
+}
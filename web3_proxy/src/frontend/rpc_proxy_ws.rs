@@ -4,9 +4,9 @@
 
 use super::authorization::{ip_is_authorized, key_is_authorized, Authorization, RequestMetadata};
 use crate::errors::{Web3ProxyError, Web3ProxyResponse};
-use crate::jsonrpc::JsonRpcId;
+use crate::jsonrpc::{EthSubscribeParams, JsonRpcId};
 use crate::{
-    app::Web3ProxyApp,
+    app::{Web3ProxyApp, WsResumeCache},
     errors::Web3ProxyResult,
     jsonrpc::{JsonRpcForwardedResponse, JsonRpcForwardedResponseEnum, JsonRpcRequest},
 };
@@ -14,28 +14,33 @@ use anyhow::Context;
 use axum::headers::{Origin, Referer, UserAgent};
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::Path,
+    extract::{Path, Query},
     response::{IntoResponse, Redirect},
     Extension, TypedHeader,
 };
 use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
-use ethers::types::U64;
+use ethers::types::{TxHash, U64};
 use futures::SinkExt;
 use futures::{
-    future::AbortHandle,
+    future::{AbortHandle, Abortable},
     stream::{SplitSink, SplitStream, StreamExt},
 };
 use handlebars::Handlebars;
 use hashbrown::HashMap;
 use http::{HeaderMap, StatusCode};
+use moka::future::CacheBuilder;
+use serde::Deserialize;
 use serde_json::json;
 use std::net::IpAddr;
+use std::num::NonZeroU64;
 use std::str::from_utf8_mut;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{self, AtomicU64};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{broadcast, OwnedSemaphorePermit, RwLock};
-use tracing::{info, trace};
+use tokio::time::{interval_at, sleep_until, Instant};
+use tracing::{info, trace, warn};
 
 /// How to select backend servers for a request
 #[derive(Copy, Clone, Debug, Default)]
@@ -52,6 +57,59 @@ pub enum ProxyMode {
     Debug,
 }
 
+/// Resume tokens are chosen by clients. Longer tokens are rejected
+const MAX_RESUME_TOKEN_LEN: usize = 128;
+
+/// Query params for websocket connections
+#[derive(Debug, Default, Deserialize)]
+pub struct WebsocketQuery {
+    /// Get back the subscriptions of a recently closed websocket that connected with the same token
+    pub resume_token: Option<String>,
+}
+
+/// A subscription that is re-established when its websocket reconnects with the same resume token
+#[derive(Clone, Debug)]
+pub struct ResumableSubscription {
+    pub subscription_id: U64,
+    pub params: serde_json::Value,
+}
+
+/// Resume tokens are only valid for the rpc key (or ip for public sockets) that made them
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct WsResumeKey {
+    rpc_secret_key_id: Option<NonZeroU64>,
+    ip: Option<IpAddr>,
+    token: String,
+}
+
+impl WsResumeKey {
+    fn new(authorization: &Authorization, token: String) -> Self {
+        let rpc_secret_key_id = authorization.checks.rpc_secret_key_id;
+
+        // clients with a key often reconnect from a different ip
+        let ip = if rpc_secret_key_id.is_some() {
+            None
+        } else {
+            Some(authorization.ip)
+        };
+
+        Self {
+            rpc_secret_key_id,
+            ip,
+            token,
+        }
+    }
+}
+
+/// An open subscription on a websocket
+struct WsSubscription {
+    handle: AbortHandle,
+    /// The eth_subscribe params. Only set if the subscription can be resumed
+    resume_params: Option<serde_json::Value>,
+    /// Set if this task is watching for a transaction that the socket sent instead of an eth_subscribe subscription
+    sent_tx: Option<TxHash>,
+}
+
 /// Public entrypoint for WebSocket JSON-RPC requests.
 /// Queries a single server at a time
 #[debug_handler]
@@ -59,9 +117,18 @@ pub async fn websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
-    _websocket_handler(ProxyMode::Best, app, &ip, origin.as_deref(), ws_upgrade).await
+    _websocket_handler(
+        ProxyMode::Best,
+        app,
+        &ip,
+        origin.as_deref(),
+        query,
+        ws_upgrade,
+    )
+    .await
 }
 
 /// Public entrypoint for WebSocket JSON-RPC requests that uses all synced servers.
@@ -71,6 +138,7 @@ pub async fn fastest_websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     // TODO: get the fastest number from the url params (default to 0/all)
@@ -80,6 +148,7 @@ pub async fn fastest_websocket_handler(
         app,
         &ip,
         origin.as_deref(),
+        query,
         ws_upgrade,
     )
     .await
@@ -92,10 +161,19 @@ pub async fn versus_websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
     origin: Option<TypedHeader<Origin>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     // TODO: config to disable this
-    _websocket_handler(ProxyMode::Versus, app, &ip, origin.as_deref(), ws_upgrade).await
+    _websocket_handler(
+        ProxyMode::Versus,
+        app,
+        &ip,
+        origin.as_deref(),
+        query,
+        ws_upgrade,
+    )
+    .await
 }
 
 async fn _websocket_handler(
@@ -103,15 +181,19 @@ async fn _websocket_handler(
     app: Arc<Web3ProxyApp>,
     ip: &IpAddr,
     origin: Option<&Origin>,
+    query: WebsocketQuery,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
+    // public sockets don't have a user. their requests and subscriptions are billed to user 0
     let (authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode).await?;
 
+    let resume_key = resume_key(&app, &authorization, query)?;
+
     let authorization = Arc::new(authorization);
 
     match ws_upgrade {
         Some(ws) => Ok(ws
-            .on_upgrade(move |socket| proxy_web3_socket(app, authorization, socket))
+            .on_upgrade(move |socket| proxy_web3_socket(app, authorization, resume_key, socket))
             .into_response()),
         None => {
             if let Some(redirect) = &app.config.redirect_public_url {
@@ -128,6 +210,7 @@ async fn _websocket_handler(
 /// Rate limit and billing based on the api key in the url.
 /// Can optionally authorized based on origin, referer, or user agent.
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
@@ -135,6 +218,7 @@ pub async fn websocket_handler_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     _websocket_handler_with_key(
@@ -145,6 +229,7 @@ pub async fn websocket_handler_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        query,
         ws_upgrade,
     )
     .await
@@ -160,6 +245,7 @@ pub async fn debug_websocket_handler_with_key(
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
     headers: HeaderMap,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     let mut response = _websocket_handler_with_key(
@@ -170,6 +256,7 @@ pub async fn debug_websocket_handler_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        query,
         ws_upgrade,
     )
     .await?;
@@ -191,6 +278,7 @@ pub async fn debug_websocket_handler_with_key(
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn fastest_websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
@@ -198,6 +286,7 @@ pub async fn fastest_websocket_handler_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     // TODO: get the fastest number from the url params (default to 0/all)
@@ -209,12 +298,14 @@ pub async fn fastest_websocket_handler_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        query,
         ws_upgrade,
     )
     .await
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn versus_websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     InsecureClientIp(ip): InsecureClientIp,
@@ -222,6 +313,7 @@ pub async fn versus_websocket_handler_with_key(
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    Query(query): Query<WebsocketQuery>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     _websocket_handler_with_key(
@@ -232,6 +324,7 @@ pub async fn versus_websocket_handler_with_key(
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        query,
         ws_upgrade,
     )
     .await
@@ -246,6 +339,7 @@ async fn _websocket_handler_with_key(
     origin: Option<&Origin>,
     referer: Option<&Referer>,
     user_agent: Option<&UserAgent>,
+    query: WebsocketQuery,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     let rpc_key = rpc_key.parse()?;
@@ -255,12 +349,18 @@ async fn _websocket_handler_with_key(
 
     trace!("websocket_handler_with_key {:?}", authorization);
 
+    // don't open a socket for a user that has already used up their free compute units
+    if let Some(quota) = app.compute_unit_quota_for(&authorization) {
+        quota.check(authorization.checks.user_id).await?;
+    }
+
+    let resume_key = resume_key(&app, &authorization, query)?;
+
     let authorization = Arc::new(authorization);
 
     match ws_upgrade {
-        Some(ws_upgrade) => {
-            Ok(ws_upgrade.on_upgrade(move |socket| proxy_web3_socket(app, authorization, socket)))
-        }
+        Some(ws_upgrade) => Ok(ws_upgrade
+            .on_upgrade(move |socket| proxy_web3_socket(app, authorization, resume_key, socket))),
         None => {
             // if no websocket upgrade, this is probably a user loading the url with their browser
             match (
@@ -297,9 +397,41 @@ async fn _websocket_handler_with_key(
     }
 }
 
+/// The params to save for a subscription. None if the subscription can't be resumed
+fn resume_params(params: &serde_json::Value) -> Option<serde_json::Value> {
+    match serde_json::from_value::<EthSubscribeParams>(params.clone()) {
+        Ok(x) if x.is_resumable() => Some(params.clone()),
+        _ => None,
+    }
+}
+
+/// None if the client didn't send a resume token or if resuming is disabled
+fn resume_key(
+    app: &Web3ProxyApp,
+    authorization: &Authorization,
+    query: WebsocketQuery,
+) -> Web3ProxyResult<Option<WsResumeKey>> {
+    let Some(token) = query.resume_token.filter(|_| app.ws_resume_cache.is_some()) else {
+        return Ok(None);
+    };
+
+    if token.is_empty() || token.len() > MAX_RESUME_TOKEN_LEN {
+        return Err(Web3ProxyError::BadRequest(
+            format!(
+                "resume_token must be 1 to {} characters",
+                MAX_RESUME_TOKEN_LEN
+            )
+            .into(),
+        ));
+    }
+
+    Ok(Some(WsResumeKey::new(authorization, token)))
+}
+
 async fn proxy_web3_socket(
     app: Arc<Web3ProxyApp>,
     authorization: Arc<Authorization>,
+    resume_key: Option<WsResumeKey>,
     socket: WebSocket,
 ) {
     // split the websocket so we can read and write concurrently
@@ -309,7 +441,13 @@ async fn proxy_web3_socket(
     let (response_sender, response_receiver) = flume::unbounded::<Message>();
 
     tokio::spawn(write_web3_socket(response_receiver, ws_tx));
-    tokio::spawn(read_web3_socket(app, authorization, ws_rx, response_sender));
+    tokio::spawn(read_web3_socket(
+        app,
+        authorization,
+        resume_key,
+        ws_rx,
+        response_sender,
+    ));
 }
 
 /// websockets support a few more methods than http clients
@@ -319,38 +457,50 @@ async fn handle_socket_payload(
     payload: &str,
     response_sender: &flume::Sender<Message>,
     subscription_count: &AtomicU64,
-    subscriptions: Arc<RwLock<HashMap<U64, AbortHandle>>>,
-) -> Web3ProxyResult<(Message, Option<OwnedSemaphorePermit>)> {
+    subscriptions: Arc<RwLock<HashMap<U64, WsSubscription>>>,
+) -> Web3ProxyResult<(Option<Message>, Option<OwnedSemaphorePermit>)> {
     let (authorization, semaphore) = authorization.check_again(&app).await?;
 
     // TODO: handle batched requests
-    let (response_id, response) = match serde_json::from_str::<JsonRpcRequest>(payload) {
+    // requests without an id are notifications. they get no response
+    let mut notification = false;
+
+    let (response_id, response) = match JsonRpcRequest::parse(payload, app.config.strict_jsonrpc) {
         Ok(json_request) => {
             let response_id = json_request.id.clone();
+            notification = json_request.notification;
 
             // TODO: move this to a seperate function so we can use the try operator
             let response: Web3ProxyResult<JsonRpcForwardedResponseEnum> = match &json_request.method
                 [..]
             {
                 "eth_subscribe" => {
+                    let subscription_id = subscription_count.fetch_add(1, atomic::Ordering::SeqCst);
+                    let subscription_id = U64::from(subscription_id);
+
+                    let resume_params = resume_params(&json_request.params);
+
                     // TODO: how can we subscribe with proxy_mode?
                     match app
                         .eth_subscribe(
                             authorization.clone(),
                             json_request,
-                            subscription_count,
+                            subscription_id,
                             response_sender.clone(),
                         )
                         .await
                     {
                         Ok((handle, response)) => {
-                            if let Some(subscription_id) = response.result.clone() {
-                                let mut x = subscriptions.write().await;
-
-                                let key: U64 = serde_json::from_str(subscription_id.get()).unwrap();
+                            let mut x = subscriptions.write().await;
 
-                                x.insert(key, handle);
-                            }
+                            x.insert(
+                                subscription_id,
+                                WsSubscription {
+                                    handle,
+                                    resume_params,
+                                    sent_tx: None,
+                                },
+                            );
 
                             Ok(response.into())
                         }
@@ -386,8 +536,8 @@ async fn handle_socket_payload(
                         let mut x = subscriptions.write().await;
                         match x.remove(&subscription_id) {
                             None => false,
-                            Some(handle) => {
-                                handle.abort();
+                            Some(subscription) => {
+                                subscription.handle.abort();
                                 true
                             }
                         }
@@ -402,10 +552,33 @@ async fn handle_socket_payload(
 
                     Ok(response.into())
                 }
-                _ => app
-                    .proxy_web3_rpc(authorization.clone(), json_request.into())
-                    .await
-                    .map(|(_, response, _)| response),
+                _ => {
+                    let watch_tx = app.config.sent_tx_notifications
+                        && json_request.method == "eth_sendRawTransaction";
+
+                    let response = app
+                        .proxy_web3_rpc(authorization.clone(), json_request.into())
+                        .await
+                        .map(|x| x.response);
+
+                    if watch_tx
+                        && let Ok(JsonRpcForwardedResponseEnum::Single(x)) = &response
+                        && let Some(result) = x.result.as_ref()
+                        && let Ok(tx_hash) = serde_json::from_str::<TxHash>(result.get())
+                    {
+                        watch_sent_transaction(
+                            &app,
+                            &authorization,
+                            tx_hash,
+                            response_sender,
+                            subscription_count,
+                            &subscriptions,
+                        )
+                        .await;
+                    }
+
+                    response
+                }
             };
 
             (response_id, response)
@@ -416,10 +589,15 @@ async fn handle_socket_payload(
         }
     };
 
+    if notification {
+        return Ok((None, semaphore));
+    }
+
     let response_str = match response {
         Ok(x) => serde_json::to_string(&x).expect("to_string should always work here"),
         Err(err) => {
-            let (_, response_data) = err.as_response_parts();
+            let (_, response_data) =
+                err.as_response_parts_for_request(None, app.config.expose_internal_errors);
 
             let response = JsonRpcForwardedResponse::from_response_data(response_data, response_id);
 
@@ -427,12 +605,83 @@ async fn handle_socket_payload(
         }
     };
 
-    Ok((Message::Text(response_str), semaphore))
+    Ok((Some(Message::Text(response_str)), semaphore))
+}
+
+/// Watch for a transaction that this socket sent. The watcher is aborted along with the socket's subscriptions.
+/// Sockets can only watch `sent_tx_notifications_max_per_connection` transactions at once.
+async fn watch_sent_transaction(
+    app: &Arc<Web3ProxyApp>,
+    authorization: &Arc<Authorization>,
+    tx_hash: TxHash,
+    response_sender: &flume::Sender<Message>,
+    subscription_count: &AtomicU64,
+    subscriptions: &Arc<RwLock<HashMap<U64, WsSubscription>>>,
+) {
+    // the lock is held until the watcher is inserted. that way a watcher that finishes right away can't be left behind
+    let mut x = subscriptions.write().await;
+
+    if !can_watch_sent_tx(
+        &x,
+        tx_hash,
+        app.config.sent_tx_notifications_max_per_connection,
+    ) {
+        trace!(?tx_hash, "not watching sent transaction");
+        return;
+    }
+
+    let subscription_id = U64::from(subscription_count.fetch_add(1, atomic::Ordering::SeqCst));
+
+    let (handle, registration) = AbortHandle::new_pair();
+
+    let f = Abortable::new(
+        app.clone()
+            .watch_sent_transaction(authorization.clone(), tx_hash, response_sender.clone()),
+        registration,
+    );
+
+    let subscriptions = subscriptions.clone();
+
+    tokio::spawn(async move {
+        let _ = f.await;
+
+        // free up the slot. if the socket closed, it is already gone
+        subscriptions.write().await.remove(&subscription_id);
+    });
+
+    x.insert(
+        subscription_id,
+        WsSubscription {
+            handle,
+            resume_params: None,
+            sent_tx: Some(tx_hash),
+        },
+    );
+}
+
+/// False if the transaction is already being watched or the socket is watching too many transactions
+fn can_watch_sent_tx(
+    subscriptions: &HashMap<U64, WsSubscription>,
+    tx_hash: TxHash,
+    max_watching: usize,
+) -> bool {
+    let mut num_watching = 0;
+
+    for x in subscriptions.values() {
+        match x.sent_tx {
+            Some(x) if x == tx_hash => return false,
+            Some(_) => num_watching += 1,
+            None => {}
+        }
+    }
+
+    num_watching < max_watching
 }
 
 async fn read_web3_socket(
     app: Arc<Web3ProxyApp>,
     authorization: Arc<Authorization>,
+    resume_key: Option<WsResumeKey>,
     mut ws_rx: SplitStream<WebSocket>,
     response_sender: flume::Sender<Message>,
 ) {
@@ -440,14 +689,44 @@ async fn read_web3_socket(
     let subscriptions = Arc::new(RwLock::new(HashMap::new()));
     let subscription_count = Arc::new(AtomicU64::new(1));
 
+    if let Some(resume_key) = resume_key.as_ref() {
+        resume_subscriptions(
+            &app,
+            &authorization,
+            resume_key,
+            &response_sender,
+            &subscription_count,
+            &subscriptions,
+        )
+        .await;
+    }
+
     let (close_sender, mut close_receiver) = broadcast::channel(1);
 
+    // intervals can't be 0 seconds. the branches that use them are disabled instead
+    let ping_period = Duration::from_secs(app.config.ws_ping_interval.max(1));
+    let pong_timeout = Duration::from_secs(app.config.ws_pong_timeout);
+    let idle_period = Duration::from_secs(app.config.ws_idle_timeout.max(1));
+
+    let mut ping_interval = interval_at(Instant::now() + ping_period, ping_period);
+    let mut idle_interval = interval_at(Instant::now() + idle_period, idle_period);
+
+    let mut last_activity = Instant::now();
+    let mut pong_deadline: Option<Instant> = None;
+
     loop {
         tokio::select! {
             msg = ws_rx.next() => {
                 if let Some(Ok(msg)) = msg {
+                    last_activity = Instant::now();
+
+                    if let Message::Pong(_) = msg {
+                        pong_deadline = None;
+                    }
+
                     // clone things so we can handle multiple messages in parallel
                     let close_sender = close_sender.clone();
+                    let expose_internal_errors = app.config.expose_internal_errors;
                     let app = app.clone();
                     let authorization = authorization.clone();
                     let response_sender = response_sender.clone();
@@ -470,14 +749,14 @@ async fn read_web3_socket(
                                     Ok((m, s)) => (m, Some(s)),
                                     Err(err) => {
                                         // TODO: how can we get the id out of the payload?
-                                        let m = err.into_message(None);
-                                        (m, None)
+                                        let m = err.into_message(None, expose_internal_errors);
+                                        (Some(m), None)
                                     }
                                 }
                             }
                             Message::Ping(x) => {
                                 trace!("ping: {:?}", x);
-                                (Message::Pong(x), None)
+                                (Some(Message::Pong(x)), None)
                             }
                             Message::Pong(x) => {
                                 trace!("pong: {:?}", x);
@@ -485,7 +764,6 @@ async fn read_web3_socket(
                             }
                             Message::Close(_) => {
                                 info!("closing websocket connection");
-                                // TODO: do something to close subscriptions?
                                 let _ = close_sender.send(true);
                                 return;
                             }
@@ -504,23 +782,25 @@ async fn read_web3_socket(
                                     Ok((m, s)) => (m, Some(s)),
                                     Err(err) => {
                                         // TODO: how can we get the id out of the payload?
-                                        let m = err.into_message(None);
-                                        (m, None)
+                                        let m = err.into_message(None, expose_internal_errors);
+                                        (Some(m), None)
                                     }
                                 };
 
                                 // TODO: is this an okay way to convert from text to binary?
-                                let m = if let Message::Text(m) = m {
+                                let m = m.map(|m| if let Message::Text(m) = m {
                                     Message::Binary(m.as_bytes().to_vec())
                                 } else {
                                     unimplemented!();
-                                };
+                                });
 
                                 (m, s)
                             }
                         };
 
-                        if response_sender.send_async(response_msg).await.is_err() {
+                        if let Some(response_msg) = response_msg
+                            && response_sender.send_async(response_msg).await.is_err()
+                        {
                             let _ = close_sender.send(true);
                         };
                     };
@@ -533,8 +813,140 @@ async fn read_web3_socket(
             _ = close_receiver.recv() => {
                 break;
             }
+            _ = ping_interval.tick(), if app.config.ws_ping_interval > 0 => {
+                // only one ping is outstanding at a time
+                if pong_deadline.is_none() {
+                    if response_sender.send_async(Message::Ping(vec![])).await.is_err() {
+                        break;
+                    }
+
+                    pong_deadline = Some(Instant::now() + pong_timeout);
+                }
+            }
+            _ = sleep_until(pong_deadline.unwrap_or_else(Instant::now)), if pong_deadline.is_some() => {
+                info!("websocket did not answer a ping within {:?}", pong_timeout);
+                break;
+            }
+            _ = idle_interval.tick(), if app.config.ws_idle_timeout > 0 => {
+                if last_activity.elapsed() >= idle_period && subscriptions.read().await.is_empty() {
+                    info!("closing idle websocket connection");
+                    break;
+                }
+            }
         }
     }
+
+    // the client is gone or being disconnected. stop sending it subscription messages
+    // keep the subscriptions around in case the client reconnects with the same token
+    close_subscriptions(
+        app.ws_resume_cache.as_ref(),
+        resume_key,
+        &mut *subscriptions.write().await,
+    )
+    .await;
+
+    let _ = response_sender.send_async(Message::Close(None)).await;
+}
+
+/// Remember websocket subscriptions for ttl seconds after their websocket closes. None if ttl is 0
+pub fn ws_resume_cache(ttl: u64) -> Option<WsResumeCache> {
+    (ttl > 0).then(|| {
+        CacheBuilder::new(10_000)
+            .name("ws_resume_cache")
+            .time_to_live(Duration::from_secs(ttl))
+            .build()
+    })
+}
+
+/// Abort all of a closed websocket's subscriptions. The resumable ones are saved under its resume key
+async fn close_subscriptions(
+    ws_resume_cache: Option<&WsResumeCache>,
+    resume_key: Option<WsResumeKey>,
+    subscriptions: &mut HashMap<U64, WsSubscription>,
+) {
+    let mut resumable = vec![];
+
+    for (subscription_id, subscription) in subscriptions.drain() {
+        subscription.handle.abort();
+
+        if let Some(params) = subscription.resume_params {
+            resumable.push(ResumableSubscription {
+                subscription_id,
+                params,
+            });
+        }
+    }
+
+    if let (Some(resume_key), Some(ws_resume_cache)) = (resume_key, ws_resume_cache) {
+        if !resumable.is_empty() {
+            ws_resume_cache
+                .insert(resume_key, Arc::new(resumable))
+                .await;
+        }
+    }
+}
+
+/// Re-establish the subscriptions that a previous websocket with the same resume token had open.
+/// A token can only be used once. The cache forgets unused tokens after ws_resume_ttl seconds
+async fn resume_subscriptions(
+    app: &Arc<Web3ProxyApp>,
+    authorization: &Arc<Authorization>,
+    resume_key: &WsResumeKey,
+    response_sender: &flume::Sender<Message>,
+    subscription_count: &AtomicU64,
+    subscriptions: &RwLock<HashMap<U64, WsSubscription>>,
+) {
+    let Some(ws_resume_cache) = app.ws_resume_cache.as_ref() else {
+        return;
+    };
+
+    let Some(resumable) = ws_resume_cache.remove(resume_key).await else {
+        return;
+    };
+
+    let mut subscriptions = subscriptions.write().await;
+
+    for x in resumable.iter() {
+        let request = JsonRpcRequest::new(
+            JsonRpcId::None,
+            "eth_subscribe".to_string(),
+            x.params.clone(),
+        )
+        .expect("this request should always be valid");
+
+        match app
+            .eth_subscribe(
+                authorization.clone(),
+                request,
+                x.subscription_id,
+                response_sender.clone(),
+            )
+            .await
+        {
+            Ok((handle, _)) => {
+                subscriptions.insert(
+                    x.subscription_id,
+                    WsSubscription {
+                        handle,
+                        resume_params: Some(x.params.clone()),
+                        sent_tx: None,
+                    },
+                );
+            }
+            Err(err) => {
+                warn!(?err, subscription_id=%x.subscription_id, "unable to resume subscription");
+            }
+        }
+
+        // new subscriptions must not reuse a resumed id
+        subscription_count.fetch_max(x.subscription_id.as_u64() + 1, atomic::Ordering::SeqCst);
+    }
+
+    trace!(
+        "resumed {} of {} subscriptions",
+        subscriptions.len(),
+        resumable.len()
+    );
 }
 
 async fn write_web3_socket(
@@ -558,3 +970,166 @@ async fn write_web3_socket(
 
     // TODO: decrement counter for open websockets
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::pending;
+
+    /// a subscription task that runs until it is aborted
+    fn subscription(
+        resume_params: Option<serde_json::Value>,
+    ) -> (WsSubscription, tokio::task::JoinHandle<()>) {
+        let (handle, registration) = AbortHandle::new_pair();
+
+        let task = tokio::spawn(async move {
+            let _ = Abortable::new(pending::<()>(), registration).await;
+        });
+
+        (
+            WsSubscription {
+                handle,
+                resume_params,
+                sent_tx: None,
+            },
+            task,
+        )
+    }
+
+    #[test]
+    fn test_resume_params() {
+        assert!(resume_params(&json!(["newHeads"])).is_some());
+        assert!(resume_params(&json!(["newPendingTransactions"])).is_none());
+        // logs subscriptions aren't implemented, so there is nothing to resume
+        assert!(resume_params(&json!(["logs", {}])).is_none());
+    }
+
+    #[test]
+    fn test_resume_key_scope() {
+        let mut authorization = Authorization::default();
+
+        authorization.ip = "10.0.0.1".parse().unwrap();
+        let a = WsResumeKey::new(&authorization, "token".to_string());
+
+        authorization.ip = "10.0.0.2".parse().unwrap();
+        let b = WsResumeKey::new(&authorization, "token".to_string());
+
+        // public sockets can only resume from the same ip
+        assert_ne!(a, b);
+
+        // keyed sockets can resume from any ip
+        authorization.checks.rpc_secret_key_id = NonZeroU64::new(1);
+        let c = WsResumeKey::new(&authorization, "token".to_string());
+
+        authorization.ip = "10.0.0.1".parse().unwrap();
+        let d = WsResumeKey::new(&authorization, "token".to_string());
+
+        assert_eq!(c, d);
+        assert_ne!(a, c);
+    }
+
+    #[tokio::test]
+    async fn test_close_subscriptions() {
+        assert!(ws_resume_cache(0).is_none());
+
+        let ws_resume_cache = ws_resume_cache(60).unwrap();
+
+        let resume_key = WsResumeKey::new(&Authorization::default(), "token".to_string());
+
+        let (new_heads, new_heads_task) = subscription(Some(json!(["newHeads"])));
+        let (pending_txs, pending_txs_task) = subscription(None);
+
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(U64::from(1), new_heads);
+        subscriptions.insert(U64::from(2), pending_txs);
+
+        close_subscriptions(
+            Some(&ws_resume_cache),
+            Some(resume_key.clone()),
+            &mut subscriptions,
+        )
+        .await;
+
+        // every subscription stops when the socket closes
+        assert!(subscriptions.is_empty());
+        assert!(new_heads_task.await.is_ok());
+        assert!(pending_txs_task.await.is_ok());
+
+        // only the resumable one is saved
+        let saved = ws_resume_cache.remove(&resume_key).await.unwrap();
+
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].subscription_id, U64::from(1));
+        assert_eq!(saved[0].params, json!(["newHeads"]));
+
+        // tokens can only be used once
+        assert!(ws_resume_cache.remove(&resume_key).await.is_none());
+
+        // sockets without anything to resume don't save an empty entry
+        let (pending_txs, _) = subscription(None);
+        subscriptions.insert(U64::from(3), pending_txs);
+
+        close_subscriptions(
+            Some(&ws_resume_cache),
+            Some(resume_key.clone()),
+            &mut subscriptions,
+        )
+        .await;
+
+        assert!(ws_resume_cache.get(&resume_key).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sent_tx_watchers() {
+        let tx_a = TxHash::repeat_byte(0xa);
+        let tx_b = TxHash::repeat_byte(0xb);
+
+        let mut subscriptions = HashMap::new();
+
+        // eth_subscribe subscriptions don't count towards the limit
+        let (pending_txs, _) = subscription(None);
+        subscriptions.insert(U64::from(1), pending_txs);
+
+        assert!(can_watch_sent_tx(&subscriptions, tx_a, 1));
+
+        let (mut watcher, watcher_task) = subscription(None);
+        watcher.sent_tx = Some(tx_a);
+        subscriptions.insert(U64::from(2), watcher);
+
+        // the same transaction isn't watched twice
+        assert!(!can_watch_sent_tx(&subscriptions, tx_a, 2));
+        assert!(can_watch_sent_tx(&subscriptions, tx_b, 2));
+        assert!(!can_watch_sent_tx(&subscriptions, tx_b, 1));
+
+        // watchers stop when the socket closes
+        close_subscriptions(None, None, &mut subscriptions).await;
+
+        assert!(watcher_task.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resume_ttl() {
+        let ws_resume_cache = ws_resume_cache(1).unwrap();
+
+        let resume_key = WsResumeKey::new(&Authorization::default(), "token".to_string());
+
+        let (new_heads, _) = subscription(Some(json!(["newHeads"])));
+
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(U64::from(1), new_heads);
+
+        close_subscriptions(
+            Some(&ws_resume_cache),
+            Some(resume_key.clone()),
+            &mut subscriptions,
+        )
+        .await;
+
+        assert!(ws_resume_cache.get(&resume_key).is_some());
+
+        // moka uses its own clock, so this needs real time to pass
+        tokio::time::sleep(Duration::from_millis(1_100)).await;
+
+        assert!(ws_resume_cache.get(&resume_key).is_none());
+    }
+}
@@ -26,9 +26,6 @@ use std::{sync::Arc, time::Duration};
 use tokio::time::timeout;
 use tracing::trace;
 
-static HEALTH_OK: Lazy<Bytes> = Lazy::new(|| Bytes::from("OK\n"));
-static HEALTH_NOT_OK: Lazy<Bytes> = Lazy::new(|| Bytes::from(":(\n"));
-
 static BACKUPS_NEEDED_TRUE: Lazy<Bytes> = Lazy::new(|| Bytes::from("true\n"));
 static BACKUPS_NEEDED_FALSE: Lazy<Bytes> = Lazy::new(|| Bytes::from("false\n"));
 
@@ -95,19 +92,45 @@ pub async fn health(
 }
 
 // TODO: _health doesn't need to be async, but _quick_cache_ttl needs an async function
+/// Readiness, not liveness. This fails until enough backends are synced so that traffic isn't routed here during startup.
 #[inline]
 async fn _health(app: Arc<Web3ProxyApp>) -> (StatusCode, &'static str, Bytes) {
     trace!("health is not cached");
 
-    if app.balanced_rpcs.synced() {
-        (StatusCode::OK, CONTENT_TYPE_PLAIN, HEALTH_OK.clone())
+    let head_block_number = app.balanced_rpcs.head_block_num();
+    let synced_rpcs = app.balanced_rpcs.num_synced_rpcs();
+    let min_synced_rpcs = app.balanced_rpcs.min_head_rpcs();
+
+    // the same conditions as NoConsensusHeadBlock and NotEnoughRpcs
+    let error = if head_block_number.is_none() {
+        Some("no consensus head block")
+    } else if synced_rpcs < min_synced_rpcs {
+        Some("not enough synced rpcs")
     } else {
-        (
-            StatusCode::SERVICE_UNAVAILABLE,
-            CONTENT_TYPE_PLAIN,
-            HEALTH_NOT_OK.clone(),
-        )
-    }
+        None
+    };
+
+    let code = if error.is_none() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    // let operators watch removed rpcs finish their in-flight requests
+    let draining: HashMap<_, _> = app.balanced_rpcs.draining().into_iter().collect();
+
+    let body = json!({
+        "chain_id": app.config.chain_id,
+        "draining": draining,
+        "error": error,
+        "head_block_number": head_block_number,
+        "min_synced_rpcs": min_synced_rpcs,
+        "synced_rpcs": synced_rpcs,
+    });
+
+    let body = serde_json::to_vec(&body).expect("health should always serialize");
+
+    (code, CONTENT_TYPE_JSON, body.into())
 }
 
 /// Easy alerting if backup servers are in use.
@@ -1,6 +1,7 @@
 //! Handle registration, logins, and managing account data.
 pub mod authentication;
 pub mod payment;
+pub mod rate_limit;
 pub mod referral;
 pub mod rpc_keys;
 pub mod stats;
@@ -0,0 +1,72 @@
+//! Let users check their rate limits without using them up.
+use super::super::authorization::RpcSecretKey;
+use crate::app::Web3ProxyApp;
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::frontend::rpc_proxy_ws::ProxyMode;
+use axum::{
+    headers::{authorization::Bearer, Authorization},
+    response::IntoResponse,
+    Extension, Json, TypedHeader,
+};
+use axum_macros::debug_handler;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+struct RateLimitStatus {
+    /// requests allowed per period. None is unlimited
+    limit: Option<u64>,
+    /// requests left in this period. None is unlimited
+    remaining: Option<u64>,
+    /// seconds in each period
+    period: u64,
+    /// unix timestamp when the current period ends
+    reset_at: i64,
+}
+
+/// `GET /user/rate_limit` -- Use an rpc key as the bearer token to see how much of its rate limit is left.
+/// Checking does not count as a request.
+///
+/// Limits are counted in `period` second periods that line up with the clock, not `period` seconds after the first request.
+/// At `reset_at`, the count goes back to 0 and `limit` requests are available again.
+/// The limit is shared by all of the user's keys.
+#[debug_handler]
+pub async fn user_rate_limit_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let rpc_key: RpcSecretKey = bearer.token().parse()?;
+
+    let authorization_checks = app.authorization_checks(ProxyMode::Best, &rpc_key).await?;
+
+    if authorization_checks.rpc_secret_key_id.is_none() {
+        return Err(Web3ProxyError::UnknownKey);
+    }
+
+    let rate_limiter = app
+        .frontend_registered_user_rate_limiter
+        .as_ref()
+        .web3_context("rate limits are not enabled")?;
+
+    let seconds_until_reset = rate_limiter.seconds_until_reset();
+
+    let reset_at = chrono::Utc::now().timestamp() + seconds_until_reset.ceil() as i64;
+
+    let remaining = match authorization_checks.max_requests_per_period {
+        None => None,
+        Some(limit) => {
+            let used = rate_limiter.peek(authorization_checks.user_id).await?;
+
+            Some(limit.saturating_sub(used))
+        }
+    };
+
+    let status = RateLimitStatus {
+        limit: authorization_checks.max_requests_per_period,
+        remaining,
+        period: app.config.rate_limit_period,
+        reset_at,
+    };
+
+    Ok(Json(status).into_response())
+}
@@ -0,0 +1,78 @@
+//! Match client ips against lists of networks.
+//!
+//! Entries are CIDR ranges like `10.0.0.0/8` or `2001:db8::/32`. A single address is a /32 or /128.
+//! Dual-stack sockets see IPv4 clients as IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`).
+//! Those are checked as their IPv4 address too, so IPv4 entries match them.
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// IPv4-mapped IPv6 addresses become IPv4. Everything else is unchanged
+pub fn normalize_ip(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(x) => x.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    }
+}
+
+/// True if any of the networks contain the ip or its normalized form
+pub fn ip_in_networks(networks: &[IpNet], ip: &IpAddr) -> bool {
+    let normalized = normalize_ip(ip);
+
+    networks
+        .iter()
+        .any(|x| x.contains(ip) || x.contains(&normalized))
+}
+
+/// Blocked networks win over allowed networks. An empty allowed list allows every ip that isn't blocked
+pub fn ip_is_allowed(allowed: &[IpNet], blocked: &[IpNet], ip: &IpAddr) -> bool {
+    if ip_in_networks(blocked, ip) {
+        return false;
+    }
+
+    allowed.is_empty() || ip_in_networks(allowed, ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn networks(x: &[&str]) -> Vec<IpNet> {
+        x.iter().map(|x| x.parse().unwrap()).collect()
+    }
+
+    fn ip(x: &str) -> IpAddr {
+        x.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4_in_allowed_subnet() {
+        let allowed = networks(&["192.168.1.0/24"]);
+
+        assert!(ip_is_allowed(&allowed, &[], &ip("192.168.1.42")));
+        assert!(!ip_is_allowed(&allowed, &[], &ip("192.168.2.42")));
+    }
+
+    #[test]
+    fn test_ipv6_in_allowed_subnet() {
+        let allowed = networks(&["2001:db8:1:2::/64"]);
+
+        assert!(ip_is_allowed(&allowed, &[], &ip("2001:db8:1:2::abcd")));
+        assert!(!ip_is_allowed(&allowed, &[], &ip("2001:db8:1:3::abcd")));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_ipv6() {
+        assert_eq!(normalize_ip(&ip("::ffff:192.168.1.42")), ip("192.168.1.42"));
+
+        let allowed = networks(&["192.168.1.0/24"]);
+
+        assert!(ip_is_allowed(&allowed, &[], &ip("::ffff:192.168.1.42")));
+        assert!(!ip_is_allowed(&allowed, &[], &ip("::ffff:192.168.2.42")));
+
+        // blocking the ipv4 network blocks the mapped address too
+        let blocked = networks(&["192.168.1.42/32"]);
+
+        assert!(!ip_is_allowed(&[], &blocked, &ip("::ffff:192.168.1.42")));
+        assert!(ip_is_allowed(&[], &blocked, &ip("::ffff:192.168.1.43")));
+    }
+}
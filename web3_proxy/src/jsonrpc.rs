@@ -1,11 +1,14 @@
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
 use crate::response_cache::JsonRpcResponseEnum;
 use derive_more::From;
+use ethers::types::Filter;
 use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::value::{to_raw_value, RawValue};
 use std::borrow::Cow;
 use std::fmt;
+use std::io;
 use std::sync::Arc;
 
 pub trait JsonRpcParams = fmt::Debug + serde::Serialize + Send + Sync + 'static;
@@ -13,7 +16,7 @@ pub trait JsonRpcResultData = serde::Serialize + serde::de::DeserializeOwned + f
 
 // TODO: &str here instead of String should save a lot of allocations
 // TODO: generic type for params?
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Serialize)]
 pub struct JsonRpcRequest {
     pub jsonrpc: String,
     /// id could be a stricter type, but many rpcs do things against the spec
@@ -21,6 +24,9 @@ pub struct JsonRpcRequest {
     pub method: String,
     /// TODO: skip serializing if serde_json::Value::Null
     pub params: serde_json::Value,
+    /// The request had no id. It is still processed, but the user does not get a response
+    #[serde(skip)]
+    pub notification: bool,
 }
 
 #[derive(From)]
@@ -50,10 +56,77 @@ impl JsonRpcRequest {
             id: id.to_raw_value(),
             method,
             params,
+            notification: false,
         };
 
         Ok(x)
     }
+
+    /// Parse a single request from a user. If strict, `"jsonrpc": "2.0"` is required.
+    /// Deserialize is never strict
+    pub fn parse(input: &str, strict: bool) -> serde_json::Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_str(input);
+
+        let x = deserializer.deserialize_map(JsonRpcRequestVisitor { strict })?;
+
+        deserializer.end()?;
+
+        Ok(x)
+    }
+}
+
+/// The params of an eth_subscribe request. `["newHeads"]` or `["logs", {"address": ...}]`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "EthSubscribeParamsSeq")]
+pub enum EthSubscribeParams {
+    NewHeads,
+    NewPendingTransactions,
+    /// a proxy extension. sends whole transactions instead of their hashes
+    NewPendingFullTransactions,
+    /// a proxy extension. sends rlp encoded transactions instead of their hashes
+    NewPendingRawTransactions,
+    Logs(Box<Filter>),
+    /// Valid params for a subscription that the proxy doesn't support
+    Unknown(String),
+}
+
+/// The subscription's name and its optional argument
+#[derive(Deserialize)]
+struct EthSubscribeParamsSeq(String, #[serde(default)] Option<serde_json::Value>);
+
+impl TryFrom<EthSubscribeParamsSeq> for EthSubscribeParams {
+    type Error = serde_json::Error;
+
+    fn try_from(value: EthSubscribeParamsSeq) -> Result<Self, Self::Error> {
+        let EthSubscribeParamsSeq(name, arg) = value;
+
+        let x = match name.as_str() {
+            "newHeads" => Self::NewHeads,
+            "newPendingTransactions" => Self::NewPendingTransactions,
+            "newPendingFullTransactions" => Self::NewPendingFullTransactions,
+            "newPendingRawTransactions" => Self::NewPendingRawTransactions,
+            "logs" => {
+                // no filter means every log
+                let filter = match arg {
+                    None | Some(serde_json::Value::Null) => Filter::default(),
+                    Some(x) => serde_json::from_value(x)?,
+                };
+
+                Self::Logs(Box::new(filter))
+            }
+            _ => Self::Unknown(name),
+        };
+
+        Ok(x)
+    }
+}
+
+impl EthSubscribeParams {
+    /// newHeads means the same thing after a reconnect, so it can be resumed.
+    /// Pending transactions seen while the client was gone are lost either way. Logs subscriptions aren't supported yet
+    pub fn is_resumable(&self) -> bool {
+        matches!(self, Self::NewHeads)
+    }
 }
 
 impl fmt::Debug for JsonRpcRequest {
@@ -76,112 +149,214 @@ pub enum JsonRpcRequestEnum {
 }
 
 impl JsonRpcRequestEnum {
+    /// Parse a request or batch from a user. If strict, every request needs `"jsonrpc": "2.0"`.
+    /// Deserialize is never strict
+    pub fn parse(input: &str, strict: bool) -> serde_json::Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_str(input);
+
+        let x = deserializer.deserialize_any(JsonRpcRequestEnumVisitor { strict })?;
+
+        deserializer.end()?;
+
+        Ok(x)
+    }
+
     pub fn first_id(&self) -> Option<Box<RawValue>> {
         match self {
             Self::Batch(x) => x.first().map(|x| x.id.clone()),
             Self::Single(x) => Some(x.id.clone()),
         }
     }
+
+    /// True if nothing should be sent back. A batch of only notifications gets no response at all
+    pub fn is_notification(&self) -> bool {
+        match self {
+            Self::Batch(x) => !x.is_empty() && x.iter().all(|x| x.notification),
+            Self::Single(x) => x.notification,
+        }
+    }
 }
 
-impl<'de> Deserialize<'de> for JsonRpcRequestEnum {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum JsonRpcRequestField {
+    JsonRpc,
+    Id,
+    Method,
+    Params,
+    #[serde(other)]
+    Other,
+}
+
+/// Deserialize a single request. The error messages name the field that is wrong.
+/// If strict, `"jsonrpc": "2.0"` is required. Otherwise it may be missing or any string because some clients don't follow the spec.
+struct JsonRpcRequestVisitor {
+    strict: bool,
+}
+
+impl<'de> Visitor<'de> for JsonRpcRequestVisitor {
+    type Value = JsonRpcRequest;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON-RPC request object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
     where
-        D: Deserializer<'de>,
+        A: MapAccess<'de>,
     {
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field {
-            JsonRpc,
-            Id,
-            Method,
-            Params,
+        let mut jsonrpc = None;
+        let mut id = None;
+        let mut method = None;
+        let mut params = None;
+
+        while let Some(key) = map.next_key()? {
+            match key {
+                JsonRpcRequestField::JsonRpc => {
+                    if jsonrpc.is_some() {
+                        return Err(de::Error::duplicate_field("jsonrpc"));
+                    }
+                    jsonrpc = Some(map.next_value::<serde_json::Value>()?);
+                }
+                JsonRpcRequestField::Id => {
+                    if id.is_some() {
+                        return Err(de::Error::duplicate_field("id"));
+                    }
+                    id = Some(map.next_value()?);
+                }
+                JsonRpcRequestField::Method => {
+                    if method.is_some() {
+                        return Err(de::Error::duplicate_field("method"));
+                    }
+                    method = Some(map.next_value::<serde_json::Value>()?);
+                }
+                JsonRpcRequestField::Params => {
+                    if params.is_some() {
+                        return Err(de::Error::duplicate_field("params"));
+                    }
+                    params = Some(map.next_value()?);
+                }
+                JsonRpcRequestField::Other => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
         }
 
-        struct JsonRpcBatchVisitor;
+        let jsonrpc = match jsonrpc {
+            Some(serde_json::Value::String(x)) if !self.strict || x == "2.0" => x,
+            Some(x) if self.strict => {
+                return Err(de::Error::custom(format!(
+                    "field `jsonrpc` must be \"2.0\", not {}",
+                    x
+                )));
+            }
+            // some providers don't follow the spec and dont include the jsonrpc key
+            // i think "2.0" should be a fine default to handle these incompatible clones
+            Some(_) | None if !self.strict => "2.0".to_string(),
+            _ => return Err(de::Error::missing_field("jsonrpc")),
+        };
 
-        impl<'de> Visitor<'de> for JsonRpcBatchVisitor {
-            type Value = JsonRpcRequestEnum;
+        // a request without an id is a notification. `"id": null` is a normal request
+        let (id, notification) = match id {
+            Some(id) => (id, false),
+            None => (JsonRpcId::None.to_raw_value(), true),
+        };
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("JsonRpcRequestEnum")
+        let method = match method {
+            Some(serde_json::Value::String(x)) => x,
+            Some(x) => {
+                return Err(de::Error::custom(format!(
+                    "field `method` must be a string, not {}",
+                    x
+                )));
             }
+            None => return Err(de::Error::missing_field("method")),
+        };
 
-            fn visit_seq<V>(self, mut seq: V) -> Result<JsonRpcRequestEnum, V::Error>
-            where
-                V: SeqAccess<'de>,
-            {
-                // TODO: what size should we use as the default?
-                let mut batch: Vec<JsonRpcRequest> =
-                    Vec::with_capacity(seq.size_hint().unwrap_or(10));
+        Ok(JsonRpcRequest {
+            jsonrpc,
+            id,
+            method,
+            params: params.unwrap_or_default(),
+            notification,
+        })
+    }
+}
 
-                while let Ok(Some(s)) = seq.next_element::<JsonRpcRequest>() {
-                    batch.push(s);
-                }
+impl<'de> Deserialize<'de> for JsonRpcRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(JsonRpcRequestVisitor { strict: false })
+    }
+}
 
-                Ok(JsonRpcRequestEnum::Batch(batch))
-            }
+struct JsonRpcRequestEnumVisitor {
+    strict: bool,
+}
 
-            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-            where
-                A: MapAccess<'de>,
-            {
-                // TODO: i feel like this should be easier
-                let mut jsonrpc = None;
-                let mut id = None;
-                let mut method = None;
-                let mut params = None;
-
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        Field::JsonRpc => {
-                            // throw away the value
-                            // TODO: should we check that it's 2.0?
-                            // TODO: how do we skip over this value entirely?
-                            jsonrpc = Some(map.next_value()?);
-                        }
-                        Field::Id => {
-                            if id.is_some() {
-                                return Err(de::Error::duplicate_field("id"));
-                            }
-                            id = Some(map.next_value()?);
-                        }
-                        Field::Method => {
-                            if method.is_some() {
-                                return Err(de::Error::duplicate_field("method"));
-                            }
-                            method = Some(map.next_value()?);
-                        }
-                        Field::Params => {
-                            if params.is_some() {
-                                return Err(de::Error::duplicate_field("params"));
-                            }
-                            params = Some(map.next_value()?);
-                        }
-                    }
-                }
+impl<'de> Visitor<'de> for JsonRpcRequestEnumVisitor {
+    type Value = JsonRpcRequestEnum;
 
-                // some providers don't follow the spec and dont include the jsonrpc key
-                // i think "2.0" should be a fine default to handle these incompatible clones
-                let jsonrpc = jsonrpc.unwrap_or_else(|| "2.0".to_string());
-                // TODO: Errors returned by the try operator get shown in an ugly way
-                let id = id.ok_or_else(|| de::Error::missing_field("id"))?;
-                let method = method.ok_or_else(|| de::Error::missing_field("method"))?;
-
-                let single = JsonRpcRequest {
-                    jsonrpc,
-                    id,
-                    method,
-                    params: params.unwrap_or_default(),
-                };
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON-RPC request object or a batch of them")
+    }
 
-                Ok(JsonRpcRequestEnum::Single(single))
-            }
+    fn visit_seq<V>(self, mut seq: V) -> Result<JsonRpcRequestEnum, V::Error>
+    where
+        V: SeqAccess<'de>,
+    {
+        // TODO: what size should we use as the default?
+        let mut batch: Vec<JsonRpcRequest> = Vec::with_capacity(seq.size_hint().unwrap_or(10));
+
+        // a malformed request fails the whole batch instead of silently cutting it short
+        while let Some(s) = seq.next_element_seed(JsonRpcRequestSeed {
+            strict: self.strict,
+        })? {
+            batch.push(s);
+        }
+
+        Ok(JsonRpcRequestEnum::Batch(batch))
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let single = JsonRpcRequestVisitor {
+            strict: self.strict,
         }
+        .visit_map(map)?;
 
-        let batch_visitor = JsonRpcBatchVisitor {};
+        Ok(JsonRpcRequestEnum::Single(single))
+    }
+}
 
-        deserializer.deserialize_any(batch_visitor)
+/// lets batched requests use the same strictness as the batch
+struct JsonRpcRequestSeed {
+    strict: bool,
+}
+
+impl<'de> de::DeserializeSeed<'de> for JsonRpcRequestSeed {
+    type Value = JsonRpcRequest;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(JsonRpcRequestVisitor {
+            strict: self.strict,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonRpcRequestEnum {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(JsonRpcRequestEnumVisitor { strict: false })
     }
 }
 
@@ -198,6 +373,23 @@ pub struct JsonRpcErrorData {
     pub data: Option<serde_json::Value>,
 }
 
+impl JsonRpcErrorData {
+    /// The error that geth gives for unknown or expired filter ids.
+    /// Clients know to create a new filter when they see this.
+    pub fn filter_not_found() -> Self {
+        Self {
+            code: -32000,
+            message: "filter not found".into(),
+            data: None,
+        }
+    }
+
+    /// The spec's code for a method the server doesn't implement
+    pub fn is_method_not_found(&self) -> bool {
+        self.code == -32601
+    }
+}
+
 impl From<&'static str> for JsonRpcErrorData {
     fn from(value: &'static str) -> Self {
         Self {
@@ -231,12 +423,75 @@ pub struct JsonRpcForwardedResponse {
     pub error: Option<JsonRpcErrorData>,
 }
 
+/// Counts the bytes that serde_json would write without allocating a String for them
+#[derive(Default)]
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn serialized_len<T: Serialize + ?Sized>(x: &T) -> usize {
+    let mut counter = ByteCounter::default();
+
+    serde_json::to_writer(&mut counter, x).expect("this should always be valid json");
+
+    counter.0
+}
+
 impl JsonRpcRequest {
     pub fn num_bytes(&self) -> usize {
-        // TODO: not sure how to do this without wasting a ton of allocations
-        serde_json::to_string(self)
-            .expect("this should always be valid json")
-            .len()
+        serialized_len(self)
+    }
+
+    pub fn params_bytes(&self) -> usize {
+        serialized_len(&self.params)
+    }
+
+    /// Reject requests with params larger than max_params_bytes. None = allow any size
+    pub fn check_params_bytes(&self, max_params_bytes: Option<usize>) -> Web3ProxyResult<()> {
+        if let Some(max_params_bytes) = max_params_bytes {
+            let params_bytes = self.params_bytes();
+
+            if params_bytes > max_params_bytes {
+                return Err(Web3ProxyError::BadRequest(
+                    format!(
+                        "params of {} bytes are larger than the max of {}",
+                        params_bytes, max_params_bytes
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl JsonRpcForwardedResponse {
+    /// The length of this response once it is serialized.
+    /// The result is already raw json, so this only counts the envelope around it and doesn't serialize the result again
+    pub fn num_bytes(&self) -> usize {
+        // {"jsonrpc":"2.0","id":ID}
+        let mut num_bytes =
+            r#"{"jsonrpc":"","id":}"#.len() + self.jsonrpc.len() + self.id.get().len();
+
+        if let Some(result) = self.result.as_ref() {
+            num_bytes += r#","result":"#.len() + result.get().len();
+        }
+
+        if let Some(error) = self.error.as_ref() {
+            num_bytes += r#","error":"#.len() + serialized_len(error);
+        }
+
+        num_bytes
     }
 }
 
@@ -321,6 +576,83 @@ pub enum JsonRpcForwardedResponseEnum {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ethers::types::Address;
+
+    #[test]
+    fn this_oversized_params() {
+        let addresses: Vec<_> = (0..10_000).map(|_| Address::zero()).collect();
+
+        let request: JsonRpcRequest = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_getLogs",
+            "params": [{"address": addresses}],
+        }))
+        .unwrap();
+
+        assert!(request.params_bytes() > 100_000);
+
+        assert!(matches!(
+            request.check_params_bytes(Some(100_000)),
+            Err(Web3ProxyError::BadRequest(_))
+        ));
+
+        request.check_params_bytes(Some(1_000_000)).unwrap();
+        request.check_params_bytes(None).unwrap();
+    }
+
+    #[test]
+    fn forwarded_response_num_bytes() {
+        let id = RawValue::from_string("1".to_string()).unwrap();
+
+        let result = JsonRpcForwardedResponse::from_raw_response(
+            RawValue::from_string(r#"{"number":"0x1"}"#.to_string())
+                .unwrap()
+                .into(),
+            id.clone(),
+        );
+
+        assert_eq!(
+            result.num_bytes(),
+            serde_json::to_string(&result).unwrap().len()
+        );
+
+        let error = JsonRpcForwardedResponse::from_str("oops", Some(-32000), Some(id));
+
+        assert_eq!(
+            error.num_bytes(),
+            serde_json::to_string(&error).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn deserialize_eth_subscribe_params() {
+        let x: EthSubscribeParams = serde_json::from_value(json!(["newHeads"])).unwrap();
+        assert_eq!(x, EthSubscribeParams::NewHeads);
+
+        let x: EthSubscribeParams = serde_json::from_value(json!(["logs"])).unwrap();
+        assert_eq!(x, EthSubscribeParams::Logs(Default::default()));
+
+        let x: EthSubscribeParams = serde_json::from_value(json!([
+            "logs",
+            {"address": "0x0000000000000000000000000000000000000001"}
+        ]))
+        .unwrap();
+        assert_eq!(
+            x,
+            EthSubscribeParams::Logs(Box::new(Filter::new().address(Address::from_low_u64_be(1))))
+        );
+
+        let x: EthSubscribeParams = serde_json::from_value(json!(["syncing"])).unwrap();
+        assert_eq!(x, EthSubscribeParams::Unknown("syncing".to_string()));
+
+        // malformed params are errors instead of unknown subscriptions
+        assert!(serde_json::from_value::<EthSubscribeParams>(json!([])).is_err());
+        assert!(serde_json::from_value::<EthSubscribeParams>(json!("newHeads")).is_err());
+        assert!(
+            serde_json::from_value::<EthSubscribeParams>(json!(["logs", {"address": 1}])).is_err()
+        );
+    }
 
     #[test]
     fn this_deserialize_single() {
@@ -363,4 +695,145 @@ mod tests {
 
         assert!(matches!(output, JsonRpcRequestEnum::Batch(_)));
     }
+
+    #[test]
+    fn this_deserialize_notification() {
+        let input = r#"{"jsonrpc":"2.0","method":"eth_sendRawTransaction","params":["0x00"]}"#;
+
+        let output: JsonRpcRequestEnum = serde_json::from_str(input).unwrap();
+
+        assert!(output.is_notification());
+
+        // a null id still wants a response
+        let input = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":null}"#;
+
+        let output: JsonRpcRequestEnum = serde_json::from_str(input).unwrap();
+
+        assert!(!output.is_notification());
+
+        // a batch only skips the response if every request is a notification
+        let input = r#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"jsonrpc":"2.0","method":"eth_chainId"}]"#;
+
+        let output: JsonRpcRequestEnum = serde_json::from_str(input).unwrap();
+
+        assert!(!output.is_notification());
+
+        match output {
+            JsonRpcRequestEnum::Batch(x) => {
+                assert!(!x[0].notification);
+                assert!(x[1].notification);
+            }
+            x => panic!("expected a batch, got {:?}", x),
+        }
+
+        let input = r#"[{"jsonrpc":"2.0","method":"eth_chainId"}]"#;
+
+        let output: JsonRpcRequestEnum = serde_json::from_str(input).unwrap();
+
+        assert!(output.is_notification());
+    }
+
+    fn parse(input: &str, strict: bool) -> Result<JsonRpcRequestEnum, serde_json::Error> {
+        JsonRpcRequestEnum::parse(input, strict)
+    }
+
+    #[test]
+    fn this_strict_jsonrpc_version() {
+        let valid = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","id":1}"#;
+        assert!(parse(valid, true).is_ok());
+
+        let missing = r#"{"method":"eth_blockNumber","id":1}"#;
+        let err = parse(missing, true).unwrap_err();
+        assert!(
+            err.to_string().contains("missing field `jsonrpc`"),
+            "{}",
+            err
+        );
+
+        let wrong = r#"{"jsonrpc":"1.0","method":"eth_blockNumber","id":1}"#;
+        let err = parse(wrong, true).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r#"field `jsonrpc` must be "2.0", not "1.0""#),
+            "{}",
+            err
+        );
+
+        let number = r#"{"jsonrpc":2,"method":"eth_blockNumber","id":1}"#;
+        let err = parse(number, true).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r#"field `jsonrpc` must be "2.0", not 2"#),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn this_lenient_jsonrpc_version() {
+        // some clients omit the version
+        let missing = r#"{"method":"eth_blockNumber","id":1}"#;
+        let JsonRpcRequestEnum::Single(output) = parse(missing, false).unwrap() else {
+            panic!("expected a single request");
+        };
+        assert_eq!(output.jsonrpc, "2.0");
+
+        let number = r#"{"jsonrpc":2,"method":"eth_blockNumber","id":1}"#;
+        assert!(parse(number, false).is_ok());
+    }
+
+    #[test]
+    fn this_invalid_method() {
+        for strict in [true, false] {
+            let missing = r#"{"jsonrpc":"2.0","params":[],"id":1}"#;
+            let err = parse(missing, strict).unwrap_err();
+            assert!(
+                err.to_string().contains("missing field `method`"),
+                "{}",
+                err
+            );
+
+            let number = r#"{"jsonrpc":"2.0","method":1,"id":1}"#;
+            let err = parse(number, strict).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("field `method` must be a string, not 1"),
+                "{}",
+                err
+            );
+
+            let null = r#"{"jsonrpc":"2.0","method":null,"id":1}"#;
+            let err = parse(null, strict).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("field `method` must be a string, not null"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn this_invalid_batch_item() {
+        // the bad request used to silently end the batch early
+        let input = r#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"jsonrpc":"2.0","id":2},{"jsonrpc":"2.0","method":"eth_chainId","id":3}]"#;
+
+        let err = parse(input, false).unwrap_err();
+        assert!(
+            err.to_string().contains("missing field `method`"),
+            "{}",
+            err
+        );
+
+        let input = r#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"method":"eth_chainId","id":2}]"#;
+
+        assert!(parse(input, false).is_ok());
+
+        let err = parse(input, true).unwrap_err();
+        assert!(
+            err.to_string().contains("missing field `jsonrpc`"),
+            "{}",
+            err
+        );
+    }
 }
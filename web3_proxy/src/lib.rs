@@ -1,23 +1,43 @@
+#![feature(if_let_guard)]
 #![feature(let_chains)]
 #![feature(trait_alias)]
 
 pub mod admin_queries;
 pub mod app;
 pub mod block_number;
+pub mod call_bundle;
+pub mod call_gas;
+pub mod compute_unit_quota;
 pub mod compute_units;
 pub mod config;
+#[cfg(feature = "disk_cache")]
+pub mod disk_response_cache;
 pub mod errors;
+pub mod fee_history;
+pub mod filters;
 pub mod frontend;
 pub mod http_params;
+pub mod ip_filter;
 pub mod jsonrpc;
+pub mod logs_chunks;
+pub mod logs_pagination;
+pub mod method_filter;
 pub mod pagerduty;
 pub mod prometheus;
+pub mod raw_transaction;
 pub mod referral_code;
 pub mod relational_db;
 pub mod response_cache;
 pub mod rpcs;
+pub mod shared_response_cache;
+pub mod signer;
 pub mod stats;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod trace_limits;
+pub mod trace_replay;
 pub mod user_token;
+pub mod webhooks;
 
 use serde::Deserialize;
 
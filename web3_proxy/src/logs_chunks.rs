@@ -0,0 +1,251 @@
+//! Split eth_getLogs queries over large block ranges into smaller queries.
+//!
+//! Backends limit how many blocks one eth_getLogs can cover, and every provider has a different limit.
+//! Each chunk is sent on its own and the logs are concatenated.
+//! Chunks don't overlap and are kept in order, so the logs stay in block order.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::jsonrpc::JsonRpcErrorData;
+use crate::response_cache::JsonRpcResponseEnum;
+use ethers::types::{BlockNumber, U64};
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::sync::Arc;
+
+/// How many chunks of one query are sent to the backends at the same time
+pub const MAX_CONCURRENT_CHUNKS: usize = 4;
+
+/// Queries that would need more chunks than this are rejected instead of fanning out to thousands of requests
+pub const MAX_CHUNKS: u64 = 100;
+
+#[derive(Debug, PartialEq)]
+pub struct LogsChunk {
+    pub from_block: U64,
+    pub to_block: U64,
+    pub params: serde_json::Value,
+}
+
+/// Split the filter's block range into chunks of at most max_blocks.
+/// Returns None if the query doesn't need to be split or its range can't be known here.
+/// "pending", "safe", and "finalized" are left for the backends to handle.
+pub fn split_logs_range(
+    params: &serde_json::Value,
+    head_block_num: Option<U64>,
+    max_blocks: u64,
+) -> Web3ProxyResult<Option<Vec<LogsChunk>>> {
+    let filter = match params.get(0).and_then(|x| x.as_object()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    if filter.contains_key("blockHash") || max_blocks == 0 {
+        return Ok(None);
+    }
+
+    let resolve = |key: &str| -> Option<U64> {
+        let block_num = match filter.get(key) {
+            None | Some(serde_json::Value::Null) => BlockNumber::Latest,
+            Some(x) => serde_json::from_value(x.clone()).ok()?,
+        };
+
+        match block_num {
+            BlockNumber::Earliest => Some(U64::zero()),
+            BlockNumber::Latest => head_block_num,
+            BlockNumber::Number(x) => Some(x),
+            BlockNumber::Finalized | BlockNumber::Safe | BlockNumber::Pending => None,
+        }
+    };
+
+    let (Some(from_block), Some(to_block)) = (resolve("fromBlock"), resolve("toBlock")) else {
+        return Ok(None);
+    };
+
+    // let the backend give its usual error for a backwards range
+    if from_block > to_block {
+        return Ok(None);
+    }
+
+    let num_blocks = (to_block - from_block).as_u64().saturating_add(1);
+
+    if num_blocks <= max_blocks {
+        return Ok(None);
+    }
+
+    let num_chunks = (num_blocks - 1) / max_blocks + 1;
+
+    if num_chunks > MAX_CHUNKS {
+        return Err(Web3ProxyError::BadRequest(
+            format!(
+                "eth_getLogs block range is too large. the limit is {} blocks",
+                max_blocks.saturating_mul(MAX_CHUNKS)
+            )
+            .into(),
+        ));
+    }
+
+    let mut chunks = Vec::with_capacity(num_chunks as usize);
+
+    let mut chunk_start = from_block;
+
+    while chunk_start <= to_block {
+        let chunk_end = to_block.min(chunk_start + max_blocks - 1);
+
+        let mut chunk_filter = filter.clone();
+
+        chunk_filter.insert("fromBlock".to_string(), json!(chunk_start));
+        chunk_filter.insert("toBlock".to_string(), json!(chunk_end));
+
+        chunks.push(LogsChunk {
+            from_block: chunk_start,
+            to_block: chunk_end,
+            params: json!([chunk_filter]),
+        });
+
+        chunk_start = chunk_end + 1;
+    }
+
+    Ok(Some(chunks))
+}
+
+/// Concatenate the chunks' logs in the order of the chunks.
+/// If any chunk failed, the whole query fails with an error that says which blocks failed.
+pub fn merge_logs_chunks(
+    responses: Vec<(
+        &LogsChunk,
+        Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>>,
+    )>,
+) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+    let mut logs: Vec<Box<RawValue>> = vec![];
+
+    for (chunk, response) in responses {
+        let context = format!(
+            "eth_getLogs failed for blocks {} to {}",
+            chunk.from_block, chunk.to_block
+        );
+
+        match response {
+            Ok(JsonRpcResponseEnum::Result { value, .. }) => {
+                let chunk_logs: Vec<Box<RawValue>> = serde_json::from_str(value.get())?;
+
+                logs.extend(chunk_logs);
+            }
+            Ok(JsonRpcResponseEnum::RpcError { error_data, .. }) => {
+                return Ok(JsonRpcErrorData {
+                    message: format!("{}: {}", context, error_data.message).into(),
+                    code: error_data.code,
+                    data: error_data.data,
+                }
+                .into());
+            }
+            Err(err) => {
+                return Err(Web3ProxyError::WithContext(
+                    Some(Box::new(err)),
+                    context.into(),
+                ));
+            }
+        }
+    }
+
+    Ok(json!(logs).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_ranges(chunks: &[LogsChunk]) -> Vec<(u64, u64)> {
+        chunks
+            .iter()
+            .map(|x| (x.from_block.as_u64(), x.to_block.as_u64()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_logs_range() {
+        let params = json!([{"fromBlock": "0x0", "toBlock": "0x18", "address": "0x0000000000000000000000000000000000000001"}]);
+
+        let chunks = split_logs_range(&params, None, 10).unwrap().unwrap();
+
+        assert_eq!(chunk_ranges(&chunks), vec![(0, 9), (10, 19), (20, 24)]);
+
+        // everything except the range is kept
+        assert_eq!(
+            chunks[1].params,
+            json!([{"fromBlock": "0xa", "toBlock": "0x13", "address": "0x0000000000000000000000000000000000000001"}])
+        );
+
+        // small ranges aren't split
+        assert_eq!(split_logs_range(&params, None, 25).unwrap(), None);
+
+        // latest is the head block
+        let params = json!([{"fromBlock": "0x5"}]);
+
+        let chunks = split_logs_range(&params, Some(24.into()), 10)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(chunk_ranges(&chunks), vec![(5, 14), (15, 24)]);
+
+        // without a head block, latest is unknown
+        assert_eq!(split_logs_range(&params, None, 10).unwrap(), None);
+
+        // block hashes and pending aren't split
+        let params = json!([{"blockHash": "0x0000000000000000000000000000000000000000000000000000000000000001"}]);
+        assert_eq!(split_logs_range(&params, Some(24.into()), 1).unwrap(), None);
+
+        let params = json!([{"fromBlock": "0x0", "toBlock": "pending"}]);
+        assert_eq!(split_logs_range(&params, Some(24.into()), 1).unwrap(), None);
+
+        // too many chunks is an error
+        let params = json!([{"fromBlock": "earliest", "toBlock": "latest"}]);
+        assert!(split_logs_range(&params, Some(1_000_000.into()), 10).is_err());
+    }
+
+    #[test]
+    fn test_merge_logs_chunks() {
+        let params = json!([{"fromBlock": "0x0", "toBlock": "0x13"}]);
+
+        let chunks = split_logs_range(&params, None, 10).unwrap().unwrap();
+
+        let first: JsonRpcResponseEnum<Arc<RawValue>> =
+            json!([{"blockNumber": "0x1"}, {"blockNumber": "0x2"}]).into();
+        let second: JsonRpcResponseEnum<Arc<RawValue>> = json!([{"blockNumber": "0xb"}]).into();
+
+        let merged =
+            merge_logs_chunks(vec![(&chunks[0], Ok(first)), (&chunks[1], Ok(second))]).unwrap();
+
+        match merged {
+            JsonRpcResponseEnum::Result { value, .. } => {
+                let value: serde_json::Value = serde_json::from_str(value.get()).unwrap();
+
+                assert_eq!(
+                    value,
+                    json!([{"blockNumber": "0x1"}, {"blockNumber": "0x2"}, {"blockNumber": "0xb"}])
+                );
+            }
+            x => panic!("expected logs. got {:?}", x),
+        }
+
+        // one failed chunk fails the whole query
+        let first: JsonRpcResponseEnum<Arc<RawValue>> = json!([{"blockNumber": "0x1"}]).into();
+        let second: JsonRpcResponseEnum<Arc<RawValue>> = JsonRpcErrorData {
+            message: "query timeout exceeded".into(),
+            code: -32000,
+            data: None,
+        }
+        .into();
+
+        let merged =
+            merge_logs_chunks(vec![(&chunks[0], Ok(first)), (&chunks[1], Ok(second))]).unwrap();
+
+        match merged {
+            JsonRpcResponseEnum::RpcError { error_data, .. } => {
+                assert_eq!(error_data.code, -32000);
+                assert_eq!(
+                    error_data.message,
+                    "eth_getLogs failed for blocks 10 to 19: query timeout exceeded"
+                );
+            }
+            x => panic!("expected an error. got {:?}", x),
+        }
+    }
+}
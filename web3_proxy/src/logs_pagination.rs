@@ -0,0 +1,246 @@
+//! Optional pagination for eth_getLogs.
+//!
+//! This is a proxy extension. It is not part of the standard JSON-RPC api.
+//! Add `"paginate": true` to the filter object to opt in. The result is then an object like
+//! `{"logs": [...], "nextPageToken": "0x..."}` instead of a plain array of logs.
+//! Send the token back as `"pageToken"` in an otherwise identical filter to get the next page.
+//! `nextPageToken` is null on the last page.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::response_cache::JsonRpcResponseEnum;
+use ethers::types::{Bytes, U256, U64};
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub const PAGINATE_KEY: &str = "paginate";
+pub const PAGE_TOKEN_KEY: &str = "pageToken";
+
+/// Where the next page starts. Encoded into the opaque page token
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogPosition {
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogPositionFields {
+    block_number: Option<U64>,
+    log_index: Option<U256>,
+}
+
+impl LogPosition {
+    pub fn to_token(self) -> String {
+        let mut x = Vec::with_capacity(16);
+
+        x.extend_from_slice(&self.block_number.to_be_bytes());
+        x.extend_from_slice(&self.log_index.to_be_bytes());
+
+        Bytes::from(x).to_string()
+    }
+
+    pub fn from_token(token: &str) -> Web3ProxyResult<Self> {
+        let x = Bytes::from_str(token)
+            .ok()
+            .filter(|x| x.len() == 16)
+            .ok_or_else(|| Web3ProxyError::BadRequest("invalid pageToken".into()))?;
+
+        let (block_number, log_index) = x.split_at(8);
+
+        Ok(Self {
+            block_number: u64::from_be_bytes(block_number.try_into().unwrap()),
+            log_index: u64::from_be_bytes(log_index.try_into().unwrap()),
+        })
+    }
+
+    /// pending logs don't have a position
+    fn from_log(log: &RawValue) -> Option<Self> {
+        let x: LogPositionFields = serde_json::from_str(log.get()).ok()?;
+
+        Some(Self {
+            block_number: x.block_number?.as_u64(),
+            log_index: x.log_index?.low_u64(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LogsPage {
+    start: Option<LogPosition>,
+}
+
+impl LogsPage {
+    /// Remove the pagination fields from eth_getLogs params so that the backends never see them.
+    /// Returns None if the request did not ask for pagination.
+    pub fn take_from_params(params: &mut serde_json::Value) -> Web3ProxyResult<Option<Self>> {
+        let filter = match params.get_mut(0).and_then(|x| x.as_object_mut()) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let paginate = filter.remove(PAGINATE_KEY);
+        let page_token = filter.remove(PAGE_TOKEN_KEY);
+
+        let paginate = match paginate {
+            None => page_token.is_some(),
+            Some(serde_json::Value::Bool(x)) => x,
+            Some(_) => {
+                return Err(Web3ProxyError::BadRequest(
+                    "paginate must be a boolean".into(),
+                ))
+            }
+        };
+
+        if !paginate {
+            return Ok(None);
+        }
+
+        let start = match page_token {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(x)) => Some(LogPosition::from_token(&x)?),
+            Some(_) => {
+                return Err(Web3ProxyError::BadRequest(
+                    "pageToken must be a string".into(),
+                ))
+            }
+        };
+
+        if let Some(start) = start {
+            // no need to ask the backends for blocks that were on earlier pages
+            if !filter.contains_key("blockHash") {
+                filter.insert(
+                    "fromBlock".to_string(),
+                    json!(U64::from(start.block_number)),
+                );
+            }
+        }
+
+        Ok(Some(Self { start }))
+    }
+
+    /// Turn a full eth_getLogs result into one page of it.
+    /// Every page has at least one log, even if that log is larger than max_bytes.
+    pub fn paginate(
+        &self,
+        response_data: JsonRpcResponseEnum<Arc<RawValue>>,
+        max_bytes: usize,
+    ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let value = match response_data {
+            JsonRpcResponseEnum::Result { value, .. } => value,
+            x @ JsonRpcResponseEnum::RpcError { .. } => return Ok(x),
+        };
+
+        let logs: Vec<Box<RawValue>> = serde_json::from_str(value.get())?;
+
+        let mut page = Vec::new();
+        let mut page_bytes = 0;
+        let mut next_page = None;
+
+        for log in logs {
+            let position = LogPosition::from_log(&log);
+
+            if let (Some(start), Some(position)) = (self.start, position) {
+                if position < start {
+                    // this log was on an earlier page
+                    continue;
+                }
+            }
+
+            let log_bytes = log.get().len();
+
+            if !page.is_empty() && page_bytes + log_bytes > max_bytes {
+                if let Some(position) = position {
+                    next_page = Some(position);
+                    break;
+                }
+            }
+
+            page_bytes += log_bytes;
+            page.push(log);
+        }
+
+        let x = json!({
+            "logs": page,
+            "nextPageToken": next_page.map(LogPosition::to_token),
+        });
+
+        Ok(x.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(block_number: u64, log_index: u64) -> serde_json::Value {
+        json!({
+            "blockNumber": U64::from(block_number),
+            "logIndex": U64::from(log_index),
+            "data": "0x00",
+        })
+    }
+
+    #[test]
+    fn test_logs_pagination() {
+        let mut params = json!([{"fromBlock": "0x1", "toBlock": "0x3", "paginate": true}]);
+
+        let page = LogsPage::take_from_params(&mut params).unwrap().unwrap();
+
+        assert_eq!(params, json!([{"fromBlock": "0x1", "toBlock": "0x3"}]));
+
+        let logs = json!([log(1, 0), log(1, 1), log(2, 0), log(3, 0)]);
+        let log_bytes = serde_json::to_string(&log(1, 0)).unwrap().len();
+
+        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = logs.clone().into();
+
+        let first = page.paginate(response_data, log_bytes * 2).unwrap();
+
+        let first: serde_json::Value = match first {
+            JsonRpcResponseEnum::Result { value, .. } => serde_json::from_str(value.get()).unwrap(),
+            _ => panic!("expected a result"),
+        };
+
+        assert_eq!(first["logs"], json!([log(1, 0), log(1, 1)]));
+
+        let token = first["nextPageToken"].as_str().unwrap();
+
+        assert_eq!(
+            LogPosition::from_token(token).unwrap(),
+            LogPosition {
+                block_number: 2,
+                log_index: 0
+            }
+        );
+
+        let mut params =
+            json!([{"fromBlock": "0x1", "toBlock": "0x3", "paginate": true, "pageToken": token}]);
+
+        let page = LogsPage::take_from_params(&mut params).unwrap().unwrap();
+
+        assert_eq!(params, json!([{"fromBlock": "0x2", "toBlock": "0x3"}]));
+
+        // even if the backend returns logs from before the token, they are skipped
+        let second = page.paginate(logs.into(), log_bytes * 2).unwrap();
+
+        let second: serde_json::Value = match second {
+            JsonRpcResponseEnum::Result { value, .. } => serde_json::from_str(value.get()).unwrap(),
+            _ => panic!("expected a result"),
+        };
+
+        assert_eq!(second["logs"], json!([log(2, 0), log(3, 0)]));
+        assert!(second["nextPageToken"].is_null());
+    }
+
+    #[test]
+    fn test_logs_pagination_opt_in() {
+        let mut params = json!([{"fromBlock": "0x1"}]);
+
+        assert!(LogsPage::take_from_params(&mut params).unwrap().is_none());
+
+        let mut params = json!([{"fromBlock": "0x1", "pageToken": "0x1234"}]);
+
+        assert!(LogsPage::take_from_params(&mut params).is_err());
+    }
+}
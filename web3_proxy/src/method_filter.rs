@@ -0,0 +1,354 @@
+//! Operator-configured lists of allowed and blocked JSON-RPC methods.
+//!
+//! These are checked before the built-in list of blocked methods.
+//! An entry is either an exact method name or a prefix ending in `*`, like `debug_*`.
+//! Dangerous namespaces like `personal_` are only allowed by entries that name them. A bare `*` does not allow them.
+//! Chain-specific namespaces like `bor_` are only proxied on their chains unless an allowed entry matches them.
+//! Methods in requires_premium use the same kind of entries.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use serde::Deserialize;
+
+/// These methods are rejected unless an allowed_methods entry matches them.
+/// Most of them manage a node's accounts or internals. Others are long gone from clients.
+pub const BLOCKED_METHODS: &[&str] = &[
+    "db_getHex",
+    "db_getString",
+    "db_putHex",
+    "db_putString",
+    "debug_accountRange",
+    "debug_backtraceAt",
+    "debug_blockProfile",
+    "debug_bundler_clearState",
+    "debug_bundler_dumpMempool",
+    "debug_bundler_sendBundleNow",
+    "debug_chaindbCompact",
+    "debug_chaindbProperty",
+    "debug_cpuProfile",
+    "debug_freeOSMemory",
+    "debug_freezeClient",
+    "debug_gcStats",
+    "debug_goTrace",
+    "debug_memStats",
+    "debug_mutexProfile",
+    "debug_setBlockProfileRate",
+    "debug_setGCPercent",
+    "debug_setHead",
+    "debug_setMutexProfileFraction",
+    "debug_standardTraceBadBlockToFile",
+    "debug_standardTraceBlockToFile",
+    "debug_startCPUProfile",
+    "debug_startGoTrace",
+    "debug_stopCPUProfile",
+    "debug_stopGoTrace",
+    "debug_writeBlockProfile",
+    "debug_writeMemProfile",
+    "debug_writeMutexProfile",
+    "erigon_cacheCheck",
+    "eth_compileLLL",
+    "eth_compileSerpent",
+    "eth_compileSolidity",
+    "eth_getCompilers",
+    "eth_sign",
+    "eth_signTransaction",
+    "eth_submitHashrate",
+    "eth_submitWork",
+    "les_addBalance",
+    "les_setClientParams",
+    "les_setDefaultParams",
+    "miner_setEtherbase",
+    "miner_setExtra",
+    "miner_setGasLimit",
+    "miner_setGasPrice",
+    "miner_start",
+    "miner_stop",
+    "personal_ecRecover",
+    "personal_importRawKey",
+    "personal_listAccounts",
+    "personal_lockAccount",
+    "personal_newAccount",
+    "personal_sendTransaction",
+    "personal_sign",
+    "personal_unlockAccount",
+    "shh_addToGroup",
+    "shh_getFilterChanges",
+    "shh_getMessages",
+    "shh_hasIdentity",
+    "shh_newFilter",
+    "shh_newGroup",
+    "shh_newIdentity",
+    "shh_post",
+    "shh_uninstallFilter",
+    "shh_version",
+];
+
+/// Wildcards only match methods in these namespaces if the wildcard names the namespace
+const DANGEROUS_PREFIXES: &[&str] = &["miner_", "personal_"];
+
+/// Namespaces that only exist on some chains. Other chains reject them instead of passing them to backends that don't know them
+const CHAIN_SPECIFIC_PREFIXES: &[(&str, &[u64])] = &[
+    // polygon and its testnets mumbai and amoy
+    ("bor_", &[137, 80001, 80002]),
+    // polygon zkevm and its testnets
+    ("zkevm_", &[1101, 1442, 2442]),
+];
+
+/// The allowed and blocked methods for one user tier
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct MethodFilter {
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    #[serde(default)]
+    pub blocked_methods: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MethodAccess {
+    /// an allowed_methods entry matched. skip the built-in list of blocked methods
+    Allowed,
+    /// a blocked_methods entry matched
+    Blocked,
+    /// nothing matched. the built-in list of blocked methods decides
+    Default,
+}
+
+impl MethodFilter {
+    pub fn check(&self, method: &str) -> MethodAccess {
+        check_method(&self.allowed_methods, &self.blocked_methods, method)
+    }
+}
+
+/// Blocked entries win over allowed entries
+pub fn check_method(allowed: &[String], blocked: &[String], method: &str) -> MethodAccess {
+    if blocked.iter().any(|x| method_matches(x, method)) {
+        MethodAccess::Blocked
+    } else if allowed.iter().any(|x| method_matches(x, method)) {
+        MethodAccess::Allowed
+    } else {
+        MethodAccess::Default
+    }
+}
+
+/// The user tier's filter is checked first. If it doesn't match, the app's lists are checked.
+pub fn check_method_for_tier(
+    tier_filter: Option<&MethodFilter>,
+    allowed: &[String],
+    blocked: &[String],
+    method: &str,
+) -> MethodAccess {
+    if let Some(tier_filter) = tier_filter {
+        let access = tier_filter.check(method);
+
+        if access != MethodAccess::Default {
+            return access;
+        }
+    }
+
+    check_method(allowed, blocked, method)
+}
+
+/// The blocked_methods entry that blocks the method. The tier's entries are checked first, like in check_method_for_tier.
+/// Entries come from the config, so they are safe to use as metric labels. Method names come from users and are not
+pub fn blocked_entry<'a>(
+    tier_filter: Option<&'a MethodFilter>,
+    blocked: &'a [String],
+    method: &str,
+) -> Option<&'a str> {
+    tier_filter
+        .into_iter()
+        .flat_map(|x| x.blocked_methods.iter())
+        .chain(blocked.iter())
+        .find(|x| method_matches(x, method))
+        .map(|x| x.as_str())
+}
+
+/// False if the method is in a namespace that belongs to other chains
+pub fn method_available_on_chain(method: &str, chain_id: u64) -> bool {
+    CHAIN_SPECIFIC_PREFIXES
+        .iter()
+        .filter(|(prefix, _)| method.starts_with(prefix))
+        .all(|(_, chain_ids)| chain_ids.contains(&chain_id))
+}
+
+/// Err(PaymentRequired) if only premium users may call the method and this user is not premium
+pub fn check_premium(
+    requires_premium: &[String],
+    premium: bool,
+    method: &str,
+) -> Web3ProxyResult<()> {
+    if !premium && requires_premium.iter().any(|x| method_matches(x, method)) {
+        return Err(Web3ProxyError::PaymentRequired);
+    }
+
+    Ok(())
+}
+
+fn method_matches(entry: &str, method: &str) -> bool {
+    let Some(prefix) = entry.strip_suffix('*') else {
+        return entry == method;
+    };
+
+    if !method.starts_with(prefix) {
+        return false;
+    }
+
+    // "*" and "p*" don't count as naming the personal_ namespace
+    DANGEROUS_PREFIXES
+        .iter()
+        .filter(|x| method.starts_with(*x))
+        .all(|x| prefix.starts_with(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_allowlisted_debug_method() {
+        let tier_filter = MethodFilter {
+            allowed_methods: strings(&["debug_traceTransaction"]),
+            blocked_methods: vec![],
+        };
+
+        assert_eq!(
+            check_method_for_tier(Some(&tier_filter), &[], &[], "debug_traceTransaction"),
+            MethodAccess::Allowed
+        );
+
+        // personal_sign isn't in the allowlist, so the built-in list still blocks it
+        let access = check_method_for_tier(Some(&tier_filter), &[], &[], "personal_sign");
+        assert_eq!(access, MethodAccess::Default);
+        assert!(BLOCKED_METHODS.contains(&"personal_sign"));
+
+        // without the tier, debug_traceTransaction is only proxied as usual
+        assert_eq!(
+            check_method_for_tier(None, &[], &[], "debug_traceTransaction"),
+            MethodAccess::Default
+        );
+    }
+
+    #[test]
+    fn test_wildcards_skip_dangerous_namespaces() {
+        let allowed = strings(&["*"]);
+
+        assert_eq!(
+            check_method(&allowed, &[], "debug_setHead"),
+            MethodAccess::Allowed
+        );
+        assert_eq!(
+            check_method(&allowed, &[], "personal_sign"),
+            MethodAccess::Default
+        );
+        assert_eq!(
+            check_method(&allowed, &[], "miner_start"),
+            MethodAccess::Default
+        );
+
+        // naming the namespace allows it
+        let allowed = strings(&["personal_*", "miner_start"]);
+
+        assert_eq!(
+            check_method(&allowed, &[], "personal_sign"),
+            MethodAccess::Allowed
+        );
+        assert_eq!(
+            check_method(&allowed, &[], "miner_start"),
+            MethodAccess::Allowed
+        );
+        assert_eq!(
+            check_method(&allowed, &[], "miner_stop"),
+            MethodAccess::Default
+        );
+    }
+
+    #[test]
+    fn test_blocked_entry() {
+        let tier_filter = MethodFilter {
+            allowed_methods: vec![],
+            blocked_methods: strings(&["debug_*"]),
+        };
+        let blocked = strings(&["trace_*", "debug_traceCall"]);
+
+        // the tier's entry wins
+        assert_eq!(
+            blocked_entry(Some(&tier_filter), &blocked, "debug_traceCall"),
+            Some("debug_*")
+        );
+        assert_eq!(
+            blocked_entry(None, &blocked, "debug_traceCall"),
+            Some("debug_traceCall")
+        );
+        // every trace_ method shares one entry
+        assert_eq!(
+            blocked_entry(Some(&tier_filter), &blocked, "trace_anythingAtAll"),
+            Some("trace_*")
+        );
+        assert_eq!(
+            blocked_entry(Some(&tier_filter), &blocked, "eth_call"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_chain_specific_methods() {
+        assert!(method_available_on_chain("bor_getAuthor", 137));
+        assert!(method_available_on_chain("bor_getAuthor", 80002));
+        assert!(!method_available_on_chain("bor_getAuthor", 1));
+        assert!(method_available_on_chain("zkevm_batchNumber", 1101));
+        assert!(method_available_on_chain("zkevm_batchNumber", 2442));
+        assert!(!method_available_on_chain("zkevm_batchNumber", 137));
+        assert!(method_available_on_chain("eth_blockNumber", 1));
+    }
+
+    #[test]
+    fn test_requires_premium() {
+        let requires_premium = strings(&["debug_*", "trace_*"]);
+
+        // free users are blocked
+        assert!(matches!(
+            check_premium(&requires_premium, false, "trace_block"),
+            Err(Web3ProxyError::PaymentRequired)
+        ));
+
+        // premium users are not
+        assert!(check_premium(&requires_premium, true, "trace_block").is_ok());
+
+        // free methods stay open to everyone
+        assert!(check_premium(&requires_premium, false, "eth_call").is_ok());
+        assert!(check_premium(&[], false, "trace_block").is_ok());
+    }
+
+    #[test]
+    fn test_blocked_wins() {
+        let allowed = strings(&["debug_*"]);
+        let blocked = strings(&["debug_traceBlockByNumber"]);
+
+        assert_eq!(
+            check_method(&allowed, &blocked, "debug_traceBlockByNumber"),
+            MethodAccess::Blocked
+        );
+        assert_eq!(
+            check_method(&allowed, &blocked, "debug_traceTransaction"),
+            MethodAccess::Allowed
+        );
+
+        // the tier's allowlist wins over the app's blocklist
+        let tier_filter = MethodFilter {
+            allowed_methods: strings(&["debug_traceBlockByNumber"]),
+            blocked_methods: vec![],
+        };
+
+        assert_eq!(
+            check_method_for_tier(
+                Some(&tier_filter),
+                &allowed,
+                &blocked,
+                "debug_traceBlockByNumber"
+            ),
+            MethodAccess::Allowed
+        );
+    }
+}
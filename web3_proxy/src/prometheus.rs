@@ -2,8 +2,11 @@ use axum::headers::HeaderName;
 use axum::http::HeaderValue;
 use axum::response::{IntoResponse, Response};
 use axum::{routing::get, Extension, Router};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use std::fmt::Write;
 use std::net::SocketAddr;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use tracing::info;
@@ -19,6 +22,7 @@ pub async fn serve(
     // routes should be ordered most to least common
     let router = Router::new()
         .route("/", get(root))
+        .route("/metrics", get(root))
         .layer(Extension(app.clone()));
 
     // note: the port here might be 0
@@ -57,3 +61,281 @@ async fn root(Extension(app): Extension<Arc<Web3ProxyApp>>) -> Response {
 
     r
 }
+
+/// write a single gauge in the prometheus text format
+pub fn write_gauge(w: &mut String, name: &str, help: &str, value: u64) {
+    // writing to a String can't fail
+    let _ = writeln!(w, "# HELP {} {}", name, help);
+    let _ = writeln!(w, "# TYPE {} gauge", name);
+    let _ = writeln!(w, "{} {}", name, value);
+}
+
+/// Gauges labeled by rpc name.
+pub fn write_rpc_gauges(
+    w: &mut String,
+    name: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (String, u64)>,
+) {
+    write_rpc_values(w, name, help, "gauge", values)
+}
+
+/// Counters labeled by rpc name.
+pub fn write_rpc_counters(
+    w: &mut String,
+    name: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (String, u64)>,
+) {
+    write_rpc_values(w, name, help, "counter", values)
+}
+
+fn write_rpc_values(
+    w: &mut String,
+    name: &str,
+    help: &str,
+    metric_type: &str,
+    values: impl IntoIterator<Item = (String, u64)>,
+) {
+    // writing to a String can't fail
+    let _ = writeln!(w, "# HELP {} {}", name, help);
+    let _ = writeln!(w, "# TYPE {} {}", name, metric_type);
+
+    for (rpc, value) in values {
+        let _ = writeln!(
+            w,
+            "{}{{rpc=\"{}\"}} {}",
+            name,
+            escape_label_value(&rpc),
+            value
+        );
+    }
+}
+
+/// Counters labeled by rpc name and error kind.
+pub fn write_rpc_error_counters(
+    w: &mut String,
+    name: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (String, &'static str, u64)>,
+) {
+    // writing to a String can't fail
+    let _ = writeln!(w, "# HELP {} {}", name, help);
+    let _ = writeln!(w, "# TYPE {} counter", name);
+
+    for (rpc, kind, value) in values {
+        let _ = writeln!(
+            w,
+            "{}{{rpc=\"{}\",kind=\"{}\"}} {}",
+            name,
+            escape_label_value(&rpc),
+            kind,
+            value
+        );
+    }
+}
+
+/// Escape a label value for the prometheus text format. Only backslash, double quote, and newline need it
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Counters labeled by method.
+/// serde_prometheus doesn't include HELP or TYPE lines, so these are written by hand.
+/// Every key is kept forever, so callers must only use keys from a bounded set. Never pass a method straight from a user.
+#[derive(Debug, Default)]
+pub struct MethodCounter(RwLock<HashMap<String, AtomicU64>>);
+
+impl MethodCounter {
+    pub fn incr(&self, method: &str) {
+        // most of the time the method has already been seen. only take the write lock for new methods
+        if let Some(x) = self.0.read().get(method) {
+            x.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.0
+            .write()
+            .entry(method.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, method: &str) -> u64 {
+        self.0
+            .read()
+            .get(method)
+            .map(|x| x.load(Ordering::Relaxed))
+            .unwrap_or_default()
+    }
+
+    /// write the counter in the prometheus text format
+    pub fn write_prometheus(&self, w: &mut String, name: &str, help: &str) {
+        self.write_prometheus_with_label(w, name, help, "method")
+    }
+
+    /// write the counter in the prometheus text format with something other than methods as the keys
+    pub fn write_prometheus_with_label(&self, w: &mut String, name: &str, help: &str, label: &str) {
+        // writing to a String can't fail
+        let _ = writeln!(w, "# HELP {} {}", name, help);
+        let _ = writeln!(w, "# TYPE {} counter", name);
+
+        for (key, count) in self.0.read().iter() {
+            let _ = writeln!(
+                w,
+                "{}{{{}=\"{}\"}} {}",
+                name,
+                label,
+                escape_label_value(key),
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+/// Track how useful the jsonrpc response cache is
+#[derive(Debug, Default)]
+pub struct ResponseCacheMetrics {
+    /// the response was already in the cache
+    pub hits: MethodCounter,
+    /// the response had to be fetched from a backend rpc
+    pub misses: MethodCounter,
+    /// the response was already being fetched by another request. we waited for that instead of querying a backend rpc
+    pub inflight_dedup_hits: MethodCounter,
+}
+
+/// Track requests for methods that are rejected without asking a backend rpc
+#[derive(Debug, Default)]
+pub struct RejectedMethodMetrics {
+    /// the method is never proxied. either it is built-in or the operator blocked it
+    pub blocked: MethodCounter,
+    /// the method is not supported yet
+    pub not_implemented: MethodCounter,
+}
+
+/// Track which private relays accepted transactions
+#[derive(Debug, Default)]
+pub struct PrivateRelayMetrics {
+    /// sent to private_rpcs. keyed by method because all of them are sent to at once
+    pub primary: MethodCounter,
+    /// private_rpcs failed, but this secondary private rpc succeeded. keyed by rpc name
+    pub secondary: MethodCounter,
+    /// this secondary private rpc errored and the next one was tried. keyed by rpc name
+    pub secondary_errors: MethodCounter,
+    /// every configured private relay failed. keyed by method
+    pub failed: MethodCounter,
+}
+
+impl PrivateRelayMetrics {
+    pub fn to_prometheus(&self) -> String {
+        let mut w = String::new();
+
+        self.primary.write_prometheus(
+            &mut w,
+            "web3_proxy_private_relay_primary_total",
+            "Transactions accepted by the primary private relays.",
+        );
+        self.secondary.write_prometheus_with_label(
+            &mut w,
+            "web3_proxy_private_relay_secondary_total",
+            "Transactions accepted by each secondary private relay after the primary relays failed.",
+            "rpc",
+        );
+        self.secondary_errors.write_prometheus_with_label(
+            &mut w,
+            "web3_proxy_private_relay_secondary_errors_total",
+            "Transactions that a secondary private relay failed to accept.",
+            "rpc",
+        );
+        self.failed.write_prometheus(
+            &mut w,
+            "web3_proxy_private_relay_failed_total",
+            "Transactions that every configured private relay failed to accept.",
+        );
+
+        w
+    }
+}
+
+impl RejectedMethodMetrics {
+    pub fn to_prometheus(&self) -> String {
+        let mut w = String::new();
+
+        self.blocked.write_prometheus(
+            &mut w,
+            "web3_proxy_blocked_method_requests_total",
+            "Requests rejected because their method is blocked.",
+        );
+        self.not_implemented.write_prometheus(
+            &mut w,
+            "web3_proxy_unimplemented_method_requests_total",
+            "Requests rejected because their method is not implemented yet.",
+        );
+
+        w
+    }
+}
+
+impl ResponseCacheMetrics {
+    pub fn to_prometheus(&self) -> String {
+        let mut w = String::new();
+
+        self.hits.write_prometheus(
+            &mut w,
+            "web3_proxy_cache_hits_total",
+            "Responses served from the response cache.",
+        );
+        self.misses.write_prometheus(
+            &mut w,
+            "web3_proxy_cache_misses_total",
+            "Cacheable responses that had to be fetched from a backend rpc.",
+        );
+        self.inflight_dedup_hits.write_prometheus(
+            &mut w,
+            "web3_proxy_inflight_dedup_hits_total",
+            "Responses that waited for an identical request that was already in flight.",
+        );
+
+        w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("eth_call"), "eth_call");
+        assert_eq!(escape_label_value("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+        // unicode is valid in label values and is left alone
+        assert_eq!(escape_label_value("ñ"), "ñ");
+    }
+
+    #[test]
+    fn test_method_counter_labels() {
+        let counter = MethodCounter::default();
+
+        counter.incr("eth_call");
+        counter.incr("eth_call");
+        counter.incr("other");
+
+        let mut w = String::new();
+
+        counter.write_prometheus(&mut w, "test_total", "test");
+
+        assert!(w.contains("test_total{method=\"eth_call\"} 2\n"));
+        assert!(w.contains("test_total{method=\"other\"} 1\n"));
+    }
+}
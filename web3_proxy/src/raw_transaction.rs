@@ -0,0 +1,152 @@
+//! Check eth_sendRawTransaction params before they are sent to the private relays.
+//!
+//! Every relay rejects a transaction that doesn't decode or that is for another chain.
+//! Rejecting those here gives the user a faster, clearer error and keeps the relays from seeing junk.
+//!
+//! Only legacy, EIP-2930 (0x01) and EIP-1559 (0x02) transactions can be decoded.
+//! Other typed transactions, like EIP-4844 blob transactions (0x03), are sent to the relays without being checked.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use ethers::types::{Bytes, Transaction, U256};
+use ethers::utils::rlp::{Decodable, Rlp};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Every transaction costs at least this much gas
+const MIN_TX_GAS: u64 = 21_000;
+
+/// EIP-2718 typed transactions start with a type byte below this. Legacy transactions start with an rlp list prefix
+const MAX_TX_TYPE: u8 = 0x7f;
+
+/// How strictly to check raw transactions. Some relays accept unusual transactions, so this is configurable.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RawTxValidation {
+    /// send the transaction to the relays without checking it
+    #[default]
+    Off,
+    /// the transaction must decode. if it has a chain id, it must be this chain's
+    Basic,
+    /// like basic, but the transaction must also have a chain id and enough gas for a transaction
+    Strict,
+}
+
+/// Err(BadRequest) if the first param of eth_sendRawTransaction is not a valid transaction for this chain
+pub fn validate_raw_transaction(
+    params: &serde_json::Value,
+    chain_id: u64,
+    mode: RawTxValidation,
+) -> Web3ProxyResult<()> {
+    if mode == RawTxValidation::Off {
+        return Ok(());
+    }
+
+    let raw_tx = params
+        .get(0)
+        .and_then(|x| x.as_str())
+        .ok_or_else(|| Web3ProxyError::BadRequest("raw transaction must be a hex string".into()))?;
+
+    let raw_tx = Bytes::from_str(raw_tx)
+        .map_err(|_| Web3ProxyError::BadRequest("raw transaction is not valid hex".into()))?;
+
+    if raw_tx.is_empty() {
+        return Err(Web3ProxyError::BadRequest(
+            "raw transaction is empty".into(),
+        ));
+    }
+
+    // ethers can't decode newer transaction types. leave those for the relays to check
+    let tx_type = raw_tx[0];
+    if tx_type <= MAX_TX_TYPE && tx_type != 0x01 && tx_type != 0x02 {
+        return Ok(());
+    }
+
+    let tx = Transaction::decode(&Rlp::new(raw_tx.as_ref())).map_err(|err| {
+        Web3ProxyError::BadRequest(format!("raw transaction failed to decode: {}", err).into())
+    })?;
+
+    match tx.chain_id {
+        Some(tx_chain_id) if tx_chain_id != U256::from(chain_id) => {
+            return Err(Web3ProxyError::BadRequest(
+                format!(
+                    "raw transaction is for chain {}, not chain {}",
+                    tx_chain_id, chain_id
+                )
+                .into(),
+            ));
+        }
+        None if mode == RawTxValidation::Strict => {
+            return Err(Web3ProxyError::BadRequest(
+                "raw transaction must have a chain id".into(),
+            ));
+        }
+        _ => {}
+    }
+
+    if mode == RawTxValidation::Strict && tx.gas < U256::from(MIN_TX_GAS) {
+        return Err(Web3ProxyError::BadRequest(
+            format!(
+                "raw transaction gas of {} is less than the minimum of {}",
+                tx.gas, MIN_TX_GAS
+            )
+            .into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// the signed example transaction from EIP-155. it is for chain 1
+    const EIP155_TX: &str = "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+
+    #[test]
+    fn test_wrong_chain_id() {
+        let params = json!([EIP155_TX]);
+
+        for mode in [RawTxValidation::Basic, RawTxValidation::Strict] {
+            assert!(validate_raw_transaction(&params, 1, mode).is_ok());
+
+            let err = validate_raw_transaction(&params, 5, mode).unwrap_err();
+
+            match err {
+                Web3ProxyError::BadRequest(msg) => {
+                    assert_eq!(msg, "raw transaction is for chain 1, not chain 5")
+                }
+                err => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        // nothing is checked when validation is off
+        assert!(validate_raw_transaction(&params, 5, RawTxValidation::Off).is_ok());
+    }
+
+    #[test]
+    fn test_malformed_raw_transaction() {
+        for params in [
+            json!([]),
+            json!(["0xzz"]),
+            json!(["0x"]),
+            json!(["0xdeadbeef"]),
+            json!(["0x02deadbeef"]),
+        ] {
+            assert!(matches!(
+                validate_raw_transaction(&params, 1, RawTxValidation::Basic),
+                Err(Web3ProxyError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_unknown_transaction_types_are_not_checked() {
+        // a blob transaction's type byte. the body isn't decoded, so it can be anything
+        let params = json!(["0x03deadbeef"]);
+
+        for mode in [RawTxValidation::Basic, RawTxValidation::Strict] {
+            assert!(validate_raw_transaction(&params, 1, mode).is_ok());
+        }
+    }
+}
@@ -1,17 +1,25 @@
 use crate::{
     block_number::BlockNumAndHash, errors::Web3ProxyError, jsonrpc::JsonRpcErrorData,
+    rpcs::blockchain::Web3ProxyBlock,
 };
+use arc_swap::ArcSwapOption;
 use derive_more::From;
 use ethers::{
     providers::{HttpClientError, JsonRpcError, ProviderError, WsClientError},
-    types::{U64},
+    types::{H256, U64},
 };
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use hashbrown::hash_map::DefaultHashBuilder;
 use moka::future::Cache;
+use moka::Expiry;
 use serde_json::value::RawValue;
 use std::{
     hash::{BuildHasher, Hash, Hasher},
+    io::{self, Read, Write},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 #[derive(Clone, Debug, Eq, From)]
@@ -27,6 +35,12 @@ impl JsonRpcQueryCacheKey {
     pub fn hash(&self) -> u64 {
         self.hash
     }
+    pub fn from_block(&self) -> Option<&BlockNumAndHash> {
+        self.from_block.as_ref()
+    }
+    pub fn to_block(&self) -> Option<&BlockNumAndHash> {
+        self.to_block.as_ref()
+    }
     pub fn from_block_num(&self) -> Option<&U64> {
         self.from_block.as_ref().map(|x| x.num())
     }
@@ -70,8 +84,9 @@ impl JsonRpcQueryCacheKey {
         method.hash(&mut hasher);
 
         // TODO: make sure preserve_order feature is OFF
-        // TODO: is there a faster way to do this?
-        params.to_string().hash(&mut hasher);
+        // stream the params into the hasher instead of building a String of them first
+        serde_json::to_writer(HashWriter(&mut hasher), params)
+            .expect("params should always serialize");
 
         cache_errors.hash(&mut hasher);
 
@@ -86,7 +101,243 @@ impl JsonRpcQueryCacheKey {
     }
 }
 
-pub type JsonRpcResponseCache = Cache<u64, JsonRpcResponseEnum<Arc<RawValue>>>;
+/// Requests with params larger than this are not cached
+pub const MAX_CACHED_PARAMS_BYTES: usize = 100_000;
+
+/// Feeds serialized bytes straight into a hasher
+struct HashWriter<'a, H: Hasher>(&'a mut H);
+
+impl<H: Hasher> Write for HashWriter<'_, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub type JsonRpcResponseCache = Cache<u64, CachedJsonRpcResponse>;
+
+/// results smaller than this are not worth the cpu time to compress
+pub const MIN_COMPRESSED_BYTES: u32 = 1024;
+
+/// What is actually stored in the JsonRpcResponseCache
+#[derive(Clone, Debug)]
+pub enum CachedJsonRpcResponse {
+    Uncompressed {
+        response: JsonRpcResponseEnum<Arc<RawValue>>,
+        /// None expires with the cache's default policy
+        ttl: Option<Duration>,
+        cached_at: Instant,
+    },
+    /// a deflated `JsonRpcResponseEnum::Result`
+    Compressed {
+        deflated: Arc<[u8]>,
+        /// the size of the result before compression
+        num_bytes: u32,
+        /// None expires with the cache's default policy
+        ttl: Option<Duration>,
+        cached_at: Instant,
+    },
+}
+
+impl CachedJsonRpcResponse {
+    /// errors are never compressed. they are usually small
+    pub fn new(value: JsonRpcResponseEnum<Arc<RawValue>>, compress: bool) -> Self {
+        if !compress {
+            return Self::uncompressed(value);
+        }
+
+        match &value {
+            JsonRpcResponseEnum::Result {
+                value: result,
+                num_bytes,
+            } if *num_bytes >= MIN_COMPRESSED_BYTES => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+
+                // writing to a Vec can't fail
+                let deflated = encoder
+                    .write_all(result.get().as_bytes())
+                    .and_then(|_| encoder.finish());
+
+                match deflated {
+                    Ok(deflated) if deflated.len() < *num_bytes as usize => Self::Compressed {
+                        deflated: deflated.into(),
+                        num_bytes: *num_bytes,
+                        ttl: None,
+                        cached_at: Instant::now(),
+                    },
+                    // compression didn't help
+                    _ => Self::uncompressed(value),
+                }
+            }
+            _ => Self::uncompressed(value),
+        }
+    }
+
+    fn uncompressed(response: JsonRpcResponseEnum<Arc<RawValue>>) -> Self {
+        Self::Uncompressed {
+            response,
+            ttl: None,
+            cached_at: Instant::now(),
+        }
+    }
+
+    /// expire this response after `ttl` instead of with the cache's default policy
+    pub fn with_ttl(mut self, new_ttl: Option<Duration>) -> Self {
+        match &mut self {
+            Self::Uncompressed { ttl, .. } | Self::Compressed { ttl, .. } => *ttl = new_ttl,
+        }
+
+        self
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        match self {
+            Self::Uncompressed { ttl, .. } | Self::Compressed { ttl, .. } => *ttl,
+        }
+    }
+
+    /// how long ago this response was put into the cache
+    pub fn age(&self) -> Duration {
+        match self {
+            Self::Uncompressed { cached_at, .. } | Self::Compressed { cached_at, .. } => {
+                cached_at.elapsed()
+            }
+        }
+    }
+
+    /// the size of the response when it is sent to the user
+    pub fn num_bytes(&self) -> u32 {
+        match self {
+            Self::Uncompressed { response, .. } => response.num_bytes(),
+            Self::Compressed { num_bytes, .. } => *num_bytes,
+        }
+    }
+
+    /// the size of the response while it is in the cache
+    pub fn stored_bytes(&self) -> u32 {
+        match self {
+            Self::Uncompressed { response, .. } => response.num_bytes(),
+            Self::Compressed { deflated, .. } => deflated.len() as u32,
+        }
+    }
+
+    pub fn into_response(self) -> Result<JsonRpcResponseEnum<Arc<RawValue>>, Web3ProxyError> {
+        match self {
+            Self::Uncompressed { response, .. } => Ok(response),
+            Self::Compressed {
+                deflated,
+                num_bytes,
+                ..
+            } => {
+                let mut inflated = String::with_capacity(num_bytes as usize);
+
+                DeflateDecoder::new(deflated.as_ref()).read_to_string(&mut inflated)?;
+
+                let value: Arc<RawValue> = RawValue::from_string(inflated)?.into();
+
+                Ok(JsonRpcResponseEnum::Result { value, num_bytes })
+            }
+        }
+    }
+}
+
+impl From<JsonRpcResponseEnum<Arc<RawValue>>> for CachedJsonRpcResponse {
+    fn from(value: JsonRpcResponseEnum<Arc<RawValue>>) -> Self {
+        Self::uncompressed(value)
+    }
+}
+
+/// If a request's response came out of the JsonRpcResponseCache
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheStatus {
+    Hit {
+        /// how long the response was in the cache
+        age: Duration,
+    },
+    Miss,
+}
+
+impl CacheStatus {
+    /// Combine statuses from multiple cache lookups. It is only a hit if every lookup hit.
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Hit { age: a }, Self::Hit { age: b }) => Self::Hit { age: a.max(b) },
+            _ => Self::Miss,
+        }
+    }
+
+    /// merge a status that might not be set. a request that never used the cache has no status
+    pub fn merge_opt(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.merge(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Most responses are keyed on a block hash and stay in the cache until they go idle.
+/// Responses with a ttl expire after it, even if they are still being used.
+pub struct JsonRpcResponseExpiry {
+    pub time_to_idle: Duration,
+}
+
+impl Expiry<u64, CachedJsonRpcResponse> for JsonRpcResponseExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &u64,
+        value: &CachedJsonRpcResponse,
+        _current_time: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl().unwrap_or(self.time_to_idle))
+    }
+
+    fn expire_after_read(
+        &self,
+        _key: &u64,
+        value: &CachedJsonRpcResponse,
+        _current_time: Instant,
+        current_duration: Option<Duration>,
+        _last_modified_at: Instant,
+    ) -> Option<Duration> {
+        if value.ttl().is_some() {
+            // reads don't extend a ttl
+            current_duration
+        } else {
+            Some(self.time_to_idle)
+        }
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &u64,
+        value: &CachedJsonRpcResponse,
+        _current_time: Instant,
+        _current_duration: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(value.ttl().unwrap_or(self.time_to_idle))
+    }
+}
+
+/// how many bytes something takes up in a cache
+pub trait StoredBytes {
+    fn stored_bytes(&self) -> u32;
+}
+
+impl<R> StoredBytes for JsonRpcResponseEnum<R> {
+    fn stored_bytes(&self) -> u32 {
+        self.num_bytes()
+    }
+}
+
+impl StoredBytes for CachedJsonRpcResponse {
+    fn stored_bytes(&self) -> u32 {
+        CachedJsonRpcResponse::stored_bytes(self)
+    }
+}
 
 /// TODO: we might need one that holds RawValue and one that holds serde_json::Value
 #[derive(Clone, Debug)]
@@ -145,15 +396,19 @@ impl<R> TryFrom<Web3ProxyError> for JsonRpcResponseEnum<R> {
     type Error = Web3ProxyError;
 
     fn try_from(value: Web3ProxyError) -> Result<Self, Self::Error> {
-        if let Web3ProxyError::EthersProvider(ref err) = value {
-            if let Ok(x) = JsonRpcErrorData::try_from(err) {
-                let x = x.into();
+        // the error's data (revert reasons, nonce info, etc.) is kept with the code and message
+        let x = match value {
+            Web3ProxyError::EthersProvider(ref err) => JsonRpcErrorData::try_from(err).ok(),
+            Web3ProxyError::EthersHttpClient(ref err) => JsonRpcErrorData::try_from(err).ok(),
+            Web3ProxyError::EthersWsClient(ref err) => JsonRpcErrorData::try_from(err).ok(),
+            Web3ProxyError::JsonRpcErrorData(ref err) => Some(err.clone()),
+            _ => None,
+        };
 
-                return Ok(x);
-            }
+        match x {
+            Some(x) => Ok(x.into()),
+            None => Err(value),
         }
-
-        Err(value)
     }
 }
 
@@ -254,8 +509,9 @@ impl<'a> TryFrom<&'a WsClientError> for JsonRpcErrorData {
 pub struct JsonRpcResponseWeigher(pub u32);
 
 impl JsonRpcResponseWeigher {
-    pub fn weigh<K, R>(&self, _key: &K, value: &JsonRpcResponseEnum<R>) -> u32 {
-        let x = value.num_bytes();
+    /// weigh the stored representation. if the response is compressed, that is smaller than the response sent to users
+    pub fn weigh<K, V: StoredBytes>(&self, _key: &K, value: &V) -> u32 {
+        let x = value.stored_bytes();
 
         if x > self.0 {
             // return max. the item may start to be inserted into the cache, but it will be immediatly removed
@@ -266,11 +522,112 @@ impl JsonRpcResponseWeigher {
     }
 }
 
+/// eth_blockNumber is one of the most common methods.
+/// Its response is serialized once per head block instead of once per request.
+#[derive(Debug, Default)]
+pub struct BlockNumberResponseCache(ArcSwapOption<(U64, Arc<RawValue>)>);
+
+impl BlockNumberResponseCache {
+    /// Call this with every new head block
+    pub fn update(&self, block_num: U64) {
+        let value = serde_json::value::to_raw_value(&block_num)
+            .expect("block numbers should always serialize");
+
+        self.0.store(Some(Arc::new((block_num, value.into()))));
+    }
+
+    /// None if the cached response is for a different block. The caller should serialize the number itself
+    pub fn get(&self, block_num: &U64) -> Option<JsonRpcResponseEnum<Arc<RawValue>>> {
+        let cached = self.0.load();
+
+        let (cached_num, value) = cached.as_deref()?;
+
+        if cached_num == block_num {
+            Some(value.clone().into())
+        } else {
+            None
+        }
+    }
+}
+
+/// The head block serialized once for every newHeads subscriber instead of once per subscriber.
+#[derive(Default)]
+pub struct NewHeadsResponseCache(ArcSwapOption<(U64, H256, Arc<RawValue>)>);
+
+impl NewHeadsResponseCache {
+    fn serialize(head_block: &Web3ProxyBlock) -> Arc<(U64, H256, Arc<RawValue>)> {
+        let value = serde_json::value::to_raw_value(&head_block.block)
+            .expect("blocks should always serialize");
+
+        Arc::new((*head_block.number(), *head_block.hash(), value.into()))
+    }
+
+    /// Call this with every new head block
+    pub fn update(&self, head_block: &Web3ProxyBlock) {
+        // a subscriber might have already stored it
+        if let Some((_, cached_hash, _)) = self.0.load().as_deref()
+            && cached_hash == head_block.hash()
+        {
+            return;
+        }
+
+        self.0.store(Some(Self::serialize(head_block)));
+    }
+
+    /// The serialized block. Subscribers can see a new head before the cache is updated.
+    /// The first one to miss stores the block so that the others don't serialize it again.
+    pub fn get(&self, head_block: &Web3ProxyBlock) -> Arc<RawValue> {
+        let cached = self.0.load();
+
+        if let Some((cached_num, cached_hash, value)) = cached.as_deref() {
+            if cached_hash == head_block.hash() {
+                return value.clone();
+            }
+
+            // a subscriber that is behind must not replace a newer head
+            if cached_num > head_block.number() {
+                return Self::serialize(head_block).2.clone();
+            }
+        }
+
+        let new = Self::serialize(head_block);
+
+        // only replace what was loaded. if this loses the swap, another subscriber or the updater already stored a block
+        let previous = self.0.compare_and_swap(&cached, Some(new.clone()));
+
+        if let Some((_, cached_hash, value)) = previous.as_deref()
+            && cached_hash == head_block.hash()
+        {
+            return value.clone();
+        }
+
+        new.2.clone()
+    }
+}
+
+/// The eth_subscription message for one subscriber. Only the subscription id is formatted per subscriber.
+pub fn new_heads_notification(subscription_id: &U64, head_block: &RawValue) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"eth_subscription","params":{{"subscription":"{:#x}","result":{}}}}}"#,
+        subscription_id,
+        head_block.get()
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use super::JsonRpcResponseEnum;
+    use super::{
+        new_heads_notification, BlockNumberResponseCache, CachedJsonRpcResponse,
+        JsonRpcResponseEnum, NewHeadsResponseCache,
+    };
+    use crate::errors::Web3ProxyError;
+    use crate::jsonrpc::JsonRpcForwardedResponse;
     use crate::response_cache::JsonRpcResponseWeigher;
+    use crate::rpcs::blockchain::Web3ProxyBlock;
+    use ethers::providers::{HttpClientError, JsonRpcError, ProviderError};
+    use ethers::types::{Block, TxHash, H256, U64};
     use moka::future::{Cache, CacheBuilder, ConcurrentCacheExt};
+    use serde_json::json;
     use serde_json::value::RawValue;
     use std::{sync::Arc, time::Duration};
 
@@ -331,4 +688,167 @@ mod tests {
         // now it should be empty
         assert!(test_cache.get(&2).is_none());
     }
+
+    #[test]
+    fn test_compressed_response_weight() {
+        let weigher = JsonRpcResponseWeigher(100_000);
+
+        // very compressible
+        let result = format!("\"0x{}\"", "0".repeat(10_000));
+
+        let response: JsonRpcResponseEnum<Arc<RawValue>> =
+            RawValue::from_string(result.clone()).unwrap().into();
+
+        let num_bytes = response.num_bytes();
+
+        assert_eq!(num_bytes as usize, result.len());
+
+        // without compression, the full size counts against the cache
+        let uncompressed = CachedJsonRpcResponse::new(response.clone(), false);
+
+        assert_eq!(weigher.weigh(&(), &uncompressed), num_bytes);
+
+        // with compression, only the stored size counts against the cache
+        let compressed = CachedJsonRpcResponse::new(response, true);
+
+        assert!(matches!(
+            compressed,
+            CachedJsonRpcResponse::Compressed { .. }
+        ));
+        assert_eq!(compressed.num_bytes(), num_bytes);
+        assert_eq!(weigher.weigh(&(), &compressed), compressed.stored_bytes());
+        assert!(compressed.stored_bytes() < num_bytes / 10);
+
+        // a ttl doesn't change what is stored
+        let compressed = compressed.with_ttl(Some(Duration::from_secs(3)));
+
+        assert_eq!(compressed.ttl(), Some(Duration::from_secs(3)));
+        assert_eq!(weigher.weigh(&(), &compressed), compressed.stored_bytes());
+
+        // and it should come back out exactly the same
+        match compressed.into_response().unwrap() {
+            JsonRpcResponseEnum::Result {
+                value,
+                num_bytes: x,
+            } => {
+                assert_eq!(value.get(), result);
+                assert_eq!(x, num_bytes);
+            }
+            x => panic!("unexpected response: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_upstream_error_data_passthrough() {
+        let data = r#"{"nonce":"0x5","reason":"0x08c379a00000000000000000000000000000000000000000000000000000000000000020"}"#;
+
+        let upstream = JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(serde_json::from_str(data).unwrap()),
+        };
+
+        let err: Web3ProxyError =
+            ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(upstream)))
+                .into();
+
+        let expected = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"error":{{"code":3,"message":"execution reverted","data":{}}}}}"#,
+            data
+        );
+
+        // errors that are sent to the user
+        let (_, response_data) = err.as_response_parts::<Arc<RawValue>>(false);
+
+        let response = JsonRpcForwardedResponse::from_response_data(
+            response_data,
+            RawValue::from_string("1".to_string()).unwrap(),
+        );
+
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected);
+
+        // errors that are cached
+        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = err.try_into().unwrap();
+
+        let response = JsonRpcForwardedResponse::from_response_data(
+            response_data,
+            RawValue::from_string("1".to_string()).unwrap(),
+        );
+
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_block_number_response_cache() {
+        let cache = BlockNumberResponseCache::default();
+
+        assert!(cache.get(&U64::from(1)).is_none());
+
+        cache.update(U64::from(17_000_000));
+
+        match cache.get(&U64::from(17_000_000)) {
+            Some(JsonRpcResponseEnum::Result { value, num_bytes }) => {
+                assert_eq!(value.get(), "\"0x1036640\"");
+                assert_eq!(num_bytes, 11);
+            }
+            x => panic!("unexpected response: {:?}", x),
+        }
+
+        // a different head block is a miss until the cache is updated
+        assert!(cache.get(&U64::from(17_000_001)).is_none());
+    }
+
+    #[test]
+    fn test_new_heads_notification() {
+        let block = Block::<TxHash> {
+            hash: Some(H256::repeat_byte(1)),
+            number: Some(17_000_000.into()),
+            ..Default::default()
+        };
+
+        let head_block = Web3ProxyBlock::try_new(Arc::new(block)).unwrap();
+
+        let cache = NewHeadsResponseCache::default();
+
+        // a subscriber that sees the block before the updater stores it for everyone else
+        let first = cache.get(&head_block);
+
+        let cached = cache.get(&head_block);
+
+        assert!(Arc::ptr_eq(&first, &cached));
+
+        // the updater doesn't serialize it again
+        cache.update(&head_block);
+
+        // every subscriber shares the same serialized block
+        assert!(Arc::ptr_eq(&cached, &cache.get(&head_block)));
+
+        // a subscriber that is behind doesn't replace a newer block
+        let old_block = Block::<TxHash> {
+            hash: Some(H256::repeat_byte(2)),
+            number: Some(16_999_999.into()),
+            ..Default::default()
+        };
+
+        let old_block = Web3ProxyBlock::try_new(Arc::new(old_block)).unwrap();
+
+        let old = cache.get(&old_block);
+
+        assert!(!Arc::ptr_eq(&old, &cached));
+        assert!(Arc::ptr_eq(&cached, &cache.get(&head_block)));
+
+        let subscription_id = U64::from(26);
+
+        let expected = serde_json::to_string(&json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {
+                "subscription": subscription_id,
+                "result": head_block.block,
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(new_heads_notification(&subscription_id, &cached), expected);
+    }
 }
@@ -6,8 +6,9 @@ use super::transactions::TxStatus;
 use crate::config::{average_block_interval, BlockAndRpc};
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
 use crate::frontend::authorization::Authorization;
+use crate::webhooks::WebhookEvent;
 use derive_more::From;
-use ethers::prelude::{Block, TxHash, H256, U64};
+use ethers::prelude::{Block, BlockNumber, TxHash, H256, U64};
 use moka::future::Cache;
 use serde::ser::SerializeStruct;
 use serde::Serialize;
@@ -17,7 +18,7 @@ use std::time::Duration;
 use std::{fmt::Display, sync::Arc};
 use tokio::sync::broadcast;
 use tokio::time::timeout;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, trace, warn};
 
 // TODO: type for Hydrated Blocks with their full transactions?
 pub type ArcBlock = Arc<Block<TxHash>>;
@@ -333,6 +334,42 @@ impl Web3Rpcs {
         }
     }
 
+    /// Ask the backends for the latest "safe" and "finalized" blocks so that requests for those tags can be cached like any other old block.
+    /// Chains without finality return an error or null. Their tags are left for the backends to handle, and they are asked less and less often.
+    pub async fn update_finalized_blocks(&self, head_block_num: &U64) {
+        if !self.finalized_blocks.should_refresh(head_block_num) {
+            return;
+        }
+
+        let safe = self.tagged_block_number(BlockNumber::Safe).await;
+        let finalized = self.tagged_block_number(BlockNumber::Finalized).await;
+
+        self.finalized_blocks
+            .refreshed(head_block_num, safe.is_some() || finalized.is_some());
+
+        self.finalized_blocks.update(safe, finalized);
+    }
+
+    async fn tagged_block_number(&self, tag: BlockNumber) -> Option<U64> {
+        let block: Option<Block<TxHash>> = match self
+            .internal_request(
+                "eth_getBlockByNumber",
+                &(tag, false),
+                Some(2),
+                Some(Duration::from_secs(5)),
+            )
+            .await
+        {
+            Ok(x) => x,
+            Err(err) => {
+                trace!(?err, ?tag, "unable to fetch tagged block");
+                return None;
+            }
+        };
+
+        block?.number
+    }
+
     /// Convenience method to get the cannonical block at a given block height.
     pub async fn block_hash(
         &self,
@@ -501,6 +538,21 @@ impl Web3Rpcs {
                             error!("timeout while refreshing consensus finder: {:#?}", timeout);
                         }
                     }
+
+                    // no blocks from any rpc in a while. check if the consensus head is stuck
+                    let head_block = self.head_block();
+
+                    let head_block_age = head_block.as_ref().map(|x| x.age());
+
+                    if head_block_age
+                        .map(|x| x > self.max_head_block_age)
+                        .unwrap_or(true)
+                    {
+                        self.send_webhook(WebhookEvent::ConsensusHeadStalled {
+                            head_block_num: head_block.map(|x| *x.number()),
+                            head_block_age_seconds: head_block_age.map(|x| x.as_secs()),
+                        });
+                    }
                 }
             }
         }
@@ -0,0 +1,224 @@
+//! Take rpcs that keep failing out of rotation for a while instead of sending them every request.
+//!
+//! Closed is the normal state. Too many errors within the window opens the breaker.
+//! After the cooldown, the breaker is half-open and lets a single probe request through.
+//! A successful probe closes the breaker. A failed probe opens it again.
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitBreakerState {
+    /// for prometheus gauges
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BreakerInner {
+    Closed { errors: VecDeque<Instant> },
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// open after this many errors
+    max_errors: usize,
+    /// only errors within this long of each other count
+    window: Duration,
+    /// how long to stay open before probing
+    cooldown: Duration,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(max_errors: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            // the first error always counts, so 0 is the same as 1
+            max_errors: (max_errors as usize).max(1),
+            window,
+            cooldown,
+            inner: Mutex::new(BreakerInner::Closed {
+                errors: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitBreakerState {
+        match *self.inner.lock() {
+            BreakerInner::Closed { .. } => CircuitBreakerState::Closed,
+            BreakerInner::Open { until } if Instant::now() < until => CircuitBreakerState::Open,
+            // the cooldown is over. the next request will probe
+            BreakerInner::Open { .. } | BreakerInner::HalfOpen { .. } => {
+                CircuitBreakerState::HalfOpen
+            }
+        }
+    }
+
+    /// Like try_allow, but nothing is reserved. Use this to skip an open breaker before doing any other work
+    pub fn check(&self) -> Result<(), Instant> {
+        let now = Instant::now();
+
+        match *self.inner.lock() {
+            BreakerInner::Open { until } if now < until => Err(until),
+            BreakerInner::HalfOpen { probe_started } if now < probe_started + self.cooldown => {
+                Err(probe_started + self.cooldown)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Ok if a request can be sent. Err with when to try again if the breaker is open.
+    /// When half-open, only one probe request at a time is allowed. Ok takes the probe, so only call this right before sending.
+    pub fn try_allow(&self) -> Result<(), Instant> {
+        let now = Instant::now();
+
+        let mut inner = self.inner.lock();
+
+        match *inner {
+            BreakerInner::Closed { .. } => Ok(()),
+            BreakerInner::Open { until } if now < until => Err(until),
+            BreakerInner::Open { .. } => {
+                *inner = BreakerInner::HalfOpen { probe_started: now };
+                Ok(())
+            }
+            BreakerInner::HalfOpen { probe_started } if now < probe_started + self.cooldown => {
+                // a probe is in flight. if it never reports back, another probe is allowed after the cooldown
+                Err(probe_started + self.cooldown)
+            }
+            BreakerInner::HalfOpen { .. } => {
+                *inner = BreakerInner::HalfOpen { probe_started: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// try_allow took the probe, but the request was never sent. let the next request probe instead
+    pub fn cancel_probe(&self) {
+        let mut inner = self.inner.lock();
+
+        if let BreakerInner::HalfOpen { .. } = *inner {
+            *inner = BreakerInner::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+
+    /// the rpc gave a good response. a half-open breaker closes
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock();
+
+        if let BreakerInner::HalfOpen { .. } = *inner {
+            *inner = BreakerInner::Closed {
+                errors: VecDeque::new(),
+            };
+        }
+    }
+
+    /// the rpc gave a bad response. returns true if this opened the breaker
+    pub fn record_error(&self) -> bool {
+        let now = Instant::now();
+
+        let mut inner = self.inner.lock();
+
+        match &mut *inner {
+            BreakerInner::Closed { errors } => {
+                while let Some(oldest) = errors.front() {
+                    if now.duration_since(*oldest) > self.window {
+                        errors.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+
+                errors.push_back(now);
+
+                if errors.len() >= self.max_errors {
+                    *inner = BreakerInner::Open {
+                        until: now + self.cooldown,
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerInner::HalfOpen { .. } => {
+                // the probe failed
+                *inner = BreakerInner::Open {
+                    until: now + self.cooldown,
+                };
+                true
+            }
+            // requests that started before the breaker opened can still fail
+            BreakerInner::Open { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_secs(30));
+
+        assert_eq!(breaker.state(), CircuitBreakerState::Closed);
+
+        // errors outside the window don't add up
+        assert!(!breaker.record_error());
+        assert!(!breaker.record_error());
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert!(!breaker.record_error());
+        assert!(breaker.try_allow().is_ok());
+
+        // errors inside the window do
+        assert!(!breaker.record_error());
+        assert!(breaker.record_error());
+        assert_eq!(breaker.state(), CircuitBreakerState::Open);
+        assert!(breaker.try_allow().is_err());
+
+        // after the cooldown, one probe is allowed
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.state(), CircuitBreakerState::HalfOpen);
+
+        // checking doesn't take the probe
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+
+        assert!(breaker.try_allow().is_ok());
+        assert!(breaker.check().is_err());
+        assert!(breaker.try_allow().is_err());
+
+        // a probe that was never sent can be taken again right away
+        breaker.cancel_probe();
+        assert_eq!(breaker.state(), CircuitBreakerState::HalfOpen);
+        assert!(breaker.try_allow().is_ok());
+
+        // a failed probe opens it again
+        assert!(breaker.record_error());
+        assert_eq!(breaker.state(), CircuitBreakerState::Open);
+
+        // a successful probe closes it
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(breaker.try_allow().is_ok());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitBreakerState::Closed);
+        assert!(breaker.try_allow().is_ok());
+    }
+}
@@ -1,9 +1,10 @@
 use super::blockchain::Web3ProxyBlock;
 use super::many::Web3Rpcs;
-use super::one::Web3Rpc;
+use super::one::{Web3Rpc, MAX_HEAD_DISAGREEMENTS};
 use super::transactions::TxStatus;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
 use crate::frontend::authorization::Authorization;
+use crate::webhooks::WebhookEvent;
 use base64::engine::general_purpose;
 use derive_more::Constructor;
 use ethers::prelude::{H256, U64};
@@ -126,20 +127,21 @@ impl RankedRpcs {
     //     todo!()
     // }
 
+    /// `head_quorum` is the number of rpcs that need to agree on a block for it to be the head
     pub fn from_votes(
-        min_synced_rpcs: usize,
+        head_quorum: usize,
         min_sum_soft_limit: u32,
         max_lag_block: U64,
         votes: HashMap<Web3ProxyBlock, (HashSet<&Arc<Web3Rpc>>, u32)>,
         heads: HashMap<Arc<Web3Rpc>, Web3ProxyBlock>,
     ) -> Option<Self> {
-        // find the blocks that meets our min_sum_soft_limit and min_synced_rpcs
+        // find the blocks that meets our min_sum_soft_limit and head_quorum
         let mut votes: Vec<_> = votes
             .into_iter()
             .filter_map(|(block, (rpcs, sum_soft_limit))| {
                 if *block.number() < max_lag_block
                     || sum_soft_limit < min_sum_soft_limit
-                    || rpcs.len() < min_synced_rpcs
+                    || rpcs.len() < head_quorum
                 {
                     None
                 } else {
@@ -172,8 +174,30 @@ impl RankedRpcs {
 
                 rpc_data.insert(x.clone(), data);
 
-                if ranked_rpcs.contains(x) {
-                    continue;
+                if ranked_rpcs.contains(x) || x_head.hash() == best_block.hash() {
+                    // this rpc agrees with the quorum
+                    x.head_disagreements.store(0, atomic::Ordering::Relaxed);
+
+                    if ranked_rpcs.contains(x) {
+                        continue;
+                    }
+                } else if x_head.number() == best_block.number() {
+                    // same height but a different hash. this rpc is on a different chain
+                    // rpcs that are behind or ahead of the quorum (or whose ancestors we couldn't load) aren't counted
+                    let disagreements =
+                        x.head_disagreements.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+
+                    if disagreements == MAX_HEAD_DISAGREEMENTS {
+                        warn!(
+                            "{} disagrees with the quorum! rpc={} con={}",
+                            x, x_head, best_block
+                        );
+                    } else {
+                        debug!(
+                            "{} disagrees with the quorum. rpc={} con={}",
+                            x, x_head, best_block
+                        );
+                    }
                 }
 
                 if *x_head.number() < max_lag_block {
@@ -367,6 +391,15 @@ impl Web3Rpcs {
         self.head_block().map(|x| *x.number())
     }
 
+    /// the highest head block of any rpc. this may be ahead of the consensus head
+    pub fn highest_block_num(&self) -> Option<U64> {
+        self.by_name
+            .read()
+            .values()
+            .filter_map(|x| x.head_block_num())
+            .max()
+    }
+
     pub fn synced(&self) -> bool {
         let consensus = self.watch_ranked_rpcs.borrow();
 
@@ -390,6 +423,20 @@ impl Web3Rpcs {
 
 type FirstSeenCache = Cache<H256, Instant>;
 
+/// Take an rpc out of every block's votes. Blocks left without any votes are removed.
+fn remove_votes(
+    votes: &mut HashMap<Web3ProxyBlock, (HashSet<&Arc<Web3Rpc>>, u32)>,
+    rpc: &Arc<Web3Rpc>,
+) {
+    votes.retain(|_, (rpcs, sum_soft_limit)| {
+        if rpcs.remove(rpc) {
+            *sum_soft_limit = sum_soft_limit.saturating_sub(rpc.soft_limit);
+        }
+
+        !rpcs.is_empty()
+    });
+}
+
 /// A ConsensusConnections builder that tracks all connection heads across multiple groups of servers
 pub struct ConsensusFinder {
     rpc_heads: HashMap<Arc<Web3Rpc>, Web3ProxyBlock>,
@@ -684,7 +731,20 @@ impl ConsensusFinder {
                 if let Some(max_age) = self.max_head_block_age {
                     if rpc_head_block.age() > max_age {
                         trace!("rpc_head_block from {} is too old! {}", rpc, rpc_head_block);
-                        return Ok(self.remove(&rpc).is_some());
+
+                        let removed = self.remove(&rpc).is_some();
+
+                        if removed {
+                            web3_connections.send_webhook(WebhookEvent::BackendUnhealthy {
+                                rpc: rpc.name.clone(),
+                                reason: format!(
+                                    "head block is {}s old",
+                                    rpc_head_block.age().as_secs()
+                                ),
+                            });
+                        }
+
+                        return Ok(removed);
                     }
                 }
 
@@ -700,7 +760,16 @@ impl ConsensusFinder {
             None => {
                 // false if this rpc was already removed
                 // true if rpc head changed from being synced to not
-                self.remove(&rpc).is_some()
+                let removed = self.remove(&rpc).is_some();
+
+                if removed {
+                    web3_connections.send_webhook(WebhookEvent::BackendUnhealthy {
+                        rpc: rpc.name.clone(),
+                        reason: "no head block".to_string(),
+                    });
+                }
+
+                removed
             }
         };
 
@@ -891,27 +960,98 @@ impl ConsensusFinder {
             }
         }
 
+        let mut heads = self.rpc_heads.clone();
+
+        if let Some(tolerance) = web3_rpcs.unconfirmed_head_tolerance {
+            // every rpc votes in backup_votes, so use those to find heads that nobody else has seen
+            let unconfirmed = self.unconfirmed_heads(&backup_votes, tolerance);
+
+            // these rpcs don't get to vote or serve requests until another rpc sees their head
+            for rpc in unconfirmed {
+                remove_votes(&mut primary_votes, &rpc);
+                remove_votes(&mut backup_votes, &rpc);
+
+                heads.remove(&rpc);
+            }
+        }
+
+        // a block needs at least min_synced_rpcs votes. head_quorum can require more
+        let head_quorum = web3_rpcs.head_quorum.max(web3_rpcs.min_synced_rpcs);
+
         // we finished processing all tiers. check for primary results (if anything but the last tier found consensus, we already returned above)
         if let Some(consensus) = RankedRpcs::from_votes(
-            web3_rpcs.min_synced_rpcs,
+            head_quorum,
             web3_rpcs.min_sum_soft_limit,
             max_lag_block_number,
             primary_votes,
-            self.rpc_heads.clone(),
+            heads.clone(),
         ) {
             return Ok(Some(consensus));
         }
 
         // primary votes didn't work. hopefully backup tiers are synced
         Ok(RankedRpcs::from_votes(
-            web3_rpcs.min_synced_rpcs,
+            head_quorum,
             web3_rpcs.min_sum_soft_limit,
             max_lag_block_number,
             backup_votes,
-            self.rpc_heads.clone(),
+            heads,
         ))
     }
 
+    /// Find rpcs whose head block is unknown to every other rpc even though other rpcs have reached the same height.
+    /// Heads are given `tolerance` to propagate before they are rejected.
+    fn unconfirmed_heads(
+        &self,
+        votes: &HashMap<Web3ProxyBlock, (HashSet<&Arc<Web3Rpc>>, u32)>,
+        tolerance: Duration,
+    ) -> HashSet<Arc<Web3Rpc>> {
+        let mut unconfirmed = HashSet::new();
+
+        if self.rpc_heads.len() < 2 {
+            // with only one rpc, there is nothing to compare against
+            return unconfirmed;
+        }
+
+        for (rpc, rpc_head) in self.rpc_heads.iter() {
+            let Some((voters, _)) = votes.get(rpc_head) else {
+                // this head is too old or too far behind to be part of the voting
+                continue;
+            };
+
+            if voters.len() > 1 {
+                // another rpc has this block in its chain
+                continue;
+            }
+
+            if !self
+                .rpc_heads
+                .iter()
+                .any(|(other, other_head)| other != rpc && other_head.number() >= rpc_head.number())
+            {
+                // no other rpc has reached this height yet. this rpc might just be fast
+                continue;
+            }
+
+            if let Some(first_seen) = self.first_seen.get(rpc_head.hash()) {
+                if first_seen.elapsed() < tolerance {
+                    // give the block time to reach the other rpcs
+                    continue;
+                }
+            }
+
+            // head_disagreements is left alone. from_votes only counts conflicting hashes at the consensus height
+            debug!(
+                "{} has a head that no other rpc has seen. rpc={}",
+                rpc, rpc_head
+            );
+
+            unconfirmed.insert(rpc.clone());
+        }
+
+        unconfirmed
+    }
+
     pub fn best_tier(&self) -> Option<u32> {
         self.rpc_heads
             .iter()
@@ -929,8 +1069,204 @@ impl ConsensusFinder {
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use ethers::types::Block;
+
     // #[test]
     // fn test_simplest_case_consensus_head_connections() {
     //     todo!();
     // }
+
+    fn block(number: u64) -> Web3ProxyBlock {
+        Arc::new(Block {
+            hash: Some(H256::random()),
+            number: Some(number.into()),
+            timestamp: chrono::Utc::now().timestamp().into(),
+            ..Default::default()
+        })
+        .try_into()
+        .unwrap()
+    }
+
+    fn rpc(name: &str) -> Arc<Web3Rpc> {
+        Arc::new(Web3Rpc {
+            name: name.to_string(),
+            soft_limit: 1_000,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_lagging_rpcs_serve_older_blocks() {
+        let head = block(100);
+        let behind = block(98);
+        let far_behind = block(94);
+
+        let head_rpc = rpc("head");
+        let behind_rpc = rpc("behind");
+        let far_behind_rpc = rpc("far_behind");
+
+        let mut votes = HashMap::new();
+        votes.insert(head.clone(), (HashSet::from_iter([&head_rpc]), 1_000));
+
+        let mut heads = HashMap::new();
+        heads.insert(head_rpc.clone(), head.clone());
+        heads.insert(behind_rpc.clone(), behind);
+        heads.insert(far_behind_rpc.clone(), far_behind);
+
+        // max_head_block_lag of 5
+        let max_lag_block = *head.number() - U64::from(5);
+
+        let ranked = RankedRpcs::from_votes(1, 1_000, max_lag_block, votes, heads).unwrap();
+
+        assert_eq!(ranked.head_block, head);
+        assert!(ranked.all().contains(&head_rpc));
+        assert!(ranked.all().contains(&behind_rpc));
+        assert!(!ranked.all().contains(&far_behind_rpc));
+
+        // only the rpc on the consensus head can serve the head block
+        let needed = *head.number();
+        assert!(ranked.rpc_will_work_now(&[], Some(&needed), None, &head_rpc));
+        assert!(!ranked.rpc_will_work_now(&[], Some(&needed), None, &behind_rpc));
+
+        // the lagging rpc can serve the blocks that it has
+        let needed = U64::from(98);
+        assert!(ranked.rpc_will_work_now(&[], Some(&needed), None, &behind_rpc));
+    }
+
+    #[test]
+    fn test_head_disagreements() {
+        let head = block(100);
+        let fork = block(100);
+        let behind = block(99);
+
+        let head_rpc = rpc("head");
+        let fork_rpc = rpc("fork");
+        let behind_rpc = rpc("behind");
+        let backup_rpc = Arc::new(Web3Rpc {
+            name: "backup".to_string(),
+            soft_limit: 1_000,
+            backup: true,
+            ..Default::default()
+        });
+
+        let from_votes = |fork_rpc_head: &Web3ProxyBlock| {
+            // backups don't vote when the primary rpcs are synced
+            let mut votes = HashMap::new();
+            if fork_rpc_head == &head {
+                votes.insert(
+                    head.clone(),
+                    (HashSet::from_iter([&head_rpc, &fork_rpc]), 2_000),
+                );
+            } else {
+                votes.insert(head.clone(), (HashSet::from_iter([&head_rpc]), 1_000));
+                // the fork has less soft limit, so the head wins
+                votes.insert(
+                    fork_rpc_head.clone(),
+                    (HashSet::from_iter([&fork_rpc]), 500),
+                );
+            }
+
+            let mut heads = HashMap::new();
+            heads.insert(head_rpc.clone(), head.clone());
+            heads.insert(fork_rpc.clone(), fork_rpc_head.clone());
+            heads.insert(behind_rpc.clone(), behind.clone());
+            heads.insert(backup_rpc.clone(), head.clone());
+
+            RankedRpcs::from_votes(1, 500, 90.into(), votes, heads).unwrap()
+        };
+
+        for i in 1..=MAX_HEAD_DISAGREEMENTS {
+            let ranked = from_votes(&fork);
+
+            assert_eq!(ranked.head_block, head);
+
+            // a different hash at the consensus height is a disagreement
+            assert_eq!(
+                fork_rpc.head_disagreements.load(atomic::Ordering::Relaxed),
+                i
+            );
+        }
+
+        assert!(fork_rpc.disagrees_with_quorum());
+
+        // rpcs that are behind or are backups on the consensus head are not disagreeing
+        assert_eq!(
+            behind_rpc
+                .head_disagreements
+                .load(atomic::Ordering::Relaxed),
+            0
+        );
+        assert_eq!(
+            backup_rpc
+                .head_disagreements
+                .load(atomic::Ordering::Relaxed),
+            0
+        );
+
+        // agreeing with the quorum again resets the counter
+        from_votes(&head);
+
+        assert_eq!(
+            fork_rpc.head_disagreements.load(atomic::Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_heads() {
+        let head = block(100);
+        let fork = block(100);
+        let behind = block(90);
+
+        let head_rpc_1 = rpc("head_1");
+        let head_rpc_2 = rpc("head_2");
+        let fork_rpc = rpc("fork");
+        let behind_rpc = rpc("behind");
+
+        let mut consensus_finder = ConsensusFinder::new(None, None);
+
+        consensus_finder
+            .insert(head_rpc_1.clone(), head.clone())
+            .await;
+        consensus_finder
+            .insert(head_rpc_2.clone(), head.clone())
+            .await;
+        consensus_finder
+            .insert(fork_rpc.clone(), fork.clone())
+            .await;
+        consensus_finder.insert(behind_rpc.clone(), behind).await;
+
+        // the behind rpc is too far back to be part of the voting
+        let mut votes = HashMap::new();
+        votes.insert(
+            head.clone(),
+            (HashSet::from_iter([&head_rpc_1, &head_rpc_2]), 2_000),
+        );
+        votes.insert(fork.clone(), (HashSet::from_iter([&fork_rpc]), 1_000));
+
+        // the fork was just seen. give it time to propagate
+        let unconfirmed = consensus_finder.unconfirmed_heads(&votes, Duration::from_secs(60));
+        assert!(unconfirmed.is_empty());
+
+        let unconfirmed = consensus_finder.unconfirmed_heads(&votes, Duration::ZERO);
+        assert_eq!(unconfirmed, HashSet::from_iter([fork_rpc.clone()]));
+
+        // leaving the rpc out of consensus doesn't count as a disagreement
+        assert_eq!(
+            fork_rpc.head_disagreements.load(atomic::Ordering::Relaxed),
+            0
+        );
+
+        // a fast rpc that is ahead of everyone else is not rejected
+        let ahead = block(101);
+        let ahead_rpc = rpc("ahead");
+        consensus_finder
+            .insert(ahead_rpc.clone(), ahead.clone())
+            .await;
+        votes.insert(ahead, (HashSet::from_iter([&ahead_rpc]), 1_000));
+
+        let unconfirmed = consensus_finder.unconfirmed_heads(&votes, Duration::ZERO);
+        assert!(!unconfirmed.contains(&ahead_rpc));
+    }
 }
@@ -0,0 +1,123 @@
+//! Per-rpc request latency percentiles for InfluxDB.
+//!
+//! peak_latency and median_latency are for load balancing and only know about recent requests.
+//! This histogram collects every request between flushes so that dashboards can graph each backend's latency.
+use hdrhistogram::Histogram;
+use influxdb2::models::DataPoint;
+use parking_lot::Mutex;
+use std::time::Duration;
+
+/// requests slower than this are recorded as this
+const MAX_LATENCY_MS: u64 = 5 * 60 * 1_000;
+
+#[derive(Debug)]
+pub struct LatencyHistogram(Mutex<Histogram<u64>>);
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        let histogram = Histogram::new_with_bounds(1, MAX_LATENCY_MS, 3)
+            .expect("latency histogram bounds should always be valid");
+
+        Self(Mutex::new(histogram))
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let ms = (latency.as_millis() as u64).clamp(1, MAX_LATENCY_MS);
+
+        self.0.lock().saturating_record(ms);
+    }
+
+    /// The percentiles since the last call. None if there were no requests
+    pub fn take(&self) -> Option<LatencyPercentiles> {
+        let mut histogram = self.0.lock();
+
+        if histogram.is_empty() {
+            return None;
+        }
+
+        let x = LatencyPercentiles {
+            count: histogram.len(),
+            p50_ms: histogram.value_at_quantile(0.50),
+            p90_ms: histogram.value_at_quantile(0.90),
+            p99_ms: histogram.value_at_quantile(0.99),
+        };
+
+        histogram.reset();
+
+        Some(x)
+    }
+}
+
+impl LatencyPercentiles {
+    /// `url` should only be the host. full urls can have api keys in them
+    pub fn build_timeseries_point(
+        &self,
+        chain_id: u64,
+        rpc: &str,
+        url: Option<&str>,
+        timestamp: i64,
+    ) -> anyhow::Result<DataPoint> {
+        let mut builder = DataPoint::builder("rpc_latency")
+            .tag("chain_id", chain_id.to_string())
+            .tag("rpc", rpc);
+
+        if let Some(url) = url {
+            builder = builder.tag("url", url);
+        }
+
+        let point = builder
+            .field("count", self.count as i64)
+            .field("p50_ms", self.p50_ms as i64)
+            .field("p90_ms", self.p90_ms as i64)
+            .field("p99_ms", self.p99_ms as i64)
+            .timestamp(timestamp)
+            .build()?;
+
+        Ok(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let histogram = LatencyHistogram::default();
+
+        assert_eq!(histogram.take(), None);
+
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        let x = histogram.take().unwrap();
+
+        assert_eq!(x.count, 100);
+        assert_eq!(x.p50_ms, 50);
+        assert_eq!(x.p90_ms, 90);
+        assert_eq!(x.p99_ms, 99);
+
+        // taking resets the histogram
+        assert_eq!(histogram.take(), None);
+
+        // slow requests are clamped instead of dropped. the histogram is accurate to 3 significant figures
+        histogram.record(Duration::from_secs(3_600));
+
+        let x = histogram.take().unwrap();
+
+        assert_eq!(x.count, 1);
+        assert!(x.p99_ms >= MAX_LATENCY_MS);
+        assert!(x.p99_ms < MAX_LATENCY_MS + MAX_LATENCY_MS / 1_000);
+    }
+}
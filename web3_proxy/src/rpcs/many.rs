@@ -1,26 +1,30 @@
 //! Load balanced communication with a group of web3 rpc providers
 use super::blockchain::{BlocksByHashCache, BlocksByNumberCache, Web3ProxyBlock};
 use super::consensus::{RankedRpcs, ShouldWaitForBlock};
-use super::one::Web3Rpc;
+use super::one::{Web3Rpc, CONCURRENCY_MAX_WAIT};
 use super::request::{OpenRequestHandle, OpenRequestResult, RequestErrorHandler};
+use super::retry::RetryPolicy;
 use crate::app::{flatten_handle, Web3ProxyApp, Web3ProxyJoinHandle};
+use crate::block_number::FinalizedBlocks;
 use crate::config::{average_block_interval, BlockAndRpc, TxHashAndRpc, Web3RpcConfig};
 use crate::errors::{Web3ProxyError, Web3ProxyResult};
 use crate::frontend::authorization::{Authorization, RequestMetadata};
 use crate::frontend::rpc_proxy_ws::ProxyMode;
 use crate::frontend::status::MokaCacheSerializer;
 use crate::jsonrpc::{JsonRpcErrorData, JsonRpcParams, JsonRpcResultData};
-use crate::rpcs::transactions::TxStatus;
+use crate::rpcs::transactions::{PendingTransactionCache, TxStatus};
+use crate::webhooks::{WebhookEvent, WebhookSender};
 use counter::Counter;
 use derive_more::From;
-use ethers::prelude::{ProviderError, TxHash, U64};
-use futures::future::try_join_all;
+use ethers::prelude::{ProviderError, U64};
+use futures::future::{join_all, try_join_all};
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use hashbrown::HashMap;
+use influxdb2::models::DataPoint;
 use itertools::Itertools;
 use migration::sea_orm::DatabaseConnection;
-use moka::future::{Cache, CacheBuilder};
+use moka::future::CacheBuilder;
 use parking_lot::RwLock;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
@@ -46,6 +50,8 @@ pub struct Web3Rpcs {
     /// any requests will be forwarded to one (or more) of these connections
     /// TODO: hopefully this not being an async lock will be okay. if you need it across awaits, clone the arc
     pub(crate) by_name: RwLock<HashMap<String, Arc<Web3Rpc>>>,
+    /// rpcs that were removed or replaced. they are waiting for their in-flight requests to finish before disconnecting
+    pub(crate) draining: Arc<RwLock<HashMap<String, Arc<Web3Rpc>>>>,
     /// all providers with the same consensus head block. won't update if there is no `self.watch_consensus_head_sender`
     /// TODO: document that this is a watch sender and not a broadcast! if things get busy, blocks might get missed
     /// TODO: why is watch_consensus_head_sender in an Option, but this one isn't?
@@ -54,7 +60,7 @@ pub struct Web3Rpcs {
     /// this head receiver makes it easy to wait until there is a new block
     pub(super) watch_head_block: Option<watch::Sender<Option<Web3ProxyBlock>>>,
     /// keep track of transactions that we have sent through subscriptions
-    pub(super) pending_transaction_cache: Cache<TxHash, TxStatus>,
+    pub(super) pending_transaction_cache: PendingTransactionCache,
     pub(super) pending_tx_id_receiver: flume::Receiver<TxHashAndRpc>,
     pub(super) pending_tx_id_sender: flume::Sender<TxHashAndRpc>,
     /// TODO: this map is going to grow forever unless we do some sort of pruning. maybe store pruned in redis?
@@ -64,6 +70,10 @@ pub struct Web3Rpcs {
     pub(super) blocks_by_number: BlocksByNumberCache,
     /// the number of rpcs required to agree on consensus for the head block (thundering herd protection)
     pub(super) min_synced_rpcs: usize,
+    /// the number of rpcs that must agree on a block before the consensus head advances to it (bad rpc protection)
+    pub(super) head_quorum: usize,
+    /// how long a head block that no other rpc has seen is tolerated before the rpc is left out of consensus (bad rpc protection)
+    pub(super) unconfirmed_head_tolerance: Option<Duration>,
     /// the soft limit required to agree on consensus for the head block. (thundering herd protection)
     pub(super) min_sum_soft_limit: u32,
     /// how far behind the highest known block height we can be before we stop serving requests
@@ -71,6 +81,12 @@ pub struct Web3Rpcs {
     /// how old our consensus head block we can be before we stop serving requests
     /// calculated based on max_head_block_lag and averge block times
     pub(super) max_head_block_age: Duration,
+    /// retry idempotent requests on other rpcs after transport errors
+    pub(super) retry_policy: RetryPolicy,
+    /// alert operators when rpcs become unhealthy or the head block stalls
+    pub(super) webhooks: Option<Arc<WebhookSender>>,
+    /// the latest "safe" and "finalized" blocks. used to turn those tags into concrete block numbers
+    pub(crate) finalized_blocks: FinalizedBlocks,
 }
 
 impl Web3Rpcs {
@@ -79,13 +95,17 @@ impl Web3Rpcs {
     pub async fn spawn(
         chain_id: u64,
         db_conn: Option<DatabaseConnection>,
+        head_quorum: Option<usize>,
         max_head_block_lag: Option<U64>,
         min_head_rpcs: usize,
         min_sum_soft_limit: u32,
         name: String,
-        pending_transaction_cache: Cache<TxHash, TxStatus>,
+        pending_transaction_cache: PendingTransactionCache,
         pending_tx_sender: Option<broadcast::Sender<TxStatus>>,
+        retry_policy: RetryPolicy,
+        unconfirmed_head_tolerance: Option<Duration>,
         watch_consensus_head_sender: Option<watch::Sender<Option<Web3ProxyBlock>>>,
+        webhooks: Option<Arc<WebhookSender>>,
     ) -> anyhow::Result<(
         Arc<Self>,
         Web3ProxyJoinHandle<()>,
@@ -127,6 +147,9 @@ impl Web3Rpcs {
             blocks_by_number,
             by_name,
             chain_id,
+            draining: Default::default(),
+            finalized_blocks: Default::default(),
+            head_quorum: head_quorum.unwrap_or(min_head_rpcs),
             max_head_block_age,
             max_head_block_lag,
             min_synced_rpcs: min_head_rpcs,
@@ -135,8 +158,11 @@ impl Web3Rpcs {
             pending_transaction_cache,
             pending_tx_id_receiver,
             pending_tx_id_sender,
+            retry_policy,
+            unconfirmed_head_tolerance,
             watch_head_block: watch_consensus_head_sender,
             watch_ranked_rpcs: watch_consensus_rpcs_sender,
+            webhooks,
         });
 
         let authorization = Arc::new(Authorization::internal(db_conn)?);
@@ -150,6 +176,35 @@ impl Web3Rpcs {
         Ok((connections, handle, consensus_connections_watcher))
     }
 
+    /// Err if applying these configs would leave this group without enough rpcs. Nothing is changed
+    pub fn check_server_configs(
+        &self,
+        rpc_configs: &HashMap<String, Web3RpcConfig>,
+    ) -> Web3ProxyResult<()> {
+        let enabled: Vec<_> = rpc_configs.values().filter(|x| !x.disabled).collect();
+
+        if enabled.len() < self.min_synced_rpcs {
+            return Err(Web3ProxyError::NotEnoughRpcs {
+                num_known: enabled.len(),
+                min_head_rpcs: self.min_synced_rpcs,
+            });
+        }
+
+        // safety check on sum soft limit
+        // TODO: will need to think about this more once sum_soft_limit is dynamic
+        let sum_soft_limit = enabled.iter().fold(0, |acc, x| acc + x.soft_limit);
+
+        // TODO: require a buffer?
+        if sum_soft_limit < self.min_sum_soft_limit {
+            return Err(Web3ProxyError::NotEnoughSoftLimit {
+                available: sum_soft_limit,
+                needed: self.min_sum_soft_limit,
+            });
+        }
+
+        Ok(())
+    }
+
     /// update the rpcs in this group
     pub async fn apply_server_configs(
         &self,
@@ -168,22 +223,21 @@ impl Web3Rpcs {
             return Ok(());
         }
 
-        // safety check on sum soft limit
-        // TODO: will need to think about this more once sum_soft_limit is dynamic
-        let sum_soft_limit = rpc_configs.values().fold(0, |acc, x| acc + x.soft_limit);
-
-        // TODO: require a buffer?
-        if sum_soft_limit < self.min_sum_soft_limit {
-            return Err(Web3ProxyError::NotEnoughSoftLimit {
-                available: sum_soft_limit,
-                needed: self.min_sum_soft_limit,
-            });
-        }
+        self.check_server_configs(&rpc_configs)?;
 
         let chain_id = app.config.chain_id;
 
         let block_interval = average_block_interval(chain_id);
 
+        let drain_timeout = Duration::from_secs(app.config.rpc_drain_timeout);
+
+        // any rpcs that are not in the new configs will be drained
+        let enabled_names: Vec<String> = rpc_configs
+            .iter()
+            .filter(|(_, x)| !x.disabled)
+            .map(|(name, _)| name.clone())
+            .collect();
+
         // turn configs into connections (in parallel)
         let mut spawn_handles: FuturesUnordered<_> = rpc_configs
             .into_iter()
@@ -205,6 +259,7 @@ impl Web3Rpcs {
 
                 let pending_tx_id_sender = Some(self.pending_tx_id_sender.clone());
                 let blocks_by_hash_cache = self.blocks_by_hash.clone();
+                let webhooks = self.webhooks.clone();
 
                 debug!("spawning tasks for {}", server_name);
 
@@ -218,6 +273,7 @@ impl Web3Rpcs {
                     blocks_by_hash_cache,
                     block_sender,
                     pending_tx_id_sender,
+                    webhooks,
                 ));
 
                 Some(handle)
@@ -253,11 +309,8 @@ impl Web3Rpcs {
                         // make sure that any new requests use the new connection
                         self.by_name.write().insert(rpc.name.clone(), rpc);
 
-                        // tell the old rpc to disconnect
-                        if let Some(ref disconnect_sender) = old_rpc.disconnect_watch {
-                            trace!("telling {} to disconnect", old_rpc);
-                            disconnect_sender.send_replace(true);
-                        }
+                        // let the old rpc finish its requests before it disconnects
+                        self.drain_rpc(old_rpc, drain_timeout);
                     } else {
                         self.by_name.write().insert(rpc.name.clone(), rpc);
                     }
@@ -275,6 +328,27 @@ impl Web3Rpcs {
             }
         }
 
+        // rpcs that were removed from the config (or disabled) stop getting new requests
+        let removed_rpcs: Vec<_> = {
+            let mut by_name = self.by_name.write();
+
+            let removed_names: Vec<_> = by_name
+                .keys()
+                .filter(|x| !enabled_names.contains(x))
+                .cloned()
+                .collect();
+
+            removed_names
+                .into_iter()
+                .filter_map(|x| by_name.remove(&x))
+                .collect()
+        };
+
+        for old_rpc in removed_rpcs {
+            info!("removing {}", old_rpc);
+            self.drain_rpc(old_rpc, drain_timeout);
+        }
+
         let num_rpcs = self.len();
 
         if num_rpcs < self.min_synced_rpcs {
@@ -287,6 +361,41 @@ impl Web3Rpcs {
         Ok(())
     }
 
+    /// Stop sending requests to an rpc that is no longer in `by_name`.
+    /// In-flight requests are allowed to finish (up to drain_timeout) before it disconnects.
+    fn drain_rpc(&self, rpc: Arc<Web3Rpc>, drain_timeout: Duration) {
+        let draining = self.draining.clone();
+
+        draining.write().insert(rpc.name.clone(), rpc.clone());
+
+        tokio::spawn(async move {
+            rpc.drain(drain_timeout).await;
+
+            let mut draining = draining.write();
+
+            // a newer rpc with the same name might have started draining while we waited
+            if draining.get(&rpc.name).map(|x| Arc::ptr_eq(x, &rpc)) == Some(true) {
+                draining.remove(&rpc.name);
+            }
+        });
+    }
+
+    /// names and active request counts of the rpcs that are still draining
+    pub fn draining(&self) -> Vec<(String, usize)> {
+        self.draining
+            .read()
+            .values()
+            .map(|x| (x.name.clone(), x.active_requests()))
+            .sorted()
+            .collect()
+    }
+
+    pub(super) fn send_webhook(&self, event: WebhookEvent) {
+        if let Some(ref webhooks) = self.webhooks {
+            webhooks.send(event);
+        }
+    }
+
     pub fn get(&self, conn_name: &str) -> Option<Arc<Web3Rpc>> {
         self.by_name.read().get(conn_name).map(Arc::clone)
     }
@@ -299,6 +408,15 @@ impl Web3Rpcs {
         self.by_name.read().is_empty()
     }
 
+    /// Every rpc's request latency percentiles since the last call
+    pub fn latency_points(&self, chain_id: u64, timestamp: i64) -> Vec<DataPoint> {
+        self.by_name
+            .read()
+            .values()
+            .filter_map(|x| x.latency_point(chain_id, timestamp))
+            .collect()
+    }
+
     pub fn min_head_rpcs(&self) -> usize {
         self.min_synced_rpcs
     }
@@ -457,6 +575,9 @@ impl Web3Rpcs {
     ) -> OpenRequestResult {
         let mut earliest_retry_at = None;
 
+        // rpcs that were only turned away because they are at max_concurrent_requests
+        let mut full_rpcs = vec![];
+
         for (rpc_a, rpc_b) in potential_rpcs.iter().circular_tuple_windows() {
             trace!("{} vs {}", rpc_a, rpc_b);
             // TODO: cached key to save a read lock
@@ -484,6 +605,13 @@ impl Web3Rpcs {
                         retry_at.duration_since(Instant::now()).as_secs_f32()
                     );
 
+                    if faster_rpc.is_full(authorization.priority()) {
+                        if !full_rpcs.contains(&faster_rpc) {
+                            full_rpcs.push(faster_rpc);
+                        }
+                        continue;
+                    }
+
                     if earliest_retry_at.is_none() {
                         earliest_retry_at = Some(retry_at);
                     } else {
@@ -500,6 +628,48 @@ impl Web3Rpcs {
             }
         }
 
+        if !full_rpcs.is_empty() {
+            // every rpc was tried without waiting. now wait on all the full ones at once and take the first permit
+            // don't wait past when a rate limited rpc would be ready again
+            let max_wait = earliest_retry_at
+                .map(|x| x.saturating_duration_since(Instant::now()))
+                .unwrap_or(CONCURRENCY_MAX_WAIT)
+                .min(CONCURRENCY_MAX_WAIT);
+
+            let mut waiting: FuturesUnordered<_> = full_rpcs
+                .into_iter()
+                .map(|rpc| async move {
+                    (
+                        rpc,
+                        rpc.wait_for_concurrency_permit(authorization, error_handler, max_wait)
+                            .await,
+                    )
+                })
+                .collect();
+
+            while let Some((rpc, x)) = waiting.next().await {
+                match x {
+                    Ok(OpenRequestResult::Handle(handle)) => {
+                        trace!("opened handle after waiting: {}", rpc);
+                        return OpenRequestResult::Handle(handle);
+                    }
+                    Ok(OpenRequestResult::RetryAt(retry_at)) => {
+                        if earliest_retry_at.is_none() {
+                            earliest_retry_at = Some(retry_at);
+                        } else {
+                            earliest_retry_at = earliest_retry_at.min(Some(retry_at));
+                        }
+                    }
+                    Ok(OpenRequestResult::NotReady) => {
+                        trace!("best_rpc not ready: {}", rpc);
+                    }
+                    Err(err) => {
+                        trace!("No request handle for {}. err={:?}", rpc, err)
+                    }
+                }
+            }
+        }
+
         if let Some(retry_at) = earliest_retry_at {
             OpenRequestResult::RetryAt(retry_at)
         } else {
@@ -507,6 +677,18 @@ impl Web3Rpcs {
         }
     }
 
+    /// Pick one of the ranked rpcs whose head is the given block
+    pub fn rpc_on_block(&self, block: &Web3ProxyBlock) -> Option<Arc<Web3Rpc>> {
+        let ranked_rpcs = self.watch_ranked_rpcs.borrow().clone()?;
+
+        ranked_rpcs
+            .all()
+            .iter()
+            .filter(|x| x.is_on_block(block))
+            .min_by_key(|x| x.shuffle_for_load_balancing_on(None))
+            .cloned()
+    }
+
     pub async fn wait_for_best_rpc(
         &self,
         request_metadata: Option<&Arc<RequestMetadata>>,
@@ -525,6 +707,22 @@ impl Web3Rpcs {
             .and_then(|x| x.authorization.clone())
             .unwrap_or_default();
 
+        // pinned batches use their rpc if it can serve the request. if it can't, any rpc is better than an error
+        if let Some(pinned_rpc) = request_metadata.and_then(|x| x.pinned_rpc.get())
+            && !skip_rpcs.contains(pinned_rpc)
+            && min_block_needed.map(|x| pinned_rpc.has_block_data(x)).unwrap_or(true)
+            && max_block_needed.map(|x| pinned_rpc.has_block_data(x)).unwrap_or(true)
+        {
+            match pinned_rpc
+                .try_request_handle(&authorization, error_handler)
+                .await
+            {
+                Ok(OpenRequestResult::Handle(x)) => return Ok(OpenRequestResult::Handle(x)),
+                Ok(_) => trace!("pinned rpc {} is not ready", pinned_rpc),
+                Err(err) => trace!(?err, "pinned rpc {} failed", pinned_rpc),
+            }
+        }
+
         let mut watch_ranked_rpcs = self.watch_ranked_rpcs.subscribe();
 
         let mut potential_rpcs = Vec::with_capacity(self.len());
@@ -814,6 +1012,8 @@ impl Web3Rpcs {
 
         let mut last_provider_error = None;
 
+        let mut retries = 0;
+
         // TODO: the loop here feels somewhat redundant with the loop in best_available_rpc
         loop {
             if let Some(max_wait) = max_wait {
@@ -835,6 +1035,9 @@ impl Web3Rpcs {
                 .await?
             {
                 OpenRequestResult::Handle(active_request_handle) => {
+                    let active_request_handle = active_request_handle
+                        .with_request_ulid(request_metadata.map(|x| x.request_ulid));
+
                     // save the rpc in case we get an error and want to retry on another server
                     // TODO: look at backend_requests instead
                     let rpc = active_request_handle.clone_connection();
@@ -878,6 +1081,7 @@ impl Web3Rpcs {
                                 Err(err) => {
                                     warn!(?err, "error from {}", rpc);
 
+                                    // the request never reached a node. try the next rpc
                                     last_provider_error = Some(error);
 
                                     continue;
@@ -887,6 +1091,14 @@ impl Web3Rpcs {
                             // some errors should be retried on other nodes
                             let error_msg = error.message.as_ref();
 
+                            // the node ran the call. return its code and message as is
+                            // reverts can say "limit" or "exceeded" without being rate limits
+                            if matches!(method, "eth_call" | "eth_estimateGas")
+                                && (error.code == 3 || error_msg.starts_with("execution reverted"))
+                            {
+                                return Err(error.into());
+                            }
+
                             // different providers do different codes. check all of them
                             // TODO: there's probably more strings to add here
                             let rate_limit_substrings = ["limit", "exceeded", "quota usage"];
@@ -1012,6 +1224,34 @@ impl Web3Rpcs {
                     }
                 }
                 OpenRequestResult::NotReady => {
+                    // every rpc failed to answer. reads get a few more rounds with a backoff so that a short outage doesn't fail the request
+                    if last_provider_error.is_some()
+                        && self.retry_policy.should_retry(method, retries)
+                    {
+                        let backoff = self.retry_policy.backoff(retries);
+
+                        // stay within the request's timeout
+                        if let Some(max_wait) = max_wait
+                            && start.elapsed() + backoff > max_wait
+                        {
+                            break;
+                        }
+
+                        retries += 1;
+
+                        if let Some(request_metadata) = request_metadata {
+                            request_metadata.retries.fetch_add(1, Ordering::AcqRel);
+                        }
+
+                        trace!(%method, ?backoff, %retries, "retrying on every rpc");
+
+                        sleep(backoff).await;
+
+                        skip_rpcs.clear();
+
+                        continue;
+                    }
+
                     break;
                 }
             }
@@ -1037,6 +1277,26 @@ impl Web3Rpcs {
         let num_conns = self.len();
         let num_skipped = skip_rpcs.len();
 
+        // nothing will be ready until a new month starts
+        let num_with_budget = self
+            .by_name
+            .read()
+            .values()
+            .filter(|x| !x.request_budget_exhausted())
+            .count();
+
+        if num_conns > 0 && num_with_budget == 0 {
+            error!(
+                "every rpc in {} has used up its monthly request limit",
+                self
+            );
+
+            return Err(Web3ProxyError::NotEnoughRpcs {
+                num_known: num_with_budget,
+                min_head_rpcs: self.min_synced_rpcs,
+            });
+        }
+
         let needed = min_block_needed.max(max_block_needed);
 
         let head_block_num = watch_consensus_rpcs
@@ -1051,6 +1311,16 @@ impl Web3Rpcs {
                 min_block_needed, max_block_needed, head_block_num, num_conns
             );
         } else if head_block_num.as_ref() > needed {
+            if let Some(needed) = needed
+                && !self.by_name.read().values().any(|x| x.has_block_data(needed))
+            {
+                // no amount of retrying will help. none of the servers keep blocks this old
+                return Err(Web3ProxyError::NotEnoughArchiveRpcs {
+                    needed: *needed,
+                    head_block_num: head_block_num.unwrap_or_default(),
+                });
+            }
+
             // we have synced past the needed block
             // TODO: this is likely caused by rate limits. make the error message better
             error!(
@@ -1076,6 +1346,27 @@ impl Web3Rpcs {
         .into())
     }
 
+    /// the most peers that any rpc reports. rpcs that don't answer are skipped. None if none of them answer
+    pub async fn max_peer_count(&self) -> Option<U64> {
+        let rpcs: Vec<_> = self.by_name.read().values().cloned().collect();
+
+        let peer_counts = rpcs.iter().map(|rpc| {
+            rpc.internal_request::<_, U64>(
+                "net_peerCount",
+                &[(); 0],
+                Some(RequestErrorHandler::DebugLevel),
+                Some(1),
+                Some(Duration::from_secs(5)),
+            )
+        });
+
+        join_all(peer_counts)
+            .await
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .max()
+    }
+
     /// be sure there is a timeout on this or it might loop forever
     #[allow(clippy::too_many_arguments)]
     pub async fn try_send_all_synced_connections<P: JsonRpcParams>(
@@ -1132,6 +1423,13 @@ impl Web3Rpcs {
                             .store(only_backups_used, Ordering::Release);
                     }
 
+                    let request_ulid = request_metadata.map(|x| x.request_ulid);
+
+                    let active_request_handles = active_request_handles
+                        .into_iter()
+                        .map(|x| x.with_request_ulid(request_ulid))
+                        .collect();
+
                     let x = self
                         .try_send_parallel_requests(active_request_handles, method, params)
                         .await?;
@@ -1204,6 +1502,63 @@ impl Web3Rpcs {
         Err(Web3ProxyError::NoServersSynced)
     }
 
+    /// Send to one rpc at a time, in order of their names, until one of them succeeds.
+    /// Unlike try_send_all_synced_connections, this never has a request in flight to more than one rpc.
+    /// The names of the rpcs that errored are returned along with the result.
+    pub async fn try_send_in_order<P: JsonRpcParams>(
+        &self,
+        method: &str,
+        params: &P,
+        request_metadata: Option<&Arc<RequestMetadata>>,
+        error_level: Option<RequestErrorHandler>,
+    ) -> (Web3ProxyResult<(Arc<Web3Rpc>, Box<RawValue>)>, Vec<String>) {
+        let mut all_rpcs: Vec<_> = self.by_name.read().values().cloned().collect();
+
+        all_rpcs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let authorization = request_metadata
+            .and_then(|x| x.authorization.clone())
+            .unwrap_or_default();
+
+        let request_ulid = request_metadata.map(|x| x.request_ulid);
+
+        let mut failed = vec![];
+        let mut last_err = Web3ProxyError::NoServersSynced;
+
+        for rpc in all_rpcs {
+            let handle = match rpc.try_request_handle(&authorization, error_level).await {
+                Ok(OpenRequestResult::Handle(handle)) => handle.with_request_ulid(request_ulid),
+                Ok(_) => {
+                    trace!("{} is not available. skipping", rpc);
+                    continue;
+                }
+                Err(err) => {
+                    warn!(?err, "error getting request handle for {}", rpc);
+                    continue;
+                }
+            };
+
+            if let Some(request_metadata) = request_metadata {
+                request_metadata.backend_requests.lock().push(rpc.clone());
+            }
+
+            match handle
+                .request::<_, Box<RawValue>>(method, &json!(params))
+                .await
+            {
+                Ok(x) => return (Ok((rpc, x)), failed),
+                Err(err) => {
+                    warn!(?err, %method, "{} failed. trying the next rpc", rpc);
+
+                    failed.push(rpc.name.clone());
+                    last_err = err.into();
+                }
+            }
+        }
+
+        (Err(last_err), failed)
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub async fn try_proxy_connection<P: JsonRpcParams, R: JsonRpcResultData>(
         &self,
@@ -1256,7 +1611,7 @@ impl Serialize for Web3Rpcs {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Web3Rpcs", 6)?;
+        let mut state = serializer.serialize_struct("Web3Rpcs", 7)?;
 
         {
             let by_name = self.by_name.read();
@@ -1265,6 +1620,8 @@ impl Serialize for Web3Rpcs {
             state.serialize_field("conns", &rpcs)?;
         }
 
+        state.serialize_field("draining", &self.draining())?;
+
         {
             let consensus_rpcs = self.watch_ranked_rpcs.borrow().clone();
             // TODO: rename synced_connections to consensus_rpcs
@@ -1281,7 +1638,7 @@ impl Serialize for Web3Rpcs {
             &(
                 MokaCacheSerializer(&self.blocks_by_hash),
                 MokaCacheSerializer(&self.blocks_by_number),
-                MokaCacheSerializer(&self.pending_transaction_cache),
+                MokaCacheSerializer(&self.pending_transaction_cache.local),
             ),
         )?;
 
@@ -1302,6 +1659,53 @@ impl Serialize for Web3Rpcs {
     }
 }
 
+#[cfg(test)]
+impl Web3Rpcs {
+    /// No rpcs, but the given blocks are already cached. The last block is the head block.
+    /// Enough for tests of code that only looks up known blocks.
+    pub(crate) async fn with_cached_blocks(blocks: Vec<Web3ProxyBlock>) -> Self {
+        let (block_sender, _) = flume::unbounded();
+        let (pending_tx_id_sender, pending_tx_id_receiver) = flume::unbounded();
+        let (watch_ranked_rpcs, _) = watch::channel(None);
+        let (watch_head_block, _) = watch::channel(blocks.last().cloned());
+
+        let rpcs = Self {
+            block_sender,
+            by_name: Default::default(),
+            draining: Default::default(),
+            chain_id: 1,
+            name: "test".to_string(),
+            watch_head_block: Some(watch_head_block),
+            watch_ranked_rpcs,
+            pending_transaction_cache: PendingTransactionCache::new(
+                1,
+                100,
+                Duration::from_secs(60),
+                None,
+            ),
+            pending_tx_id_receiver,
+            pending_tx_id_sender,
+            blocks_by_hash: CacheBuilder::new(100).build(),
+            blocks_by_number: CacheBuilder::new(100).build(),
+            max_head_block_age: Duration::from_secs(60),
+            max_head_block_lag: 5.into(),
+            retry_policy: Default::default(),
+            webhooks: None,
+            finalized_blocks: Default::default(),
+            head_quorum: 1,
+            unconfirmed_head_tolerance: None,
+            min_synced_rpcs: 1,
+            min_sum_soft_limit: 1,
+        };
+
+        for block in blocks {
+            rpcs.try_cache_block(block, true).await.unwrap();
+        }
+
+        rpcs
+    }
+}
+
 mod tests {
     #![allow(unused_imports)]
 
@@ -1312,7 +1716,7 @@ mod tests {
     use ethers::types::H256;
     use ethers::types::{Block, U256};
     use latency::PeakEwmaLatency;
-    use moka::future::CacheBuilder;
+    use moka::future::{Cache, CacheBuilder};
     use parking_lot::RwLock;
     use std::time::{SystemTime, UNIX_EPOCH};
     use tracing::trace;
@@ -1485,13 +1889,17 @@ mod tests {
         let rpcs = Web3Rpcs {
             block_sender: block_sender.clone(),
             by_name: RwLock::new(rpcs_by_name),
+            draining: Default::default(),
             chain_id,
             name: "test".to_string(),
             watch_head_block: Some(watch_consensus_head_sender),
             watch_ranked_rpcs,
-            pending_transaction_cache: CacheBuilder::new(100)
-                .time_to_live(Duration::from_secs(60))
-                .build(),
+            pending_transaction_cache: PendingTransactionCache::new(
+                1,
+                100,
+                Duration::from_secs(60),
+                None,
+            ),
             pending_tx_id_receiver,
             pending_tx_id_sender,
             blocks_by_hash: CacheBuilder::new(100)
@@ -1504,6 +1912,11 @@ mod tests {
             max_head_block_age: Duration::from_secs(60),
             // TODO: test max_head_block_lag?
             max_head_block_lag: 5.into(),
+            retry_policy: Default::default(),
+            webhooks: None,
+            finalized_blocks: Default::default(),
+            head_quorum: 1,
+            unconfirmed_head_tolerance: None,
             min_synced_rpcs: 1,
             min_sum_soft_limit: 1,
         };
@@ -1755,13 +2168,17 @@ mod tests {
         let rpcs = Web3Rpcs {
             block_sender,
             by_name: RwLock::new(rpcs_by_name),
+            draining: Default::default(),
             chain_id,
             name: "test".to_string(),
             watch_head_block: Some(watch_consensus_head_sender),
             watch_ranked_rpcs,
-            pending_transaction_cache: CacheBuilder::new(100)
-                .time_to_live(Duration::from_secs(120))
-                .build(),
+            pending_transaction_cache: PendingTransactionCache::new(
+                1,
+                100,
+                Duration::from_secs(120),
+                None,
+            ),
             pending_tx_id_receiver,
             pending_tx_id_sender,
             blocks_by_hash: CacheBuilder::new(100)
@@ -1770,10 +2187,15 @@ mod tests {
             blocks_by_number: CacheBuilder::new(100)
                 .time_to_live(Duration::from_secs(120))
                 .build(),
+            head_quorum: 1,
+            unconfirmed_head_tolerance: None,
             min_synced_rpcs: 1,
             min_sum_soft_limit: 4_000,
             max_head_block_age: Duration::from_secs(60),
             max_head_block_lag: 5.into(),
+            retry_policy: Default::default(),
+            webhooks: None,
+            finalized_blocks: Default::default(),
         };
 
         let authorization = Arc::new(Authorization::internal(None).unwrap());
@@ -1944,19 +2366,30 @@ mod tests {
         let rpcs = Web3Rpcs {
             block_sender,
             by_name: RwLock::new(rpcs_by_name),
+            draining: Default::default(),
             chain_id,
             name: "test".to_string(),
             watch_head_block: Some(watch_consensus_head_sender),
             watch_ranked_rpcs,
-            pending_transaction_cache: Cache::new(10_000),
+            pending_transaction_cache: PendingTransactionCache::new(
+                1,
+                10_000,
+                Duration::from_secs(300),
+                None,
+            ),
             pending_tx_id_receiver,
             pending_tx_id_sender,
             blocks_by_hash: Cache::new(10_000),
             blocks_by_number: Cache::new(10_000),
+            head_quorum: 1,
+            unconfirmed_head_tolerance: None,
             min_synced_rpcs: 1,
             min_sum_soft_limit: 1_000,
             max_head_block_age: Duration::from_secs(60),
             max_head_block_lag: 5.into(),
+            retry_policy: Default::default(),
+            webhooks: None,
+            finalized_blocks: Default::default(),
         };
 
         let authorization = Arc::new(Authorization::internal(None).unwrap());
@@ -2023,6 +2456,40 @@ mod tests {
             "wrong number of connections"
         )
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_check_server_configs() {
+        let rpcs = Web3Rpcs::with_cached_blocks(vec![]).await;
+
+        let rpc_config = |soft_limit: u32, disabled: bool| Web3RpcConfig {
+            http_url: Some("http://127.0.0.1:8545".to_string()),
+            soft_limit,
+            disabled,
+            ..Default::default()
+        };
+
+        let configs = HashMap::from([("a".to_string(), rpc_config(1, false))]);
+        assert!(rpcs.check_server_configs(&configs).is_ok());
+
+        // disabled rpcs don't count
+        let configs = HashMap::from([("a".to_string(), rpc_config(1, true))]);
+        assert!(matches!(
+            rpcs.check_server_configs(&configs),
+            Err(Web3ProxyError::NotEnoughRpcs { .. })
+        ));
+
+        let configs = HashMap::from([
+            ("a".to_string(), rpc_config(0, false)),
+            ("b".to_string(), rpc_config(5, true)),
+        ]);
+        assert!(matches!(
+            rpcs.check_server_configs(&configs),
+            Err(Web3ProxyError::NotEnoughSoftLimit { .. })
+        ));
+
+        // nothing was changed
+        assert!(rpcs.is_empty());
+    }
 }
 
 #[cfg(test)]
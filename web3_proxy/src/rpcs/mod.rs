@@ -1,8 +1,13 @@
 // TODO: all pub, or export useful things here instead?
 pub mod blockchain;
+pub mod circuit_breaker;
 pub mod consensus;
+pub mod latency_histogram;
 pub mod many;
 pub mod one;
+pub mod priority_semaphore;
 pub mod provider;
 pub mod request;
+pub mod request_budget;
+pub mod retry;
 pub mod transactions;
@@ -1,37 +1,66 @@
 //! Rate-limited communication with a web3 provider.
 use super::blockchain::{ArcBlock, BlocksByHashCache, Web3ProxyBlock};
-use super::provider::{connect_http, connect_ws, EthersHttpProvider, EthersWsProvider};
-use super::request::{OpenRequestHandle, OpenRequestResult};
-use crate::app::{flatten_handle, Web3ProxyJoinHandle};
+use super::circuit_breaker::{CircuitBreaker, CircuitBreakerState};
+use super::latency_histogram::LatencyHistogram;
+use super::priority_semaphore::{PrioritySemaphore, RequestPriority};
+use super::provider::{
+    connect_http, connect_ws, extra_headers, EthersHttpProvider, EthersWsProvider,
+    RequestIdProvider,
+};
+use super::request::{
+    OpenRequestHandle, OpenRequestResult, UpstreamErrorCounts, UpstreamErrorKind,
+};
+use super::request_budget::RequestBudget;
+use crate::app::{
+    flatten_handle, Web3ProxyJoinHandle, APP_USER_AGENT, HTTP_CONNECT_TIMEOUT, HTTP_REQUEST_TIMEOUT,
+};
 use crate::config::{BlockAndRpc, Web3RpcConfig};
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
 use crate::frontend::authorization::Authorization;
 use crate::jsonrpc::{JsonRpcParams, JsonRpcResultData};
 use crate::rpcs::request::RequestErrorHandler;
+use crate::webhooks::{WebhookEvent, WebhookSender};
 use anyhow::{anyhow, Context};
 use arc_swap::ArcSwapOption;
 use ethers::prelude::{Bytes, Middleware, TxHash, U64};
 use ethers::types::{Address, Transaction, U256};
 use futures::future::try_join_all;
 use futures::StreamExt;
+use influxdb2::models::DataPoint;
 use latency::{EwmaLatency, PeakEwmaLatency, RollingQuantileLatency};
 use migration::sea_orm::DatabaseConnection;
 use nanorand::Rng;
 use parking_lot::RwLock;
 use redis_rate_limiter::{RedisPool, RedisRateLimitResult, RedisRateLimiter};
+use reqwest::header::HeaderName;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use serde_json::json;
 use std::cmp::Reverse;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize};
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
 use std::{cmp::Ordering, sync::Arc};
-use tokio::sync::watch;
-use tokio::time::{interval, sleep, sleep_until, Duration, Instant, MissedTickBehavior};
+use tokio::sync::{watch, OwnedSemaphorePermit, TryAcquireError};
+use tokio::time::{interval, sleep, sleep_until, timeout, Duration, Instant, MissedTickBehavior};
 use tracing::{debug, info, trace, warn, Level};
 use url::Url;
 
+/// after this many consecutive disagreements with the consensus head, an rpc is deprioritized
+pub const MAX_HEAD_DISAGREEMENTS: u32 = 3;
+
+/// The first wait before reconnecting. This doubles after every failure
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest wait before reconnecting. Jitter can add up to half of this
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// When an rpc is at max_concurrent_requests, retry it this soon if its permits are not waited for
+const CONCURRENCY_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The longest a request waits for a permit on rpcs that are at max_concurrent_requests before trying everything again
+pub(super) const CONCURRENCY_MAX_WAIT: Duration = Duration::from_secs(1);
+
 /// An active connection to a Web3 RPC server like geth or erigon.
 #[derive(Default)]
 pub struct Web3Rpc {
@@ -39,12 +68,17 @@ pub struct Web3Rpc {
     pub block_interval: Duration,
     pub display_name: Option<String>,
     pub db_conn: Option<DatabaseConnection>,
-    /// most all requests prefer use the http_provider
+    /// ethers' provider for the http_url. requests are sent with the request_id_provider instead
     pub(super) http_provider: Option<EthersHttpProvider>,
+    /// requests are sent with this instead of the http_provider. unlike ethers, it can see the status and headers of responses
+    pub(super) request_id_provider: Option<RequestIdProvider>,
     /// the websocket url is only used for subscriptions
     pub(super) ws_url: Option<Url>,
     /// the websocket provider is only used for subscriptions
     pub(super) ws_provider: ArcSwapOption<EthersWsProvider>,
+    /// true while ws_provider is connected. requests wait on this instead of polling while it reconnects
+    /// ws_connected is only inside an Option so that the "Default" derive works. it will always be set.
+    pub(super) ws_connected: Option<watch::Sender<bool>>,
     /// keep track of hard limits
     /// hard_limit_until is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) hard_limit_until: Option<watch::Sender<Instant>>,
@@ -57,6 +91,8 @@ pub struct Web3Rpc {
     pub(super) automatic_block_limit: bool,
     /// only use this rpc if everything else is lagging too far. this allows us to ignore fast but very low limit rpcs
     pub backup: bool,
+    /// configured as an archive server. the block data limit is not checked
+    pub archive: bool,
     /// TODO: have an enum for this so that "no limit" prints pretty?
     pub(super) block_data_limit: AtomicU64,
     /// head_block is only inside an Option so that the "Default" derive works. it will always be set.
@@ -69,6 +105,9 @@ pub struct Web3Rpc {
     pub(super) peak_latency: Option<PeakEwmaLatency>,
     /// Automatically set priority
     pub(super) tier: AtomicU32,
+    /// How many times in a row this rpc's head was on a different chain than the consensus head.
+    /// Rpcs that persistently disagree with the quorum are deprioritized.
+    pub(super) head_disagreements: AtomicU32,
     /// Track total internal requests served
     pub(super) internal_requests: AtomicUsize,
     /// Track total external requests served
@@ -76,8 +115,22 @@ pub struct Web3Rpc {
     /// Track time used by external requests served
     /// request_ms_histogram is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) median_latency: Option<RollingQuantileLatency>,
+    /// Track request latency percentiles between flushes to InfluxDB
+    pub(super) latency_histogram: LatencyHistogram,
     /// Track in-flight requests
     pub(super) active_requests: AtomicUsize,
+    /// Track why requests failed
+    pub(super) upstream_errors: UpstreamErrorCounts,
+    /// Bound in-flight requests. Requests over the limit are sent to other rpcs. Premium requests get permits first
+    pub(super) concurrency_semaphore: Option<PrioritySemaphore>,
+    /// A draining rpc does not accept new requests. It disconnects once its active requests finish
+    pub(super) draining: AtomicBool,
+    /// take this rpc out of rotation for a while if it errors too much
+    pub(super) circuit_breaker: Option<CircuitBreaker>,
+    /// take this rpc out of rotation for the rest of the month once it has served this many requests
+    pub(super) request_budget: Option<RequestBudget>,
+    /// tell operators when the circuit breaker opens
+    pub(super) webhooks: Option<Arc<WebhookSender>>,
     /// disconnect_watch is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) disconnect_watch: Option<watch::Sender<bool>>,
     /// created_at is only inside an Option so that the "Default" derive works. it will always be set.
@@ -100,9 +153,109 @@ impl Web3Rpc {
         block_map: BlocksByHashCache,
         block_and_rpc_sender: Option<flume::Sender<BlockAndRpc>>,
         tx_id_sender: Option<flume::Sender<(TxHash, Arc<Self>)>>,
+        webhooks: Option<Arc<WebhookSender>>,
     ) -> anyhow::Result<(Arc<Web3Rpc>, Web3ProxyJoinHandle<()>)> {
+        let tx_id_sender = if config.subscribe_txs {
+            tx_id_sender
+        } else {
+            None
+        };
+
+        let new_connection = Arc::new(Self::new(
+            config,
+            name,
+            chain_id,
+            db_conn,
+            http_client,
+            redis_pool,
+            block_interval,
+            block_and_rpc_sender.is_some(),
+            webhooks,
+        )?);
+
+        // subscribe to new blocks and new transactions
+        // subscribing starts the connection (with retries)
+        // TODO: make transaction subscription optional (just pass None for tx_id_sender)
+        let handle = {
+            let new_connection = new_connection.clone();
+            tokio::spawn(async move {
+                new_connection
+                    .subscribe_with_reconnect(
+                        block_map,
+                        block_and_rpc_sender,
+                        chain_id,
+                        tx_id_sender,
+                    )
+                    .await
+            })
+        };
+
+        Ok((new_connection, handle))
+    }
+
+    /// Connect to the rpc and run the same checks as a new subscription, but stop there.
+    /// This lets `check_config --dry-run` find bad urls and wrong chains without starting the proxy.
+    pub async fn dry_run(
+        config: Web3RpcConfig,
+        name: String,
+        chain_id: u64,
+        http_client: Option<reqwest::Client>,
+        redis_pool: Option<RedisPool>,
+        block_interval: Duration,
+    ) -> Web3ProxyResult<()> {
+        let rpc = Arc::new(Self::new(
+            config,
+            name,
+            chain_id,
+            None,
+            http_client,
+            redis_pool,
+            block_interval,
+            true,
+            None,
+        )?);
+
+        if let Some(url) = rpc.ws_url.clone() {
+            let x = connect_ws(url, 0)
+                .await
+                .with_context(|| format!("failed connecting to {}", rpc))?;
+
+            rpc.ws_provider.store(Some(Arc::new(x)));
+        }
+
+        rpc.check_provider(chain_id).await
+    }
+
+    /// Everything needed to connect, but no connection yet
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        config: Web3RpcConfig,
+        name: String,
+        chain_id: u64,
+        db_conn: Option<DatabaseConnection>,
+        http_client: Option<reqwest::Client>,
+        redis_pool: Option<RedisPool>,
+        block_interval: Duration,
+        track_blocks: bool,
+        webhooks: Option<Arc<WebhookSender>>,
+    ) -> anyhow::Result<Self> {
         let created_at = Instant::now();
 
+        let request_budget = match (config.monthly_request_limit, redis_pool.as_ref()) {
+            (None, _) => None,
+            (Some(monthly_limit), Some(redis_pool)) => Some(RequestBudget::new(
+                chain_id,
+                &name,
+                monthly_limit,
+                redis_pool.clone(),
+            )),
+            (Some(_), None) => {
+                return Err(anyhow::anyhow!(
+                    "no redis client pool! needed for monthly request limit"
+                ))
+            }
+        };
+
         let hard_limit = match (config.hard_limit, redis_pool) {
             (None, None) => None,
             (Some(hard_limit), Some(redis_pool)) => {
@@ -125,17 +278,17 @@ impl Web3Rpc {
             }
         };
 
-        let tx_id_sender = if config.subscribe_txs {
-            tx_id_sender
-        } else {
-            None
-        };
-
         let backup = config.backup;
 
-        let block_data_limit: AtomicU64 = config.block_data_limit.unwrap_or_default().into();
-        let automatic_block_limit = (block_data_limit.load(atomic::Ordering::Acquire) == 0)
-            && block_and_rpc_sender.is_some();
+        // archive servers have every block. there is no need to check
+        let block_data_limit: AtomicU64 = if config.archive {
+            u64::MAX
+        } else {
+            config.block_data_limit.unwrap_or_default()
+        }
+        .into();
+        let automatic_block_limit =
+            (block_data_limit.load(atomic::Ordering::Acquire) == 0) && track_blocks;
 
         // have a sender for tracking hard limit anywhere. we use this in case we
         // and track on servers that have a configured hard limit
@@ -163,14 +316,69 @@ impl Web3Rpc {
 
         let median_request_latency = RollingQuantileLatency::spawn_median(1_000).await;
 
-        let http_provider = if let Some(http_url) = config.http_url {
+        let (http_provider, request_id_provider) = if let Some(http_url) = config.http_url {
             let http_url = http_url.parse::<Url>()?;
 
-            Some(connect_http(http_url, http_client, block_interval)?)
+            // reqwest's timeouts and default headers are set when the client is built, so overriding them needs a dedicated client
+            let http_client = if config.connect_timeout.is_some()
+                || config.request_timeout.is_some()
+                || !config.extra_headers.is_empty()
+            {
+                let connect_timeout = config
+                    .connect_timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(HTTP_CONNECT_TIMEOUT);
+                let request_timeout = config
+                    .request_timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(HTTP_REQUEST_TIMEOUT);
+
+                // only the names of the extra headers are logged
+                debug!(%name, ?connect_timeout, ?request_timeout, extra_headers=?config.extra_headers.keys(), "dedicated http client");
+
+                Some(
+                    reqwest::ClientBuilder::new()
+                        .connect_timeout(connect_timeout)
+                        .timeout(request_timeout)
+                        .default_headers(extra_headers(&config.extra_headers)?)
+                        .user_agent(APP_USER_AGENT)
+                        .build()?,
+                )
+            } else {
+                http_client
+            };
+
+            let request_id_header = config
+                .request_id_header
+                .map(|x| x.parse::<HeaderName>())
+                .transpose()?;
+
+            let request_id_provider = Some(RequestIdProvider::new(
+                request_id_header,
+                http_url.clone(),
+                http_client.clone(),
+            ));
+
+            (
+                Some(connect_http(http_url, http_client, block_interval)?),
+                request_id_provider,
+            )
 
             // TODO: check the provider is on the right chain
         } else {
-            None
+            if config.request_id_header.is_some() {
+                warn!(%name, "request_id_header requires http_url");
+            }
+
+            if config.connect_timeout.is_some() || config.request_timeout.is_some() {
+                warn!(%name, "connect_timeout and request_timeout require http_url");
+            }
+
+            if !config.extra_headers.is_empty() {
+                warn!(%name, "extra_headers requires http_url");
+            }
+
+            (None, None)
         };
 
         let ws_url = if let Some(ws_url) = config.ws_url {
@@ -183,11 +391,28 @@ impl Web3Rpc {
 
         let (disconnect_watch, _) = watch::channel(false);
 
+        let (ws_connected, _) = watch::channel(false);
+
+        let circuit_breaker = config.circuit_breaker_errors.map(|max_errors| {
+            CircuitBreaker::new(
+                max_errors,
+                Duration::from_secs(config.circuit_breaker_window),
+                Duration::from_secs(config.circuit_breaker_cooldown),
+            )
+        });
+
+        let concurrency_semaphore = config
+            .max_concurrent_requests
+            .map(|x| PrioritySemaphore::new(x as usize));
+
         let new_rpc = Self {
+            archive: config.archive,
             automatic_block_limit,
             backup,
             block_data_limit,
             block_interval,
+            circuit_breaker,
+            concurrency_semaphore,
             created_at: Some(created_at),
             db_conn,
             display_name: config.display_name,
@@ -198,44 +423,34 @@ impl Web3Rpc {
             name,
             peak_latency: Some(peak_latency),
             median_latency: Some(median_request_latency),
+            request_budget,
+            request_id_provider,
             soft_limit: config.soft_limit,
             ws_url,
+            ws_connected: Some(ws_connected),
             disconnect_watch: Some(disconnect_watch),
+            webhooks,
             ..Default::default()
         };
 
-        let new_connection = Arc::new(new_rpc);
-
-        // subscribe to new blocks and new transactions
-        // subscribing starts the connection (with retries)
-        // TODO: make transaction subscription optional (just pass None for tx_id_sender)
-        let handle = {
-            let new_connection = new_connection.clone();
-            tokio::spawn(async move {
-                // TODO: this needs to be a subscribe_with_reconnect that does a retry with jitter and exponential backoff
-                new_connection
-                    .subscribe_with_reconnect(
-                        block_map,
-                        block_and_rpc_sender,
-                        chain_id,
-                        tx_id_sender,
-                    )
-                    .await
-            })
-        };
+        Ok(new_rpc)
+    }
 
-        Ok((new_connection, handle))
+    /// true if this rpc has disagreed with the consensus head too many times in a row
+    pub fn disagrees_with_quorum(&self) -> bool {
+        self.head_disagreements.load(atomic::Ordering::Relaxed) >= MAX_HEAD_DISAGREEMENTS
     }
 
     /// sort by...
     /// - backups last
+    /// - rpcs that disagree with the quorum last
     /// - tier (ascending)
     /// - block number (descending)
     /// TODO: tests on this!
     /// TODO: should tier or block number take priority?
     /// TODO: should this return a struct that implements sorting traits?
     /// TODO: move this to consensus.rs
-    fn sort_on(&self, max_block: Option<U64>) -> (bool, Reverse<U64>, u32) {
+    fn sort_on(&self, max_block: Option<U64>) -> (bool, bool, Reverse<U64>, u32) {
         let mut head_block = self
             .head_block
             .as_ref()
@@ -250,14 +465,16 @@ impl Web3Rpc {
 
         let backup = self.backup;
 
-        (!backup, Reverse(head_block), tier)
+        let disagrees = self.disagrees_with_quorum();
+
+        (!backup, disagrees, Reverse(head_block), tier)
     }
 
     /// TODO: move this to consensus.rs
     pub fn sort_for_load_balancing_on(
         &self,
         max_block: Option<U64>,
-    ) -> ((bool, Reverse<U64>, u32), Duration) {
+    ) -> ((bool, bool, Reverse<U64>, u32), Duration) {
         let sort_on = self.sort_on(max_block);
 
         let weighted_peak_latency = self.weighted_peak_latency();
@@ -274,7 +491,7 @@ impl Web3Rpc {
     pub fn shuffle_for_load_balancing_on(
         &self,
         max_block: Option<U64>,
-    ) -> ((bool, Reverse<U64>, u32), u8) {
+    ) -> ((bool, bool, Reverse<U64>, u32), u8) {
         let sort_on = self.sort_on(max_block);
 
         let mut rng = nanorand::tls_rng();
@@ -284,6 +501,27 @@ impl Web3Rpc {
         (sort_on, r)
     }
 
+    /// Request latency percentiles since the last call. None if there were no requests.
+    /// Only the host of the url is included. Full urls can have api keys in them.
+    pub fn latency_point(&self, chain_id: u64, timestamp: i64) -> Option<DataPoint> {
+        let percentiles = self.latency_histogram.take()?;
+
+        let host = self
+            .http_provider
+            .as_ref()
+            .map(|x| x.url())
+            .or(self.ws_url.as_ref())
+            .and_then(|x| x.host_str());
+
+        match percentiles.build_timeseries_point(chain_id, &self.name, host, timestamp) {
+            Ok(x) => Some(x),
+            Err(err) => {
+                warn!(?err, "unable to build latency point for {}", self);
+                None
+            }
+        }
+    }
+
     pub fn weighted_peak_latency(&self) -> Duration {
         let peak_latency = if let Some(peak_latency) = self.peak_latency.as_ref() {
             peak_latency.latency()
@@ -385,6 +623,26 @@ impl Web3Rpc {
         self.block_data_limit.load(atomic::Ordering::Acquire).into()
     }
 
+    /// None if this rpc hasn't sent a head block yet
+    pub fn head_block_num(&self) -> Option<U64> {
+        self.head_block
+            .as_ref()?
+            .borrow()
+            .as_ref()
+            .map(|x| *x.number())
+    }
+
+    /// true if this rpc's head is the given block
+    pub fn is_on_block(&self, block: &Web3ProxyBlock) -> bool {
+        self.head_block
+            .as_ref()
+            .unwrap()
+            .borrow()
+            .as_ref()
+            .map(|x| x.hash() == block.hash())
+            .unwrap_or(false)
+    }
+
     /// TODO: get rid of this now that consensus rpcs does it
     pub fn has_block_data(&self, needed_block_num: &U64) -> bool {
         let head_block_num = match self.head_block.as_ref().unwrap().borrow().as_ref() {
@@ -539,6 +797,93 @@ impl Web3Rpc {
         *self.disconnect_watch.as_ref().unwrap().borrow()
     }
 
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(atomic::Ordering::Acquire)
+    }
+
+    pub fn active_requests(&self) -> usize {
+        self.active_requests.load(atomic::Ordering::Acquire)
+    }
+
+    /// How many requests to this rpc have failed with this kind of error
+    pub fn upstream_errors(&self, kind: UpstreamErrorKind) -> u64 {
+        self.upstream_errors.get(kind)
+    }
+
+    /// None if there is no monthly request limit
+    pub fn request_budget_remaining(&self) -> Option<u64> {
+        self.request_budget.as_ref().map(|x| x.remaining())
+    }
+
+    /// true if this rpc has used up its monthly request limit
+    pub fn request_budget_exhausted(&self) -> bool {
+        self.request_budget
+            .as_ref()
+            .map(|x| x.is_exhausted())
+            .unwrap_or(false)
+    }
+
+    /// None if the circuit breaker is disabled
+    pub fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        self.circuit_breaker.as_ref().map(|x| x.state())
+    }
+
+    /// feed a response into the circuit breaker
+    pub(super) fn record_response(&self, success: bool) {
+        let Some(circuit_breaker) = self.circuit_breaker.as_ref() else {
+            return;
+        };
+
+        if success {
+            circuit_breaker.record_success();
+        } else if circuit_breaker.record_error() {
+            warn!("circuit breaker opened for {}", self);
+
+            if let Some(ref webhooks) = self.webhooks {
+                webhooks.send(WebhookEvent::CircuitBreakerOpened {
+                    rpc: self.name.clone(),
+                });
+            }
+        }
+    }
+
+    /// Stop giving this rpc new requests and wait (up to drain_timeout) for its in-flight requests to finish.
+    /// Then tell it to disconnect.
+    pub async fn drain(&self, drain_timeout: Duration) {
+        self.draining.store(true, atomic::Ordering::Release);
+
+        let deadline = Instant::now() + drain_timeout;
+
+        let mut check_interval = interval(Duration::from_millis(100));
+        check_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let active_requests = self.active_requests();
+
+            if active_requests == 0 {
+                debug!("{} drained", self);
+                break;
+            }
+
+            if Instant::now() >= deadline {
+                warn!(
+                    %active_requests,
+                    "{} did not drain in {}s. disconnecting anyways",
+                    self,
+                    drain_timeout.as_secs_f32()
+                );
+                break;
+            }
+
+            check_interval.tick().await;
+        }
+
+        if let Some(ref disconnect_sender) = self.disconnect_watch {
+            trace!("telling {} to disconnect", self);
+            disconnect_sender.send_replace(true);
+        }
+    }
+
     async fn healthcheck(
         self: &Arc<Self>,
         error_handler: Option<RequestErrorHandler>,
@@ -600,7 +945,11 @@ impl Web3Rpc {
         chain_id: u64,
         tx_id_sender: Option<flume::Sender<(TxHash, Arc<Self>)>>,
     ) -> Web3ProxyResult<()> {
+        let mut backoff = RECONNECT_MIN_BACKOFF;
+
         loop {
+            let start = Instant::now();
+
             if let Err(err) = self
                 .clone()
                 .subscribe(
@@ -620,14 +969,25 @@ impl Web3Rpc {
                 break;
             }
 
+            // the subscription was healthy for a while. this is a new problem so try again quickly
+            if start.elapsed() > RECONNECT_MAX_BACKOFF {
+                backoff = RECONNECT_MIN_BACKOFF;
+            }
+
+            // jitter keeps every rpc from reconnecting at the same moment after a shared outage
+            let jitter = nanorand::tls_rng().generate_range(0..=backoff.as_millis() as u64 / 2);
+
+            let wait = backoff + Duration::from_millis(jitter);
+
             if self.backup {
-                debug!("reconnecting to {} in 30 seconds", self);
+                debug!("reconnecting to {} in {:?}", self, wait);
             } else {
-                info!("reconnecting to {} in 30 seconds", self);
+                info!("reconnecting to {} in {:?}", self, wait);
             }
 
-            // TODO: exponential backoff with jitter
-            sleep(Duration::from_secs(30)).await;
+            sleep(wait).await;
+
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
         }
 
         Ok(())
@@ -662,6 +1022,10 @@ impl Web3Rpc {
             let x = Arc::new(x);
 
             self.ws_provider.store(Some(x));
+
+            if let Some(ws_connected) = self.ws_connected.as_ref() {
+                ws_connected.send_replace(true);
+            }
         }
 
         if self.should_disconnect() {
@@ -796,6 +1160,10 @@ impl Web3Rpc {
         // TODO: tell ethers to disconnect?
         self.ws_provider.store(None);
 
+        if let Some(ws_connected) = self.ws_connected.as_ref() {
+            ws_connected.send_replace(false);
+        }
+
         Ok(())
     }
 
@@ -993,6 +1361,11 @@ impl Web3Rpc {
                     // TODO: when can this happen? log? emit a stat?
                     trace!("{} has no handle ready", self);
 
+                    if self.is_draining() {
+                        // a draining rpc will never be ready again
+                        return Err(Web3ProxyError::NoHandleReady);
+                    }
+
                     if let Some(max_wait_until) = max_wait_until {
                         if Instant::now() > max_wait_until {
                             return Err(Web3ProxyError::NoHandleReady);
@@ -1008,12 +1381,123 @@ impl Web3Rpc {
         }
     }
 
+    /// True for websocket-only rpcs while their websocket is disconnected
+    fn is_reconnecting(&self) -> bool {
+        self.http_provider.is_none() && self.ws_url.is_some() && self.ws_provider.load().is_none()
+    }
+
+    /// Wait for the websocket provider to reconnect.
+    /// Returns false if it is still disconnected after max_wait.
+    pub(super) async fn wait_for_ws_provider(&self, max_wait: Duration) -> bool {
+        let Some(ws_connected) = self.ws_connected.as_ref() else {
+            return false;
+        };
+
+        let mut ws_connected = ws_connected.subscribe();
+
+        matches!(
+            timeout(max_wait, ws_connected.wait_for(|x| *x)).await,
+            Ok(Ok(_))
+        )
+    }
+
+    /// Never waits for a permit. A full rpc is treated like a rate limited one so that the request goes to another rpc or waits
     pub async fn try_request_handle(
         self: &Arc<Self>,
         authorization: &Arc<Authorization>,
         error_handler: Option<RequestErrorHandler>,
     ) -> Web3ProxyResult<OpenRequestResult> {
-        // TODO: if websocket is reconnecting, return an error?
+        let concurrency_permit = self
+            .concurrency_semaphore
+            .as_ref()
+            .map(|x| x.try_acquire(authorization.priority()))
+            .transpose();
+
+        self.request_handle_with_permit(authorization, error_handler, concurrency_permit)
+            .await
+    }
+
+    /// Wait up to max_wait for a permit on an rpc that is at max_concurrent_requests.
+    /// Premium requests get the next free permit before free requests.
+    pub async fn wait_for_concurrency_permit(
+        self: &Arc<Self>,
+        authorization: &Arc<Authorization>,
+        error_handler: Option<RequestErrorHandler>,
+        max_wait: Duration,
+    ) -> Web3ProxyResult<OpenRequestResult> {
+        let concurrency_permit = match self.concurrency_semaphore.as_ref() {
+            None => Ok(None),
+            Some(x) => x
+                .acquire(authorization.priority(), max_wait)
+                .await
+                .map(Some),
+        };
+
+        self.request_handle_with_permit(authorization, error_handler, concurrency_permit)
+            .await
+    }
+
+    /// Stop sending requests until retry_at. Never shortens a wait that a Retry-After header or the rate limiter asked for
+    pub(super) fn hard_limit_until_at_least(&self, retry_at: Instant) {
+        if let Some(hard_limit_until) = self.hard_limit_until.as_ref() {
+            hard_limit_until.send_if_modified(|x| {
+                if retry_at > *x {
+                    *x = retry_at;
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+    }
+
+    /// True if the rpc is at max_concurrent_requests for this priority.
+    /// Rpcs without max_concurrent_requests are never full
+    pub(super) fn is_full(&self, priority: RequestPriority) -> bool {
+        self.concurrency_semaphore
+            .as_ref()
+            .map(|x| x.is_full(priority))
+            .unwrap_or(false)
+    }
+
+    async fn request_handle_with_permit(
+        self: &Arc<Self>,
+        authorization: &Arc<Authorization>,
+        error_handler: Option<RequestErrorHandler>,
+        concurrency_permit: Result<Option<OwnedSemaphorePermit>, TryAcquireError>,
+    ) -> Web3ProxyResult<OpenRequestResult> {
+        // the permit is checked first so that a full rpc doesn't use up rate limits or a circuit breaker probe
+        let concurrency_permit = match concurrency_permit {
+            Ok(x) => x,
+            Err(TryAcquireError::NoPermits) => {
+                trace!("max concurrent requests on {}", self);
+                return Ok(OpenRequestResult::RetryAt(
+                    Instant::now() + CONCURRENCY_RETRY_DELAY,
+                ));
+            }
+            Err(TryAcquireError::Closed) => {
+                warn!("concurrency semaphore on {} is closed", self);
+                return Ok(OpenRequestResult::NotReady);
+            }
+        };
+
+        // websocket-only rpcs are skipped while they reconnect instead of making the request wait for them
+        if self.is_reconnecting() {
+            trace!("waiting for {} to reconnect", self);
+            return Ok(OpenRequestResult::NotReady);
+        }
+
+        // draining rpcs finish their in-flight requests but do not get new ones
+        if self.is_draining() {
+            return Ok(OpenRequestResult::NotReady);
+        }
+
+        // rpcs that keep erroring are skipped until their cooldown is over
+        if let Some(circuit_breaker) = self.circuit_breaker.as_ref() {
+            if let Err(retry_at) = circuit_breaker.check() {
+                return Ok(OpenRequestResult::RetryAt(retry_at));
+            }
+        }
 
         // check cached rate limits
         if let Some(hard_limit_until) = self.hard_limit_until.as_ref() {
@@ -1047,9 +1531,7 @@ impl Web3Rpc {
                         );
                     }
 
-                    if let Some(hard_limit_until) = self.hard_limit_until.as_ref() {
-                        hard_limit_until.send_replace(retry_at);
-                    }
+                    self.hard_limit_until_at_least(retry_at);
 
                     return Ok(OpenRequestResult::RetryAt(retry_at));
                 }
@@ -1060,8 +1542,52 @@ impl Web3Rpc {
             }
         };
 
-        let handle =
-            OpenRequestHandle::new(authorization.clone(), self.clone(), error_handler).await;
+        // skip a used up budget without asking redis or taking a circuit breaker probe
+        if let Some(request_budget) = self.request_budget.as_ref() {
+            if request_budget.is_exhausted() {
+                trace!("monthly request limit used up on {}", self);
+                return Ok(OpenRequestResult::NotReady);
+            }
+        }
+
+        // the request will be sent. if the breaker is half-open, this is the probe
+        // another request might have taken the probe since it was checked above
+        if let Some(circuit_breaker) = self.circuit_breaker.as_ref() {
+            if let Err(retry_at) = circuit_breaker.try_allow() {
+                return Ok(OpenRequestResult::RetryAt(retry_at));
+            }
+        }
+
+        // counted last so that requests that were turned away above don't use up the budget
+        // internal requests (head block polling, health checks) count too. the provider bills for them the same as for user requests
+        if let Some(request_budget) = self.request_budget.as_ref() {
+            let allowed = match request_budget.try_spend().await {
+                Ok(x) => x,
+                Err(err) => {
+                    // redis being down shouldn't take the rpc out of rotation. go with what we last knew
+                    warn!(?err, "unable to spend request budget on {}", self);
+                    !request_budget.is_exhausted()
+                }
+            };
+
+            if !allowed {
+                trace!("monthly request limit used up on {}", self);
+
+                if let Some(circuit_breaker) = self.circuit_breaker.as_ref() {
+                    circuit_breaker.cancel_probe();
+                }
+
+                return Ok(OpenRequestResult::NotReady);
+            }
+        }
+
+        let handle = OpenRequestHandle::new(
+            authorization.clone(),
+            self.clone(),
+            error_handler,
+            concurrency_permit,
+        )
+        .await;
 
         Ok(handle.into())
     }
@@ -1177,13 +1703,15 @@ impl Serialize for Web3Rpc {
         S: Serializer,
     {
         // 3 is the number of fields in the struct.
-        let mut state = serializer.serialize_struct("Web3Rpc", 14)?;
+        let mut state = serializer.serialize_struct("Web3Rpc", 18)?;
 
         // the url is excluded because it likely includes private information. just show the name that we use in keys
         state.serialize_field("name", &self.name)?;
         // a longer name for display to users
         state.serialize_field("display_name", &self.display_name)?;
 
+        state.serialize_field("archive", &self.archive)?;
+
         state.serialize_field("backup", &self.backup)?;
 
         match self.block_data_limit.load(atomic::Ordering::Acquire) {
@@ -1197,6 +1725,11 @@ impl Serialize for Web3Rpc {
 
         state.serialize_field("tier", &self.tier)?;
 
+        state.serialize_field(
+            "head_disagreements",
+            &self.head_disagreements.load(atomic::Ordering::Relaxed),
+        )?;
+
         state.serialize_field("soft_limit", &self.soft_limit)?;
 
         // TODO: maybe this is too much data. serialize less?
@@ -1222,6 +1755,10 @@ impl Serialize for Web3Rpc {
             &self.active_requests.load(atomic::Ordering::Relaxed),
         )?;
 
+        state.serialize_field("draining", &self.is_draining())?;
+
+        state.serialize_field("circuit_breaker", &self.circuit_breaker_state())?;
+
         {
             let head_delay_ms = self.head_delay.read().latency().as_secs_f32() * 1000.0;
             state.serialize_field("head_delay_ms", &(head_delay_ms))?;
@@ -1351,6 +1888,78 @@ mod tests {
         assert!(!x.has_block_data(&(head_block.number() + 1000)));
     }
 
+    #[test]
+    fn test_is_full() {
+        // soft_limit is requests per second. it has nothing to do with how many requests are in flight
+        let x = Web3Rpc {
+            name: "name".to_string(),
+            soft_limit: 2,
+            ..Default::default()
+        };
+
+        x.active_requests.store(2, atomic::Ordering::Release);
+
+        // without max_concurrent_requests, there is nothing to prioritize
+        assert!(!x.is_full(RequestPriority::Free));
+        assert!(!x.is_full(RequestPriority::Premium));
+
+        let x = Web3Rpc {
+            name: "name".to_string(),
+            soft_limit: 2,
+            concurrency_semaphore: Some(PrioritySemaphore::new(1)),
+            ..Default::default()
+        };
+
+        let _permit = x
+            .concurrency_semaphore
+            .as_ref()
+            .unwrap()
+            .try_acquire(RequestPriority::Free)
+            .unwrap();
+
+        assert!(x.is_full(RequestPriority::Free));
+        assert!(x.is_full(RequestPriority::Premium));
+        assert!(x.is_full(RequestPriority::Internal));
+    }
+
+    #[test]
+    fn test_hard_limit_until_at_least() {
+        let now = Instant::now();
+
+        let (hard_limit_until, _) = watch::channel(now);
+
+        let x = Web3Rpc {
+            name: "name".to_string(),
+            hard_limit_until: Some(hard_limit_until),
+            ..Default::default()
+        };
+
+        let later = now + Duration::from_secs(30);
+
+        x.hard_limit_until_at_least(later);
+
+        // a shorter wait doesn't replace a longer Retry-After
+        x.hard_limit_until_at_least(now + Duration::from_secs(1));
+
+        assert_eq!(*x.hard_limit_until.as_ref().unwrap().borrow(), later);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_ws_only_rpc_is_skipped() {
+        let x = Arc::new(Web3Rpc {
+            name: "name".to_string(),
+            ws_url: Some("ws://127.0.0.1:1".parse().unwrap()),
+            ..Default::default()
+        });
+
+        let authorization = Arc::new(Authorization::internal(None).unwrap());
+
+        assert!(matches!(
+            x.try_request_handle(&authorization, None).await,
+            Ok(OpenRequestResult::NotReady)
+        ));
+    }
+
     /*
     // TODO: think about how to bring the concept of a "lagged" node back
     #[test]
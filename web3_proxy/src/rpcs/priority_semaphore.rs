@@ -0,0 +1,184 @@
+//! Bound in-flight requests to an rpc, but let premium requests in ahead of free ones when it is full.
+//!
+//! While any premium or internal request is waiting, free requests can't take a permit. Free requests that are already waiting give their permit to the premium request and wait again.
+//! Free requests still get permits whenever no premium or internal requests are waiting.
+//! Internal requests (head block polling, health checks) are never deferred.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::timeout;
+
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum RequestPriority {
+    #[default]
+    Free,
+    Premium,
+    /// the proxy's own requests
+    Internal,
+}
+
+#[derive(Debug)]
+pub struct PrioritySemaphore {
+    semaphore: Arc<Semaphore>,
+    /// how many premium or internal requests are waiting for a permit
+    priority_waiting: AtomicUsize,
+}
+
+/// Counts a waiting premium or internal request until it gets a permit or gives up
+struct PriorityWaiting<'a>(&'a AtomicUsize);
+
+impl<'a> PriorityWaiting<'a> {
+    fn new(priority_waiting: &'a AtomicUsize) -> Self {
+        priority_waiting.fetch_add(1, Ordering::AcqRel);
+
+        Self(priority_waiting)
+    }
+}
+
+impl Drop for PriorityWaiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl PrioritySemaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(permits)),
+            priority_waiting: 0.into(),
+        }
+    }
+
+    /// Never waits. Free requests get NoPermits while a premium or internal request is waiting
+    pub fn try_acquire(
+        &self,
+        priority: RequestPriority,
+    ) -> Result<OwnedSemaphorePermit, TryAcquireError> {
+        if priority == RequestPriority::Free && self.priority_waiting.load(Ordering::Acquire) > 0 {
+            return Err(TryAcquireError::NoPermits);
+        }
+
+        self.semaphore.clone().try_acquire_owned()
+    }
+
+    /// Wait up to max_wait for a permit. Waiting premium and internal requests get permits before waiting free requests
+    pub async fn acquire(
+        &self,
+        priority: RequestPriority,
+        max_wait: Duration,
+    ) -> Result<OwnedSemaphorePermit, TryAcquireError> {
+        match self.try_acquire(priority) {
+            Err(TryAcquireError::NoPermits) => {}
+            x => return x,
+        }
+
+        let f = async {
+            match priority {
+                RequestPriority::Premium | RequestPriority::Internal => {
+                    let _waiting = PriorityWaiting::new(&self.priority_waiting);
+
+                    self.semaphore.clone().acquire_owned().await
+                }
+                RequestPriority::Free => loop {
+                    let permit = self.semaphore.clone().acquire_owned().await?;
+
+                    if self.priority_waiting.load(Ordering::Acquire) == 0 {
+                        break Ok(permit);
+                    }
+
+                    // hand the permit to the premium request and get back in line
+                    drop(permit);
+
+                    tokio::task::yield_now().await;
+                },
+            }
+        };
+
+        match timeout(max_wait, f).await {
+            Ok(Ok(x)) => Ok(x),
+            Ok(Err(_)) => Err(TryAcquireError::Closed),
+            Err(_) => Err(TryAcquireError::NoPermits),
+        }
+    }
+
+    /// True if try_acquire would give this priority NoPermits
+    pub fn is_full(&self, priority: RequestPriority) -> bool {
+        self.semaphore.available_permits() == 0
+            || (priority == RequestPriority::Free
+                && self.priority_waiting.load(Ordering::Acquire) > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_premium_first_under_contention() {
+        let semaphore = Arc::new(PrioritySemaphore::new(1));
+
+        let held = semaphore.try_acquire(RequestPriority::Free).unwrap();
+
+        // a free request starts waiting first
+        let free = {
+            let semaphore = semaphore.clone();
+
+            tokio::spawn(async move {
+                semaphore
+                    .acquire(RequestPriority::Free, Duration::from_secs(10))
+                    .await
+            })
+        };
+
+        tokio::task::yield_now().await;
+
+        let premium = {
+            let semaphore = semaphore.clone();
+
+            tokio::spawn(async move {
+                semaphore
+                    .acquire(RequestPriority::Premium, Duration::from_secs(10))
+                    .await
+            })
+        };
+
+        // let the premium request get into the queue
+        tokio::task::yield_now().await;
+
+        assert_eq!(
+            semaphore.try_acquire(RequestPriority::Free).unwrap_err(),
+            TryAcquireError::NoPermits
+        );
+
+        // the released permit goes to the premium request, even though the free request was waiting longer
+        drop(held);
+
+        let premium_permit = premium.await.unwrap().unwrap();
+
+        assert!(!free.is_finished());
+
+        // free requests are still served once no premium requests are waiting
+        drop(premium_permit);
+
+        assert!(free.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_premium_gives_up() {
+        let semaphore = PrioritySemaphore::new(1);
+
+        let _held = semaphore.try_acquire(RequestPriority::Free).unwrap();
+
+        assert_eq!(
+            semaphore
+                .acquire(RequestPriority::Premium, Duration::from_millis(10))
+                .await
+                .unwrap_err(),
+            TryAcquireError::NoPermits
+        );
+
+        // a premium request that gave up doesn't keep blocking free requests
+        assert_eq!(semaphore.priority_waiting.load(Ordering::Acquire), 0);
+    }
+}
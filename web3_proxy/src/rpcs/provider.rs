@@ -1,5 +1,19 @@
-use ethers::providers::{Authorization, ConnectionDetails};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use ethers::providers::{
+    Authorization, ConnectionDetails, HttpClientError, JsonRpcError, ProviderError, RpcError,
+};
+use hashbrown::HashMap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::value::RawValue;
+use std::error::Error;
+use std::fmt;
 use std::time::Duration;
+use ulid::Ulid;
 use url::Url;
 
 // TODO: our own structs for these that handle streaming large responses
@@ -24,6 +38,27 @@ pub fn extract_auth(url: &mut Url) -> Option<Authorization> {
     }
 }
 
+/// Headers for a backend's dedicated http client. They are sent with every request.
+/// The values are marked sensitive because they are often api keys. Errors only include the header's name
+pub fn extra_headers(headers: &HashMap<String, String>) -> anyhow::Result<HeaderMap> {
+    let mut header_map = HeaderMap::with_capacity(headers.len());
+
+    for (name, value) in headers.iter() {
+        let header_name = name
+            .parse::<HeaderName>()
+            .with_context(|| format!("invalid extra_headers name: {}", name))?;
+
+        let mut header_value = HeaderValue::from_str(value)
+            .with_context(|| format!("invalid extra_headers value for {}", name))?;
+
+        header_value.set_sensitive(true);
+
+        header_map.insert(header_name, header_value);
+    }
+
+    Ok(header_map)
+}
+
 /// Note, if the http url has an authority the http_client param is ignored and a dedicated http_client will be used
 /// TODO: take a reqwest::Client or a reqwest::ClientBuilder. that way we can do things like set compression even when auth is set
 pub fn connect_http(
@@ -56,6 +91,234 @@ pub fn connect_http(
     Ok(provider)
 }
 
+/// ethers' Http provider can only set headers when its client is built, and it hides the status and headers of responses.
+/// This sends requests over http with our own client.
+/// If `header` is set, the proxy's request id is sent in it so that backend logs can be matched to proxy logs.
+/// Http 429 responses become a `TooManyRequests` error that keeps the Retry-After header.
+/// Error responses with a null or string "id" are kept. ethers fails to deserialize those and the error's code and message would be lost.
+#[derive(Clone, Debug)]
+pub struct RequestIdProvider {
+    auth: Option<Authorization>,
+    client: reqwest::Client,
+    header: Option<HeaderName>,
+    url: Url,
+}
+
+/// A backend answered with http 429 Too Many Requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TooManyRequests {
+    /// How long the backend asked us to wait. None if it didn't send a valid Retry-After header
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(x) => write!(f, "429 Too Many Requests. retry after {:?}", x),
+            None => write!(f, "429 Too Many Requests"),
+        }
+    }
+}
+
+impl Error for TooManyRequests {}
+
+impl TooManyRequests {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| parse_retry_after(x, Utc::now()));
+
+        Self { retry_after }
+    }
+
+    /// ethers boxes transport errors as a `dyn RpcError`, which can't be downcast. The typed error is kept as its source
+    pub fn from_provider_error(err: &ProviderError) -> Option<&Self> {
+        match err {
+            ProviderError::JsonRpcClientError(err) => err.source()?.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps `TooManyRequests` so that it can be sent through ethers' error types
+#[derive(Debug)]
+struct TooManyRequestsError(TooManyRequests);
+
+impl fmt::Display for TooManyRequestsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for TooManyRequestsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl RpcError for TooManyRequestsError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        None
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        None
+    }
+}
+
+impl From<TooManyRequests> for ProviderError {
+    fn from(value: TooManyRequests) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(TooManyRequestsError(value)))
+    }
+}
+
+/// A request to a backend failed before we got a response.
+/// ethers' `HttpClientError` is transparent, so its reqwest error can't be found through `source`. This keeps it there
+#[derive(Debug)]
+pub struct HttpTransportError(pub reqwest::Error);
+
+impl fmt::Display for HttpTransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for HttpTransportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl RpcError for HttpTransportError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        None
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        None
+    }
+}
+
+impl HttpTransportError {
+    pub fn from_provider_error(err: &ProviderError) -> Option<&reqwest::Error> {
+        match err {
+            ProviderError::JsonRpcClientError(err) => err.source()?.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpTransportError> for ProviderError {
+    fn from(value: HttpTransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(value))
+    }
+}
+
+/// Retry-After is either a number of seconds or an http date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+
+    // a date in the past means we can retry now
+    (retry_at.with_timezone(&Utc) - now)
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+#[derive(Deserialize)]
+struct RequestIdResponse {
+    #[serde(default)]
+    result: Option<Box<RawValue>>,
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+impl RequestIdProvider {
+    pub fn new(
+        header: Option<HeaderName>,
+        mut url: Url,
+        http_client: Option<reqwest::Client>,
+    ) -> Self {
+        let auth = extract_auth(&mut url);
+
+        Self {
+            auth,
+            client: http_client.unwrap_or_default(),
+            header,
+            url,
+        }
+    }
+
+    pub async fn request<P: Serialize + ?Sized, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &P,
+        request_ulid: Option<Ulid>,
+    ) -> Result<R, ProviderError> {
+        let mut headers = HeaderMap::with_capacity(2);
+
+        if let (Some(header), Some(request_ulid)) = (self.header.as_ref(), request_ulid) {
+            // a ulid is always a valid header value
+            headers.insert(
+                header.clone(),
+                HeaderValue::from_str(&request_ulid.to_string()).unwrap(),
+            );
+        }
+
+        if let Some(auth) = self.auth.as_ref() {
+            if let Ok(auth) = HeaderValue::from_str(&auth.to_string()) {
+                headers.insert(AUTHORIZATION, auth);
+            }
+        }
+
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .headers(headers)
+            .json(&body)
+            .send()
+            .await
+            .map_err(HttpTransportError)?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(TooManyRequests::from_headers(response.headers()).into());
+        }
+
+        let text = response.text().await.map_err(HttpTransportError)?;
+
+        let response: RequestIdResponse =
+            serde_json::from_str(&text).map_err(|err| HttpClientError::SerdeJson {
+                err,
+                text: text.clone(),
+            })?;
+
+        if let Some(err) = response.error {
+            return Err(HttpClientError::JsonRpcError(err).into());
+        }
+
+        let result = response.result.as_ref().map(|x| x.get()).unwrap_or("null");
+
+        let result =
+            serde_json::from_str(result).map_err(|err| HttpClientError::SerdeJson { err, text })?;
+
+        Ok(result)
+    }
+}
+
 pub async fn connect_ws(mut url: Url, reconnects: usize) -> anyhow::Result<EthersWsProvider> {
     let auth = extract_auth(&mut url);
 
@@ -78,3 +341,216 @@ pub async fn connect_ws(mut url: Url, reconnects: usize) -> anyhow::Result<Ether
 
     Ok(provider)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::Web3ProxyError;
+    use crate::response_cache::JsonRpcResponseEnum;
+    use crate::test_utils::mock_server;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use ethers::types::U64;
+    use http::StatusCode;
+
+    #[tokio::test]
+    async fn test_request_id_header() {
+        let (header_sender, header_receiver) = flume::bounded(1);
+
+        let router = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap| {
+                let header_sender = header_sender.clone();
+
+                async move {
+                    let request_id = headers
+                        .get("x-request-id")
+                        .map(|x| x.to_str().unwrap().to_string());
+
+                    header_sender.send(request_id).unwrap();
+
+                    Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+                }
+            }),
+        );
+
+        let url = mock_server(router).parse().unwrap();
+
+        let provider =
+            RequestIdProvider::new(Some(HeaderName::from_static("x-request-id")), url, None);
+
+        let request_ulid = Ulid::new();
+
+        let block_number: U64 = provider
+            .request("eth_blockNumber", &json!([]), Some(request_ulid))
+            .await
+            .unwrap();
+
+        assert_eq!(block_number, U64::one());
+
+        assert_eq!(
+            header_receiver.recv_async().await.unwrap(),
+            Some(request_ulid.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers() {
+        let (header_sender, header_receiver) = flume::bounded(2);
+
+        let router = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap| {
+                let header_sender = header_sender.clone();
+
+                async move {
+                    let api_key = headers
+                        .get("x-api-key")
+                        .map(|x| x.to_str().unwrap().to_string());
+
+                    header_sender.send(api_key).unwrap();
+
+                    Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+                }
+            }),
+        );
+
+        let url: Url = mock_server(router).parse().unwrap();
+
+        let headers = extra_headers(&HashMap::from_iter([(
+            "X-Api-Key".to_string(),
+            "secret".to_string(),
+        )]))
+        .unwrap();
+
+        let http_client = reqwest::ClientBuilder::new()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+
+        // both of the providers that share the client send the header
+        let provider = connect_http(
+            url.clone(),
+            Some(http_client.clone()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        let block_number: U64 = provider.request("eth_blockNumber", ()).await.unwrap();
+
+        assert_eq!(block_number, U64::one());
+
+        let provider = RequestIdProvider::new(None, url, Some(http_client));
+
+        let block_number: U64 = provider
+            .request("eth_blockNumber", &json!([]), None)
+            .await
+            .unwrap();
+
+        assert_eq!(block_number, U64::one());
+
+        for _ in 0..2 {
+            assert_eq!(
+                header_receiver.recv_async().await.unwrap(),
+                Some("secret".to_string())
+            );
+        }
+
+        // invalid headers are an error that doesn't include the value
+        let err = extra_headers(&HashMap::from_iter([(
+            "X-Api-Key".to_string(),
+            "secret\n".to_string(),
+        )]))
+        .unwrap_err();
+
+        assert!(!format!("{:?}", err).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests() {
+        let router = Router::new().route(
+            "/",
+            post(|| async {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, "30")],
+                    "slow down",
+                )
+            }),
+        );
+
+        let url = mock_server(router).parse().unwrap();
+
+        let provider = RequestIdProvider::new(None, url, None);
+
+        let err = provider
+            .request::<_, U64>("eth_blockNumber", &json!([]), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            TooManyRequests::from_provider_error(&err),
+            Some(&TooManyRequests {
+                retry_after: Some(Duration::from_secs(30))
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_is_not_rewritten() {
+        // ethers can't parse a null id, so this used to become a generic 500
+        let router = Router::new().route(
+            "/",
+            post(|| async {
+                Json(json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32000, "message": "execution reverted", "data": "0x"},
+                }))
+            }),
+        );
+
+        let url = mock_server(router).parse().unwrap();
+
+        let provider = RequestIdProvider::new(None, url, None);
+
+        let err = provider
+            .request::<_, Box<RawValue>>("eth_call", &json!([{}, "latest"]), None)
+            .await
+            .unwrap_err();
+
+        let (status_code, response) = Web3ProxyError::from(err).as_response_parts::<()>(false);
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        match response {
+            JsonRpcResponseEnum::RpcError { error_data, .. } => {
+                assert_eq!(error_data.code, -32000);
+                assert_eq!(error_data.message, "execution reverted");
+                assert_eq!(error_data.data, Some(json!("0x")));
+            }
+            x => panic!("expected an error response. got {:?}", x),
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use super::one::Web3Rpc;
-use crate::errors::{Web3ProxyErrorContext, Web3ProxyResult};
+use super::provider::{HttpTransportError, TooManyRequests};
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
 use crate::frontend::authorization::{Authorization, AuthorizationType};
 use crate::jsonrpc::{JsonRpcParams, JsonRpcResultData};
-use anyhow::Context;
 use chrono::Utc;
 use derive_more::From;
 use entities::revert_log;
@@ -12,10 +12,16 @@ use ethers::types::{Address, Bytes};
 use migration::sea_orm::{self, ActiveEnum, ActiveModelTrait};
 use nanorand::Rng;
 use serde_json::json;
-use std::sync::atomic;
+use std::sync::atomic::{self, AtomicU64};
 use std::sync::Arc;
+use tokio::sync::OwnedSemaphorePermit;
 use tokio::time::{Duration, Instant};
 use tracing::{debug, error, info, trace, warn, Level};
+use ulid::Ulid;
+
+/// How long a request waits for a websocket that disconnected after its rpc was picked.
+/// Rpcs are not picked while their websocket is disconnected, so this only covers that race
+const WS_RECONNECT_MAX_WAIT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, From)]
 pub enum OpenRequestResult {
@@ -32,7 +38,11 @@ pub enum OpenRequestResult {
 pub struct OpenRequestHandle {
     authorization: Arc<Authorization>,
     error_handler: RequestErrorHandler,
+    /// sent to rpcs that have a request_id_header configured
+    request_ulid: Option<Ulid>,
     rpc: Arc<Web3Rpc>,
+    /// released when the handle is dropped
+    _concurrency_permit: Option<OwnedSemaphorePermit>,
 }
 
 /// Depending on the context, RPC errors require different handling.
@@ -53,13 +63,113 @@ pub enum RequestErrorHandler {
     Save,
 }
 
-// TODO: second param could be skipped since we don't need it here
-#[derive(serde::Deserialize, serde::Serialize)]
-struct EthCallParams((EthCallFirstParams, Option<serde_json::Value>));
+/// How long to stop sending requests to an rpc that rate limited us without saying for how long
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Retry-After headers longer than this are ignored
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Why a request to an rpc failed.
+/// Counted per rpc so that operators can tell rpcs that rate limit us from ones that time out or return bad data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamErrorKind {
+    /// the request took too long
+    Timeout,
+    /// the rpc told us to slow down
+    RateLimitedUpstream,
+    /// "execution reverted". the rpc is fine. the call failed
+    Revert,
+    /// any other jsonrpc error, or a response that we couldn't parse
+    RpcError,
+    /// the request never got a response
+    ConnectionError,
+}
+
+impl UpstreamErrorKind {
+    pub const ALL: [Self; 5] = [
+        Self::Timeout,
+        Self::RateLimitedUpstream,
+        Self::Revert,
+        Self::RpcError,
+        Self::ConnectionError,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::RateLimitedUpstream => "rate_limited_upstream",
+            Self::Revert => "revert",
+            Self::RpcError => "rpc_error",
+            Self::ConnectionError => "connection_error",
+        }
+    }
+
+    pub fn from_provider_error(err: &ProviderError) -> Self {
+        if TooManyRequests::from_provider_error(err).is_some() {
+            return Self::RateLimitedUpstream;
+        }
+
+        if let Some(err) = HttpTransportError::from_provider_error(err) {
+            return Self::from_reqwest_error(err);
+        }
+
+        match err {
+            ProviderError::JsonRpcClientError(err) => {
+                // JsonRpc and Application errors get rolled into the JsonRpcClientError
+                if let Some(err) = err.as_error_response() {
+                    if err.message.starts_with("execution reverted") {
+                        Self::Revert
+                    } else if err.message.contains("limit") || err.message.contains("request") {
+                        Self::RateLimitedUpstream
+                    } else {
+                        Self::RpcError
+                    }
+                } else if err.as_serde_error().is_some() {
+                    // the rpc responded with something that wasn't jsonrpc
+                    Self::RpcError
+                } else {
+                    // websocket transport errors. their messages include the url, so they aren't checked
+                    Self::ConnectionError
+                }
+            }
+            ProviderError::HTTPError(err) => Self::from_reqwest_error(err),
+            // we only make CustomErrors when no provider is connected
+            ProviderError::CustomError(_) => Self::ConnectionError,
+            _ => Self::RpcError,
+        }
+    }
+
+    /// Uses the error's kind and status. The message includes the backend's url, so it isn't checked
+    fn from_reqwest_error(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
+            Self::RateLimitedUpstream
+        } else {
+            Self::ConnectionError
+        }
+    }
+}
+
+/// Count errors from one rpc by their kind
+#[derive(Debug, Default)]
+pub struct UpstreamErrorCounts([AtomicU64; UpstreamErrorKind::ALL.len()]);
+
+impl UpstreamErrorCounts {
+    pub fn incr(&self, kind: UpstreamErrorKind) {
+        self.0[kind as usize].fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub fn get(&self, kind: UpstreamErrorKind) -> u64 {
+        self.0[kind as usize].load(atomic::Ordering::Relaxed)
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 struct EthCallFirstParams {
     to: Option<Address>,
+    /// newer clients send "input" instead of "data"
+    #[serde(alias = "input")]
     data: Option<Bytes>,
 }
 
@@ -75,8 +185,39 @@ impl From<Level> for RequestErrorHandler {
     }
 }
 
+/// Parse a reverted request so that it can be saved.
+/// Weird but valid params are skipped instead of failing the request.
+fn revert_to_save<P: JsonRpcParams>(
+    method: &str,
+    params: &P,
+) -> Option<(Method, EthCallFirstParams)> {
+    let method = match Method::try_from_value(&method.to_string()) {
+        Ok(x) => x,
+        Err(err) => {
+            debug!(%method, ?err, "unable to save reverts for this method");
+            return None;
+        }
+    };
+
+    // only the call is saved. block numbers and state overrides after it are ignored
+    let params = json!(params).get(0).cloned().unwrap_or_default();
+
+    match serde_json::from_value::<EthCallFirstParams>(params) {
+        Ok(x) => Some((method, x)),
+        Err(err) => {
+            debug!(
+                ?method,
+                ?err,
+                "failed parsing eth_call params. unable to save revert"
+            );
+            None
+        }
+    }
+}
+
 impl Authorization {
     /// Save a RPC call that return "execution reverted" to the database.
+    /// Revert logs are best effort. If the database is down, the revert is skipped and logged
     async fn save_revert(
         self: Arc<Self>,
         method: Method,
@@ -90,7 +231,10 @@ impl Authorization {
             }
         };
 
-        let db_conn = self.db_conn.as_ref().context("no database connection")?;
+        let Some(db_conn) = self.db_conn.as_ref() else {
+            trace!("cannot save revert without a database");
+            return Ok(());
+        };
 
         // TODO: should the database set the timestamp?
         // we intentionally use "now" and not the time the request started
@@ -99,7 +243,7 @@ impl Authorization {
 
         let to = params.to.unwrap_or_else(Address::zero).as_bytes().to_vec();
 
-        let call_data = params.data.map(|x| x.to_string());
+        let call_data = params.data.map(|x| x.to_vec());
 
         let rl = revert_log::ActiveModel {
             rpc_key_id: sea_orm::Set(rpc_key_id),
@@ -110,10 +254,19 @@ impl Authorization {
             ..Default::default()
         };
 
-        let rl = rl
-            .save(db_conn)
-            .await
-            .web3_context("Failed saving new revert log")?;
+        let rl = match rl.save(db_conn).await {
+            Ok(x) => x,
+            Err(err) => {
+                let err = Web3ProxyError::from(err);
+
+                if err.is_database_unavailable() {
+                    warn!(?err, "database unavailable. skipped saving revert log");
+                    return Ok(());
+                }
+
+                return Err(err).web3_context("Failed saving new revert log");
+            }
+        };
 
         // TODO: what log level and format?
         trace!(revert_log=?rl);
@@ -136,6 +289,7 @@ impl OpenRequestHandle {
         authorization: Arc<Authorization>,
         rpc: Arc<Web3Rpc>,
         error_handler: Option<RequestErrorHandler>,
+        concurrency_permit: Option<OwnedSemaphorePermit>,
     ) -> Self {
         // TODO: take request_id as an argument?
         // TODO: attach a unique id to this? customer requests have one, but not internal queries
@@ -148,10 +302,18 @@ impl OpenRequestHandle {
         Self {
             authorization,
             error_handler,
+            request_ulid: None,
             rpc,
+            _concurrency_permit: concurrency_permit,
         }
     }
 
+    /// tag the request so that the backend's logs can be matched to ours
+    pub fn with_request_ulid(mut self, request_ulid: Option<Ulid>) -> Self {
+        self.request_ulid = request_ulid;
+        self
+    }
+
     pub fn connection_name(&self) -> String {
         self.rpc.name.clone()
     }
@@ -194,14 +356,23 @@ impl OpenRequestHandle {
 
         // TODO: replace ethers-rs providers with our own that supports streaming the responses
         // TODO: replace ethers-rs providers with our own that handles "id" being null
-        let response: Result<R, _> = if let Some(ref p) = self.rpc.http_provider {
-            p.request(method, params).await
+        let response: Result<R, _> = if let Some(p) = self.rpc.request_id_provider.as_ref() {
+            p.request(method, params, self.request_ulid).await
         } else if let Some(p) = self.rpc.ws_provider.load().as_ref() {
             p.request(method, params).await
+        } else if self.rpc.wait_for_ws_provider(WS_RECONNECT_MAX_WAIT).await
+            && let Some(p) = self.rpc.ws_provider.load().as_ref()
+        {
+            // the websocket reconnected while we waited
+            p.request(method, params).await
         } else {
-            return Err(ProviderError::CustomError(
-                "no provider configured!".to_string(),
-            ));
+            self.rpc.record_response(false);
+
+            // don't wait forever. the caller can try another rpc
+            return Err(ProviderError::CustomError(format!(
+                "no provider connected for {}",
+                self.rpc
+            )));
         };
 
         // we do NOT want to measure errors, so we intentionally do not record this latency now.
@@ -226,7 +397,14 @@ impl OpenRequestHandle {
                     // trace!(%method, "skipping save on revert");
                     RequestErrorHandler::TraceLevel
                 } else if self.authorization.db_conn.is_some() {
-                    let log_revert_chance = self.authorization.checks.log_revert_chance;
+                    // operators can sample some methods less (or more) than others
+                    let log_revert_chance = self
+                        .authorization
+                        .checks
+                        .log_revert_chances
+                        .get(method)
+                        .copied()
+                        .unwrap_or(self.authorization.checks.log_revert_chance);
 
                     if log_revert_chance == 0 {
                         // trace!(%method, "no chance. skipping save on revert");
@@ -251,50 +429,42 @@ impl OpenRequestHandle {
                 self.error_handler
             };
 
-            // TODO: simple enum -> string derive?
             // TODO: if ProviderError::UnsupportedRpc, we should retry on another server
-            #[derive(Debug)]
-            enum ResponseTypes {
-                Revert,
-                RateLimit,
-                Error,
-            }
+            let response_type = UpstreamErrorKind::from_provider_error(err);
 
-            // check for "execution reverted" here
-            // TODO: move this info a function on ResponseErrorType
-            let response_type = if let ProviderError::JsonRpcClientError(err) = err {
-                // JsonRpc and Application errors get rolled into the JsonRpcClientError
-                let msg = err.as_error_response().map(|x| x.message.clone());
-
-                if let Some(msg) = msg {
-                    trace!(%msg, "jsonrpc error message");
-
-                    if msg.starts_with("execution reverted") {
-                        trace!("revert from {}", self.rpc);
-                        ResponseTypes::Revert
-                    } else if msg.contains("limit") || msg.contains("request") {
-                        // TODO: too verbose
-                        if self.rpc.backup {
-                            trace!("rate limit from {}", self.rpc);
-                        } else {
-                            warn!("rate limit from {}", self.rpc);
-                        }
-                        ResponseTypes::RateLimit
+            self.rpc.upstream_errors.incr(response_type);
+
+            match response_type {
+                UpstreamErrorKind::RateLimitedUpstream => {
+                    // TODO: too verbose
+                    if self.rpc.backup {
+                        trace!("rate limit from {}", self.rpc);
                     } else {
-                        ResponseTypes::Error
+                        warn!("rate limit from {}", self.rpc);
                     }
-                } else {
-                    ResponseTypes::Error
                 }
-            } else {
-                ResponseTypes::Error
-            };
+                UpstreamErrorKind::Revert => trace!("revert from {}", self.rpc),
+                _ => {}
+            }
+
+            match response_type {
+                // rate limits are handled by hard_limit_until below
+                UpstreamErrorKind::RateLimitedUpstream => {}
+                // a jsonrpc error means the rpc is up. it's probably a bad request
+                UpstreamErrorKind::Revert => self.rpc.record_response(true),
+                _ => {
+                    let has_error_response = match err {
+                        ProviderError::JsonRpcClientError(err) => err.as_error_response().is_some(),
+                        _ => false,
+                    };
+
+                    self.rpc.record_response(has_error_response);
+                }
+            }
 
-            if matches!(response_type, ResponseTypes::RateLimit) {
-                if let Some(hard_limit_until) = self.rpc.hard_limit_until.as_ref() {
-                    // TODO: how long should we actually wait? different providers have different times
+            if matches!(response_type, UpstreamErrorKind::RateLimitedUpstream) {
+                if self.rpc.hard_limit_until.is_some() {
                     // TODO: if rate_limit_period_seconds is set, use that
-                    // TODO: check response headers for rate limits too
                     // TODO: warn if production, debug if backup
                     if self.rpc.backup {
                         debug!("unexpected rate limit on {}!", self.rpc);
@@ -302,11 +472,17 @@ impl OpenRequestHandle {
                         warn!("unexpected rate limit on {}!", self.rpc);
                     }
 
-                    let retry_at = Instant::now() + Duration::from_secs(1);
+                    // http 429s can say how long to wait. a bad header shouldn't take the rpc out of rotation for too long
+                    let retry_after = TooManyRequests::from_provider_error(err)
+                        .and_then(|x| x.retry_after)
+                        .unwrap_or(DEFAULT_RETRY_AFTER)
+                        .min(MAX_RETRY_AFTER);
+
+                    let retry_at = Instant::now() + retry_after;
 
                     trace!("retry {} at: {:?}", self.rpc, retry_at);
 
-                    hard_limit_until.send_replace(retry_at);
+                    self.rpc.hard_limit_until_at_least(retry_at);
                 }
             }
 
@@ -315,7 +491,7 @@ impl OpenRequestHandle {
             match error_handler {
                 RequestErrorHandler::DebugLevel => {
                     // TODO: think about this revert check more. sometimes we might want reverts logged so this needs a flag
-                    if matches!(response_type, ResponseTypes::Revert) {
+                    if matches!(response_type, UpstreamErrorKind::Revert) {
                         trace!(
                             rpc=%self.rpc,
                             %method,
@@ -380,35 +556,173 @@ impl OpenRequestHandle {
                         "bad response",
                     );
 
-                    // TODO: do not unwrap! (doesn't matter much since we check method as a string above)
-                    let method: Method = Method::try_from_value(&method.to_string()).unwrap();
-
-                    // TODO: i don't think this prsing is correct
-                    match serde_json::from_value::<EthCallParams>(json!(params)) {
-                        Ok(params) => {
-                            // spawn saving to the database so we don't slow down the request
-                            let f = self.authorization.clone().save_revert(method, params.0 .0);
-
-                            tokio::spawn(f);
-                        }
-                        Err(err) => {
-                            warn!(
-                                %method,
-                                ?params,
-                                ?err,
-                                "failed parsing eth_call params. unable to save revert",
-                            );
-                        }
+                    if let Some((method, params)) = revert_to_save(method, params) {
+                        // spawn saving to the database so we don't slow down the request
+                        let f = self.authorization.clone().save_revert(method, params);
+
+                        tokio::spawn(async move {
+                            if let Err(err) = f.await {
+                                error!(?err, "unable to save revert");
+                            }
+                        });
                     }
                 }
             }
+        } else {
+            self.rpc.record_response(true);
         }
 
         tokio::spawn(async move {
             self.rpc.peak_latency.as_ref().unwrap().report(latency);
             self.rpc.median_latency.as_ref().unwrap().record(latency);
+            self.rpc.latency_histogram.record(latency);
         });
 
         response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpcs::provider::RequestIdProvider;
+    use crate::test_utils::mock_server;
+    use axum::routing::post;
+    use axum::Router;
+    use ethers::providers::{HttpClientError, JsonRpcError};
+    use ethers::types::U64;
+
+    #[test]
+    fn test_revert_to_save() {
+        let (method, params) = revert_to_save(
+            "eth_call",
+            &json!([{"to": "0x0000000000000000000000000000000000000001", "data": "0x1234"}, "latest"]),
+        )
+        .unwrap();
+
+        assert_eq!(method, Method::EthCall);
+        assert_eq!(params.to, Some(Address::from_low_u64_be(1)));
+
+        // state overrides are a valid third param
+        assert!(revert_to_save(
+            "eth_call",
+            &json!([{"to": "0x0000000000000000000000000000000000000001"}, "latest", {}]),
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn test_upstream_error_kind() {
+        let jsonrpc_error = |code, message: &str| {
+            ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(
+                JsonRpcError {
+                    code,
+                    message: message.to_string(),
+                    data: None,
+                },
+            )))
+        };
+
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&jsonrpc_error(3, "execution reverted: nope")),
+            UpstreamErrorKind::Revert
+        );
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&jsonrpc_error(-32005, "daily limit reached")),
+            UpstreamErrorKind::RateLimitedUpstream
+        );
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&jsonrpc_error(-32000, "header not found")),
+            UpstreamErrorKind::RpcError
+        );
+
+        let bad_data = ProviderError::JsonRpcClientError(Box::new(HttpClientError::SerdeJson {
+            err: serde_json::from_str::<serde_json::Value>("<html>").unwrap_err(),
+            text: "<html>".to_string(),
+        }));
+
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&bad_data),
+            UpstreamErrorKind::RpcError
+        );
+
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&ProviderError::CustomError(
+                "no provider connected for llamanodes".to_string()
+            )),
+            UpstreamErrorKind::ConnectionError
+        );
+        // the message isn't checked. it might include a url with "429" or "timeout" in it
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&ProviderError::CustomError(
+                "no provider connected for https://rpc.example/timeout/429".to_string()
+            )),
+            UpstreamErrorKind::ConnectionError
+        );
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&ProviderError::from(TooManyRequests {
+                retry_after: None
+            })),
+            UpstreamErrorKind::RateLimitedUpstream
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_transport_error_kind() {
+        // nothing is listening on this port
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let url = format!("http://127.0.0.1:{}/key-429-timeout", port)
+            .parse()
+            .unwrap();
+
+        let err = RequestIdProvider::new(None, url, None)
+            .request::<_, U64>("eth_blockNumber", &json!([]), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&err),
+            UpstreamErrorKind::ConnectionError
+        );
+
+        let router = Router::new().route(
+            "/",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                "too late"
+            }),
+        );
+
+        let url = mock_server(router).parse().unwrap();
+
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let err = RequestIdProvider::new(None, url, Some(http_client))
+            .request::<_, U64>("eth_blockNumber", &json!([]), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            UpstreamErrorKind::from_provider_error(&err),
+            UpstreamErrorKind::Timeout
+        );
+    }
+
+    #[test]
+    fn test_malformed_revert_params() {
+        // none of these should panic. they just aren't saved
+        assert!(revert_to_save("eth_call", &json!([{"to": 1}])).is_none());
+        assert!(revert_to_save("eth_call", &json!([{"data": "not hex"}])).is_none());
+        assert!(revert_to_save("eth_call", &json!("0x1234")).is_none());
+        assert!(revert_to_save("eth_call", &json!([])).is_none());
+        assert!(revert_to_save("eth_getBalance", &json!([{}])).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+//! Monthly request budgets for rpcs from paid providers.
+//!
+//! Requests are counted in redis so that every proxy shares the same totals.
+//! A request is counted before it is sent, so the budget is a hard cap. Requests over the budget are never sent.
+//! Once a proxy sees that the budget is used up, it stops asking redis until the next month starts.
+//! The proxy's own requests are counted too. The provider bills for them the same as for user requests.
+use crate::errors::Web3ProxyResult;
+use chrono::{DateTime, Datelike, Utc};
+use redis_rate_limiter::redis;
+use redis_rate_limiter::RedisPool;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// keys expire a little after their month ends
+const MONTH_TTL: usize = 32 * 86_400;
+
+pub struct RequestBudget {
+    key_prefix: String,
+    monthly_limit: u64,
+    redis_pool: RedisPool,
+    /// the month (like 202310) that the budget was used up in. 0 if it hasn't been used up
+    exhausted_month: AtomicU32,
+    /// the requests left this month as of the last request
+    remaining: AtomicU64,
+}
+
+/// like 202310 for October 2023
+fn month(now: DateTime<Utc>) -> u32 {
+    now.year() as u32 * 100 + now.month()
+}
+
+impl RequestBudget {
+    pub fn new(chain_id: u64, rpc_name: &str, monthly_limit: u64, redis_pool: RedisPool) -> Self {
+        Self {
+            key_prefix: format!("request_budget:{}:{}", chain_id, rpc_name),
+            monthly_limit,
+            redis_pool,
+            exhausted_month: 0.into(),
+            remaining: monthly_limit.into(),
+        }
+    }
+
+    /// true if the budget for this month is known to be used up. This doesn't ask redis
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted_month.load(Ordering::Acquire) == month(Utc::now())
+    }
+
+    /// the requests left this month as of the last request
+    pub fn remaining(&self) -> u64 {
+        if self.is_exhausted() {
+            0
+        } else {
+            self.remaining.load(Ordering::Acquire)
+        }
+    }
+
+    /// Count a request against this month's budget. false if the budget is used up and the request should not be sent.
+    pub async fn try_spend(&self) -> Web3ProxyResult<bool> {
+        let now = Utc::now();
+
+        let month = month(now);
+
+        if self.exhausted_month.load(Ordering::Acquire) == month {
+            return Ok(false);
+        }
+
+        let key = format!("{}:{}", self.key_prefix, month);
+
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        let (spent,): (u64,) = redis::pipe()
+            .incr(&key, 1)
+            .expire(&key, MONTH_TTL)
+            .ignore()
+            .query_async(&mut *redis_conn)
+            .await?;
+
+        let remaining = self.monthly_limit.saturating_sub(spent);
+
+        self.remaining.store(remaining, Ordering::Release);
+
+        if spent > self.monthly_limit {
+            self.exhausted_month.store(month, Ordering::Release);
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::month;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_month() {
+        assert_eq!(
+            month(Utc.with_ymd_and_hms(2023, 10, 31, 23, 59, 59).unwrap()),
+            202310
+        );
+        assert_eq!(
+            month(Utc.with_ymd_and_hms(2023, 11, 1, 0, 0, 0).unwrap()),
+            202311
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Retry reads after every rpc failed with a transport error.
+//!
+//! A request that can't reach a node always goes to the next rpc. That doesn't count as a retry.
+//! Once every rpc has failed, reads try them all again with a backoff, up to a limit.
+//! Only errors that never reached a node are retried. Reverts and other jsonrpc errors are the node's answer and are returned as is.
+//! Methods that change state on the node aren't reads. Sending a transaction twice to the same rpc could spend twice.
+use nanorand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryPolicy {
+    /// how many more times to try every rpc after all of them failed. 0 disables retries
+    pub max_retries: usize,
+    /// the wait before the first retry. it doubles for every retry after that
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, backoff_ms: u64) -> Self {
+        Self {
+            max_retries,
+            backoff: Duration::from_millis(backoff_ms),
+        }
+    }
+
+    /// `retries` is how many times this request has already gone back through every rpc
+    pub fn should_retry(&self, method: &str, retries: usize) -> bool {
+        retries < self.max_retries && is_idempotent(method)
+    }
+
+    /// Exponential backoff with up to 50% jitter so that one bad rpc doesn't send a burst of retries to the next one
+    pub fn backoff(&self, retries: usize) -> Duration {
+        let backoff = self.backoff * 2u32.saturating_pow(retries.min(16) as u32);
+
+        let jitter = nanorand::tls_rng().generate_range(0..=backoff.as_millis() as u64 / 2);
+
+        backoff + Duration::from_millis(jitter)
+    }
+}
+
+/// False for methods that change state on the node
+pub fn is_idempotent(method: &str) -> bool {
+    !(method.starts_with("eth_send")
+        || method.starts_with("eth_submit")
+        || method.starts_with("personal_")
+        || matches!(
+            method,
+            "eth_newBlockFilter"
+                | "eth_newFilter"
+                | "eth_newPendingTransactionFilter"
+                | "eth_uninstallFilter"
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::new(2, 10);
+
+        assert!(policy.should_retry("eth_getBalance", 0));
+        assert!(policy.should_retry("eth_getBalance", 1));
+        assert!(!policy.should_retry("eth_getBalance", 2));
+
+        assert!(!policy.should_retry("eth_sendRawTransaction", 0));
+        assert!(!policy.should_retry("eth_newFilter", 0));
+
+        assert!(!RetryPolicy::default().should_retry("eth_getBalance", 0));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(3, 10);
+
+        for retries in 0..3 {
+            let min = Duration::from_millis(10 << retries);
+
+            let backoff = policy.backoff(retries);
+
+            assert!(backoff >= min);
+            assert!(backoff <= min + min / 2);
+        }
+    }
+}
@@ -5,7 +5,11 @@ use super::request::OpenRequestResult;
 use crate::errors::Web3ProxyResult;
 use crate::frontend::authorization::Authorization;
 use ethers::prelude::{ProviderError, Transaction, TxHash};
+use moka::future::{Cache, CacheBuilder};
+use redis_rate_limiter::redis::AsyncCommands;
+use redis_rate_limiter::RedisPool;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 use tracing::{debug, trace, Level};
 
@@ -17,6 +21,108 @@ pub enum TxStatus {
     Orphaned(Transaction),
 }
 
+impl TxStatus {
+    fn from_tx(tx: Transaction) -> Self {
+        match tx.block_hash {
+            // the transaction is already confirmed. no need to save in the pending_transactions map
+            Some(_) => Self::Confirmed(tx),
+            None => Self::Pending(tx),
+        }
+    }
+}
+
+/// Transactions that have already been sent to subscribers.
+/// Entries expire so that this can't grow unbounded.
+/// If a redis pool is given, transactions are also shared with other proxies so that only one of them has to query for each transaction.
+#[derive(Clone)]
+pub struct PendingTransactionCache {
+    chain_id: u64,
+    pub(crate) local: Cache<TxHash, TxStatus>,
+    redis_pool: Option<RedisPool>,
+    ttl: Duration,
+}
+
+impl PendingTransactionCache {
+    pub fn new(
+        chain_id: u64,
+        max_capacity: u64,
+        ttl: Duration,
+        redis_pool: Option<RedisPool>,
+    ) -> Self {
+        // all these are the same size, so no need for a weigher
+        let local = CacheBuilder::new(max_capacity)
+            .name("pending_transactions")
+            .time_to_live(ttl)
+            .build();
+
+        Self {
+            chain_id,
+            local,
+            redis_pool,
+            ttl,
+        }
+    }
+
+    fn redis_key(&self, tx_hash: &TxHash) -> String {
+        format!("pending_tx:{}:{:?}", self.chain_id, tx_hash)
+    }
+
+    pub fn contains(&self, tx_hash: &TxHash) -> bool {
+        self.local.contains_key(tx_hash)
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.local.entry_count()
+    }
+
+    /// check if another proxy already queried this transaction
+    async fn get_shared(&self, tx_hash: &TxHash) -> Option<TxStatus> {
+        let redis_pool = self.redis_pool.as_ref()?;
+
+        let mut redis_conn = match redis_pool.get().await {
+            Ok(x) => x,
+            Err(err) => {
+                trace!(?err, "no redis connection for pending transactions");
+                return None;
+            }
+        };
+
+        let tx: Option<String> = redis_conn
+            .get(self.redis_key(tx_hash))
+            .await
+            .map_err(|err| trace!(?err, "failed reading pending transaction from redis"))
+            .ok()?;
+
+        let tx = serde_json::from_str(&tx?).ok()?;
+
+        Some(TxStatus::from_tx(tx))
+    }
+
+    pub async fn insert(&self, tx_hash: TxHash, tx_status: TxStatus) {
+        if let (Some(redis_pool), TxStatus::Pending(tx)) = (self.redis_pool.as_ref(), &tx_status) {
+            // errors here aren't important. the other proxies will just query the transaction themselves
+            match (redis_pool.get().await, serde_json::to_string(tx)) {
+                (Ok(mut redis_conn), Ok(tx)) => {
+                    if let Err(err) = redis_conn
+                        .set_ex::<_, _, ()>(
+                            self.redis_key(&tx_hash),
+                            tx,
+                            self.ttl.as_secs() as usize,
+                        )
+                        .await
+                    {
+                        trace!(?err, "failed saving pending transaction to redis");
+                    }
+                }
+                (Err(err), _) => trace!(?err, "no redis connection for pending transactions"),
+                (_, Err(err)) => trace!(?err, "failed serializing pending transaction"),
+            }
+        }
+
+        self.local.insert(tx_hash, tx_status).await;
+    }
+}
+
 impl Web3Rpcs {
     async fn query_transaction_status(
         &self,
@@ -53,13 +159,7 @@ impl Web3Rpcs {
             }
         };
 
-        match &tx.block_hash {
-            Some(_block_hash) => {
-                // the transaction is already confirmed. no need to save in the pending_transactions map
-                Ok(Some(TxStatus::Confirmed(tx)))
-            }
-            None => Ok(Some(TxStatus::Pending(tx))),
-        }
+        Ok(Some(TxStatus::from_tx(tx)))
     }
 
     /// dedupe transaction and send them to any listening clients
@@ -80,11 +180,26 @@ impl Web3Rpcs {
         }
 
         // trace!(?pending_tx_id, "checking pending_transactions on {}", rpc);
-        if self.pending_transaction_cache.get(&pending_tx_id).is_some() {
+        if self.pending_transaction_cache.contains(&pending_tx_id) {
             // this transaction has already been processed
             return Ok(());
         }
 
+        if let Some(tx_state) = self
+            .pending_transaction_cache
+            .get_shared(&pending_tx_id)
+            .await
+        {
+            // another proxy already queried this transaction
+            self.pending_transaction_cache
+                .insert(pending_tx_id, tx_state.clone())
+                .await;
+
+            let _ = pending_tx_sender.send(tx_state);
+
+            return Ok(());
+        }
+
         // query the rpc for this transaction
         // it is possible that another rpc is also being queried. thats fine. we want the fastest response
         match self
@@ -92,6 +207,10 @@ impl Web3Rpcs {
             .await
         {
             Ok(Some(tx_state)) => {
+                self.pending_transaction_cache
+                    .insert(pending_tx_id, tx_state.clone())
+                    .await;
+
                 let _ = pending_tx_sender.send(tx_state);
 
                 trace!("sent tx {:?}", pending_tx_id);
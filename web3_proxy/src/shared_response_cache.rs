@@ -0,0 +1,434 @@
+//! Share response cache entries between instances through redis.
+//!
+//! Instances push the responses they fetch for old blocks onto a capped list.
+//! A freshly started instance loads that list into its own cache instead of asking the backends for everything again.
+//! If lookups are enabled, every response is also saved under its own key with a ttl, and those keys are checked after the in-memory cache misses.
+//! Local cache key hashes are not guaranteed to match across builds or platforms, so the request inputs are shared and every instance hashes them itself.
+use crate::block_number::BlockNumAndHash;
+use crate::errors::Web3ProxyResult;
+use crate::response_cache::{
+    CachedJsonRpcResponse, JsonRpcQueryCacheKey, JsonRpcResponseCache, JsonRpcResponseEnum,
+};
+use ethers::types::H256;
+use ethers::utils::keccak256;
+use redis_rate_limiter::redis::{self, AsyncCommands};
+use redis_rate_limiter::RedisPool;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, trace};
+
+/// The request inputs that identify a response on every instance
+#[derive(Serialize)]
+struct SharedCacheKey<'a> {
+    method: &'a str,
+    params: &'a serde_json::Value,
+    from_block: Option<&'a BlockNumAndHash>,
+    to_block: Option<&'a BlockNumAndHash>,
+    cache_errors: bool,
+}
+
+impl<'a> SharedCacheKey<'a> {
+    fn new(
+        cache_key: &'a JsonRpcQueryCacheKey,
+        method: &'a str,
+        params: &'a serde_json::Value,
+    ) -> Self {
+        Self {
+            method,
+            params,
+            from_block: cache_key.from_block(),
+            to_block: cache_key.to_block(),
+            cache_errors: cache_key.cache_errors(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct SharedCacheEntry {
+    method: String,
+    params: serde_json::Value,
+    from_block: Option<BlockNumAndHash>,
+    to_block: Option<BlockNumAndHash>,
+    cache_errors: bool,
+    result: Box<RawValue>,
+}
+
+impl SharedCacheEntry {
+    fn local_key(&self) -> JsonRpcQueryCacheKey {
+        JsonRpcQueryCacheKey::new(
+            self.from_block.clone(),
+            self.to_block.clone(),
+            &self.method,
+            &self.params,
+            self.cache_errors,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct SharedResponseCache {
+    chain_id: u64,
+    max_entries: usize,
+    min_depth: u64,
+    max_bytes: usize,
+    /// None if responses are only shared through the warm list
+    lookup_ttl: Option<Duration>,
+    redis_pool: RedisPool,
+}
+
+impl SharedResponseCache {
+    pub fn new(
+        chain_id: u64,
+        max_entries: usize,
+        min_depth: u64,
+        max_bytes: usize,
+        lookup_ttl: Option<Duration>,
+        redis_pool: RedisPool,
+    ) -> Self {
+        Self {
+            chain_id,
+            // 0 would make LTRIM keep the whole list
+            max_entries: max_entries.max(1),
+            min_depth,
+            max_bytes,
+            lookup_ttl,
+            redis_pool,
+        }
+    }
+
+    fn redis_key(&self) -> String {
+        format!("shared_response_cache:{}", self.chain_id)
+    }
+
+    /// the request inputs can be large, so the redis key is a hash of them
+    fn lookup_key(&self, key: &SharedCacheKey) -> serde_json::Result<String> {
+        let key = serde_json::to_vec(key)?;
+
+        Ok(format!(
+            "shared_response_cache:{}:{:?}",
+            self.chain_id,
+            H256::from(keccak256(key))
+        ))
+    }
+
+    /// Only responses for blocks that are unlikely to be reorged are put on the warm list.
+    /// Warmed entries have no ttl, so keys without a block are never put on it. Some of them are only cached for a method's ttl.
+    fn should_share(&self, cache_key: &JsonRpcQueryCacheKey, head_block_num: u64) -> bool {
+        let newest_block = cache_key
+            .to_block_num()
+            .or_else(|| cache_key.from_block_num());
+
+        match newest_block {
+            None => false,
+            Some(x) => head_block_num.saturating_sub(x.as_u64()) >= self.min_depth,
+        }
+    }
+
+    /// A method's ttl is used if it is shorter than the lookup ttl
+    fn ttl_secs(lookup_ttl: Duration, method_ttl: Option<Duration>) -> usize {
+        let ttl = method_ttl.map_or(lookup_ttl, |x| x.min(lookup_ttl));
+
+        // redis rejects an expiration of 0
+        ttl.as_secs().max(1) as usize
+    }
+
+    /// Check redis for a response that another instance already fetched.
+    /// Any error reading redis is treated as a miss.
+    pub async fn get(
+        &self,
+        cache_key: &JsonRpcQueryCacheKey,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Option<JsonRpcResponseEnum<Arc<RawValue>>> {
+        self.lookup_ttl?;
+
+        let key = self
+            .lookup_key(&SharedCacheKey::new(cache_key, method, params))
+            .ok()?;
+
+        let value = match self.fetch(key).await {
+            Ok(x) => x?,
+            Err(err) => {
+                trace!(?err, "failed reading the shared response cache");
+                return None;
+            }
+        };
+
+        let value = RawValue::from_string(value).ok()?;
+
+        Some(value.into())
+    }
+
+    async fn fetch(&self, key: String) -> Web3ProxyResult<Option<String>> {
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        let value = redis_conn.get(key).await?;
+
+        Ok(value)
+    }
+
+    /// Share a response with other instances. This never slows down or fails the request.
+    /// Errors and responses larger than max_bytes are never shared.
+    pub fn publish(
+        &self,
+        cache_key: &JsonRpcQueryCacheKey,
+        method: &str,
+        params: &serde_json::Value,
+        response_data: &JsonRpcResponseEnum<Arc<RawValue>>,
+        head_block_num: u64,
+        method_ttl: Option<Duration>,
+    ) {
+        let JsonRpcResponseEnum::Result { value, num_bytes } = response_data else {
+            return;
+        };
+
+        if *num_bytes as usize > self.max_bytes {
+            return;
+        }
+
+        let lookup = match self.lookup_ttl {
+            None => None,
+            Some(lookup_ttl) => {
+                match self.lookup_key(&SharedCacheKey::new(cache_key, method, params)) {
+                    Ok(key) => Some((key, Self::ttl_secs(lookup_ttl, method_ttl))),
+                    Err(err) => {
+                        trace!(?err, "failed building the shared response cache key");
+                        None
+                    }
+                }
+            }
+        };
+
+        let entry = self
+            .should_share(cache_key, head_block_num)
+            .then(|| SharedCacheEntry {
+                method: method.to_string(),
+                params: params.clone(),
+                from_block: cache_key.from_block().cloned(),
+                to_block: cache_key.to_block().cloned(),
+                cache_errors: cache_key.cache_errors(),
+                result: value.as_ref().to_owned(),
+            });
+
+        if lookup.is_none() && entry.is_none() {
+            return;
+        }
+
+        let value = value.get().to_string();
+
+        let x = self.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = x.push(lookup, value, entry).await {
+                trace!(?err, "failed sharing response");
+            }
+        });
+    }
+
+    async fn push(
+        &self,
+        lookup: Option<(String, usize)>,
+        value: String,
+        entry: Option<SharedCacheEntry>,
+    ) -> Web3ProxyResult<()> {
+        let mut pipe = redis::pipe();
+
+        if let Some((key, ttl)) = lookup {
+            pipe.set_ex(key, value, ttl).ignore();
+        }
+
+        if let Some(entry) = entry {
+            let key = self.redis_key();
+
+            // the list is trimmed on every push so it never grows past max_entries
+            pipe.lpush(&key, serde_json::to_string(&entry)?)
+                .ignore()
+                .ltrim(&key, 0, self.max_entries as isize - 1)
+                .ignore();
+        }
+
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        pipe.query_async::<_, ()>(&mut *redis_conn).await?;
+
+        Ok(())
+    }
+
+    /// Load the most recently shared responses into the local cache.
+    /// Returns how many entries were loaded.
+    pub async fn warm(
+        &self,
+        cache: &JsonRpcResponseCache,
+        compress: bool,
+    ) -> Web3ProxyResult<usize> {
+        let mut redis_conn = self.redis_pool.get().await?;
+
+        let entries: Vec<String> = redis_conn
+            .lrange(self.redis_key(), 0, self.max_entries as isize - 1)
+            .await?;
+
+        let mut loaded = 0;
+
+        for entry in entries {
+            let entry: SharedCacheEntry = match serde_json::from_str(&entry) {
+                Ok(x) => x,
+                Err(err) => {
+                    debug!(?err, "skipping invalid shared response");
+                    continue;
+                }
+            };
+
+            let cache_key = entry.local_key();
+
+            if cache.contains_key(&cache_key.hash()) {
+                continue;
+            }
+
+            let response_data: JsonRpcResponseEnum<Arc<RawValue>> = entry.result.into();
+
+            cache
+                .insert(
+                    cache_key.hash(),
+                    CachedJsonRpcResponse::new(response_data, compress),
+                )
+                .await;
+
+            loaded += 1;
+        }
+
+        info!(%loaded, "warmed the response cache from redis");
+
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U64;
+    use redis_rate_limiter::{DeadpoolRuntime, RedisConfig};
+    use serde_json::json;
+
+    fn shared_response_cache(lookup_ttl: Option<Duration>) -> SharedResponseCache {
+        // the pool doesn't connect until it is used
+        let redis_pool = RedisConfig::from_url("redis://127.0.0.1")
+            .builder()
+            .unwrap()
+            .runtime(DeadpoolRuntime::Tokio1)
+            .build()
+            .unwrap();
+
+        SharedResponseCache::new(1, 100, 64, 1024, lookup_ttl, redis_pool)
+    }
+
+    fn block(num: u64) -> BlockNumAndHash {
+        (U64::from(num), H256::repeat_byte(num as u8)).into()
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let params = json!([{"to": "0x0000000000000000000000000000000000000001"}, "0x10"]);
+
+        let cache_key =
+            JsonRpcQueryCacheKey::new(Some(block(16)), None, "eth_call", &params, false);
+
+        let entry = SharedCacheEntry {
+            method: "eth_call".to_string(),
+            params: params.clone(),
+            from_block: cache_key.from_block().cloned(),
+            to_block: None,
+            cache_errors: false,
+            result: RawValue::from_string("\"0x01\"".to_string()).unwrap(),
+        };
+
+        let entry: SharedCacheEntry =
+            serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+
+        // another instance builds the same local key from the shared entry
+        assert_eq!(entry.local_key().hash(), cache_key.hash());
+        assert_eq!(entry.result.get(), "\"0x01\"");
+    }
+
+    #[test]
+    fn test_lookup_key() {
+        let x = shared_response_cache(Some(Duration::from_secs(600)));
+
+        let params = json!(["0x10", false]);
+
+        let a =
+            JsonRpcQueryCacheKey::new(Some(block(16)), None, "eth_getBlockByNumber", &params, true);
+        let b =
+            JsonRpcQueryCacheKey::new(Some(block(17)), None, "eth_getBlockByNumber", &params, true);
+
+        let key_a = x
+            .lookup_key(&SharedCacheKey::new(&a, "eth_getBlockByNumber", &params))
+            .unwrap();
+
+        // the same inputs always give the same key
+        assert_eq!(
+            key_a,
+            x.lookup_key(&SharedCacheKey::new(&a, "eth_getBlockByNumber", &params))
+                .unwrap()
+        );
+        assert!(key_a.starts_with("shared_response_cache:1:0x"));
+
+        // a different block is a different response
+        assert_ne!(
+            key_a,
+            x.lookup_key(&SharedCacheKey::new(&b, "eth_getBlockByNumber", &params))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_should_share() {
+        let x = shared_response_cache(None);
+
+        let params = json!([]);
+
+        let deep = JsonRpcQueryCacheKey::new(Some(block(10)), None, "eth_call", &params, false);
+        let shallow = JsonRpcQueryCacheKey::new(Some(block(100)), None, "eth_call", &params, false);
+
+        assert!(x.should_share(&deep, 100));
+        assert!(!x.should_share(&shallow, 100));
+
+        // blockless responses might only be cached for a method's ttl. the warm list would keep them forever
+        let blockless = JsonRpcQueryCacheKey::new(None, None, "eth_gasPrice", &params, false);
+
+        assert!(!x.should_share(&blockless, 100));
+    }
+
+    #[test]
+    fn test_ttl_secs() {
+        let lookup_ttl = Duration::from_secs(600);
+
+        assert_eq!(SharedResponseCache::ttl_secs(lookup_ttl, None), 600);
+        assert_eq!(
+            SharedResponseCache::ttl_secs(lookup_ttl, Some(Duration::from_secs(12))),
+            12
+        );
+        // a method ttl doesn't make shared entries live longer
+        assert_eq!(
+            SharedResponseCache::ttl_secs(lookup_ttl, Some(Duration::from_secs(3_600))),
+            600
+        );
+        assert_eq!(
+            SharedResponseCache::ttl_secs(lookup_ttl, Some(Duration::ZERO)),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookups_disabled() {
+        let x = shared_response_cache(None);
+
+        let params = json!([]);
+
+        let cache_key = JsonRpcQueryCacheKey::new(None, None, "eth_chainId", &params, false);
+
+        // returns before touching redis
+        assert!(x.get(&cache_key, "eth_chainId", &params).await.is_none());
+    }
+}
@@ -0,0 +1,223 @@
+//! Optional eth_sendTransaction support through an external signing service.
+//!
+//! The proxy never holds keys. eth_sendTransaction is forwarded to the signer as eth_signTransaction.
+//! The signed transaction that comes back is broadcast the same way as eth_sendRawTransaction.
+//!
+//! IMPORTANT! Anyone who can use an entitled rpc key can spend from every account that the signer will sign for.
+//! The signer must do its own checks on which accounts, recipients, and amounts are allowed.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::jsonrpc::JsonRpcErrorData;
+use ethers::types::Bytes;
+use serde::Deserialize;
+use serde_json::json;
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignedTransaction {
+    /// web3signer and most other signers return the raw transaction
+    Raw(Bytes),
+    /// clef returns the raw transaction and the decoded transaction
+    WithTx { raw: Bytes },
+}
+
+#[derive(Deserialize)]
+struct SignerResponse {
+    result: Option<SignedTransaction>,
+    error: Option<JsonRpcErrorData>,
+}
+
+pub struct SigningService {
+    client: reqwest::Client,
+    /// database ids of the rpc keys that may use the signer
+    rpc_key_ids: Vec<u64>,
+    timeout: Duration,
+    url: String,
+}
+
+impl SigningService {
+    pub fn new(
+        url: String,
+        rpc_key_ids: Vec<u64>,
+        timeout: Duration,
+        http_client: Option<reqwest::Client>,
+    ) -> Self {
+        Self {
+            client: http_client.unwrap_or_default(),
+            rpc_key_ids,
+            timeout,
+            url,
+        }
+    }
+
+    /// Ask the signer to sign the transaction in the params of an eth_sendTransaction request.
+    /// Requests from keys that aren't entitled never reach the signer.
+    /// Rejections from the signer are returned to the user as is.
+    pub async fn sign_transaction(
+        &self,
+        rpc_secret_key_id: Option<NonZeroU64>,
+        params: &serde_json::Value,
+    ) -> Web3ProxyResult<Bytes> {
+        let entitled = rpc_secret_key_id
+            .map(|x| self.rpc_key_ids.contains(&x.get()))
+            .unwrap_or(false);
+
+        if !entitled {
+            return Err(Web3ProxyError::AccessDenied(
+                "this key is not allowed to use eth_sendTransaction".into(),
+            ));
+        }
+
+        let tx = params
+            .get(0)
+            .filter(|x| x.is_object())
+            .ok_or_else(|| Web3ProxyError::BadRequest("expected a transaction object".into()))?;
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_signTransaction",
+            "params": [tx],
+        });
+
+        let response: SignerResponse = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|err| Web3ProxyError::BadResponse(format!("signer failed: {}", err).into()))?
+            .json()
+            .await
+            .map_err(|err| {
+                Web3ProxyError::BadResponse(format!("invalid signer response: {}", err).into())
+            })?;
+
+        match (response.result, response.error) {
+            (_, Some(error_data)) => Err(Web3ProxyError::JsonRpcErrorData(error_data)),
+            (Some(SignedTransaction::Raw(raw)), None) => Ok(raw),
+            (Some(SignedTransaction::WithTx { raw }), None) => Ok(raw),
+            (None, None) => Err(Web3ProxyError::BadResponse(
+                "signer returned no transaction".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mock_server;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// the url of a signer that only signs for 0x..01, and a count of the requests it has seen
+    fn mock_signer() -> (String, Arc<AtomicUsize>) {
+        let num_requests = Arc::new(AtomicUsize::new(0));
+
+        let router = Router::new().route(
+            "/",
+            post({
+                let num_requests = num_requests.clone();
+
+                move |Json(request): Json<serde_json::Value>| async move {
+                    num_requests.fetch_add(1, Ordering::SeqCst);
+
+                    assert_eq!(request["method"], "eth_signTransaction");
+
+                    if request["params"][0]["from"] == "0x0000000000000000000000000000000000000001"
+                    {
+                        Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x02f8"}))
+                    } else {
+                        Json(json!({
+                            "jsonrpc": "2.0",
+                            "id": 1,
+                            "error": {"code": -32000, "message": "account not allowed"},
+                        }))
+                    }
+                }
+            }),
+        );
+
+        (mock_server(router), num_requests)
+    }
+
+    #[tokio::test]
+    async fn test_signing_service() {
+        let (url, _) = mock_signer();
+
+        let signer = SigningService::new(url, vec![7], Duration::from_secs(5), None);
+
+        let key_id = NonZeroU64::new(7);
+
+        let raw = signer
+            .sign_transaction(
+                key_id,
+                &json!([{
+                    "from": "0x0000000000000000000000000000000000000001",
+                    "to": "0x0000000000000000000000000000000000000002",
+                    "value": "0x1",
+                }]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(raw, Bytes::from_str("0x02f8").unwrap());
+
+        // the signer's rejection is passed through
+        let err = signer
+            .sign_transaction(
+                key_id,
+                &json!([{"from": "0x0000000000000000000000000000000000000003"}]),
+            )
+            .await
+            .unwrap_err();
+
+        match err {
+            Web3ProxyError::JsonRpcErrorData(x) => assert_eq!(x.message, "account not allowed"),
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        // params without a transaction never reach the signer
+        assert!(matches!(
+            signer.sign_transaction(key_id, &json!([])).await,
+            Err(Web3ProxyError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_only_entitled_keys_reach_the_signer() {
+        let (url, num_requests) = mock_signer();
+
+        let signer = SigningService::new(url, vec![7], Duration::from_secs(5), None);
+
+        let params = json!([{
+            "from": "0x0000000000000000000000000000000000000001",
+            "to": "0x0000000000000000000000000000000000000002",
+            "value": "0x1",
+        }]);
+
+        // another user's key and requests without a key
+        for key_id in [NonZeroU64::new(8), None] {
+            assert!(matches!(
+                signer.sign_transaction(key_id, &params).await,
+                Err(Web3ProxyError::AccessDenied(_))
+            ));
+        }
+
+        assert_eq!(num_requests.load(Ordering::SeqCst), 0);
+
+        assert!(signer
+            .sign_transaction(NonZeroU64::new(7), &params)
+            .await
+            .is_ok());
+
+        assert_eq!(num_requests.load(Ordering::SeqCst), 1);
+    }
+}
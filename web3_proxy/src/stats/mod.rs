@@ -19,7 +19,8 @@ use entities::{balance, referee, referrer, rpc_accounting_v2, rpc_key};
 use influxdb2::models::DataPoint;
 use migration::sea_orm::prelude::Decimal;
 use migration::sea_orm::{
-    self, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
+    self, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
 };
 use migration::sea_orm::{DatabaseTransaction, QuerySelect};
 use migration::{Expr, LockType, OnConflict};
@@ -28,10 +29,9 @@ use parking_lot::Mutex;
 use std::borrow::Cow;
 use std::mem;
 use std::num::NonZeroU64;
-use std::str::FromStr;
 use std::sync::atomic::{self, Ordering};
 use std::sync::Arc;
-use tracing::trace;
+use tracing::{trace, warn};
 
 pub use stat_buffer::{SpawnedStatBuffer, StatBuffer};
 
@@ -204,10 +204,10 @@ impl BufferedRpcQueryStats {
         self.latest_balance = latest_balance.clone();
     }
 
-    async fn _save_db_stats(
+    async fn _save_db_stats<C: ConnectionTrait>(
         &self,
         chain_id: u64,
-        db_conn: &DatabaseConnection,
+        db_conn: &C,
         key: &RpcQueryKey,
     ) -> Web3ProxyResult<()> {
         let period_datetime = Utc.timestamp_opt(key.response_timestamp, 0).unwrap();
@@ -662,49 +662,60 @@ impl BufferedRpcQueryStats {
             )));
         }
 
-        // First of all, save the statistics to the database:
-        self._save_db_stats(chain_id, db_conn, &key).await?;
+        // the statistics and the balance updates are saved together.
+        // if anything fails before the commit, none of it was saved and the whole stat can be saved again later
+        let txn = db_conn.begin().await?;
 
-        // Return early if no credits were used, or if user is anonymous
-        if self.sum_credits_used == 0.into() {
-            return Ok(());
-        }
-        let rpc_secret_key_id: &NonZeroU64 = match &key.rpc_secret_key_id {
-            Some(x) => x,
-            None => return Ok(()),
-        };
+        self._save_db_stats(chain_id, &txn, &key).await?;
 
-        // Start a transaction
-        let txn = db_conn.begin().await?;
-        // Fetch any items that we will be modifying
-        let (sender_rpc_entity, _sender_balance, referral_objects) =
-            self._get_relevant_entities(rpc_secret_key_id, &txn).await?;
+        // no balance changes if no credits were used or if the user is anonymous
+        let rpc_secret_key_id = key
+            .rpc_secret_key_id
+            .as_ref()
+            .filter(|_| self.sum_credits_used != 0.into());
 
-        // Compute Changes in balance for user and referrer, incl. referral logic
-        let (deltas, referral_objects): (Deltas, Option<(referee::Model, referrer::Model)>) = self
-            ._compute_balance_deltas(_sender_balance, referral_objects)
-            .await?;
+        let balance_updates = if let Some(rpc_secret_key_id) = rpc_secret_key_id {
+            // Fetch any items that we will be modifying
+            let (sender_rpc_entity, _sender_balance, referral_objects) =
+                self._get_relevant_entities(rpc_secret_key_id, &txn).await?;
 
-        // Update balances in the database
-        self._update_balances_in_db(&deltas, &txn, &sender_rpc_entity, &referral_objects)
-            .await?;
+            // Compute Changes in balance for user and referrer, incl. referral logic
+            let (deltas, referral_objects): (Deltas, Option<(referee::Model, referrer::Model)>) =
+                self._compute_balance_deltas(_sender_balance, referral_objects)
+                    .await?;
+
+            // Update balances in the database
+            self._update_balances_in_db(&deltas, &txn, &sender_rpc_entity, &referral_objects)
+                .await?;
+
+            Some((deltas, sender_rpc_entity, referral_objects))
+        } else {
+            None
+        };
 
         // Finally commit the transaction in the database
         txn.commit()
             .await
-            .context("Failed to update referral and balance updates")?;
+            .context("Failed to save stats and balance updates")?;
 
         // Update balanaces in the cache.
         // do this after commiting the database so that invalidated caches definitely query commited data
-        self._update_balance_in_cache(
-            &deltas,
-            db_conn,
-            &sender_rpc_entity,
-            &referral_objects,
-            rpc_secret_key_cache,
-            user_balance_cache,
-        )
-        .await?;
+        // the stat is saved now. returning an error here would save it again, so errors are only logged
+        if let Some((deltas, sender_rpc_entity, referral_objects)) = balance_updates {
+            if let Err(err) = self
+                ._update_balance_in_cache(
+                    &deltas,
+                    db_conn,
+                    &sender_rpc_entity,
+                    &referral_objects,
+                    rpc_secret_key_cache,
+                    user_balance_cache,
+                )
+                .await
+            {
+                warn!(?err, "unable to update the balance cache");
+            }
+        }
 
         Ok(())
     }
@@ -812,15 +823,16 @@ impl TryFrom<RequestMetadata> for RpcQueryStats {
 
         let cu = ComputeUnit::new(&metadata.method, metadata.chain_id, response_bytes);
 
-        // TODO: get from config? a helper function? how should we pick this?
-        let usd_per_cu = match metadata.chain_id {
-            137 => Decimal::from_str("0.000000533333333333333"),
-            _ => Decimal::from_str("0.000000400000000000000"),
-        }?;
+        let usd_per_cu = ComputeUnit::usd_per_cu(metadata.chain_id);
 
         let cache_hit = !backend_rpcs_used.is_empty();
 
-        let compute_unit_cost = cu.cost(archive_request, cache_hit, usd_per_cu);
+        let compute_unit_cost = cu.cost(
+            archive_request,
+            metadata.archive_multiplier,
+            cache_hit,
+            usd_per_cu,
+        );
 
         let method = mem::take(&mut metadata.method);
 
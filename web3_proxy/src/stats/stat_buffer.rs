@@ -11,9 +11,9 @@ use migration::sea_orm::DatabaseConnection;
 use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio::time::{interval, sleep};
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct BufferedRpcQueryStats {
     pub frontend_requests: u64,
     pub backend_requests: u64,
@@ -195,22 +195,46 @@ impl StatBuffer {
         let mut count = 0;
 
         if let Some(db_conn) = self.db_conn.as_ref() {
-            count = self.accounting_db_buffer.len();
-            for (key, stat) in self.accounting_db_buffer.drain() {
+            let mut pending = self
+                .accounting_db_buffer
+                .drain()
+                .collect::<Vec<_>>()
+                .into_iter();
+
+            while let Some((key, stat)) = pending.next() {
                 // TODO: batch saves
                 // TODO: i don't like passing key (which came from the stat) to the function on the stat. but it works for now
-                if let Err(err) = stat
+                match stat
+                    .clone()
                     .save_db(
                         self.chain_id,
                         db_conn,
-                        key,
+                        key.clone(),
                         &self.rpc_secret_key_cache,
                         &self.user_balance_cache,
                     )
                     .await
                 {
-                    error!("unable to save accounting entry! err={:?}", err);
-                };
+                    Ok(()) => count += 1,
+                    Err(err) if err.is_database_unavailable() => {
+                        // keep this stat and everything after it for the next save instead of failing them one by one
+                        let skipped = pending.len() + 1;
+
+                        warn!(
+                            ?err,
+                            "database unavailable. keeping {} accounting entries for later",
+                            skipped
+                        );
+
+                        self.accounting_db_buffer.insert(key, stat);
+                        self.accounting_db_buffer.extend(pending);
+
+                        break;
+                    }
+                    Err(err) => {
+                        error!("unable to save accounting entry! err={:?}", err);
+                    }
+                }
             }
         }
 
@@ -293,3 +317,58 @@ impl StatBuffer {
         count
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moka::future::Cache;
+    use std::borrow::Cow;
+    use std::num::NonZeroU64;
+
+    #[tokio::test]
+    async fn test_requeue_when_database_unavailable() {
+        let key = RpcQueryKey {
+            response_timestamp: 0,
+            archive_needed: false,
+            error_response: false,
+            method: Cow::Borrowed("eth_call"),
+            origin: None,
+            rpc_secret_key_id: NonZeroU64::new(1),
+            rpc_key_user_id: NonZeroU64::new(1),
+        };
+
+        let stat = BufferedRpcQueryStats {
+            frontend_requests: 3,
+            backend_requests: 2,
+            sum_credits_used: Decimal::new(3, 0),
+            ..Default::default()
+        };
+
+        let mut stat_buffer = StatBuffer {
+            accounting_db_buffer: HashMap::from([(key.clone(), stat)]),
+            billing_period_seconds: 60,
+            chain_id: 1,
+            db_conn: Some(DatabaseConnection::Disconnected),
+            db_save_interval_seconds: 10,
+            global_timeseries_buffer: Default::default(),
+            influxdb_client: None,
+            opt_in_timeseries_buffer: Default::default(),
+            rpc_secret_key_cache: Cache::new(1),
+            user_balance_cache: Cache::new(1),
+            timestamp_precision: TimestampPrecision::Seconds,
+            tsdb_save_interval_seconds: 10,
+        };
+
+        // nothing is saved. the stat and its balance changes stay buffered together
+        assert_eq!(stat_buffer.save_relational_stats().await, 0);
+        assert_eq!(stat_buffer.accounting_db_buffer.len(), 1);
+
+        // saving again doesn't count anything twice
+        assert_eq!(stat_buffer.save_relational_stats().await, 0);
+
+        let requeued = stat_buffer.accounting_db_buffer.get(&key).unwrap();
+        assert_eq!(requeued.frontend_requests, 3);
+        assert_eq!(requeued.backend_requests, 2);
+        assert_eq!(requeued.sum_credits_used, Decimal::new(3, 0));
+    }
+}
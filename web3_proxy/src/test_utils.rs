@@ -0,0 +1,14 @@
+//! Helpers shared by the unit tests.
+use axum::Router;
+
+/// Serve the router on a random local port until the test's runtime stops. Returns the server's url
+pub fn mock_server(router: Router) -> String {
+    let server =
+        axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+
+    let url = format!("http://{}", server.local_addr());
+
+    tokio::spawn(server);
+
+    url
+}
@@ -0,0 +1,185 @@
+//! Limits on the most expensive trace_* requests.
+//!
+//! trace_filter scans every block in its range and trace_callMany runs every call in its batch.
+//! Limits are set per method. The app config has the default limits. User tiers can have their own, usually higher, limits.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use ethers::types::U64;
+use hashbrown::HashMap;
+use serde::Deserialize;
+
+/// The limits for one user tier
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct TraceLimits {
+    /// the most blocks that one request can cover, by method. the first param must be a filter with fromBlock and toBlock, like trace_filter's.
+    /// methods that aren't listed are unlimited
+    #[serde(default)]
+    pub max_block_range: HashMap<String, u64>,
+    /// the most calls in one request, by method. the first param must be a list of calls, like trace_callMany's.
+    /// methods that aren't listed are unlimited
+    #[serde(default)]
+    pub max_calls: HashMap<String, usize>,
+}
+
+impl TraceLimits {
+    /// BadRequest if the request is over a limit. Block tags like "latest" are resolved with head_block_num
+    pub fn check(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+        head_block_num: u64,
+    ) -> Web3ProxyResult<()> {
+        if let Some(max_block_range) = self.max_block_range.get(method) {
+            let block_range = filter_block_range(params, head_block_num);
+
+            if block_range > *max_block_range {
+                return Err(Web3ProxyError::BadRequest(
+                    format!(
+                        "{} covers {} blocks. the max is {}",
+                        method, block_range, max_block_range
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        if let Some(max_calls) = self.max_calls.get(method) {
+            let num_calls = params
+                .get(0)
+                .and_then(|x| x.as_array())
+                .map(|x| x.len())
+                .unwrap_or_default();
+
+            if num_calls > *max_calls {
+                return Err(Web3ProxyError::BadRequest(
+                    format!(
+                        "{} has {} calls. the max is {}",
+                        method, num_calls, max_calls
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How many blocks a filter like trace_filter's covers.
+/// Like erigon, a missing fromBlock is the first block and a missing toBlock is the head block.
+fn filter_block_range(params: &serde_json::Value, head_block_num: u64) -> u64 {
+    let filter = params.get(0);
+
+    let from_block = block_param(filter.and_then(|x| x.get("fromBlock")), 0, head_block_num);
+    let to_block = block_param(
+        filter.and_then(|x| x.get("toBlock")),
+        head_block_num,
+        head_block_num,
+    );
+
+    (to_block + 1).saturating_sub(from_block)
+}
+
+/// Invalid block params are left for the backends to reject
+fn block_param(param: Option<&serde_json::Value>, default: u64, head_block_num: u64) -> u64 {
+    match param {
+        None | Some(serde_json::Value::Null) => default,
+        Some(x) => match x.as_str() {
+            Some("earliest") => 0,
+            Some("latest" | "pending" | "safe" | "finalized") => head_block_num,
+            _ => serde_json::from_value::<U64>(x.clone())
+                .map(|x| x.as_u64())
+                .unwrap_or(default),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_trace_filter_block_range() {
+        let limits = TraceLimits {
+            max_block_range: HashMap::from([("trace_filter".to_string(), 100)]),
+            ..Default::default()
+        };
+
+        let params = json!([{"fromBlock": "0x64", "toBlock": "0xc7"}]);
+        assert_eq!(filter_block_range(&params, 1_000), 100);
+        assert!(limits.check("trace_filter", &params, 1_000).is_ok());
+
+        let params = json!([{"fromBlock": "0x64", "toBlock": "0xc8"}]);
+        assert!(limits.check("trace_filter", &params, 1_000).is_err());
+
+        // tags are resolved with the head block
+        let params = json!([{"fromBlock": "0x3e8", "toBlock": "latest"}]);
+        assert_eq!(filter_block_range(&params, 1_050), 51);
+
+        // a missing fromBlock starts at the first block
+        let params = json!([{"toBlock": "latest"}]);
+        assert_eq!(filter_block_range(&params, 1_000_000), 1_000_001);
+        assert!(limits.check("trace_filter", &params, 1_000_000).is_err());
+
+        // backwards ranges are for the backends to reject
+        let params = json!([{"fromBlock": "0xc8", "toBlock": "0x64"}]);
+        assert_eq!(filter_block_range(&params, 1_000), 0);
+
+        // without a limit, anything goes
+        let params = json!([{"fromBlock": "earliest", "toBlock": "latest"}]);
+        assert!(TraceLimits::default()
+            .check("trace_filter", &params, 1_000_000)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_trace_call_many_calls() {
+        let limits = TraceLimits {
+            max_calls: HashMap::from([("trace_callMany".to_string(), 2)]),
+            ..Default::default()
+        };
+
+        let call = json!([{"to": "0x0000000000000000000000000000000000000000"}, ["trace"]]);
+
+        let params = json!([[call, call], "latest"]);
+        assert!(limits.check("trace_callMany", &params, 1_000).is_ok());
+
+        let params = json!([[call, call, call], "latest"]);
+        assert!(limits.check("trace_callMany", &params, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_limits_are_per_method() {
+        let limits: TraceLimits = toml::from_str(
+            r#"
+            [max_block_range]
+            trace_filter = 1_000
+            arbtrace_filter = 10
+
+            [max_calls]
+            trace_callMany = 5
+            "#,
+        )
+        .unwrap();
+
+        let params = json!([{"fromBlock": "0x1", "toBlock": "0x64"}]);
+
+        assert!(limits.check("trace_filter", &params, 1_000).is_ok());
+        assert!(limits.check("arbtrace_filter", &params, 1_000).is_err());
+
+        // methods without a limit are unlimited
+        assert!(limits.check("trace_block", &params, 1_000).is_ok());
+
+        let call = json!([{"to": "0x0000000000000000000000000000000000000000"}, ["trace"]]);
+        let params = json!([[call, call, call], "latest"]);
+
+        assert!(limits.check("trace_callMany", &params, 1_000).is_ok());
+
+        match limits.check("trace_filter", &json!([{"toBlock": "latest"}]), 1_000) {
+            Err(Web3ProxyError::BadRequest(msg)) => {
+                assert_eq!(msg, "trace_filter covers 1001 blocks. the max is 1000")
+            }
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
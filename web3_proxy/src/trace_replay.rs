@@ -0,0 +1,91 @@
+//! Cache for `trace_replayTransaction`.
+//! Replaying a mined transaction always gives the same result, and it is one of the most expensive methods there is.
+//! Results are only cached once the transaction's block is deep enough that it won't be reorged.
+use crate::response_cache::JsonRpcQueryCacheKey;
+use ethers::types::{H256, U64};
+use itertools::Itertools;
+use serde_json::json;
+
+/// The params for `trace_replayTransaction`
+#[derive(Debug)]
+pub struct TraceReplayParams {
+    pub tx_hash: H256,
+    pub trace_types: Vec<String>,
+}
+
+impl TraceReplayParams {
+    /// returns None if the params are not valid. the backends will give the user a proper error
+    pub fn try_from_params(params: &serde_json::Value) -> Option<Self> {
+        let params = params.as_array()?;
+
+        let tx_hash = serde_json::from_value(params.get(0)?.clone()).ok()?;
+
+        let trace_types = serde_json::from_value(params.get(1)?.clone()).ok()?;
+
+        Some(Self {
+            tx_hash,
+            trace_types,
+        })
+    }
+
+    /// The order of the trace types doesn't change the result, so it doesn't change the key either
+    pub fn cache_key(&self) -> JsonRpcQueryCacheKey {
+        let trace_types: Vec<_> = self.trace_types.iter().sorted().dedup().collect();
+
+        JsonRpcQueryCacheKey::new(
+            None,
+            None,
+            "trace_replayTransaction",
+            &json!([self.tx_hash, trace_types]),
+            false,
+        )
+    }
+}
+
+/// true if a transaction mined in tx_block is deep enough to cache its traces
+pub fn is_confirmed(tx_block: U64, head_block: U64, confirmations: u64) -> bool {
+    head_block.saturating_sub(tx_block).as_u64() >= confirmations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_replay_cache_key() {
+        let tx_hash = H256::repeat_byte(1);
+
+        let key = |trace_types: serde_json::Value| {
+            TraceReplayParams::try_from_params(&json!([tx_hash, trace_types]))
+                .unwrap()
+                .cache_key()
+                .hash()
+        };
+
+        // the same trace types in a different order are a hit
+        assert_eq!(
+            key(json!(["trace", "vmTrace"])),
+            key(json!(["vmTrace", "trace"]))
+        );
+        assert_eq!(key(json!(["trace", "trace"])), key(json!(["trace"])));
+
+        // different trace types are a miss
+        assert_ne!(key(json!(["trace", "vmTrace"])), key(json!(["trace"])));
+
+        // so are different transactions
+        let other = TraceReplayParams::try_from_params(&json!([H256::repeat_byte(2), ["trace"]]))
+            .unwrap()
+            .cache_key()
+            .hash();
+
+        assert_ne!(key(json!(["trace"])), other);
+    }
+
+    #[test]
+    fn test_trace_replay_confirmations() {
+        assert!(is_confirmed(100.into(), 164.into(), 64));
+        assert!(!is_confirmed(100.into(), 163.into(), 64));
+        // a node that is behind shouldn't underflow
+        assert!(!is_confirmed(100.into(), 90.into(), 64));
+    }
+}
@@ -0,0 +1,190 @@
+//! Fire-and-forget webhooks so that operators can alert on important events without scraping logs.
+//!
+//! Webhooks are sent in the background. They are retried a few times with backoff, but they never slow down or fail requests.
+use crate::config::AppConfig;
+use ethers::types::U64;
+use hashbrown::HashSet;
+use moka::future::{Cache, CacheBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::sleep;
+use tracing::{debug, trace, warn};
+
+/// How many times to try sending a webhook before giving up
+const WEBHOOK_MAX_TRIES: u32 = 3;
+
+/// Don't send the same event (for the same rpc or user) more often than this
+const WEBHOOK_DEDUP_SECONDS: u64 = 60;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    BackendUnhealthy,
+    CircuitBreakerOpened,
+    ConsensusHeadStalled,
+    QuotaExceeded,
+}
+
+/// The json body of a webhook.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// an rpc stopped being synced
+    BackendUnhealthy { rpc: String, reason: String },
+    /// an rpc is failing so much that requests are no longer sent to it
+    CircuitBreakerOpened { rpc: String },
+    /// no new consensus head block in longer than expected
+    ConsensusHeadStalled {
+        head_block_num: Option<U64>,
+        head_block_age_seconds: Option<u64>,
+    },
+    /// a user hit their rate limit
+    QuotaExceeded {
+        user_id: u64,
+        rpc_key_id: Option<u64>,
+    },
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            Self::BackendUnhealthy { .. } => WebhookEventKind::BackendUnhealthy,
+            Self::CircuitBreakerOpened { .. } => WebhookEventKind::CircuitBreakerOpened,
+            Self::ConsensusHeadStalled { .. } => WebhookEventKind::ConsensusHeadStalled,
+            Self::QuotaExceeded { .. } => WebhookEventKind::QuotaExceeded,
+        }
+    }
+
+    /// repeats of the same key are only sent once every WEBHOOK_DEDUP_SECONDS
+    fn dedup_key(&self) -> String {
+        match self {
+            Self::BackendUnhealthy { rpc, .. } => format!("backend_unhealthy:{}", rpc),
+            Self::CircuitBreakerOpened { rpc } => format!("circuit_breaker_opened:{}", rpc),
+            Self::ConsensusHeadStalled { .. } => "consensus_head_stalled".to_string(),
+            Self::QuotaExceeded { user_id, .. } => format!("quota_exceeded:{}", user_id),
+        }
+    }
+}
+
+pub struct WebhookSender {
+    chain_id: u64,
+    client: reqwest::Client,
+    /// None means every event is sent
+    events: Option<HashSet<WebhookEventKind>>,
+    hostname: Option<String>,
+    recently_sent: Cache<String, ()>,
+    semaphore: Arc<Semaphore>,
+    url: String,
+}
+
+impl WebhookSender {
+    /// returns None if no webhook_url is configured
+    pub fn new(
+        config: &AppConfig,
+        http_client: Option<reqwest::Client>,
+        hostname: Option<String>,
+    ) -> Option<Arc<Self>> {
+        let url = config.webhook_url.clone()?;
+
+        let events = if config.webhook_events.is_empty() {
+            None
+        } else {
+            Some(config.webhook_events.iter().copied().collect())
+        };
+
+        let recently_sent = CacheBuilder::new(10_000)
+            .name("webhooks_recently_sent")
+            .time_to_live(Duration::from_secs(WEBHOOK_DEDUP_SECONDS))
+            .build();
+
+        let x = Self {
+            chain_id: config.chain_id,
+            client: http_client.unwrap_or_default(),
+            events,
+            hostname,
+            recently_sent,
+            semaphore: Arc::new(Semaphore::new(config.webhook_max_concurrency.max(1))),
+            url,
+        };
+
+        Some(Arc::new(x))
+    }
+
+    /// Send the event in the background. This never blocks and never fails.
+    /// If too many webhooks are already in flight, the event is dropped.
+    pub fn send(self: &Arc<Self>, event: WebhookEvent) {
+        if let Some(ref events) = self.events {
+            if !events.contains(&event.kind()) {
+                return;
+            }
+        }
+
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(x) => x,
+            Err(_) => {
+                warn!(?event, "too many webhooks in flight. dropping");
+                return;
+            }
+        };
+
+        let sender = self.clone();
+
+        tokio::spawn(async move {
+            let entry = sender
+                .recently_sent
+                .entry(event.dedup_key())
+                .or_insert(())
+                .await;
+
+            if !entry.is_fresh() {
+                trace!(?event, "webhook recently sent");
+                return;
+            }
+
+            sender.send_with_retries(&event).await;
+
+            drop(permit);
+        });
+    }
+
+    async fn send_with_retries(&self, event: &WebhookEvent) {
+        let mut body = json!(event);
+
+        body["chain_id"] = json!(self.chain_id);
+        body["hostname"] = json!(self.hostname);
+        body["timestamp"] = json!(chrono::Utc::now().timestamp());
+
+        let mut backoff = Duration::from_secs(1);
+
+        for try_num in 1..=WEBHOOK_MAX_TRIES {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(&body)
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|x| x.error_for_status());
+
+            match response {
+                Ok(_) => {
+                    trace!(?event, "webhook sent");
+                    return;
+                }
+                Err(err) if try_num < WEBHOOK_MAX_TRIES => {
+                    debug!(?err, %try_num, "webhook failed. retrying in {:?}", backoff);
+
+                    sleep(backoff).await;
+
+                    backoff *= 2;
+                }
+                Err(err) => {
+                    warn!(?err, ?event, "webhook failed");
+                }
+            }
+        }
+    }
+}